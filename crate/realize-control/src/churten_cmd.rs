@@ -49,6 +49,378 @@ pub(crate) async fn execute_churten_is_running(
     }
 }
 
+/// Execute the churten status command
+pub(crate) async fn execute_churten_status(
+    control: &control_capnp::control::Client,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let churten = client::get_churten(&control).await?;
+    let result = churten.status_request().send().promise.await?;
+    let status = result.get()?.get_res()?;
+    output::print_info(
+        output_mode,
+        if status.get_running() {
+            "Churten running"
+        } else {
+            "Churten stopped"
+        },
+    );
+    for schedule in status.get_download_schedules()?.iter() {
+        let arena = schedule.get_arena()?.to_str()?;
+        let label = if arena.is_empty() {
+            "Downloads".to_string()
+        } else {
+            format!("Downloads [{arena}]")
+        };
+        let next_change = schedule.get_next_change_secs();
+        let state = match (schedule.get_open(), next_change) {
+            (true, 0) => "allowed at any time".to_string(),
+            (false, 0) => "never allowed".to_string(),
+            (true, secs) => format!("allowed until {}", format_local_time(secs)?),
+            (false, secs) => format!("next window starts {}", format_local_time(secs)?),
+        };
+        output::print_info(output_mode, format!("{label}: {state}"));
+    }
+    output::print_info(
+        output_mode,
+        format!(
+            "Bandwidth: {}",
+            format_bandwidth_limit(status.get_bandwidth_limit())
+        ),
+    );
+
+    Ok(0)
+}
+
+/// Execute the churten limit command
+pub(crate) async fn execute_churten_limit(
+    control: &control_capnp::control::Client,
+    bytes_per_second: u64,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let churten = client::get_churten(&control).await?;
+    let mut request = churten.set_bandwidth_limit_request();
+    request.get().set_bytes_per_second(bytes_per_second);
+    request.send().promise.await?;
+    output::print_success(
+        output_mode,
+        "OK",
+        format!("Bandwidth: {}", format_bandwidth_limit(bytes_per_second)),
+    );
+
+    Ok(0)
+}
+
+/// Describe a bandwidth limit, with 0 meaning unlimited.
+pub(crate) fn format_bandwidth_limit(bytes_per_second: u64) -> String {
+    if bytes_per_second == 0 {
+        "unlimited".to_string()
+    } else {
+        format!("{bytes_per_second} bytes/s")
+    }
+}
+
+/// Filters of the churten history command.
+#[derive(clap::Args, Debug)]
+pub(crate) struct HistoryArgs {
+    /// Only show jobs of that arena
+    #[arg(long)]
+    arena: Option<String>,
+
+    /// Only show jobs on that path or on files within that directory
+    #[arg(long)]
+    path: Option<String>,
+
+    /// Only show jobs that ended that way
+    #[arg(long, value_enum)]
+    status: Option<HistoryStatus>,
+
+    /// Only show jobs that ended at or after that time, either a
+    /// local date and time, such as "2025-06-01 08:00", or a
+    /// duration before now, such as "12h"
+    #[arg(long, value_parser = |s: &str| parse_time(s))]
+    since: Option<u64>,
+
+    /// Only show jobs that ended before that time, written as for
+    /// --since
+    #[arg(long, value_parser = |s: &str| parse_time(s))]
+    until: Option<u64>,
+
+    /// Show at most that many jobs; 0 for no limit
+    #[arg(long, default_value_t = 50)]
+    limit: u32,
+}
+
+#[derive(Debug, Clone, clap::ValueEnum)]
+pub(crate) enum HistoryStatus {
+    Done,
+    Abandoned,
+    Cancelled,
+    Failed,
+}
+
+/// Execute the churten history command
+pub(crate) async fn execute_churten_history(
+    control: &control_capnp::control::Client,
+    args: &HistoryArgs,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.job_history_request();
+    let mut req = request.get().init_req();
+    if let Some(arena) = &args.arena {
+        req.set_arena(arena);
+    }
+    if let Some(path) = &args.path {
+        req.set_path_prefix(path);
+    }
+    req.set_outcome(match args.status {
+        None => control_capnp::job_history_request::Outcome::Any,
+        Some(HistoryStatus::Done) => control_capnp::job_history_request::Outcome::Done,
+        Some(HistoryStatus::Abandoned) => control_capnp::job_history_request::Outcome::Abandoned,
+        Some(HistoryStatus::Cancelled) => control_capnp::job_history_request::Outcome::Cancelled,
+        Some(HistoryStatus::Failed) => control_capnp::job_history_request::Outcome::Failed,
+    });
+    req.set_since_secs(args.since.unwrap_or(0));
+    req.set_until_secs(args.until.unwrap_or(0));
+    req.set_limit(args.limit);
+
+    let result = request.send().promise.await?;
+    let history = result.get()?.get_res()?;
+    if history.is_empty() {
+        output::print_success(output_mode, "OK", "No jobs");
+
+        return Ok(0);
+    }
+    for entry in history.iter() {
+        let job_type = match entry.get_job_type() {
+            Ok(control_capnp::JobType::Download) => "download",
+            Ok(control_capnp::JobType::Realize) => "realize",
+            Ok(control_capnp::JobType::Unrealize) => "unrealize",
+            Err(_) => "unknown",
+        };
+        let progress = entry.get_progress()?;
+        let outcome = match progress.get_type() {
+            Ok(control_capnp::job_progress::Type::Done) => "done".to_string(),
+            Ok(control_capnp::job_progress::Type::Abandoned) => "abandoned".to_string(),
+            Ok(control_capnp::job_progress::Type::Cancelled) => "cancelled".to_string(),
+            Ok(control_capnp::job_progress::Type::Failed) => {
+                format!("failed: {}", progress.get_message()?.to_str()?)
+            }
+            _ => "unknown".to_string(),
+        };
+        output::print_info(
+            output_mode,
+            format!(
+                "{} [{}]/{} {job_type} {outcome} ({} bytes in {}s)",
+                format_local_time(entry.get_end_secs())?,
+                entry.get_arena()?.to_str()?,
+                entry.get_path()?.to_str()?,
+                entry.get_bytes(),
+                entry.get_end_secs().saturating_sub(entry.get_start_secs()),
+            ),
+        );
+    }
+
+    Ok(0)
+}
+
+/// Execute the churten plan command
+pub(crate) async fn execute_churten_plan(
+    control: &control_capnp::control::Client,
+    arena: Option<&str>,
+    path: Option<&str>,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.job_plan_request();
+    if let Some(arena) = arena {
+        request.get().set_arena(arena);
+    }
+    if let Some(path) = path {
+        request.get().set_path_prefix(path);
+    }
+
+    let result = request.send().promise.await?;
+    let plan = result.get()?.get_res()?;
+    if plan.is_empty() {
+        output::print_success(output_mode, "OK", "No pending jobs");
+
+        return Ok(0);
+    }
+    let mut downloads = 0;
+    let mut realizes = 0;
+    let mut unrealizes = 0;
+    let mut missing_bytes = 0;
+    for planned in plan.iter() {
+        let job_type = match planned.get_job_type() {
+            Ok(control_capnp::JobType::Download) => {
+                downloads += 1;
+                missing_bytes += planned.get_size().saturating_sub(planned.get_local_bytes());
+                "download"
+            }
+            Ok(control_capnp::JobType::Realize) => {
+                realizes += 1;
+                "realize"
+            }
+            Ok(control_capnp::JobType::Unrealize) => {
+                unrealizes += 1;
+                "unrealize"
+            }
+            Err(_) => "unknown",
+        };
+        let mut peers = vec![];
+        for peer in planned.get_peers()?.iter() {
+            peers.push(peer?.to_str()?.to_string());
+        }
+        let backoff = match planned.get_backoff_until_secs() {
+            0 => String::new(),
+            secs => format!(", failed, retry after {}", format_local_time(secs)?),
+        };
+        output::print_info(
+            output_mode,
+            format!(
+                "[{}]/{} {job_type} ({}/{} bytes local, from {}{backoff})",
+                planned.get_arena()?.to_str()?,
+                planned.get_path()?.to_str()?,
+                planned.get_local_bytes(),
+                planned.get_size(),
+                if peers.is_empty() {
+                    "no peers".to_string()
+                } else {
+                    peers.join(", ")
+                },
+            ),
+        );
+    }
+    output::print_info(
+        output_mode,
+        format!(
+            "Total: {} jobs ({downloads} download, {realizes} realize, {unrealizes} unrealize), {missing_bytes} bytes to download",
+            plan.len(),
+        ),
+    );
+
+    Ok(0)
+}
+
+/// Execute the churten cancel command
+pub(crate) async fn execute_churten_cancel(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    job_id: u64,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let churten = client::get_churten(&control).await?;
+    let mut request = churten.cancel_job_request();
+    request.get().set_arena(arena);
+    request.get().set_job_id(job_id);
+    let result = request.send().promise.await?;
+    if !result.get()?.get_cancelled() {
+        anyhow::bail!("Job {job_id} of {arena} isn't running");
+    }
+    output::print_success(output_mode, "OK", format!("Job {job_id} cancelled"));
+
+    Ok(0)
+}
+
+/// Execute the churten failed command
+pub(crate) async fn execute_churten_failed(
+    control: &control_capnp::control::Client,
+    arena: Option<&str>,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.failed_jobs_request();
+    if let Some(arena) = arena {
+        request.get().set_arena(arena);
+    }
+    let result = request.send().promise.await?;
+    let failed = result.get()?.get_res()?;
+    if failed.is_empty() {
+        output::print_success(output_mode, "OK", "No failed jobs");
+
+        return Ok(0);
+    }
+    for job in failed.iter() {
+        output::print_info(
+            output_mode,
+            format!(
+                "{} [{}]/{} failed {} times, retry after {}",
+                job.get_job_id(),
+                job.get_arena()?.to_str()?,
+                job.get_path()?.to_str()?,
+                job.get_failure_count(),
+                format_local_time(job.get_backoff_until_secs())?,
+            ),
+        );
+    }
+
+    Ok(0)
+}
+
+/// Execute the churten retry command
+pub(crate) async fn execute_churten_retry(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    job_id: u64,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.retry_job_request();
+    request.get().set_arena(arena);
+    request.get().set_job_id(job_id);
+    let result = request.send().promise.await?;
+    if !result.get()?.get_retried() {
+        anyhow::bail!("Job {job_id} of {arena} isn't a failed job");
+    }
+    output::print_success(output_mode, "OK", format!("Job {job_id} will be retried"));
+
+    Ok(0)
+}
+
+/// Execute the churten ignore command
+pub(crate) async fn execute_churten_ignore(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    path: &str,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.ignore_path_request();
+    request.get().set_arena(arena);
+    request.get().set_path(path);
+    let result = request.send().promise.await?;
+    if !result.get()?.get_ignored() {
+        anyhow::bail!("Unknown file: [{arena}]/{path}");
+    }
+    output::print_success(
+        output_mode,
+        "OK",
+        format!("[{arena}]/{path} ignored until it changes"),
+    );
+
+    Ok(0)
+}
+
+/// Parse a time given either as a local date and time or as a
+/// duration before now, and return it as seconds since the epoch.
+fn parse_time(s: &str) -> Result<u64> {
+    let zoned = if let Ok(span) = s.parse::<jiff::Span>() {
+        jiff::Zoned::now().checked_sub(span)?
+    } else if let Ok(datetime) = s.parse::<jiff::civil::DateTime>() {
+        datetime.to_zoned(jiff::tz::TimeZone::system())?
+    } else {
+        s.parse::<jiff::civil::Date>()
+            .map_err(|_| anyhow::anyhow!("invalid time: \"{s}\""))?
+            .to_zoned(jiff::tz::TimeZone::system())?
+    };
+
+    Ok(zoned.timestamp().as_second().max(0) as u64)
+}
+
+/// Format seconds since the epoch as a time in the local timezone.
+pub(crate) fn format_local_time(secs: u64) -> Result<String> {
+    let zoned = jiff::Timestamp::from_second(secs as i64)?.to_zoned(jiff::tz::TimeZone::system());
+
+    Ok(zoned.strftime("%a %Y-%m-%d %H:%M").to_string())
+}
+
 /// Execute the churten run command
 pub(crate) async fn execute_churten_run(
     control: &control_capnp::control::Client,
@@ -206,6 +206,12 @@ impl ChurtenDisplay {
                         }
                     };
                 }
+                ChurtenNotification::Pause { .. } => {
+                    log::info!("PAUSE: {}", format_log_string(job));
+                }
+                ChurtenNotification::Block { reason, .. } => {
+                    log::warn!("BLOCKED: {reason}: {}", format_log_string(job));
+                }
                 ChurtenNotification::UpdateAction { action, .. } => {
                     log::info!("{action:?} {}", format_log_string(job));
                 }
@@ -243,6 +249,14 @@ fn format_log_string(job: &JobInfo) -> String {
 
 fn update_bar_for_job(bar: &mut ProgressBar, job: &JobInfo) {
     bar.set_prefix(prefix_for_job(job));
+    match &job.progress {
+        JobProgress::Blocked(reason) => {
+            bar.set_message(format!("{}: {reason}", display_path(job)));
+        }
+        _ => {
+            bar.set_message(display_path(job));
+        }
+    }
     if bar.length().is_none() && job.byte_progress.is_some() {
         bar.set_style(output::progress_style(MessageType::PROGRESS, true));
     }
@@ -0,0 +1,90 @@
+use super::churten_cmd::format_local_time;
+use super::output::{self, OutputMode};
+use anyhow::Result;
+use base64::Engine as _;
+use realize_core::rpc::control::client;
+use realize_core::rpc::control::control_capnp;
+use realize_network::security::{KeyRotation, RawPublicKeyResolver};
+use realize_types::Peer;
+use std::path::Path;
+
+/// Execute the keys rotate command
+pub(crate) async fn execute_keys_rotate(
+    control: &control_capnp::control::Client,
+    privkey: &Path,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let resolver = RawPublicKeyResolver::from_private_key_file(privkey)?;
+    let rotation = KeyRotation::sign(&resolver)?;
+    let peers = client::rotate_key(control, &rotation).await?;
+    if peers.is_empty() {
+        anyhow::bail!("No connected peer received the new key");
+    }
+    output::print_success(
+        output_mode,
+        "OK",
+        format!(
+            "New key sent to {}",
+            peers
+                .iter()
+                .map(|p| p.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    );
+
+    Ok(0)
+}
+
+/// Execute the keys pending command
+pub(crate) async fn execute_keys_pending(
+    control: &control_capnp::control::Client,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let pending = client::pending_keys(control).await?;
+    if pending.is_empty() {
+        output::print_success(output_mode, "OK", "No pending keys");
+
+        return Ok(0);
+    }
+    for key in pending {
+        output::print_info(
+            output_mode,
+            format!(
+                "{}: {} (received {})",
+                key.peer,
+                encode_key(&key.pubkey),
+                format_local_time(key.received.as_secs())?
+            ),
+        );
+    }
+
+    Ok(0)
+}
+
+/// Execute the keys approve command
+pub(crate) async fn execute_keys_approve(
+    control: &control_capnp::control::Client,
+    peer: &str,
+    pubkey: &str,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let spki = base64::prelude::BASE64_STANDARD
+        .decode(pubkey.trim())
+        .map_err(|_| anyhow::anyhow!("Invalid key: {pubkey}"))?;
+    if !client::approve_key(control, Peer::from(peer), &spki).await? {
+        anyhow::bail!("No such key pending for {peer}");
+    }
+    output::print_success(
+        output_mode,
+        "OK",
+        format!("{peer}: key approved until restart; add it to the configuration to keep it"),
+    );
+
+    Ok(0)
+}
+
+/// Encode a public key as the body of its PEM encoding.
+fn encode_key(spki: &[u8]) -> String {
+    base64::prelude::BASE64_STANDARD.encode(spki)
+}
@@ -4,12 +4,17 @@ use output::OutputMode;
 use realize_core::rpc::control::client;
 use realize_core::utils::logging;
 use std::path::PathBuf;
+use std::time::Duration;
 use tokio::task::LocalSet;
 
 mod churten_cmd;
 mod display;
+mod keys_cmd;
 mod mark_cmd;
 mod output;
+mod pairing_cmd;
+mod peers_cmd;
+mod scrub_cmd;
 
 /// Command-line tool for controlling a running instance of realize-daemon
 #[derive(Parser, Debug)]
@@ -33,6 +38,22 @@ struct Cli {
 
 #[derive(Subcommand, Debug)]
 enum Commands {
+    #[command(flatten)]
+    Daemon(DaemonCommands),
+    /// Create a new private key for the daemon and print its public
+    /// key
+    ///
+    /// This doesn't need a running daemon.
+    Keygen {
+        /// File to write the private key to, in PEM format; must not
+        /// exist
+        privkey: PathBuf,
+    },
+}
+
+/// Commands sent to the running daemon.
+#[derive(Subcommand, Debug)]
+enum DaemonCommands {
     Churten {
         #[command(subcommand)]
         command: ChurtenCommands,
@@ -41,6 +62,94 @@ enum Commands {
         #[command(subcommand)]
         command: MarkCommands,
     },
+    Scrub {
+        #[command(subcommand)]
+        command: ScrubCommands,
+    },
+    /// Show the state of the connections to the peers
+    Peers {
+        /// Keep running and show peers again when they connect or
+        /// disconnect
+        #[arg(long)]
+        watch: bool,
+
+        #[command(subcommand)]
+        command: Option<PeersCommands>,
+    },
+    /// Manage the public keys of the peers
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommands,
+    },
+    /// Create a one-time invitation for a new peer
+    ///
+    /// Prints a token to pass to "realize-control join" on the new
+    /// peer. Until the invitation is used or expires, unknown peers
+    /// are allowed to connect, but only to pair.
+    Invite {
+        /// Address the new peer can reach this daemon at, as
+        /// host:port
+        address: String,
+        /// How long the invitation is valid, such as 10m or 1h
+        #[arg(long, default_value = "10m", value_parser = |s: &str| parse_duration(s))]
+        ttl: Duration,
+    },
+    /// Join the household of the peer that created an invitation
+    ///
+    /// Both daemons add each other to their configuration file.
+    Join {
+        /// Token printed by "realize-control invite"
+        token: String,
+        /// ID of this peer; defaults to the ID this peer has in its
+        /// own configuration
+        #[arg(long)]
+        peer: Option<String>,
+        /// Address the inviting peer can reach this daemon at, as
+        /// host:port, if any
+        #[arg(long)]
+        address: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum KeysCommands {
+    /// Send a new public key of this peer to the connected peers
+    ///
+    /// The other peers only accept the key once their operator
+    /// approves it.
+    Rotate {
+        /// File containing the new private key, in PEM format
+        privkey: PathBuf,
+    },
+    /// List the keys proposed by other peers, waiting for approval
+    Pending,
+    /// Accept a key proposed by a peer, until the daemon restarts
+    ///
+    /// Add the key to the configuration of the peer to keep it.
+    Approve {
+        peer: String,
+        /// The key, as shown by "keys pending"
+        pubkey: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum PeersCommands {
+    /// Limit the bandwidth used by all connections to and from a peer
+    ///
+    /// This replaces the limits configured for the peer, including
+    /// their schedule, until the daemon restarts.
+    Limit {
+        peer: String,
+        /// Bytes per second sent to the peer, such as 512KiB or 2MB,
+        /// or "none" for no limit
+        #[arg(value_parser = |s: &str| parse_bandwidth_limit(s))]
+        upload: u64,
+        /// Bytes per second received from the peer, such as 512KiB
+        /// or 2MB, or "none" for no limit
+        #[arg(value_parser = |s: &str| parse_bandwidth_limit(s))]
+        download: u64,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -56,6 +165,54 @@ enum ChurtenCommands {
     IsRunning,
     /// Run churten and print notifications
     Run,
+    /// Show whether churten is running and when downloads are
+    /// allowed
+    Status,
+    /// Limit the bandwidth used by churten jobs
+    ///
+    /// This doesn't apply to files read through the filesystem.
+    Limit {
+        /// Bytes per second, such as 512KiB or 2MB, or "none" to
+        /// remove the limit
+        #[arg(value_parser = |s: &str| parse_bandwidth_limit(s))]
+        bytes_per_second: u64,
+    },
+    /// Show the outcome of the jobs run by churten, the most recent
+    /// first
+    History(churten_cmd::HistoryArgs),
+    /// List the jobs churten still has to run and what they would
+    /// cost, without running them
+    Plan {
+        /// Only list jobs of that arena
+        arena: Option<String>,
+        /// Only list jobs on that path or on files within that
+        /// directory
+        path: Option<String>,
+    },
+    /// Cancel a running job
+    ///
+    /// The job is retried after a backoff period, as if it had
+    /// failed.
+    Cancel { arena: String, job_id: u64 },
+    /// List the jobs that failed and are waiting to be retried
+    Failed {
+        /// Only list jobs of that arena
+        arena: Option<String>,
+    },
+    /// Retry a failed job now instead of waiting for the end of its
+    /// backoff period
+    Retry { arena: String, job_id: u64 },
+    /// Stop creating jobs for a file until its version changes
+    Ignore { arena: String, path: String },
+}
+
+/// Parse a bandwidth limit; 0 for unlimited.
+fn parse_bandwidth_limit(str: &str) -> Result<u64, parse_size::Error> {
+    if str == "none" {
+        return Ok(0);
+    }
+
+    parse_size::Config::new().with_binary().parse_size(str)
 }
 
 #[derive(Subcommand, Debug)]
@@ -69,6 +226,10 @@ enum MarkCommands {
         arena: String,
         /// Paths to mark (optional - if not provided, sets arena mark)
         paths: Vec<String>,
+        /// Show the files that would be downloaded, realized or
+        /// unrealized instead of setting the mark
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Get marks for paths
     Get {
@@ -77,6 +238,89 @@ enum MarkCommands {
         /// Paths to get marks for
         paths: Vec<String>,
     },
+    /// Unset marks on paths, so they inherit their mark again
+    Clear {
+        /// The arena name
+        arena: String,
+        /// Paths to clear marks from
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// List the marks set on the arena and on paths
+    ///
+    /// Mark rules are listed by "mark rule list".
+    List {
+        /// The arena name
+        arena: String,
+    },
+    /// Show the marks of paths and where they come from
+    Explain {
+        /// The arena name
+        arena: String,
+        /// Paths to explain the marks of
+        paths: Vec<String>,
+    },
+    /// Manage rules that mark files by pattern or size
+    ///
+    /// A mark set on a file wins over rules, which win over marks
+    /// set on directories. If more than one rule matches, the first
+    /// one wins.
+    Rule {
+        #[command(subcommand)]
+        command: MarkRuleCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MarkRuleCommands {
+    /// Add a rule, evaluated after the existing ones
+    Add {
+        /// The mark to set (watch, keep, own)
+        #[arg(value_enum)]
+        mark: mark_cmd::MarkValue,
+        /// The arena name
+        arena: String,
+        /// Glob pattern, such as videos/**/*.mp4; a pattern without /
+        /// matches file names in all directories
+        #[arg(long)]
+        pattern: Option<String>,
+        /// Only match files of at least that size, such as 10MB
+        #[arg(long, value_parser = |s: &str| parse_size(s))]
+        min_size: Option<u64>,
+        /// Only match files of at most that size, such as 10MB
+        #[arg(long, value_parser = |s: &str| parse_size(s))]
+        max_size: Option<u64>,
+    },
+    /// List the rules, in the order in which they're evaluated
+    List {
+        /// The arena name
+        arena: String,
+    },
+    /// Remove a rule
+    Rm {
+        /// The arena name
+        arena: String,
+        /// ID of the rule, as shown by list
+        id: u64,
+    },
+}
+
+/// Parse a non-negative duration, such as 10m or 1h.
+fn parse_duration(str: &str) -> Result<Duration, jiff::Error> {
+    let duration: jiff::SignedDuration = str.parse()?;
+
+    Duration::try_from(duration)
+}
+
+/// Parse a size, such as 10MB.
+fn parse_size(str: &str) -> Result<u64, parse_size::Error> {
+    parse_size::Config::new().with_binary().parse_size(str)
+}
+
+#[derive(Subcommand, Debug)]
+enum ScrubCommands {
+    /// List files whose content was found not to match their hash
+    List,
 }
 
 /// Get the default socket path by checking for the first existing socket
@@ -127,6 +371,13 @@ async fn main() {
 }
 
 async fn execute(cli: Cli) -> anyhow::Result<i32> {
+    let command = match cli.command {
+        Commands::Keygen { privkey } => {
+            return pairing_cmd::execute_keygen(&privkey, cli.output);
+        }
+        Commands::Daemon(command) => command,
+    };
+
     // Resolve socket path
     let socket_path = resolve_socket_path(cli.socket)?;
     log::debug!("Connecting to {socket_path:?}");
@@ -137,8 +388,8 @@ async fn execute(cli: Cli) -> anyhow::Result<i32> {
             let control = client::connect(&socket_path).await?;
 
             // Execute the appropriate command
-            match cli.command {
-                Commands::Churten { command } => match command {
+            match command {
+                DaemonCommands::Churten { command } => match command {
                     ChurtenCommands::Start => {
                         churten_cmd::execute_churten_start(&control, cli.output).await
                     }
@@ -151,18 +402,160 @@ async fn execute(cli: Cli) -> anyhow::Result<i32> {
                     ChurtenCommands::Run => {
                         churten_cmd::execute_churten_run(&control, cli.output).await
                     }
+                    ChurtenCommands::Status => {
+                        churten_cmd::execute_churten_status(&control, cli.output).await
+                    }
+                    ChurtenCommands::Limit { bytes_per_second } => {
+                        churten_cmd::execute_churten_limit(&control, bytes_per_second, cli.output)
+                            .await
+                    }
+                    ChurtenCommands::History(args) => {
+                        churten_cmd::execute_churten_history(&control, &args, cli.output).await
+                    }
+                    ChurtenCommands::Plan { arena, path } => {
+                        churten_cmd::execute_churten_plan(
+                            &control,
+                            arena.as_deref(),
+                            path.as_deref(),
+                            cli.output,
+                        )
+                        .await
+                    }
+                    ChurtenCommands::Cancel { arena, job_id } => {
+                        churten_cmd::execute_churten_cancel(&control, &arena, job_id, cli.output)
+                            .await
+                    }
+                    ChurtenCommands::Failed { arena } => {
+                        churten_cmd::execute_churten_failed(&control, arena.as_deref(), cli.output)
+                            .await
+                    }
+                    ChurtenCommands::Retry { arena, job_id } => {
+                        churten_cmd::execute_churten_retry(&control, &arena, job_id, cli.output)
+                            .await
+                    }
+                    ChurtenCommands::Ignore { arena, path } => {
+                        churten_cmd::execute_churten_ignore(&control, &arena, &path, cli.output)
+                            .await
+                    }
                 },
 
-                Commands::Mark { command } => match command {
-                    MarkCommands::Set { mark, arena, paths } => {
-                        mark_cmd::execute_mark_set(&control, &mark, &arena, &paths, cli.output)
+                DaemonCommands::Mark { command } => match command {
+                    MarkCommands::Set {
+                        mark,
+                        arena,
+                        paths,
+                        dry_run,
+                    } => {
+                        if dry_run {
+                            mark_cmd::execute_mark_set_dry_run(
+                                &control, &mark, &arena, &paths, cli.output,
+                            )
                             .await
+                        } else {
+                            mark_cmd::execute_mark_set(&control, &mark, &arena, &paths, cli.output)
+                                .await
+                        }
                     }
 
                     MarkCommands::Get { arena, paths } => {
                         mark_cmd::execute_mark_get(&control, &arena, &paths, cli.output).await
                     }
+
+                    MarkCommands::Clear { arena, paths } => {
+                        mark_cmd::execute_mark_clear(&control, &arena, &paths, cli.output).await
+                    }
+
+                    MarkCommands::List { arena } => {
+                        mark_cmd::execute_mark_list(&control, &arena, cli.output).await
+                    }
+
+                    MarkCommands::Explain { arena, paths } => {
+                        mark_cmd::execute_mark_explain(&control, &arena, &paths, cli.output).await
+                    }
+
+                    MarkCommands::Rule { command } => match command {
+                        MarkRuleCommands::Add {
+                            mark,
+                            arena,
+                            pattern,
+                            min_size,
+                            max_size,
+                        } => {
+                            mark_cmd::execute_mark_rule_add(
+                                &control,
+                                &mark,
+                                &arena,
+                                pattern.as_deref(),
+                                min_size,
+                                max_size,
+                                cli.output,
+                            )
+                            .await
+                        }
+                        MarkRuleCommands::List { arena } => {
+                            mark_cmd::execute_mark_rule_list(&control, &arena, cli.output).await
+                        }
+                        MarkRuleCommands::Rm { arena, id } => {
+                            mark_cmd::execute_mark_rule_rm(&control, &arena, id, cli.output).await
+                        }
+                    },
+                },
+
+                DaemonCommands::Scrub { command } => match command {
+                    ScrubCommands::List => {
+                        scrub_cmd::execute_scrub_list(&control, cli.output).await
+                    }
+                },
+
+                DaemonCommands::Peers { watch, command } => match command {
+                    Some(PeersCommands::Limit {
+                        peer,
+                        upload,
+                        download,
+                    }) => {
+                        peers_cmd::execute_peers_limit(
+                            &control, &peer, upload, download, cli.output,
+                        )
+                        .await
+                    }
+                    None => {
+                        if watch {
+                            peers_cmd::execute_peers_watch(&control, cli.output).await
+                        } else {
+                            peers_cmd::execute_peers(&control, cli.output).await
+                        }
+                    }
                 },
+
+                DaemonCommands::Keys { command } => match command {
+                    KeysCommands::Rotate { privkey } => {
+                        keys_cmd::execute_keys_rotate(&control, &privkey, cli.output).await
+                    }
+                    KeysCommands::Pending => {
+                        keys_cmd::execute_keys_pending(&control, cli.output).await
+                    }
+                    KeysCommands::Approve { peer, pubkey } => {
+                        keys_cmd::execute_keys_approve(&control, &peer, &pubkey, cli.output).await
+                    }
+                },
+
+                DaemonCommands::Invite { address, ttl } => {
+                    pairing_cmd::execute_invite(&control, &address, ttl, cli.output).await
+                }
+                DaemonCommands::Join {
+                    token,
+                    peer,
+                    address,
+                } => {
+                    pairing_cmd::execute_join(
+                        &control,
+                        &token,
+                        peer.as_deref(),
+                        address.as_deref(),
+                        cli.output,
+                    )
+                    .await
+                }
             }
         })
         .await?;
@@ -9,6 +9,42 @@ pub(crate) enum MarkValue {
     Own,
 }
 
+impl MarkValue {
+    fn to_capnp(&self) -> control_capnp::Mark {
+        match self {
+            MarkValue::Watch => control_capnp::Mark::Watch,
+            MarkValue::Keep => control_capnp::Mark::Keep,
+            MarkValue::Own => control_capnp::Mark::Own,
+        }
+    }
+}
+
+fn mark_str(mark: Result<control_capnp::Mark, capnp::NotInSchema>) -> &'static str {
+    match mark {
+        Ok(control_capnp::Mark::Watch) => "watch",
+        Ok(control_capnp::Mark::Keep) => "keep",
+        Ok(control_capnp::Mark::Own) => "own",
+        Err(_) => "unknown",
+    }
+}
+
+/// Describe the files a rule selects.
+fn rule_criteria(rule: control_capnp::mark_rule::Reader<'_>) -> Result<String> {
+    let mut criteria = vec![];
+    let pattern = rule.get_pattern()?.to_str()?;
+    if !pattern.is_empty() {
+        criteria.push(pattern.to_string());
+    }
+    if rule.get_min_size() > 0 {
+        criteria.push(format!(">= {} bytes", rule.get_min_size()));
+    }
+    if rule.get_max_size() > 0 {
+        criteria.push(format!("<= {} bytes", rule.get_max_size()));
+    }
+
+    Ok(criteria.join(", "))
+}
+
 /// Execute the mark set command
 pub(crate) async fn execute_mark_set(
     control: &control_capnp::control::Client,
@@ -17,11 +53,7 @@ pub(crate) async fn execute_mark_set(
     paths: &[String],
     output_mode: OutputMode,
 ) -> Result<i32> {
-    let mark_value = match mark {
-        MarkValue::Watch => control_capnp::Mark::Watch,
-        MarkValue::Keep => control_capnp::Mark::Keep,
-        MarkValue::Own => control_capnp::Mark::Own,
-    };
+    let mark_value = mark.to_capnp();
 
     if paths.is_empty() {
         // Set arena mark
@@ -51,6 +83,52 @@ pub(crate) async fn execute_mark_set(
     Ok(0)
 }
 
+/// Execute the mark set command with --dry-run
+pub(crate) async fn execute_mark_set_dry_run(
+    control: &control_capnp::control::Client,
+    mark: &MarkValue,
+    arena: &str,
+    paths: &[String],
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let targets = if paths.is_empty() {
+        vec![""]
+    } else {
+        paths.iter().map(|p| p.as_str()).collect()
+    };
+    for path in targets {
+        let mut request = control.mark_impact_request();
+        request.get().set_arena(arena);
+        request.get().set_path(path);
+        request.get().set_mark(mark.to_capnp());
+        let result = request.send().promise.await?;
+        let impact = result.get()?.get_res()?;
+
+        let name = if path.is_empty() {
+            format!("[{arena}]")
+        } else {
+            path.to_string()
+        };
+        output::print_info(
+            output_mode,
+            format!(
+                "{name}: download {} files ({} bytes), realize {} files ({} bytes), unrealize {} files ({} bytes)",
+                impact.get_download_files(),
+                impact.get_download_bytes(),
+                impact.get_realize_files(),
+                impact.get_realize_bytes(),
+                impact.get_unrealize_files(),
+                impact.get_unrealize_bytes(),
+            ),
+        );
+        for warning in impact.get_warnings()?.iter() {
+            output::print_warning(output_mode, "WARNING", warning?.to_str()?);
+        }
+    }
+
+    Ok(0)
+}
+
 /// Execute the mark get command
 pub(crate) async fn execute_mark_get(
     control: &control_capnp::control::Client,
@@ -66,15 +144,171 @@ pub(crate) async fn execute_mark_get(
         let result = request.send().promise.await?;
         let mark = result.get()?.get_res()?.get_mark();
 
-        let mark_str = match mark {
-            Ok(control_capnp::Mark::Watch) => "watch",
-            Ok(control_capnp::Mark::Keep) => "keep",
-            Ok(control_capnp::Mark::Own) => "own",
-            Err(_) => "unknown",
+        output::print_info(output_mode, format!("{path}: {}", mark_str(mark)));
+    }
+
+    Ok(0)
+}
+
+/// Execute the mark clear command
+pub(crate) async fn execute_mark_clear(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    paths: &[String],
+    output_mode: OutputMode,
+) -> Result<i32> {
+    for path in paths {
+        let mut request = control.clear_mark_request();
+        request.get().set_arena(arena);
+        request.get().set_path(path);
+        request.send().promise.await?;
+    }
+    output::print_success(
+        output_mode,
+        "OK",
+        format!("Marks cleared on {} paths", paths.len()),
+    );
+
+    Ok(0)
+}
+
+/// Execute the mark list command
+pub(crate) async fn execute_mark_list(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.list_marks_request();
+    request.get().set_arena(arena);
+    let result = request.send().promise.await?;
+    let marks = result.get()?.get_res()?;
+    if marks.is_empty() {
+        output::print_success(output_mode, "OK", "No marks set");
+
+        return Ok(0);
+    }
+    for entry in marks.iter() {
+        let path = entry.get_path()?.to_str()?;
+        let mark = mark_str(entry.get_mark());
+        if path.is_empty() {
+            output::print_info(output_mode, format!("[{arena}]: {mark}"));
+        } else {
+            output::print_info(output_mode, format!("{path}: {mark}"));
+        }
+    }
+
+    Ok(0)
+}
+
+/// Execute the mark explain command
+pub(crate) async fn execute_mark_explain(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    paths: &[String],
+    output_mode: OutputMode,
+) -> Result<i32> {
+    use control_capnp::mark_explanation::source;
+
+    for path in paths {
+        let mut request = control.explain_mark_request();
+        request.get().set_arena(arena);
+        request.get().set_path(path);
+        let result = request.send().promise.await?;
+        let res = result.get()?.get_res()?;
+        let mark = mark_str(res.get_mark());
+        let source = match res.get_source().which()? {
+            source::Path(source_path) => {
+                let source_path = source_path?.to_str()?;
+                if source_path == path {
+                    "set on the path".to_string()
+                } else {
+                    format!("inherited from {source_path}")
+                }
+            }
+            source::Rule(rule) => {
+                let rule = rule?;
+                format!("set by rule {} ({})", rule.get_id(), rule_criteria(rule)?)
+            }
+            source::Arena(()) => "set on the arena".to_string(),
+            source::Default(()) => "default".to_string(),
         };
 
-        output::print_info(output_mode, format!("{path}: {mark_str}"));
+        output::print_info(output_mode, format!("{path}: {mark}, {source}"));
+    }
+
+    Ok(0)
+}
+
+/// Execute the mark rule add command
+pub(crate) async fn execute_mark_rule_add(
+    control: &control_capnp::control::Client,
+    mark: &MarkValue,
+    arena: &str,
+    pattern: Option<&str>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.add_mark_rule_request();
+    let mut params = request.get();
+    params.set_arena(arena);
+    let mut rule = params.init_rule();
+    rule.set_pattern(pattern.unwrap_or(""));
+    rule.set_min_size(min_size.unwrap_or(0));
+    rule.set_max_size(max_size.unwrap_or(0));
+    rule.set_mark(mark.to_capnp());
+    let result = request.send().promise.await?;
+    let id = result.get()?.get_id();
+    output::print_success(output_mode, "OK", format!("Rule {id} added"));
+
+    Ok(0)
+}
+
+/// Execute the mark rule list command
+pub(crate) async fn execute_mark_rule_list(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.mark_rules_request();
+    request.get().set_arena(arena);
+    let result = request.send().promise.await?;
+    let rules = result.get()?.get_res()?;
+    if rules.is_empty() {
+        output::print_success(output_mode, "OK", "No rules");
+
+        return Ok(0);
+    }
+    for rule in rules.iter() {
+        output::print_info(
+            output_mode,
+            format!(
+                "{} {}: {}",
+                rule.get_id(),
+                rule_criteria(rule)?,
+                mark_str(rule.get_mark())
+            ),
+        );
+    }
+
+    Ok(0)
+}
+
+/// Execute the mark rule rm command
+pub(crate) async fn execute_mark_rule_rm(
+    control: &control_capnp::control::Client,
+    arena: &str,
+    id: u64,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut request = control.remove_mark_rule_request();
+    request.get().set_arena(arena);
+    request.get().set_id(id);
+    let result = request.send().promise.await?;
+    if !result.get()?.get_removed() {
+        anyhow::bail!("No rule {id} in {arena}");
     }
+    output::print_success(output_mode, "OK", format!("Rule {id} removed"));
 
     Ok(0)
 }
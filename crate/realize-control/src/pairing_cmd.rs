@@ -0,0 +1,56 @@
+use super::output::{self, OutputMode};
+use anyhow::Result;
+use realize_core::rpc::control::client;
+use realize_core::rpc::control::control_capnp;
+use realize_network::security::{self, RawPublicKeyResolver};
+use realize_types::Peer;
+use std::io::Write as _;
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::Path;
+use std::time::Duration;
+
+/// Execute the invite command
+pub(crate) async fn execute_invite(
+    control: &control_capnp::control::Client,
+    address: &str,
+    ttl: Duration,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let token = client::invite(control, address, ttl).await?;
+    output::print_info(output_mode, token);
+
+    Ok(0)
+}
+
+/// Execute the join command
+pub(crate) async fn execute_join(
+    control: &control_capnp::control::Client,
+    token: &str,
+    peer: Option<&str>,
+    address: Option<&str>,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let inviter = client::join(control, token, peer.map(Peer::from), address).await?;
+    output::print_success(output_mode, "OK", format!("Joined {inviter}"));
+
+    Ok(0)
+}
+
+/// Execute the keygen command
+///
+/// This doesn't need a running daemon.
+pub(crate) fn execute_keygen(privkey: &Path, output_mode: OutputMode) -> Result<i32> {
+    let pem = security::generate_private_key_pem()?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(privkey)
+        .map_err(|err| anyhow::anyhow!("{}: {err}", privkey.display()))?;
+    file.write_all(pem.as_bytes())?;
+
+    let resolver = RawPublicKeyResolver::from_private_key_file(privkey)?;
+    output::print_info(output_mode, resolver.public_key_pem().trim_end());
+
+    Ok(0)
+}
@@ -0,0 +1,130 @@
+use super::churten_cmd::{format_bandwidth_limit, format_local_time};
+use super::output::{self, OutputMode};
+use anyhow::Result;
+use indicatif::HumanBytes;
+use realize_core::rpc::control::client;
+use realize_core::rpc::control::control_capnp;
+use realize_network::capnp::{PeerInfo, PeerStatus};
+use realize_types::Peer;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+/// Execute the peers command
+pub(crate) async fn execute_peers(
+    control: &control_capnp::control::Client,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let peers = client::get_peers(control).await?;
+    if peers.is_empty() {
+        output::print_success(output_mode, "OK", "No peers configured");
+
+        return Ok(0);
+    }
+    for info in &peers {
+        output::print_info(output_mode, format_peer(info)?);
+    }
+
+    Ok(0)
+}
+
+/// Execute the peers --watch command
+///
+/// Print the peers, then print them again whenever their status
+/// changes, until interrupted.
+pub(crate) async fn execute_peers_watch(
+    control: &control_capnp::control::Client,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let mut rx = client::watch_peers(control).await?;
+    let mut last_status: HashMap<Peer, PeerStatus> = HashMap::new();
+    while let Some(peers) = rx.recv().await {
+        for info in peers {
+            let peer = info.status.peer();
+            if last_status.get(&peer) == Some(&info.status) {
+                continue;
+            }
+            output::print_info(output_mode, format_peer(&info)?);
+            last_status.insert(peer, info.status);
+        }
+    }
+
+    Ok(0)
+}
+
+/// Execute the peers limit command
+pub(crate) async fn execute_peers_limit(
+    control: &control_capnp::control::Client,
+    peer: &str,
+    upload_bytes_per_second: u64,
+    download_bytes_per_second: u64,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    client::set_peer_limit(
+        control,
+        Peer::from(peer),
+        upload_bytes_per_second,
+        download_bytes_per_second,
+    )
+    .await?;
+    output::print_success(
+        output_mode,
+        "OK",
+        format!(
+            "{peer}: upload {}, download {}",
+            format_bandwidth_limit(upload_bytes_per_second),
+            format_bandwidth_limit(download_bytes_per_second)
+        ),
+    );
+
+    Ok(0)
+}
+
+/// Describe the connection to a peer on one line.
+fn format_peer(info: &PeerInfo) -> Result<String> {
+    let status = match info.status {
+        PeerStatus::Disconnected(_) => "disconnected",
+        PeerStatus::Connected(_) => "connected",
+        PeerStatus::Registered(_) => "registered",
+    };
+    let mut line = format!("{}: {status}", info.status.peer());
+    if let Some(addr) = &info.address {
+        write!(line, " to {addr}")?;
+    }
+    if let Some(since) = &info.connected_since {
+        write!(line, " since {}", format_local_time(since.as_secs())?)?;
+    }
+    if info.incoming {
+        line.push_str(", connected to us");
+    }
+    if info.bytes_sent > 0 || info.bytes_received > 0 {
+        write!(
+            line,
+            ", sent {} ({}/s), received {} ({}/s)",
+            HumanBytes(info.bytes_sent),
+            HumanBytes(info.send_rate),
+            HumanBytes(info.bytes_received),
+            HumanBytes(info.receive_rate),
+        )?;
+    }
+    if info.failed_attempts > 0 {
+        write!(line, ", {} failed attempts", info.failed_attempts)?;
+    }
+    if let Some(next) = &info.next_attempt {
+        write!(
+            line,
+            ", next attempt {}",
+            format_local_time(next.as_secs())?
+        )?;
+    }
+    if let Some(limit) = info.limit.upload_bytes_per_second {
+        write!(line, ", upload limited to {}/s", HumanBytes(limit))?;
+    }
+    if let Some(limit) = info.limit.download_bytes_per_second {
+        write!(line, ", download limited to {}/s", HumanBytes(limit))?;
+    }
+    if let Some(err) = &info.last_error {
+        write!(line, ", last error: {err}")?;
+    }
+
+    Ok(line)
+}
@@ -0,0 +1,45 @@
+use super::output::{self, OutputMode};
+use anyhow::Result;
+use realize_core::rpc::control::control_capnp;
+use realize_types::Hash;
+
+/// Execute the scrub list command
+pub(crate) async fn execute_scrub_list(
+    control: &control_capnp::control::Client,
+    output_mode: OutputMode,
+) -> Result<i32> {
+    let result = control.corruptions_request().send().promise.await?;
+    let corruptions = result.get()?.get_res()?;
+    if corruptions.is_empty() {
+        output::print_success(output_mode, "OK", "No corrupted files");
+
+        return Ok(0);
+    }
+    for corruption in corruptions.iter() {
+        let location = match corruption.get_location() {
+            Ok(control_capnp::corruption::Location::Index) => "file",
+            Ok(control_capnp::corruption::Location::Blob) => "cache",
+            Err(_) => "unknown",
+        };
+        output::print_info(
+            output_mode,
+            format!(
+                "[{}]/{} ({location}): expected {}, got {}",
+                corruption.get_arena()?.to_str()?,
+                corruption.get_path()?.to_str()?,
+                parse_hash(corruption.get_expected()?)?,
+                parse_hash(corruption.get_actual()?)?,
+            ),
+        );
+    }
+
+    Ok(0)
+}
+
+fn parse_hash(hash: &[u8]) -> Result<Hash> {
+    let hash: [u8; 32] = hash
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("invalid hash"))?;
+
+    Ok(Hash(hash))
+}
@@ -2,6 +2,7 @@ use assert_fs::TempDir;
 use assert_fs::prelude::*;
 use realize_core::config::Config;
 use realize_core::setup::SetupHelper;
+use realize_network::security::RawPublicKeyResolver;
 use realize_storage::Mark;
 use realize_storage::config::{ArenaConfig, CacheConfig};
 use realize_types::{Arena, Peer};
@@ -62,10 +63,23 @@ impl Fixture {
         config.network.peers.insert(
             Peer::from("a"),
             realize_network::config::PeerConfig {
-                address: None,
+                addresses: vec![],
+                relay: false,
+                pubkeys: vec![],
+                limits: Default::default(),
                 pubkey: std::fs::read_to_string(resources.join("a-spki.pem"))?,
             },
         );
+        config.network.peers.insert(
+            Peer::from("b"),
+            realize_network::config::PeerConfig {
+                addresses: vec![],
+                relay: false,
+                pubkeys: vec![],
+                limits: Default::default(),
+                pubkey: std::fs::read_to_string(resources.join("b-spki.pem"))?,
+            },
+        );
 
         let server_privkey = resources.join("a.key");
         let socket = tempdir.path().join("realize/control.socket");
@@ -334,3 +348,378 @@ async fn churten_mark_get_multiple_paths() -> anyhow::Result<()> {
         .await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn scrub_list_empty() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["scrub", "list"])?
+                .output()
+                .await?;
+
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("No corrupted files"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn peers() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture.control_command(&["peers"])?.output().await?;
+
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output_str = String::from_utf8(output.stdout)?;
+            assert_eq!("b: disconnected\n", output_str);
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn peers_limit() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["peers", "limit", "b", "64KiB", "none"])?
+                .output()
+                .await?;
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output = fixture.control_command(&["peers"])?.output().await?;
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+            let output_str = String::from_utf8(output.stdout)?;
+            assert_eq!(
+                "b: disconnected, upload limited to 64.00 KiB/s\n",
+                output_str
+            );
+
+            let output = fixture
+                .control_command(&["peers", "limit", "unknown", "none", "none"])?
+                .output()
+                .await?;
+            assert!(!output.status.success());
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn keys_pending() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["keys", "pending"])?
+                .output()
+                .await?;
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("No pending keys"),
+                "Unexpected output: {output_str}"
+            );
+
+            let output = fixture
+                .control_command(&["keys", "approve", "b", "MCowBQYDK2VwAyEA"])?
+                .output()
+                .await?;
+            assert!(!output.status.success());
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn keygen() -> anyhow::Result<()> {
+    let tempdir = TempDir::new()?;
+    let privkey = tempdir.child("new.key");
+
+    // No daemon needed.
+    let output = tokio::process::Command::new(command_path("realize-control"))
+        .arg("keygen")
+        .arg(privkey.path())
+        .output()
+        .await?;
+    assert!(
+        output.status.success(),
+        "Control command failed: {output:?}"
+    );
+    let output_str = String::from_utf8(output.stdout)?;
+    assert!(
+        output_str.contains("BEGIN PUBLIC KEY"),
+        "Unexpected output: {output_str}"
+    );
+    RawPublicKeyResolver::from_private_key_file(privkey.path())?;
+
+    // Never overwrite an existing key
+    let output = tokio::process::Command::new(command_path("realize-control"))
+        .arg("keygen")
+        .arg(privkey.path())
+        .output()
+        .await?;
+    assert!(!output.status.success());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn churten_status() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["churten", "status"])?
+                .output()
+                .await?;
+
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("Churten stopped"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+            assert!(
+                output_str.contains("Downloads: allowed at any time"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+            assert!(
+                output_str.contains("Bandwidth: unlimited"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn churten_limit() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["churten", "limit", "1MiB"])?
+                .output()
+                .await?;
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output = fixture
+                .control_command(&["churten", "status"])?
+                .output()
+                .await?;
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("Bandwidth: 1048576 bytes/s"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+
+            let output = fixture
+                .control_command(&["churten", "limit", "none"])?
+                .output()
+                .await?;
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output = fixture
+                .control_command(&["churten", "status"])?
+                .output()
+                .await?;
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("Bandwidth: unlimited"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn churten_history() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["churten", "history", "--status", "failed", "--since", "12h"])?
+                .output()
+                .await?;
+
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("No jobs"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn churten_plan() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["churten", "plan", "myarena"])?
+                .output()
+                .await?;
+
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("No pending jobs"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn churten_failed() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["churten", "failed"])?
+                .output()
+                .await?;
+
+            assert!(
+                output.status.success(),
+                "Control command failed: {output:?}"
+            );
+
+            let output_str = String::from_utf8(output.stdout)?;
+            assert!(
+                output_str.contains("No failed jobs"),
+                "Unexpected output: '{}'",
+                output_str
+            );
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn churten_retry_unknown_job() -> anyhow::Result<()> {
+    let local = LocalSet::new();
+    let fixture = Fixture::setup(&local).await?;
+
+    local
+        .run_until(async move {
+            let output = fixture
+                .control_command(&["churten", "retry", "myarena", "42"])?
+                .output()
+                .await?;
+
+            assert!(
+                !output.status.success(),
+                "Control command should have failed: {output:?}"
+            );
+
+            let stderr_str = String::from_utf8(output.stderr)?;
+            assert!(
+                stderr_str.contains("isn't a failed job"),
+                "Unexpected error: '{}'",
+                stderr_str
+            );
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?;
+    Ok(())
+}
@@ -1,7 +1,12 @@
-use realize_network::config::NetworkConfig;
+use crate::consensus::types::JobType;
+use realize_network::config::{NetworkConfig, PeerConfig};
+use realize_storage::FileUsage;
 use realize_storage::config::StorageConfig;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use realize_types::schedule::Schedule;
+use realize_types::{Arena, Peer};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct Config {
@@ -9,6 +14,10 @@ pub struct Config {
     pub network: NetworkConfig,
     #[serde(flatten)]
     pub storage: StorageConfig,
+
+    /// Configure how background jobs are run.
+    #[serde(default)]
+    pub churten: ChurtenConfig,
 }
 
 impl Default for Config {
@@ -27,6 +36,193 @@ impl Config {
                     db: PathBuf::from("cache.db"), // Default for backward compatibility
                 },
             },
+            churten: ChurtenConfig::default(),
         }
     }
 }
+
+/// Configure how many background jobs run in parallel and when.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct ChurtenConfig {
+    /// Maximum number of jobs to run in parallel.
+    #[serde(default = "default_max_parallel")]
+    pub max_parallel: usize,
+
+    /// Maximum number of jobs of a given type to run in parallel.
+    ///
+    /// This is a further limit under `max_parallel`, which applies to
+    /// all jobs together.
+    #[serde(default)]
+    pub max_parallel_per_type: HashMap<JobType, usize>,
+
+    /// Maximum number of jobs of a given arena to run in parallel.
+    ///
+    /// This is a further limit under `max_parallel`, which applies to
+    /// all jobs together.
+    #[serde(default)]
+    pub max_parallel_per_arena: HashMap<Arena, usize>,
+
+    /// Time windows within which jobs that may download file content
+    /// from peers are run.
+    ///
+    /// Such jobs are run at any time if empty.
+    #[serde(default)]
+    pub download_schedule: Schedule,
+
+    /// Download schedule of specific arenas, replacing
+    /// `download_schedule` for these arenas.
+    #[serde(default)]
+    pub download_schedule_per_arena: HashMap<Arena, Schedule>,
+
+    /// Maximum number of bytes transferred per second, shared by
+    /// all jobs. Unlimited if unset.
+    ///
+    /// This doesn't apply to files read through the filesystem.
+    #[serde(default)]
+    pub max_bytes_per_second: Option<u64>,
+
+    /// Number of bytes that must remain free on the filesystem a job
+    /// writes a file to, once the file has been written.
+    ///
+    /// Jobs that would go over that limit wait until enough space
+    /// becomes available.
+    #[serde(default)]
+    pub min_free_bytes: u64,
+
+    /// Download and temporarily keep files marked Watch that are used
+    /// often. Disabled if unset.
+    #[serde(default)]
+    pub auto_pin: Option<AutoPinConfig>,
+}
+
+impl ChurtenConfig {
+    /// Return the download schedule of the given arena.
+    pub fn download_schedule(&self, arena: Arena) -> &Schedule {
+        self.download_schedule_per_arena
+            .get(&arena)
+            .unwrap_or(&self.download_schedule)
+    }
+}
+
+impl Default for ChurtenConfig {
+    fn default() -> Self {
+        Self {
+            max_parallel: default_max_parallel(),
+            max_parallel_per_type: HashMap::new(),
+            max_parallel_per_arena: HashMap::new(),
+            download_schedule: Schedule::default(),
+            download_schedule_per_arena: HashMap::new(),
+            max_bytes_per_second: None,
+            min_free_bytes: 0,
+            auto_pin: None,
+        }
+    }
+}
+
+fn default_max_parallel() -> usize {
+    4
+}
+
+/// Configure when files marked Watch are downloaded and kept in the
+/// cache for a while, because they're used often.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct AutoPinConfig {
+    /// Pin files opened at least that many times within `window_secs`.
+    #[serde(default)]
+    pub min_opens: Option<u32>,
+
+    /// Pin files from which at least that many bytes were read within
+    /// `window_secs`.
+    #[serde(default)]
+    pub min_bytes: Option<u64>,
+
+    /// Period of time within which opens and bytes read are counted,
+    /// in seconds. Usage is kept for at most an hour.
+    #[serde(default = "default_auto_pin_window_secs")]
+    pub window_secs: u64,
+
+    /// How long files are kept once pinned, in seconds.
+    #[serde(default = "default_auto_pin_secs")]
+    pub pin_secs: u64,
+}
+
+impl AutoPinConfig {
+    /// Check whether a file used that much should be pinned.
+    pub fn should_pin(&self, usage: &FileUsage) -> bool {
+        self.min_opens
+            .map(|min| usage.opens >= min)
+            .unwrap_or(false)
+            || self
+                .min_bytes
+                .map(|min| usage.bytes >= min)
+                .unwrap_or(false)
+    }
+}
+
+fn default_auto_pin_window_secs() -> u64 {
+    3600
+}
+
+fn default_auto_pin_secs() -> u64 {
+    7 * 24 * 3600
+}
+
+/// Add a peer to the configuration file at `path`.
+///
+/// The peer is appended as a new `[peers.<id>]` table, leaving the
+/// rest of the file untouched.
+pub fn append_peer_config(path: &Path, peer: Peer, config: &PeerConfig) -> anyhow::Result<()> {
+    #[derive(serde::Serialize)]
+    struct Peers<'a> {
+        peers: BTreeMap<&'a str, &'a PeerConfig>,
+    }
+
+    let content = toml::to_string(&Peers {
+        peers: BTreeMap::from([(peer.as_str(), config)]),
+    })?;
+    let mut file = std::fs::OpenOptions::new().append(true).open(path)?;
+    write!(file, "\n{content}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+    use assert_fs::prelude::*;
+
+    #[test]
+    fn append_peer() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let file = tempdir.child("config.toml");
+        file.write_str(
+            r#"[peers.a]
+pubkey = "key_a"
+"#,
+        )?;
+
+        append_peer_config(
+            file.path(),
+            Peer::from("b"),
+            &PeerConfig {
+                addresses: vec!["b.example.com:9771".to_string()],
+                pubkey: "-----BEGIN PUBLIC KEY-----\nkey_b\n-----END PUBLIC KEY-----\n".to_string(),
+                pubkeys: vec![],
+                relay: false,
+                limits: Default::default(),
+            },
+        )?;
+
+        let config: NetworkConfig = toml::from_str(&std::fs::read_to_string(file.path())?)?;
+        assert_eq!("key_a", config.peers.get(&Peer::from("a")).unwrap().pubkey);
+        let b = config.peers.get(&Peer::from("b")).unwrap();
+        assert_eq!(vec!["b.example.com:9771".to_string()], b.addresses);
+        assert_eq!(
+            "-----BEGIN PUBLIC KEY-----\nkey_b\n-----END PUBLIC KEY-----\n",
+            b.pubkey
+        );
+
+        Ok(())
+    }
+}
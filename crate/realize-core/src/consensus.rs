@@ -1,6 +1,9 @@
+mod autopin;
 pub(crate) mod churten;
+pub(crate) mod diskspace;
 mod jobs;
 pub mod movedirs;
 pub(crate) mod progress;
+mod queue;
 pub mod tracker;
 pub mod types;
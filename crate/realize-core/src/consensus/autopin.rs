@@ -0,0 +1,176 @@
+use crate::config::AutoPinConfig;
+use realize_storage::{Inode, Mark, Storage};
+use realize_types::UnixTime;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tarpc::tokio_util::sync::CancellationToken;
+use tokio::sync::broadcast;
+
+/// Pin files marked Watch that are used often enough, according to
+/// `config`.
+///
+/// This follows the usage data collected by the cache as files are
+/// read through the filesystem. Pinned files are downloaded by the
+/// job stream, just like files marked Keep, and kept in the cache
+/// until their pin expires.
+///
+/// Runs until cancelled using the token.
+pub(crate) async fn auto_pin(
+    storage: &Arc<Storage>,
+    config: &AutoPinConfig,
+    shutdown: CancellationToken,
+) {
+    let mut rx = storage.cache().usage_updates();
+    let mut pinner = AutoPinner::new(storage, config);
+    loop {
+        let inode = tokio::select! {
+            _ = shutdown.cancelled() => {
+                return;
+            }
+            res = rx.recv() => match res {
+                Ok(inode) => inode,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return;
+                }
+            }
+        };
+        pinner.check(inode).await;
+    }
+}
+
+/// Pin files whose usage was updated, if they're used often enough.
+struct AutoPinner<'a> {
+    storage: &'a Arc<Storage>,
+    config: &'a AutoPinConfig,
+    window: Duration,
+    pin_duration: Duration,
+
+    /// Files already pinned or checked, with the time until which
+    /// they don't need to be looked at again.
+    pinned: HashMap<Inode, Instant>,
+}
+
+impl<'a> AutoPinner<'a> {
+    fn new(storage: &'a Arc<Storage>, config: &'a AutoPinConfig) -> Self {
+        Self {
+            storage,
+            config,
+            window: Duration::from_secs(config.window_secs),
+            pin_duration: Duration::from_secs(config.pin_secs),
+            pinned: HashMap::new(),
+        }
+    }
+
+    /// Check the usage of the file with the given inode and pin it
+    /// if it's used often enough.
+    ///
+    /// Return true if the file was pinned.
+    async fn check(&mut self, inode: Inode) -> bool {
+        let now = Instant::now();
+        if self
+            .pinned
+            .get(&inode)
+            .map(|until| *until > now)
+            .unwrap_or(false)
+        {
+            return false;
+        }
+        if !self
+            .config
+            .should_pin(&self.storage.cache().usage(inode, self.window))
+        {
+            return false;
+        }
+        self.pinned.retain(|_, until| *until > now);
+        self.pinned.insert(inode, now + self.pin_duration);
+        match pin(self.storage, inode, UnixTime::now().plus(self.pin_duration)).await {
+            Ok(true) => true,
+            Ok(false) => {
+                log::debug!("[{inode}] not pinned; not marked Watch or not in cache");
+
+                false
+            }
+            Err(err) => {
+                log::warn!("[{inode}] failed to pin: {err}");
+
+                false
+            }
+        }
+    }
+}
+
+/// Pin the file with the given inode until `until`, if it's marked
+/// Watch.
+///
+/// Return false if the file wasn't pinned.
+async fn pin(storage: &Arc<Storage>, inode: Inode, until: UnixTime) -> anyhow::Result<bool> {
+    let availability = storage.cache().file_availability(inode).await?;
+    let arena = availability.arena;
+    let path = availability.path;
+    if storage.get_mark(arena, &path).await? != Mark::Watch {
+        return Ok(false);
+    }
+    log::info!("[{arena}] pinning {path}, used often");
+
+    Ok(storage.pin_path(arena, &path, until).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+    use realize_storage::{Job, Notification};
+    use realize_types::{Arena, Hash, Path, Peer};
+
+    #[tokio::test]
+    async fn pin_file_opened_often() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let arena = Arena::from("myarena");
+        let storage = realize_storage::testing::storage(tempdir.path(), [arena]).await?;
+        let path = Path::parse("foo.txt")?;
+        let hash = Hash([1; 32]);
+        storage
+            .update(
+                Peer::from("other"),
+                Notification::Add {
+                    arena,
+                    index: 1,
+                    path: path.clone(),
+                    mtime: UnixTime::from_secs(1234567890),
+                    size: 100,
+                    hash: hash.clone(),
+                },
+            )
+            .await?;
+        let (inode, _) = storage.cache().lookup_path(arena, &path).await?;
+
+        let config = AutoPinConfig {
+            min_opens: Some(2),
+            min_bytes: None,
+            window_secs: 3600,
+            pin_secs: 3600,
+        };
+        let mut pinner = AutoPinner::new(&storage, &config);
+
+        storage.cache().record_access(inode);
+        storage.cache().record_read(inode, 50);
+        assert!(!pinner.check(inode).await);
+        assert!(storage.job_plan(Some(arena), None).await?.is_empty());
+
+        storage.cache().record_access(inode);
+        assert!(pinner.check(inode).await);
+        let plan = storage.job_plan(Some(arena), None).await?;
+        assert_eq!(1, plan.len());
+        assert_eq!(Job::Download(path.clone(), hash.clone()), plan[0].job);
+
+        // Already pinned
+        storage.cache().record_access(inode);
+        assert!(!pinner.check(inode).await);
+
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
 #![allow(dead_code)] // work in progress
 
+use super::autopin;
+use super::diskspace::DiskSpace;
 use super::jobs;
 use super::progress::TxByteCountProgress;
+use super::queue::{JobQueue, QueuedJob};
 use super::tracker::{JobInfo, JobInfoTracker};
-use super::types::{ChurtenNotification, JobProgress};
+use super::types::{ChurtenNotification, JobPriority, JobProgress, JobType};
+use crate::config::ChurtenConfig;
 use crate::rpc::Household;
+use async_speed_limit::Limiter;
 use futures::StreamExt;
-use realize_storage::{Job, JobId, JobStatus, Storage};
-use realize_types::Arena;
-use std::sync::Arc;
+use futures::stream::FuturesUnordered;
+use realize_storage::{Job, JobHistoryTableEntry, JobId, JobStatus, LocalAvailability, Storage};
+use realize_types::schedule::{ScheduleState, WeekMinute};
+use realize_types::{Arena, UnixTime};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tarpc::tokio_util::sync::CancellationToken;
 use tokio::sync::RwLock;
 use tokio::{sync::broadcast, task::JoinHandle};
@@ -23,6 +32,9 @@ const BROADCAST_CHANNEL_CAPACITY: usize = 128;
 /// least that many.
 const BROADCAST_CHANNEL_RESOLUTION_BYTES: u64 = 64 * 1024;
 
+/// Type, cancellation token and paused flag of the running jobs.
+type RunningJobs = Arc<Mutex<HashMap<(Arena, JobId), (JobType, CancellationToken, bool)>>>;
+
 /// A type that processes jobs and returns the result for [Churten].
 ///
 /// Outside of tests, this is normally [JobHandlerImpl].
@@ -44,17 +56,24 @@ pub(crate) trait JobHandler: Sync + Send + Clone {
 pub(crate) struct Churten<H: JobHandler> {
     storage: Arc<Storage>,
     handler: H,
+    config: ChurtenConfig,
+    limiter: Limiter,
     task: Option<(JoinHandle<()>, CancellationToken)>,
     tx: broadcast::Sender<ChurtenNotification>,
     recent_jobs: Arc<RwLock<JobInfoTracker>>,
+    running_jobs: RunningJobs,
 }
 
 impl Churten<JobHandlerImpl> {
-    pub(crate) fn new(storage: Arc<Storage>, household: Household) -> Self {
+    pub(crate) fn new(storage: Arc<Storage>, household: Household, config: ChurtenConfig) -> Self {
+        let limiter = Limiter::new(f64::INFINITY);
+
         Self::with_handler(
             Arc::clone(&storage),
-            JobHandlerImpl::new(storage, household),
+            JobHandlerImpl::new(storage, household).with_limiter(limiter.clone()),
         )
+        .with_limiter(limiter)
+        .with_config(config)
     }
 }
 
@@ -75,26 +94,105 @@ impl<H: JobHandler + 'static> Churten<H> {
         Self {
             storage,
             handler,
+            config: ChurtenConfig::default(),
+            limiter: Limiter::new(f64::INFINITY),
             task: None,
             tx,
             recent_jobs: tracker,
+            running_jobs: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Set the limits applied by the background job.
+    ///
+    /// Parallelism limits and schedules only take effect the next
+    /// time the background job is started.
+    pub(crate) fn with_config(mut self, config: ChurtenConfig) -> Self {
+        self.set_max_bytes_per_second(config.max_bytes_per_second);
+        self.config = config;
+        self
+    }
+
+    /// Set the limiter shared by the jobs run by the handler.
+    ///
+    /// The handler must use the same limiter for
+    /// [Churten::set_max_bytes_per_second] to have any effect.
+    pub(crate) fn with_limiter(mut self, limiter: Limiter) -> Self {
+        limiter.set_speed_limit(self.limiter.speed_limit());
+        self.limiter = limiter;
+        self
+    }
+
+    /// Limit the number of bytes jobs transfer per second.
+    ///
+    /// Unlimited if `None`. This takes effect immediately, including
+    /// on running jobs.
+    pub(crate) fn set_max_bytes_per_second(&self, max_bytes_per_second: Option<u64>) {
+        self.limiter.set_speed_limit(
+            max_bytes_per_second
+                .map(|bps| bps as f64)
+                .unwrap_or(f64::INFINITY),
+        );
+    }
+
+    /// Return the current transfer limit set by [Churten::set_max_bytes_per_second].
+    pub(crate) fn max_bytes_per_second(&self) -> Option<u64> {
+        let limit = self.limiter.speed_limit();
+        if limit.is_finite() {
+            Some(limit as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Return the current state of the download schedules.
+    ///
+    /// The default schedule, reported with no arena, comes first,
+    /// followed by the schedules of specific arenas.
+    pub(crate) fn download_schedules(&self) -> Vec<(Option<Arena>, ScheduleState)> {
+        let mut schedules = vec![(None, self.config.download_schedule.state_now())];
+        let mut arenas = self
+            .config
+            .download_schedule_per_arena
+            .iter()
+            .map(|(arena, schedule)| (Some(*arena), schedule.state_now()))
+            .collect::<Vec<_>>();
+        arenas.sort_by(|(a, _), (b, _)| a.map(|a| a.as_str()).cmp(&b.map(|b| b.as_str())));
+        schedules.extend(arenas);
+
+        schedules
+    }
+
     /// Return a list of recent jobs.
     ///
-    /// The number of finished jobs reported by this method is limited.
+    /// Running jobs come first, then pending jobs, in the order in
+    /// which they'll be started, then finished jobs, from the most
+    /// recent. The number of finished jobs reported by this method is
+    /// limited.
     ///
     /// This is a snapshot; for up-to-date information, call [Churten::subscribe].
     pub(crate) async fn recent_jobs(&self) -> Vec<JobInfo> {
-        self.recent_jobs.read().await.iter().cloned().collect()
+        self.recent_jobs
+            .read()
+            .await
+            .ordered()
+            .into_iter()
+            .cloned()
+            .collect()
     }
 
-    /// Return a list of active jobs.
+    /// Return a list of active jobs, in the same order as [Churten::recent_jobs].
     ///
     /// This is a snapshot; for up-to-date information, call [Churten::subscribe].
     pub(crate) async fn active_jobs(&self) -> Vec<JobInfo> {
-        self.recent_jobs.read().await.active().cloned().collect()
+        self.recent_jobs
+            .read()
+            .await
+            .ordered()
+            .into_iter()
+            .filter(|j| !j.progress.is_finished())
+            .cloned()
+            .collect()
     }
 
     /// Subscribe to [ChurtenNotification]s.
@@ -120,13 +218,49 @@ impl<H: JobHandler + 'static> Churten<H> {
             let shutdown = shutdown.clone();
             let storage = Arc::clone(&self.storage);
             let handler = self.handler.clone();
+            let config = self.config.clone();
             let tx = self.tx.clone();
+            let running_jobs = Arc::clone(&self.running_jobs);
 
-            async move { background_job(&storage, &handler, tx, shutdown).await }
+            async move {
+                let auto_pin = async {
+                    if let Some(auto_pin) = &config.auto_pin {
+                        autopin::auto_pin(&storage, auto_pin, shutdown.clone()).await;
+                    }
+                };
+                tokio::join!(
+                    background_job(
+                        &storage,
+                        &handler,
+                        config.clone(),
+                        tx,
+                        &running_jobs,
+                        shutdown.clone()
+                    ),
+                    auto_pin
+                );
+            }
         });
         self.task = Some((handle, shutdown));
     }
 
+    /// Cancel a running job.
+    ///
+    /// The job is reported as cancelled, so it'll be retried after a
+    /// backoff period, as if it had failed.
+    ///
+    /// Return false if the job isn't running.
+    pub(crate) fn cancel_job(&self, arena: Arena, job_id: JobId) -> bool {
+        match self.running_jobs.lock().unwrap().get(&(arena, job_id)) {
+            Some((_, token, _)) => {
+                token.cancel();
+
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Shutdown the background jobs, but don't wait for them to finish.
     ///
     /// Does nothing if the jobs aren't running.
@@ -138,79 +272,371 @@ impl<H: JobHandler + 'static> Churten<H> {
     }
 }
 
-/// Number of background jobs to run in parallel.
-const PARALLEL_JOB_COUNT: usize = 4;
+/// Jobs on files read by a user within that duration are started
+/// before other jobs of the same type.
+const RECENT_ACCESS: Duration = Duration::from_secs(3600);
+
+/// How often to check again whether blocked jobs now fit on disk.
+const BLOCKED_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 /// Process jobs from the job stream and report their result to [Storage].
 ///
+/// Jobs are taken from the stream as soon as they're available and
+/// queued, so they can be started in order of priority, within the
+/// limits and download schedules set by [ChurtenConfig]. As the file
+/// may have changed while the job was waiting in the queue, jobs are
+/// checked again before they're started and abandoned if outdated.
+///
+/// Running jobs that may download are paused when the download
+/// schedule of their arena ends: they're cancelled without reporting
+/// anything to [Storage] and queued again. Downloads keep whatever
+/// they've written so far, so they continue where they left off.
+///
+/// Jobs that write files are only started if there's enough disk
+/// space for them, as tracked by [DiskSpace]. Such jobs are
+/// reported as blocked until then.
+///
 /// Processing ends if the stream ends or if cancelled using the token.
 async fn background_job<H: JobHandler>(
     storage: &Arc<Storage>,
     handler: &H,
+    config: ChurtenConfig,
     tx: broadcast::Sender<ChurtenNotification>,
+    running_jobs: &RunningJobs,
     shutdown: CancellationToken,
 ) {
     log::debug!("Collecting jobs...");
-    let mut result_stream = storage
-        .job_stream()
-        .map(|(arena, job_id, job)| {
-            let job = Arc::new(job);
-            log::debug!("[{arena}] PENDING: {job_id} {job:?}");
-            let _ = tx.send(ChurtenNotification::New {
-                arena,
-                job_id,
-                job: Arc::clone(&job),
-            });
-
-            (arena, job_id, job)
-        })
-        .map(|(arena, job_id, job)| run_job(handler, arena, job_id, job, &tx, shutdown.clone()))
-        .buffer_unordered(PARALLEL_JOB_COUNT);
-    while let Some((arena, job_id, status)) = tokio::select!(
-        result = result_stream.next() => {
-            result
-        }
-        _ = shutdown.cancelled() => { None })
-    {
-        let _ = tx.send(ChurtenNotification::Finish {
-            arena,
-            job_id,
-            progress: match &status {
-                Ok(JobStatus::Done) => JobProgress::Done,
-                Ok(JobStatus::Abandoned) => JobProgress::Abandoned,
-                Err(err) => {
-                    if shutdown.is_cancelled() {
-                        JobProgress::Cancelled
-                    } else {
-                        JobProgress::Failed(err.to_string())
+    let mut job_stream = std::pin::pin!(storage.job_stream());
+    let mut stream_done = false;
+    let mut disk_space = DiskSpace::new(Arc::clone(storage), config.min_free_bytes);
+    let mut blocked = HashSet::new();
+    let mut queue = JobQueue::new(config);
+    let mut running = FuturesUnordered::new();
+
+    loop {
+        let now = WeekMinute::now();
+        disk_space.refresh();
+        while let Some(queued) =
+            queue.pop_runnable(now, |queued| match disk_space.reserve(queued) {
+                Ok(()) => true,
+                Err(reason) => {
+                    let arena = queued.arena;
+                    let job_id = queued.job_id;
+                    if blocked.insert((arena, job_id)) {
+                        log::debug!("[{arena}] BLOCKED: {job_id} {reason}");
+                        let _ = tx.send(ChurtenNotification::Block {
+                            arena,
+                            job_id,
+                            reason,
+                        });
                     }
+                    false
                 }
-            },
-        });
-        if let Err(err) = storage.job_finished(arena, job_id, status) {
-            // We don't want to interrupt job processing, even in this case.
-            log::warn!("[{arena}] failed to report status of job {job_id}: {err}");
+            })
+        {
+            blocked.remove(&(queued.arena, queued.job_id));
+            if !is_current(storage, &queued).await {
+                let arena = queued.arena;
+                let job_id = queued.job_id;
+                log::debug!("[{arena}] OUTDATED: {job_id}");
+                queue.finished(arena, queued.priority.job_type);
+                disk_space.release(arena, job_id);
+                report_finished(
+                    storage,
+                    &tx,
+                    arena,
+                    job_id,
+                    Ok(JobStatus::Abandoned),
+                    &shutdown,
+                );
+                continue;
+            }
+            let token = shutdown.child_token();
+            running_jobs.lock().unwrap().insert(
+                (queued.arena, queued.job_id),
+                (queued.priority.job_type, token.clone(), false),
+            );
+            running.push(run_job(handler, queued, &tx, token));
+        }
+        if stream_done && running.is_empty() && queue.is_empty() {
+            break;
         }
+        let next_schedule_change = queue.next_schedule_change();
+        tokio::select!(
+            biased;
+
+            Some((queued, run, status)) = running.next(), if !running.is_empty() => {
+                let arena = queued.arena;
+                let job_id = queued.job_id;
+                queue.finished(arena, queued.priority.job_type);
+                disk_space.release(arena, job_id);
+                let (_, token, paused) = running_jobs
+                    .lock()
+                    .unwrap()
+                    .remove(&(arena, job_id))
+                    .unwrap_or_else(|| (queued.priority.job_type, shutdown.clone(), false));
+                if paused && status.is_err() && !shutdown.is_cancelled() {
+                    log::debug!("[{arena}] PAUSED: {job_id}");
+                    let _ = tx.send(ChurtenNotification::Pause { arena, job_id });
+                    if let Some(outdated) = queue.requeue(queued) {
+                        report_finished(
+                            storage,
+                            &tx,
+                            outdated.arena,
+                            outdated.job_id,
+                            Ok(JobStatus::Abandoned),
+                            &token,
+                        );
+                    }
+                } else {
+                    let progress = report_finished(storage, &tx, arena, job_id, status, &token);
+                    record_job(storage, &queued, run, progress).await;
+                }
+            }
+            _ = shutdown.cancelled() => {
+                break;
+            }
+            _ = tokio::time::sleep(BLOCKED_CHECK_INTERVAL), if !blocked.is_empty() => {}
+            _ = sleep_until(next_schedule_change) => {
+                let now = WeekMinute::now();
+                for ((arena, _), (job_type, token, paused)) in running_jobs.lock().unwrap().iter_mut() {
+                    if !*paused && !queue.is_scheduled(*arena, *job_type, now) {
+                        *paused = true;
+                        token.cancel();
+                    }
+                }
+            }
+            next = job_stream.next(), if !stream_done => {
+                let Some((arena, job_id, job)) = next else {
+                    stream_done = true;
+                    continue;
+                };
+                let job = Arc::new(job);
+                let priority = job_priority(storage, arena, &job).await;
+                let disk_bytes = job_disk_bytes(storage, arena, &job).await;
+                let realize_bytes = job_realize_bytes(storage, arena, &job).await;
+                log::debug!("[{arena}] PENDING: {job_id} {job:?} {priority:?}");
+                let _ = tx.send(ChurtenNotification::New {
+                    arena,
+                    job_id,
+                    job: Arc::clone(&job),
+                    priority,
+                });
+                if let Some(replaced) = queue.push(QueuedJob {
+                    arena,
+                    job_id,
+                    job,
+                    priority,
+                    disk_bytes,
+                    realize_bytes,
+                }) {
+                    blocked.remove(&(replaced.arena, replaced.job_id));
+                    // The job for the same path that was still
+                    // pending is outdated.
+                    report_finished(
+                        storage,
+                        &tx,
+                        replaced.arena,
+                        replaced.job_id,
+                        Ok(JobStatus::Abandoned),
+                        &shutdown,
+                    );
+                }
+            }
+        );
     }
+    running_jobs.lock().unwrap().clear();
     log::debug!("Done collecting jobs...");
 }
 
-async fn run_job<H: JobHandler>(
-    handler: &H,
+/// Sleep until `time`, or forever if it's `None`.
+async fn sleep_until(time: Option<UnixTime>) {
+    match time {
+        Some(time) => tokio::time::sleep(time.duration_since(&UnixTime::now())).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Check whether a queued job is still the job to run on its path.
+///
+/// This isn't the case anymore if the file changed or the job became
+/// unnecessary while it was waiting in the queue.
+async fn is_current(storage: &Storage, queued: &QueuedJob) -> bool {
+    match storage.job_for_path(queued.arena, queued.job.path()).await {
+        Ok(Some((job_id, _))) => job_id == queued.job_id,
+        Ok(None) => false,
+        Err(err) => {
+            log::debug!(
+                "[{}] failed to check job {}: {err}",
+                queued.arena,
+                queued.job_id
+            );
+
+            true
+        }
+    }
+}
+
+/// Compute the priority of a job from what the cache knows of the
+/// file.
+async fn job_priority(storage: &Storage, arena: Arena, job: &Job) -> JobPriority {
+    let mut priority = JobPriority::new(job, u64::MAX);
+    let cache = storage.cache();
+    if let Ok((inode, _)) = cache.lookup_path(arena, job.path()).await {
+        if let Ok(metadata) = cache.file_metadata(inode).await {
+            priority.size = metadata.size;
+        }
+        priority.recently_accessed = cache
+            .last_access(inode)
+            .map(|t| t.elapsed() < RECENT_ACCESS)
+            .unwrap_or(false);
+    }
+
+    priority
+}
+
+/// Compute how many bytes a job may need to write to disk: the
+/// parts of the file that aren't available locally yet.
+///
+/// Unrealize jobs don't write anything, they move files.
+async fn job_disk_bytes(storage: &Storage, arena: Arena, job: &Job) -> u64 {
+    if let Job::Unrealize(_, _) = job {
+        return 0;
+    }
+    let cache = storage.cache();
+    let Ok((inode, _)) = cache.lookup_path(arena, job.path()).await else {
+        return 0;
+    };
+    match cache.local_availability(inode).await {
+        Ok(LocalAvailability::Missing) => cache
+            .file_metadata(inode)
+            .await
+            .map(|m| m.size)
+            .unwrap_or(0),
+        Ok(LocalAvailability::Partial(size, available)) => {
+            size.saturating_sub(available.bytecount())
+        }
+        _ => 0,
+    }
+}
+
+/// Compute how many bytes a realize job moves to the arena root: the
+/// whole file.
+async fn job_realize_bytes(storage: &Storage, arena: Arena, job: &Job) -> u64 {
+    let Job::Realize(_, _, _) = job else {
+        return 0;
+    };
+    let cache = storage.cache();
+    let Ok((inode, _)) = cache.lookup_path(arena, job.path()).await else {
+        return 0;
+    };
+
+    cache
+        .file_metadata(inode)
+        .await
+        .map(|m| m.size)
+        .unwrap_or(0)
+}
+
+/// Broadcast the result of a job and report it to [Storage].
+///
+/// `token` is the token that cancels the job; failures are reported
+/// as [JobProgress::Cancelled] if it was cancelled.
+///
+/// Return the final progress of the job, as broadcast.
+fn report_finished(
+    storage: &Storage,
+    tx: &broadcast::Sender<ChurtenNotification>,
     arena: Arena,
     job_id: JobId,
-    job: Arc<Job>,
+    status: anyhow::Result<JobStatus>,
+    token: &CancellationToken,
+) -> JobProgress {
+    let progress = match &status {
+        Ok(JobStatus::Done) => JobProgress::Done,
+        Ok(JobStatus::Abandoned) => JobProgress::Abandoned,
+        Err(err) => {
+            if token.is_cancelled() {
+                JobProgress::Cancelled
+            } else {
+                JobProgress::Failed(err.to_string())
+            }
+        }
+    };
+    let _ = tx.send(ChurtenNotification::Finish {
+        arena,
+        job_id,
+        progress: progress.clone(),
+    });
+    if let Err(err) = storage.job_finished(arena, job_id, status) {
+        // We don't want to interrupt job processing, even in this case.
+        log::warn!("[{arena}] failed to report status of job {job_id}: {err}");
+    }
+
+    progress
+}
+
+/// Keep the outcome of a job that was run in the job history.
+async fn record_job(
+    storage: &Arc<Storage>,
+    queued: &QueuedJob,
+    run: JobRun,
+    progress: JobProgress,
+) {
+    let arena = queued.arena;
+    let Some((outcome, error)) = progress.outcome() else {
+        return;
+    };
+    let entry = JobHistoryTableEntry {
+        path: queued.job.path().clone(),
+        hash: queued.job.hash().clone(),
+        kind: queued.priority.job_type.into(),
+        start: run.start,
+        end: UnixTime::now(),
+        bytes: run.written_bytes,
+        outcome,
+        error,
+    };
+    if let Err(err) = storage.record_job(arena, entry).await {
+        log::warn!(
+            "[{arena}] failed to record job {} in history: {err}",
+            queued.job_id
+        );
+    }
+}
+
+/// What happened while running a job, besides its result.
+struct JobRun {
+    /// Time at which the job started.
+    start: UnixTime,
+
+    /// Number of bytes written by the job.
+    written_bytes: u64,
+}
+
+async fn run_job<H: JobHandler>(
+    handler: &H,
+    queued: QueuedJob,
     tx: &broadcast::Sender<ChurtenNotification>,
     shutdown: CancellationToken,
-) -> (Arena, JobId, anyhow::Result<JobStatus>) {
-    log::debug!("[{arena}] STARTING: {job_id} {job:?}");
+) -> (QueuedJob, JobRun, anyhow::Result<JobStatus>) {
+    let arena = queued.arena;
+    let job_id = queued.job_id;
+    log::debug!("[{arena}] STARTING: {job_id} {:?}", queued.job);
+    let start = UnixTime::now();
     let _ = tx.send(ChurtenNotification::Start { arena, job_id });
     let mut progress = TxByteCountProgress::new(arena, job_id, tx.clone())
         .adaptive(BROADCAST_CHANNEL_CAPACITY)
         .with_min_byte_delta(BROADCAST_CHANNEL_RESOLUTION_BYTES);
-    let result = handler.run(arena, &job, &mut progress, shutdown).await;
-
-    (arena, job_id, result)
+    let result = handler
+        .run(arena, &queued.job, &mut progress, shutdown)
+        .await;
+    let run = JobRun {
+        start,
+        written_bytes: progress.written_bytes(),
+    };
+
+    (queued, run, result)
 }
 
 /// Dispatch jobs to the relevant function for processing.
@@ -218,11 +644,22 @@ async fn run_job<H: JobHandler>(
 pub(crate) struct JobHandlerImpl {
     storage: Arc<Storage>,
     household: Household,
+    limiter: Limiter,
 }
 
 impl JobHandlerImpl {
     pub(crate) fn new(storage: Arc<Storage>, household: Household) -> Self {
-        Self { storage, household }
+        Self {
+            storage,
+            household,
+            limiter: Limiter::new(f64::INFINITY),
+        }
+    }
+
+    /// Limit the transfers of download and realize jobs.
+    pub(crate) fn with_limiter(mut self, limiter: Limiter) -> Self {
+        self.limiter = limiter;
+        self
     }
 }
 
@@ -243,6 +680,7 @@ impl JobHandler for JobHandlerImpl {
                     path,
                     hash,
                     progress,
+                    &self.limiter,
                     shutdown,
                 )
                 .await
@@ -256,6 +694,7 @@ impl JobHandler for JobHandlerImpl {
                     hash,
                     index_hash.as_ref(),
                     progress,
+                    &self.limiter,
                     shutdown,
                 )
                 .await
@@ -276,7 +715,7 @@ mod tests {
     use crate::consensus::types::JobAction;
     use crate::rpc::testing::{self, HouseholdFixture};
     use realize_storage::utils::hash::{self, digest};
-    use realize_storage::{JobId, Mark};
+    use realize_storage::{JobHistoryFilter, JobId, JobKind, JobOutcome, Mark};
     use tokio::io::AsyncReadExt;
 
     struct Fixture {
@@ -323,7 +762,8 @@ mod tests {
                     ChurtenNotification::New {
                         arena,
                         job_id,
-                        job: Arc::new(Job::Download(foo, hash)),
+                        job: Arc::new(Job::Download(foo.clone(), hash.clone())),
+                        priority: JobPriority::new(&Job::Download(foo, hash), 11),
                     },
                     rx.recv().await?
                 );
@@ -402,6 +842,7 @@ mod tests {
         result_fn: Arc<dyn Fn() -> anyhow::Result<JobStatus> + Send + Sync>,
         should_send_progress: bool,
         should_cancel: bool,
+        should_wait_for_cancel: bool,
     }
 
     impl FakeJobHandler {
@@ -413,6 +854,7 @@ mod tests {
                 result_fn: Arc::new(result_fn),
                 should_send_progress: false,
                 should_cancel: false,
+                should_wait_for_cancel: false,
             }
         }
 
@@ -425,6 +867,11 @@ mod tests {
             self.should_cancel = should_cancel;
             self
         }
+
+        fn with_wait_for_cancel(mut self, should_wait: bool) -> Self {
+            self.should_wait_for_cancel = should_wait;
+            self
+        }
     }
 
     impl JobHandler for FakeJobHandler {
@@ -441,6 +888,9 @@ mod tests {
             if self.should_cancel {
                 shutdown.cancel();
             }
+            if self.should_wait_for_cancel {
+                shutdown.cancelled().await;
+            }
             if shutdown.is_cancelled() {
                 shutdown.cancel();
                 anyhow::bail!("cancelled");
@@ -481,9 +931,15 @@ mod tests {
                 let hash = digest("test content");
                 let job_id = JobId(1);
                 let job = Arc::new(Job::Download(foo.clone(), hash));
+                let priority = JobPriority::new(&job, 12);
                 // Check Pending notification
                 assert_eq!(
-                    ChurtenNotification::New { arena, job_id, job },
+                    ChurtenNotification::New {
+                        arena,
+                        job_id,
+                        job,
+                        priority
+                    },
                     rx.recv().await?
                 );
 
@@ -534,6 +990,56 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn churten_job_blocked_by_disk_space() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        fixture
+            .inner
+            .with_two_peers()
+            .await?
+            .run(async |household_a, _household_b| {
+                let a = HouseholdFixture::a();
+                let b = HouseholdFixture::b();
+                let arena = HouseholdFixture::test_arena();
+                let storage = fixture.inner.storage(a)?;
+                testing::connect(&household_a, b).await?;
+
+                let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+                let mut churten = Churten::with_handler(Arc::clone(&storage), handler).with_config(
+                    ChurtenConfig {
+                        min_free_bytes: u64::MAX,
+                        ..ChurtenConfig::default()
+                    },
+                );
+                let mut rx = churten.subscribe();
+                churten.start();
+
+                storage.set_arena_mark(arena, Mark::Keep).await?;
+                fixture.inner.write_file(b, "foo", "test content").await?;
+                let job_id = JobId(1);
+                assert!(matches!(rx.recv().await?, ChurtenNotification::New { .. }));
+
+                // The job is blocked instead of started.
+                match rx.recv().await? {
+                    ChurtenNotification::Block {
+                        arena: blocked_arena,
+                        job_id: blocked_job_id,
+                        reason,
+                    } => {
+                        assert_eq!(arena, blocked_arena);
+                        assert_eq!(job_id, blocked_job_id);
+                        assert!(reason.starts_with("needs "), "{reason}");
+                    }
+                    n => panic!("unexpected notification: {n:?}"),
+                }
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn churten_job_abandoned() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup().await?;
@@ -563,7 +1069,8 @@ mod tests {
                     ChurtenNotification::New {
                         arena,
                         job_id,
-                        job: Arc::new(Job::Download(foo.clone(), hash)),
+                        job: Arc::new(Job::Download(foo.clone(), hash.clone())),
+                        priority: JobPriority::new(&Job::Download(foo.clone(), hash), 12),
                     },
                     rx.recv().await?
                 );
@@ -617,10 +1124,16 @@ mod tests {
                 let hash = digest("test content");
                 let job_id = JobId(1);
                 let job = Arc::new(Job::Download(foo.clone(), hash.clone()));
+                let priority = JobPriority::new(&job, 12);
 
                 // Check Pending notification
                 assert_eq!(
-                    ChurtenNotification::New { arena, job_id, job },
+                    ChurtenNotification::New {
+                        arena,
+                        job_id,
+                        job,
+                        priority
+                    },
                     rx.recv().await?
                 );
 
@@ -651,6 +1164,58 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn churten_records_job_history() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        fixture
+            .inner
+            .with_two_peers()
+            .await?
+            .run(async |household_a, _household_b| {
+                let a = HouseholdFixture::a();
+                let b = HouseholdFixture::b();
+                let arena = HouseholdFixture::test_arena();
+                let storage = fixture.inner.storage(a)?;
+                testing::connect(&household_a, b).await?;
+
+                let error_msg = "Simulated job failure";
+                let handler = FakeJobHandler::new(move || Err(anyhow::anyhow!(error_msg)));
+                let mut churten = Churten::with_handler(Arc::clone(&storage), handler);
+                churten.start();
+
+                storage.set_arena_mark(arena, Mark::Keep).await?;
+                let foo = fixture.inner.write_file(b, "foo", "test content").await?;
+
+                let history = tokio::time::timeout(Duration::from_secs(5), async {
+                    loop {
+                        let history = storage
+                            .job_history(Some(arena), JobHistoryFilter::default())
+                            .await?;
+                        if !history.is_empty() {
+                            return Ok::<_, anyhow::Error>(history);
+                        }
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                })
+                .await??;
+
+                assert_eq!(1, history.len());
+                let (history_arena, entry) = &history[0];
+                assert_eq!(arena, *history_arena);
+                assert_eq!(foo, entry.path);
+                assert_eq!(digest("test content"), entry.hash);
+                assert_eq!(JobKind::Download, entry.kind);
+                assert_eq!(JobOutcome::Failed, entry.outcome);
+                assert_eq!(Some(error_msg.to_string()), entry.error);
+                assert!(entry.start <= entry.end);
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn churten_job_cancelled() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup().await?;
@@ -681,7 +1246,8 @@ mod tests {
                     ChurtenNotification::New {
                         arena,
                         job_id,
-                        job: Arc::new(Job::Download(foo.clone(), hash.clone()))
+                        job: Arc::new(Job::Download(foo.clone(), hash.clone())),
+                        priority: JobPriority::new(&Job::Download(foo.clone(), hash.clone()), 12),
                     },
                     rx.recv().await?
                 );
@@ -713,6 +1279,123 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn churten_cancel_job() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        fixture
+            .inner
+            .with_two_peers()
+            .await?
+            .run(async |household_a, _household_b| {
+                let a = HouseholdFixture::a();
+                let b = HouseholdFixture::b();
+                let arena = HouseholdFixture::test_arena();
+                let storage = fixture.inner.storage(a)?;
+                testing::connect(&household_a, b).await?;
+
+                let handler =
+                    FakeJobHandler::new(|| Ok(JobStatus::Done)).with_wait_for_cancel(true);
+                let mut churten = Churten::with_handler(Arc::clone(&storage), handler);
+                let mut rx = churten.subscribe();
+                churten.start();
+
+                storage.set_arena_mark(arena, Mark::Keep).await?;
+                fixture.inner.write_file(b, "foo", "test content").await?;
+                let job_id = JobId(1);
+
+                assert!(matches!(rx.recv().await?, ChurtenNotification::New { .. }));
+                assert_eq!(
+                    ChurtenNotification::Start { arena, job_id },
+                    rx.recv().await?
+                );
+                assert!(!churten.cancel_job(arena, JobId(99)));
+                assert!(churten.cancel_job(arena, job_id));
+                assert_eq!(
+                    ChurtenNotification::Finish {
+                        arena,
+                        job_id,
+                        progress: JobProgress::Cancelled
+                    },
+                    rx.recv().await?
+                );
+                assert!(!churten.cancel_job(arena, job_id));
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn churten_abandons_outdated_queued_job() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        fixture
+            .inner
+            .with_two_peers()
+            .await?
+            .run(async |household_a, _household_b| {
+                let a = HouseholdFixture::a();
+                let b = HouseholdFixture::b();
+                let arena = HouseholdFixture::test_arena();
+                let storage = fixture.inner.storage(a)?;
+                testing::connect(&household_a, b).await?;
+
+                let handler =
+                    FakeJobHandler::new(|| Ok(JobStatus::Done)).with_wait_for_cancel(true);
+                let mut churten = Churten::with_handler(Arc::clone(&storage), handler).with_config(
+                    ChurtenConfig {
+                        max_parallel: 1,
+                        ..ChurtenConfig::default()
+                    },
+                );
+                let mut rx = churten.subscribe();
+                churten.start();
+
+                storage.set_arena_mark(arena, Mark::Keep).await?;
+                fixture.inner.write_file(b, "foo", "test content").await?;
+                assert!(matches!(rx.recv().await?, ChurtenNotification::New { .. }));
+                assert_eq!(
+                    ChurtenNotification::Start {
+                        arena,
+                        job_id: JobId(1)
+                    },
+                    rx.recv().await?
+                );
+
+                // The job for bar waits in the queue, while foo is
+                // running, and becomes unnecessary.
+                let bar = fixture.inner.write_file(b, "bar", "test content").await?;
+                let bar_job_id = match rx.recv().await? {
+                    ChurtenNotification::New { job_id, .. } => job_id,
+                    n => panic!("unexpected notification: {n:?}"),
+                };
+                storage.set_arena_mark(arena, Mark::Watch).await?;
+                assert_eq!(None, storage.job_for_path(arena, &bar).await?);
+
+                assert!(churten.cancel_job(arena, JobId(1)));
+                loop {
+                    match rx.recv().await? {
+                        ChurtenNotification::Finish {
+                            job_id, progress, ..
+                        } if job_id == bar_job_id => {
+                            assert_eq!(JobProgress::Abandoned, progress);
+                            break;
+                        }
+                        ChurtenNotification::Start { job_id, .. } => {
+                            assert_ne!(bar_job_id, job_id);
+                        }
+                        _ => {}
+                    }
+                }
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn churten_job_no_progress_updates() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup().await?;
@@ -737,10 +1420,16 @@ mod tests {
                 let hash = digest("test content");
                 let job_id = JobId(1);
                 let job = Arc::new(Job::Download(foo, hash));
+                let priority = JobPriority::new(&job, 12);
 
                 // Check Pending notification
                 assert_eq!(
-                    ChurtenNotification::New { arena, job_id, job },
+                    ChurtenNotification::New {
+                        arena,
+                        job_id,
+                        job,
+                        priority
+                    },
                     rx.recv().await?
                 );
 
@@ -902,11 +1591,15 @@ mod tests {
                     Some(JobInfo {
                         arena,
                         id: JobId(1),
-                        job: Arc::new(Job::Download(foo1, hash::digest("content1"))),
+                        job: Arc::new(Job::Download(foo1.clone(), hash::digest("content1"))),
                         progress: JobProgress::Done,
                         action: None,
                         byte_progress: None,
                         notification_index: 0,
+                        priority: JobPriority::new(
+                            &Job::Download(foo1, hash::digest("content1")),
+                            8
+                        ),
                     }),
                     recent_jobs.remove(&JobId(1))
                 );
@@ -914,11 +1607,15 @@ mod tests {
                     Some(JobInfo {
                         arena,
                         id: JobId(2),
-                        job: Arc::new(Job::Download(foo2, hash::digest("content2"))),
+                        job: Arc::new(Job::Download(foo2.clone(), hash::digest("content2"))),
                         progress: JobProgress::Done,
                         action: None,
                         byte_progress: None,
                         notification_index: 0,
+                        priority: JobPriority::new(
+                            &Job::Download(foo2, hash::digest("content2")),
+                            8
+                        ),
                     }),
                     recent_jobs.remove(&JobId(2))
                 );
@@ -926,11 +1623,15 @@ mod tests {
                     Some(JobInfo {
                         arena,
                         id: JobId(3),
-                        job: Arc::new(Job::Download(foo3, hash::digest("content3"))),
+                        job: Arc::new(Job::Download(foo3.clone(), hash::digest("content3"))),
                         progress: JobProgress::Done,
                         action: None,
                         byte_progress: None,
                         notification_index: 0,
+                        priority: JobPriority::new(
+                            &Job::Download(foo3, hash::digest("content3")),
+                            8
+                        ),
                     }),
                     recent_jobs.remove(&JobId(3))
                 );
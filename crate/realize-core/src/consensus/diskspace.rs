@@ -0,0 +1,367 @@
+use super::queue::QueuedJob;
+use realize_storage::{JobId, MarkImpact, Storage};
+use realize_types::Arena;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Return the device ID of the filesystem that contains the given
+/// path and the number of bytes available on it.
+type FilesystemFn = Box<dyn Fn(&std::path::Path) -> std::io::Result<(u64, u64)> + Send + Sync>;
+
+/// Disk space reserved by running jobs.
+///
+/// Jobs are only started if the filesystems they write to can hold
+/// the file they write, in addition to the files of the other
+/// running jobs and [ChurtenConfig::min_free_bytes](crate::config::ChurtenConfig::min_free_bytes).
+///
+/// Reservations are kept until the job ends, even though the space
+/// is taken little by little as the file is written, so this errs on
+/// the side of caution.
+pub(crate) struct DiskSpace {
+    storage: Arc<Storage>,
+    margin: u64,
+    filesystem: FilesystemFn,
+
+    /// Device ID and free space of the directories checked since
+    /// the last call to [DiskSpace::refresh]; `None` if they couldn't
+    /// be checked.
+    checked: HashMap<PathBuf, Option<(u64, u64)>>,
+
+    /// Filesystems (device ID) and number of bytes reserved on them
+    /// by running jobs.
+    reserved: HashMap<(Arena, JobId), Vec<(u64, u64)>>,
+}
+
+impl DiskSpace {
+    pub(crate) fn new(storage: Arc<Storage>, margin: u64) -> Self {
+        Self {
+            storage,
+            margin,
+            filesystem: Box::new(filesystem),
+            checked: HashMap::new(),
+            reserved: HashMap::new(),
+        }
+    }
+
+    /// Replace the function that checks the filesystem of a
+    /// directory and the space available on it.
+    #[cfg(test)]
+    fn with_filesystem(
+        mut self,
+        filesystem: impl Fn(&std::path::Path) -> std::io::Result<(u64, u64)> + Send + Sync + 'static,
+    ) -> Self {
+        self.filesystem = Box::new(filesystem);
+        self
+    }
+
+    /// Forget the free space checked so far.
+    ///
+    /// This is called before going through the queued jobs, so free
+    /// space is checked at most once per directory every time.
+    pub(crate) fn refresh(&mut self) {
+        self.checked.clear();
+    }
+
+    /// Reserve the disk space the job needs, if there's enough.
+    ///
+    /// Downloaded data is written to the blob directory of the
+    /// arena. Realized files are moved from there to the arena root,
+    /// so they only need space there if the root is on another
+    /// filesystem.
+    ///
+    /// Return a description of the missing space if there isn't
+    /// enough space.
+    ///
+    /// If available space can't be checked, the job is let through
+    /// and fails or succeeds by itself.
+    pub(crate) fn reserve(&mut self, queued: &QueuedJob) -> Result<(), String> {
+        if queued.disk_bytes == 0 && queued.realize_bytes == 0 {
+            return Ok(());
+        }
+        let arena = queued.arena;
+        let storage = Arc::clone(&self.storage);
+        let Ok(blob_dir) = storage.blob_dir(arena) else {
+            return Ok(());
+        };
+        let Some((blob_dev, blob_free)) = self.check(arena, blob_dir) else {
+            return Ok(());
+        };
+        let mut needs = vec![(blob_dir, blob_dev, blob_free, queued.disk_bytes)];
+        if queued.realize_bytes > 0
+            && let Ok(Some(root)) = storage.arena_root(arena)
+            && let Some((root_dev, root_free)) = self.check(arena, root)
+            && root_dev != blob_dev
+        {
+            needs.push((root, root_dev, root_free, queued.realize_bytes));
+        }
+        needs.retain(|(_, _, _, bytes)| *bytes > 0);
+        for (dir, dev, free, bytes) in &needs {
+            let reserved = self
+                .reserved
+                .values()
+                .flatten()
+                .filter(|(d, _)| d == dev)
+                .map(|(_, bytes)| *bytes)
+                .sum::<u64>();
+            let available = free.saturating_sub(reserved);
+            let needed = bytes.saturating_add(self.margin);
+            if needed > available {
+                return Err(format!(
+                    "needs {needed} bytes free in {dir:?}, {available} bytes available"
+                ));
+            }
+        }
+        self.reserved.insert(
+            (arena, queued.job_id),
+            needs
+                .into_iter()
+                .map(|(_, dev, _, bytes)| (dev, bytes))
+                .collect(),
+        );
+
+        Ok(())
+    }
+
+    /// Return the device ID and free space of the filesystem of
+    /// `dir`, checking it only once until [DiskSpace::refresh] is
+    /// called.
+    fn check(&mut self, arena: Arena, dir: &std::path::Path) -> Option<(u64, u64)> {
+        if let Some(checked) = self.checked.get(dir) {
+            return *checked;
+        }
+        let checked = match (self.filesystem)(dir) {
+            Ok(fs) => Some(fs),
+            Err(err) => {
+                log::warn!("[{arena}] cannot check free space in {dir:?}: {err}");
+                None
+            }
+        };
+        self.checked.insert(dir.to_path_buf(), checked);
+
+        checked
+    }
+
+    /// Release the space reserved by a job, once it has ended.
+    pub(crate) fn release(&mut self, arena: Arena, job_id: JobId) {
+        self.reserved.remove(&(arena, job_id));
+    }
+}
+
+/// Check whether there's enough space for what a mark change would
+/// do, as computed by [Storage::mark_impact].
+///
+/// Downloaded files are written to the blob directory of the arena.
+/// Realized files are moved from there to the arena root, so they
+/// only need more space if the root is on another filesystem.
+///
+/// Return a warning for each filesystem that's too small.
+pub(crate) fn mark_impact_warnings(
+    storage: &Storage,
+    arena: Arena,
+    impact: &MarkImpact,
+) -> Vec<String> {
+    let mut warnings = vec![];
+    let Ok(blob_dir) = storage.blob_dir(arena) else {
+        return warnings;
+    };
+    if impact.download_bytes > 0
+        && let Ok(free) = free_space(blob_dir)
+        && impact.download_bytes > free
+    {
+        warnings.push(format!(
+            "downloading {} bytes exceeds the {free} bytes free in the cache, in {blob_dir:?}",
+            impact.download_bytes
+        ));
+    }
+    if impact.realize_bytes > 0
+        && let Ok(Some(root)) = storage.arena_root(arena)
+        && let (Ok(blob_meta), Ok(root_meta)) =
+            (std::fs::metadata(blob_dir), std::fs::metadata(root))
+        && blob_meta.dev() != root_meta.dev()
+        && let Ok(free) = free_space(root)
+        && impact.realize_bytes > free
+    {
+        warnings.push(format!(
+            "realizing {} bytes exceeds the {free} bytes free in the arena root, in {root:?}",
+            impact.realize_bytes
+        ));
+    }
+
+    warnings
+}
+
+/// Return the device ID of the filesystem that contains `path` and
+/// the number of bytes available on it.
+fn filesystem(path: &std::path::Path) -> std::io::Result<(u64, u64)> {
+    Ok((std::fs::metadata(path)?.dev(), free_space(path)?))
+}
+
+/// Return the number of bytes available to unprivileged users on the
+/// filesystem that contains `path`.
+fn free_space(path: &std::path::Path) -> std::io::Result<u64> {
+    let stat = nix::sys::statvfs::statvfs(path)?;
+
+    Ok((stat.blocks_available() as u64).saturating_mul(stat.fragment_size() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::types::JobPriority;
+    use assert_fs::TempDir;
+    use realize_storage::Job;
+    use realize_types::{Hash, Path};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn queued(arena: Arena, id: u64, disk_bytes: u64) -> anyhow::Result<QueuedJob> {
+        let job = Job::Download(Path::parse(&format!("file{id}"))?, Hash([1; 32]));
+        Ok(QueuedJob {
+            arena,
+            job_id: JobId(id),
+            priority: JobPriority::new(&job, disk_bytes),
+            job: Arc::new(job),
+            disk_bytes,
+            realize_bytes: 0,
+        })
+    }
+
+    fn queued_realize(
+        arena: Arena,
+        id: u64,
+        disk_bytes: u64,
+        realize_bytes: u64,
+    ) -> anyhow::Result<QueuedJob> {
+        let job = Job::Realize(Path::parse(&format!("file{id}"))?, Hash([1; 32]), None);
+        Ok(QueuedJob {
+            arena,
+            job_id: JobId(id),
+            priority: JobPriority::new(&job, realize_bytes),
+            job: Arc::new(job),
+            disk_bytes,
+            realize_bytes,
+        })
+    }
+
+    #[tokio::test]
+    async fn reserve_and_release() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let arena = Arena::from("myarena");
+        let storage = realize_storage::testing::storage(tempdir.path(), [arena]).await?;
+        let mut disk_space = DiskSpace::new(storage, 100).with_filesystem(|_| Ok((1, 1000)));
+
+        assert_eq!(Ok(()), disk_space.reserve(&queued(arena, 1, 500)?));
+        assert!(disk_space.reserve(&queued(arena, 2, 500)?).is_err());
+        assert_eq!(Ok(()), disk_space.reserve(&queued(arena, 3, 400)?));
+
+        // Jobs that don't write anything always fit.
+        assert_eq!(Ok(()), disk_space.reserve(&queued(arena, 4, 0)?));
+
+        disk_space.release(arena, JobId(1));
+        assert_eq!(Ok(()), disk_space.reserve(&queued(arena, 2, 500)?));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn not_enough_space() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let arena = Arena::from("myarena");
+        let storage = realize_storage::testing::storage(tempdir.path(), [arena]).await?;
+        let mut disk_space = DiskSpace::new(storage, 100).with_filesystem(|_| Ok((1, 1000)));
+
+        let reason = disk_space.reserve(&queued(arena, 1, 950)?).unwrap_err();
+        assert!(reason.starts_with("needs 1050 bytes free"), "{reason}");
+        assert!(reason.ends_with("1000 bytes available"), "{reason}");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_free_space_once_per_pass() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let arena = Arena::from("myarena");
+        let storage = realize_storage::testing::storage(tempdir.path(), [arena]).await?;
+        let checks = Arc::new(AtomicUsize::new(0));
+        let mut disk_space = DiskSpace::new(storage, 100).with_filesystem({
+            let checks = Arc::clone(&checks);
+            move |_| {
+                checks.fetch_add(1, Ordering::Relaxed);
+                Ok((1, 1000))
+            }
+        });
+
+        assert_eq!(Ok(()), disk_space.reserve(&queued(arena, 1, 100)?));
+        assert_eq!(Ok(()), disk_space.reserve(&queued(arena, 2, 100)?));
+        assert!(disk_space.reserve(&queued(arena, 3, 900)?).is_err());
+        assert_eq!(1, checks.load(Ordering::Relaxed));
+
+        disk_space.refresh();
+        assert_eq!(Ok(()), disk_space.reserve(&queued(arena, 4, 100)?));
+        assert_eq!(2, checks.load(Ordering::Relaxed));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reserve_realize_on_root_filesystem() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let arena = Arena::from("myarena");
+        let storage = realize_storage::testing::storage(tempdir.path(), [arena]).await?;
+        let root = realize_storage::testing::arena_root(tempdir.path(), arena);
+        let mut disk_space = DiskSpace::new(storage, 100).with_filesystem(move |path| {
+            if path == root {
+                Ok((2, 300))
+            } else {
+                Ok((1, 1000))
+            }
+        });
+
+        let reason = disk_space
+            .reserve(&queued_realize(arena, 1, 0, 500)?)
+            .unwrap_err();
+        assert!(reason.starts_with("needs 600 bytes free"), "{reason}");
+        assert!(reason.ends_with("300 bytes available"), "{reason}");
+
+        assert_eq!(
+            Ok(()),
+            disk_space.reserve(&queued_realize(arena, 2, 800, 100)?)
+        );
+        assert_eq!(
+            Ok(()),
+            disk_space.reserve(&queued_realize(arena, 3, 0, 100)?)
+        );
+        assert!(
+            disk_space
+                .reserve(&queued_realize(arena, 4, 0, 100)?)
+                .is_err()
+        );
+
+        // Downloaded data still goes to the blob directory.
+        assert!(disk_space.reserve(&queued(arena, 5, 200)?).is_err());
+
+        disk_space.release(arena, JobId(2));
+        assert_eq!(
+            Ok(()),
+            disk_space.reserve(&queued_realize(arena, 4, 0, 100)?)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn realize_on_same_filesystem_needs_no_space() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let arena = Arena::from("myarena");
+        let storage = realize_storage::testing::storage(tempdir.path(), [arena]).await?;
+        let mut disk_space = DiskSpace::new(storage, 100).with_filesystem(|_| Ok((1, 1000)));
+
+        assert_eq!(
+            Ok(()),
+            disk_space.reserve(&queued_realize(arena, 1, 0, 5000)?)
+        );
+
+        Ok(())
+    }
+}
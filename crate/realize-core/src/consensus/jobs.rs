@@ -1,5 +1,6 @@
 use super::{progress::ByteCountProgress, types::JobAction};
 use crate::rpc::Household;
+use async_speed_limit::Limiter;
 use futures::StreamExt;
 use realize_storage::{Inode, JobStatus, LocalAvailability, Storage, StorageError};
 use realize_types::{Arena, ByteRanges, Hash, Path, Peer, Signature};
@@ -23,6 +24,7 @@ pub(crate) async fn download(
     path: &Path,
     hash: &Hash,
     progress: &mut impl ByteCountProgress,
+    limiter: &Limiter,
     shutdown: CancellationToken,
 ) -> anyhow::Result<JobStatus> {
     let cache = storage.cache();
@@ -42,7 +44,7 @@ pub(crate) async fn download(
 
         LocalAvailability::Complete => {
             return verify(
-                storage, household, arena, path, inode, hash, progress, shutdown,
+                storage, household, arena, path, inode, hash, progress, limiter, shutdown,
             )
             .await;
         }
@@ -65,6 +67,7 @@ pub(crate) async fn download(
                 peers,
                 &mut blob,
                 progress,
+                limiter,
                 shutdown.clone(),
             )
             .await;
@@ -77,7 +80,7 @@ pub(crate) async fn download(
             drop(blob); // Make sure the file is closed before verifying
 
             return verify(
-                storage, household, arena, path, inode, hash, progress, shutdown,
+                storage, household, arena, path, inode, hash, progress, limiter, shutdown,
             )
             .await;
         }
@@ -85,6 +88,8 @@ pub(crate) async fn download(
 }
 
 /// Read file data from `peers` into `blob`.
+///
+/// Data is read at most as fast as `limiter` allows.
 async fn write_to_blob(
     household: &Household,
     arena: Arena,
@@ -92,6 +97,7 @@ async fn write_to_blob(
     peers: Vec<Peer>,
     blob: &mut realize_storage::Blob,
     progress: &mut impl ByteCountProgress,
+    limiter: &Limiter,
     shutdown: CancellationToken,
 ) -> Result<(), anyhow::Error> {
     let missing = ByteRanges::single(0, blob.size()).subtraction(blob.local_availability());
@@ -119,6 +125,12 @@ async fn write_to_blob(
             }
         ) {
             let (chunk_offset, chunk) = chunk?;
+            tokio::select!(
+                _ = limiter.consume(chunk.len()) => {}
+                _ = shutdown.cancelled() => {
+                    anyhow::bail!("cancelled");
+                }
+            );
             if chunk_offset != blob.offset() {
                 blob.seek(SeekFrom::Start(chunk_offset)).await?;
             }
@@ -146,6 +158,7 @@ pub(crate) async fn verify(
     inode: Inode,
     hash: &Hash,
     progress: &mut impl ByteCountProgress,
+    limiter: &Limiter,
     shutdown: CancellationToken,
 ) -> anyhow::Result<JobStatus> {
     let mut blob = storage.cache().open_file(inode).await?;
@@ -198,6 +211,12 @@ pub(crate) async fn verify(
                 anyhow::bail!("cancelled")
             }
         );
+        tokio::select!(
+            _ = limiter.consume(delta.0.len()) => {}
+            _ = shutdown.cancelled() => {
+                anyhow::bail!("cancelled")
+            }
+        );
         fixed_buf.clear();
         fast_rsync::apply_limited(limited_buf, delta.0.as_slice(), &mut fixed_buf, range_len)?;
         assert_eq!(range_len, fixed_buf.len());
@@ -234,12 +253,21 @@ pub(crate) async fn realize(
     hash: &Hash,
     index_hash: Option<&Hash>,
     progress: &mut impl ByteCountProgress,
+    limiter: &Limiter,
     shutdown: CancellationToken,
 ) -> anyhow::Result<JobStatus> {
-    // First make sure that the correct version is locally available
-    // and verified.
-    let download_status =
-        download(storage, household, arena, path, hash, progress, shutdown).await?;
+    // If peers reported the file as a hard link of a file that's
+    // available locally, link it instead of downloading a copy.
+    if storage.link(arena, path, hash, index_hash).await? {
+        return Ok(JobStatus::Done);
+    }
+
+    // Otherwise make sure that the correct version is locally
+    // available and verified.
+    let download_status = download(
+        storage, household, arena, path, hash, progress, limiter, shutdown,
+    )
+    .await?;
     if download_status != JobStatus::Done {
         return Ok(download_status);
     }
@@ -427,6 +455,7 @@ mod tests {
                         &path,
                         &hash::digest("foo then bar"),
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -446,6 +475,45 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn download_goes_through_limiter() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        fixture
+            .inner
+            .with_two_peers()
+            .await?
+            .run(async |household_a, _household_b| {
+                let a = HouseholdFixture::a();
+                let b = HouseholdFixture::b();
+                testing::connect(&household_a, b).await?;
+
+                let path = fixture.write_file(b, "foobar", "foo then bar").await?;
+                fixture.inner.wait_for_file_in_cache(a, "foobar").await?;
+
+                let limiter = Limiter::new(1024.0);
+                assert_eq!(
+                    JobStatus::Done,
+                    download(
+                        fixture.inner.storage(a)?,
+                        &household_a,
+                        HouseholdFixture::test_arena(),
+                        &path,
+                        &hash::digest("foo then bar"),
+                        &mut NoOpByteCountProgress,
+                        &limiter,
+                        CancellationToken::new(),
+                    )
+                    .await?,
+                );
+                assert_eq!(12, limiter.total_bytes_consumed());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn download_zero_length_file() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup().await?;
@@ -471,6 +539,7 @@ mod tests {
                         &path,
                         &hash::digest(""),
                         &mut progress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -528,6 +597,7 @@ mod tests {
                         &path,
                         &hash::digest("baa, baa, black sheep"),
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -582,6 +652,7 @@ mod tests {
                         &path,
                         &hash::digest("baa, baa, black sheep"),
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -626,6 +697,7 @@ mod tests {
                         &path,
                         &hash::digest("barfoo"), // mismatch
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -659,6 +731,7 @@ mod tests {
                         &Path::parse("doesnotexist")?,
                         &hash::digest(""),
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -696,6 +769,7 @@ mod tests {
                     &path,
                     &hash::digest("foobar"),
                     &mut NoOpByteCountProgress,
+                    &Limiter::new(f64::INFINITY),
                     cancelled_token,
                 )
                 .await;
@@ -748,6 +822,7 @@ mod tests {
                         &path,
                         &hash::digest("baa, baa, black sheep"),
                         &mut progress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -796,6 +871,7 @@ mod tests {
                         &path,
                         &hash::digest("baa, baa, black sheep"),
                         &mut progress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -844,6 +920,7 @@ mod tests {
                         &foobar,
                         &hash::digest(b"foo & bar"),
                         &mut progress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?
@@ -898,6 +975,7 @@ mod tests {
                         &foobar,
                         &hash::digest(b"foo & bar"),
                         &mut progress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?
@@ -954,6 +1032,7 @@ mod tests {
                         &path,
                         &hash,
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -1000,6 +1079,7 @@ mod tests {
                         &hash::digest("foo then bar"),
                         None,
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -1043,6 +1123,7 @@ mod tests {
                         &hash::digest("foo then bar"),
                         None,
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
@@ -1064,6 +1145,7 @@ mod tests {
                         &hash::digest("bar before foo!"),
                         Some(&hash::digest("foo then bar")),
                         &mut NoOpByteCountProgress,
+                        &Limiter::new(f64::INFINITY),
                         CancellationToken::new(),
                     )
                     .await?,
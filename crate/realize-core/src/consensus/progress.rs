@@ -26,6 +26,9 @@ pub(crate) struct TxByteCountProgress {
     last_bytecount_update: Option<(u64, u64)>,
     channel_capacity: Option<usize>,
     index: u32,
+    action: Option<JobAction>,
+    action_bytes: u64,
+    written_bytes: u64,
 }
 
 impl TxByteCountProgress {
@@ -44,9 +47,18 @@ impl TxByteCountProgress {
             channel_capacity: None,
             // Start at 0, this way New is 0 and Start is 1.
             index: 2,
+            action: None,
+            action_bytes: 0,
+            written_bytes: 0,
         }
     }
 
+    /// Number of bytes written to the file so far by download and
+    /// repair actions.
+    pub(crate) fn written_bytes(&self) -> u64 {
+        self.written_bytes + self.action_bytes
+    }
+
     /// Minimum current byte count difference that will be reported.
     ///
     /// Defaults to 1, that is, any change is reported.
@@ -124,6 +136,9 @@ impl TxByteCountProgress {
 
 impl ByteCountProgress for TxByteCountProgress {
     fn update_action(&mut self, action: JobAction) {
+        self.written_bytes += self.action_bytes;
+        self.action_bytes = 0;
+        self.action = Some(action);
         let index = self.next_index();
         let _ = self.tx.send(ChurtenNotification::UpdateAction {
             arena: self.arena,
@@ -133,6 +148,9 @@ impl ByteCountProgress for TxByteCountProgress {
         });
     }
     fn update(&mut self, current_bytes: u64, total_bytes: u64) {
+        if matches!(self.action, Some(JobAction::Download | JobAction::Repair)) {
+            self.action_bytes = current_bytes;
+        }
         if self.should_send(current_bytes, total_bytes) {
             self.last_bytecount_update = Some((current_bytes, total_bytes));
             let index = self.next_index();
@@ -296,6 +314,22 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn count_written_bytes() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup();
+        let mut progress = fixture.create_progress();
+        progress.update_action(JobAction::Download);
+        progress.update(512, 1024);
+        progress.update(1024, 1024);
+        progress.update_action(JobAction::Verify);
+        progress.update(1024, 1024);
+        progress.update_action(JobAction::Repair);
+        progress.update(100, 1024);
+        assert_eq!(1124, progress.written_bytes());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn channel_limits_updates_to_percent() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup();
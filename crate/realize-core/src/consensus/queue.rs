@@ -0,0 +1,435 @@
+use super::types::{JobPriority, JobType};
+use crate::config::ChurtenConfig;
+use realize_storage::{Job, JobId};
+use realize_types::schedule::WeekMinute;
+use realize_types::{Arena, Path, UnixTime};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// A job waiting in a [JobQueue].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct QueuedJob {
+    pub(crate) arena: Arena,
+    pub(crate) job_id: JobId,
+    pub(crate) job: Arc<Job>,
+    pub(crate) priority: JobPriority,
+
+    /// Number of bytes the job may need to write to the blob
+    /// directory.
+    pub(crate) disk_bytes: u64,
+
+    /// Number of bytes a realize job moves to the arena root.
+    pub(crate) realize_bytes: u64,
+}
+
+/// Pending jobs, in the order in which they should be started, and
+/// the number of running jobs, to enforce the limits and schedules
+/// of [ChurtenConfig].
+pub(crate) struct JobQueue {
+    config: ChurtenConfig,
+
+    /// Pending jobs, ordered by priority, then by the order in which
+    /// they were pushed.
+    pending: BTreeMap<(JobPriority, u64), QueuedJob>,
+
+    /// Key in `pending` of the pending job for a path.
+    by_path: HashMap<(Arena, Path), (JobPriority, u64)>,
+
+    /// Total number of running jobs.
+    running: usize,
+
+    /// Number of running jobs, per type.
+    running_per_type: HashMap<JobType, usize>,
+
+    /// Number of running jobs, per arena.
+    running_per_arena: HashMap<Arena, usize>,
+
+    /// Increasing counter, used to order jobs with the same priority.
+    counter: u64,
+}
+
+impl JobQueue {
+    pub(crate) fn new(config: ChurtenConfig) -> Self {
+        Self {
+            config,
+            pending: BTreeMap::new(),
+            by_path: HashMap::new(),
+            running: 0,
+            running_per_type: HashMap::new(),
+            running_per_arena: HashMap::new(),
+            counter: 0,
+        }
+    }
+
+    /// Check whether there are no pending jobs.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Add a pending job.
+    ///
+    /// If there already was a pending job for the same path, it is
+    /// removed from the queue and returned, as it is outdated.
+    pub(crate) fn push(&mut self, queued: QueuedJob) -> Option<QueuedJob> {
+        self.counter += 1;
+        let key = (queued.priority, self.counter);
+        let replaced = self
+            .by_path
+            .insert((queued.arena, queued.job.path().clone()), key)
+            .and_then(|old_key| self.pending.remove(&old_key));
+        self.pending.insert(key, queued);
+
+        replaced
+    }
+
+    /// Put back a job returned by [JobQueue::pop_runnable] that
+    /// couldn't finish, so it's started again later.
+    ///
+    /// If a newer job for the same path is pending, the job is
+    /// outdated; it is not queued and is returned instead.
+    pub(crate) fn requeue(&mut self, queued: QueuedJob) -> Option<QueuedJob> {
+        if self
+            .by_path
+            .contains_key(&(queued.arena, queued.job.path().clone()))
+        {
+            return Some(queued);
+        }
+        self.push(queued);
+
+        None
+    }
+
+    /// Take the pending job that should be started next, if any can
+    /// be started at time `now` without going over the limits.
+    ///
+    /// `fits` is called on jobs that could otherwise be started, in
+    /// order, until it accepts one, which is then returned. This is
+    /// used to check whether there's enough disk space for the job.
+    ///
+    /// The job that's returned is counted as running until
+    /// [JobQueue::finished] is called.
+    pub(crate) fn pop_runnable(
+        &mut self,
+        now: WeekMinute,
+        mut fits: impl FnMut(&QueuedJob) -> bool,
+    ) -> Option<QueuedJob> {
+        if self.running >= self.config.max_parallel.max(1) {
+            return None;
+        }
+        let key = self
+            .pending
+            .iter()
+            .find(|(_, queued)| self.can_start(queued, now) && fits(queued))
+            .map(|(key, _)| *key)?;
+        let queued = self.pending.remove(&key)?;
+        self.by_path
+            .remove(&(queued.arena, queued.job.path().clone()));
+        self.running += 1;
+        *self
+            .running_per_type
+            .entry(queued.priority.job_type)
+            .or_insert(0) += 1;
+        *self.running_per_arena.entry(queued.arena).or_insert(0) += 1;
+
+        Some(queued)
+    }
+
+    /// Report that a job returned by [JobQueue::pop_runnable] has
+    /// finished.
+    pub(crate) fn finished(&mut self, arena: Arena, job_type: JobType) {
+        self.running = self.running.saturating_sub(1);
+        if let Some(count) = self.running_per_type.get_mut(&job_type) {
+            *count = count.saturating_sub(1);
+        }
+        if let Some(count) = self.running_per_arena.get_mut(&arena) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Check whether a job of the given type and arena is allowed to
+    /// run at time `now` by the download schedule.
+    pub(crate) fn is_scheduled(&self, arena: Arena, job_type: JobType, now: WeekMinute) -> bool {
+        !job_type.may_download() || self.config.download_schedule(arena).is_open(now)
+    }
+
+    /// Return when the download schedule of any arena changes next,
+    /// if ever.
+    pub(crate) fn next_schedule_change(&self) -> Option<UnixTime> {
+        std::iter::once(&self.config.download_schedule)
+            .chain(self.config.download_schedule_per_arena.values())
+            .filter_map(|schedule| schedule.state_now().next_change)
+            .min()
+    }
+
+    /// Check whether the job can be started at time `now` without
+    /// going over the per-type and per-arena limits.
+    fn can_start(&self, queued: &QueuedJob, now: WeekMinute) -> bool {
+        let job_type = queued.priority.job_type;
+        if !self.is_scheduled(queued.arena, job_type, now) {
+            return false;
+        }
+        if let Some(max) = self.config.max_parallel_per_type.get(&job_type)
+            && self.running_per_type.get(&job_type).copied().unwrap_or(0) >= *max
+        {
+            return false;
+        }
+        if let Some(max) = self.config.max_parallel_per_arena.get(&queued.arena)
+            && self
+                .running_per_arena
+                .get(&queued.arena)
+                .copied()
+                .unwrap_or(0)
+                >= *max
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use realize_types::Hash;
+    use realize_types::schedule::{Schedule, TimeOfDay, TimeWindow, Weekday};
+
+    fn queued(arena: &str, id: u64, job: Job, size: u64) -> QueuedJob {
+        let priority = JobPriority::new(&job, size);
+        QueuedJob {
+            arena: Arena::from(arena),
+            job_id: JobId(id),
+            job: Arc::new(job),
+            priority,
+            disk_bytes: size,
+            realize_bytes: 0,
+        }
+    }
+
+    fn download(path: &str) -> anyhow::Result<Job> {
+        Ok(Job::Download(Path::parse(path)?, Hash([1; 32])))
+    }
+
+    fn realize(path: &str) -> anyhow::Result<Job> {
+        Ok(Job::Realize(Path::parse(path)?, Hash([1; 32]), None))
+    }
+
+    fn monday_noon() -> WeekMinute {
+        WeekMinute::new(Weekday::Mon, TimeOfDay::new(12, 0).unwrap())
+    }
+
+    fn pop_id(queue: &mut JobQueue) -> Option<u64> {
+        queue
+            .pop_runnable(monday_noon(), |_| true)
+            .map(|q| q.job_id.0)
+    }
+
+    #[test]
+    fn skip_jobs_that_dont_fit() -> anyhow::Result<()> {
+        let mut queue = JobQueue::new(ChurtenConfig::default());
+        queue.push(queued("arena", 1, download("big")?, 1000));
+        queue.push(queued("arena", 2, download("small")?, 10));
+        queue.push(queued("arena", 3, realize("realize")?, 2000));
+
+        let mut checked = vec![];
+        let fits = |q: &QueuedJob| {
+            checked.push(q.job_id.0);
+            q.disk_bytes <= 100
+        };
+        assert_eq!(
+            Some(2),
+            queue.pop_runnable(monday_noon(), fits).map(|q| q.job_id.0)
+        );
+        assert_eq!(vec![3, 2], checked);
+        assert_eq!(
+            None,
+            queue.pop_runnable(monday_noon(), |q| q.disk_bytes <= 100)
+        );
+        assert_eq!(Some(3), pop_id(&mut queue));
+
+        Ok(())
+    }
+
+    #[test]
+    fn priority_order() -> anyhow::Result<()> {
+        let mut queue = JobQueue::new(ChurtenConfig::default());
+        queue.push(queued("arena", 1, download("big")?, 1000));
+        queue.push(queued("arena", 2, download("small")?, 10));
+        queue.push(queued("arena", 3, realize("realize")?, 1000));
+        let mut recent = queued("arena", 4, download("recent")?, 2000);
+        recent.priority.recently_accessed = true;
+        queue.push(recent);
+
+        assert_eq!(Some(3), pop_id(&mut queue));
+        assert_eq!(Some(4), pop_id(&mut queue));
+        assert_eq!(Some(2), pop_id(&mut queue));
+        assert_eq!(Some(1), pop_id(&mut queue));
+        assert_eq!(None, pop_id(&mut queue));
+        assert!(queue.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_pending_job_for_same_path() -> anyhow::Result<()> {
+        let mut queue = JobQueue::new(ChurtenConfig::default());
+        assert_eq!(None, queue.push(queued("arena", 1, download("foo")?, 10)));
+        let replaced = queue.push(queued("arena", 2, download("foo")?, 20));
+        assert_eq!(Some(1), replaced.map(|q| q.job_id.0));
+
+        assert_eq!(Some(2), pop_id(&mut queue));
+        assert_eq!(None, pop_id(&mut queue));
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_parallel() -> anyhow::Result<()> {
+        let mut queue = JobQueue::new(ChurtenConfig {
+            max_parallel: 2,
+            ..ChurtenConfig::default()
+        });
+        for i in 1..=3 {
+            queue.push(queued("arena", i, download(&format!("file{i}"))?, i));
+        }
+
+        assert_eq!(Some(1), pop_id(&mut queue));
+        assert_eq!(Some(2), pop_id(&mut queue));
+        assert_eq!(None, pop_id(&mut queue));
+
+        queue.finished(Arena::from("arena"), JobType::Download);
+        assert_eq!(Some(3), pop_id(&mut queue));
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_parallel_per_type() -> anyhow::Result<()> {
+        let mut queue = JobQueue::new(ChurtenConfig {
+            max_parallel_per_type: HashMap::from([(JobType::Realize, 1)]),
+            ..ChurtenConfig::default()
+        });
+        queue.push(queued("arena", 1, realize("r1")?, 1));
+        queue.push(queued("arena", 2, realize("r2")?, 2));
+        queue.push(queued("arena", 3, download("d1")?, 3));
+
+        assert_eq!(Some(1), pop_id(&mut queue));
+        assert_eq!(Some(3), pop_id(&mut queue));
+        assert_eq!(None, pop_id(&mut queue));
+
+        queue.finished(Arena::from("arena"), JobType::Realize);
+        assert_eq!(Some(2), pop_id(&mut queue));
+
+        Ok(())
+    }
+
+    #[test]
+    fn max_parallel_per_arena() -> anyhow::Result<()> {
+        let mut queue = JobQueue::new(ChurtenConfig {
+            max_parallel_per_arena: HashMap::from([(Arena::from("slow"), 1)]),
+            ..ChurtenConfig::default()
+        });
+        queue.push(queued("slow", 1, download("a")?, 1));
+        queue.push(queued("slow", 2, download("b")?, 2));
+        queue.push(queued("fast", 3, download("c")?, 3));
+
+        assert_eq!(Some(1), pop_id(&mut queue));
+        assert_eq!(Some(3), pop_id(&mut queue));
+        assert_eq!(None, pop_id(&mut queue));
+
+        queue.finished(Arena::from("slow"), JobType::Download);
+        assert_eq!(Some(2), pop_id(&mut queue));
+
+        Ok(())
+    }
+
+    #[test]
+    fn download_schedule() -> anyhow::Result<()> {
+        let night = Schedule(vec![TimeWindow {
+            days: vec![],
+            start: TimeOfDay::new(19, 0).unwrap(),
+            end: TimeOfDay::new(7, 0).unwrap(),
+        }]);
+        let mut queue = JobQueue::new(ChurtenConfig {
+            download_schedule: night,
+            download_schedule_per_arena: HashMap::from([(
+                Arena::from("anytime"),
+                Schedule::default(),
+            )]),
+            ..ChurtenConfig::default()
+        });
+        queue.push(queued("arena", 1, download("d1")?, 1));
+        queue.push(queued("arena", 2, realize("r1")?, 1));
+        queue.push(queued(
+            "arena",
+            3,
+            Job::Unrealize(Path::parse("u1")?, Hash([1; 32])),
+            1,
+        ));
+        queue.push(queued("anytime", 4, download("d2")?, 1));
+
+        assert_eq!(Some(3), pop_id(&mut queue));
+        assert_eq!(Some(4), pop_id(&mut queue));
+        assert_eq!(None, pop_id(&mut queue));
+        assert!(!queue.is_scheduled(Arena::from("arena"), JobType::Download, monday_noon()));
+
+        let evening = WeekMinute::new(Weekday::Mon, TimeOfDay::new(20, 0).unwrap());
+        assert_eq!(
+            Some(2),
+            queue.pop_runnable(evening, |_| true).map(|q| q.job_id.0)
+        );
+        assert_eq!(
+            Some(1),
+            queue.pop_runnable(evening, |_| true).map(|q| q.job_id.0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn next_schedule_change() -> anyhow::Result<()> {
+        assert_eq!(
+            None,
+            JobQueue::new(ChurtenConfig::default()).next_schedule_change()
+        );
+
+        let night = Schedule(vec![TimeWindow {
+            days: vec![],
+            start: TimeOfDay::new(19, 0).unwrap(),
+            end: TimeOfDay::new(7, 0).unwrap(),
+        }]);
+        let queue = JobQueue::new(ChurtenConfig {
+            download_schedule_per_arena: HashMap::from([(Arena::from("arena"), night)]),
+            ..ChurtenConfig::default()
+        });
+        let now = UnixTime::now();
+        let next = queue.next_schedule_change().unwrap();
+        assert!(next.as_secs() + 60 > now.as_secs());
+        // Allow for a change of timezone offset on that day.
+        assert!(next.as_secs() <= now.as_secs() + 13 * 3600);
+
+        Ok(())
+    }
+
+    #[test]
+    fn requeue() -> anyhow::Result<()> {
+        let mut queue = JobQueue::new(ChurtenConfig::default());
+        queue.push(queued("arena", 1, download("foo")?, 10));
+        queue.push(queued("arena", 2, download("bar")?, 10));
+        let foo = queue.pop_runnable(monday_noon(), |_| true).unwrap();
+        let bar = queue.pop_runnable(monday_noon(), |_| true).unwrap();
+        queue.finished(foo.arena, JobType::Download);
+        queue.finished(bar.arena, JobType::Download);
+
+        // A newer job for foo makes the old one outdated.
+        queue.push(queued("arena", 3, download("foo")?, 10));
+        assert_eq!(Some(1), queue.requeue(foo).map(|q| q.job_id.0));
+        assert_eq!(None, queue.requeue(bar));
+
+        assert_eq!(Some(3), pop_id(&mut queue));
+        assert_eq!(Some(2), pop_id(&mut queue));
+        assert_eq!(None, pop_id(&mut queue));
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use super::types::{ChurtenNotification, JobAction, JobProgress};
+use super::types::{ChurtenNotification, JobAction, JobPriority, JobProgress};
 use realize_storage::{Job, JobId};
 use realize_types::Arena;
 use std::collections::{HashMap, VecDeque, hash_map::IntoValues};
@@ -24,6 +24,10 @@ pub struct JobInfo {
     /// Index of the last UpdateAction/UpdateByteCount notification
     /// processed for this job.
     pub notification_index: u32,
+
+    /// Determines when the job is started, compared to other
+    /// pending jobs.
+    pub priority: JobPriority,
 }
 
 impl JobInfo {
@@ -107,6 +111,22 @@ impl JobInfoTracker {
         self.jobs.values()
     }
 
+    /// Return all [JobInfo]s: running jobs first, then pending jobs
+    /// in the order in which they're going to be started, then
+    /// finished jobs, from the most recent.
+    pub fn ordered(&self) -> Vec<&JobInfo> {
+        let mut active = self.active().collect::<Vec<_>>();
+        active.sort_by_key(|j| (j.progress != JobProgress::Running, j.priority, j.id));
+        active.extend(
+            self.finished
+                .iter()
+                .rev()
+                .flat_map(|key| self.jobs.get(key)),
+        );
+
+        active
+    }
+
     /// Iterate over all active [JobInfo]s.
     pub fn active(&self) -> impl Iterator<Item = &JobInfo> {
         self.jobs.values().filter(|j| !j.progress.is_finished())
@@ -126,7 +146,7 @@ impl JobInfoTracker {
         let job_id = notification.job_id();
         let global_id = notification.global_job_id();
         match notification {
-            ChurtenNotification::New { job, .. } => {
+            ChurtenNotification::New { job, priority, .. } => {
                 if !self.jobs.contains_key(&global_id) {
                     self.jobs.insert(
                         global_id,
@@ -138,6 +158,7 @@ impl JobInfoTracker {
                             action: None,
                             byte_progress: None,
                             notification_index: 0,
+                            priority: *priority,
                         },
                     );
                     self.trim();
@@ -146,12 +167,35 @@ impl JobInfoTracker {
             }
             ChurtenNotification::Start { .. } => {
                 if let Some(info) = self.jobs.get_mut(&global_id)
-                    && info.progress == JobProgress::Pending
+                    && matches!(
+                        info.progress,
+                        JobProgress::Pending | JobProgress::Blocked(_)
+                    )
                 {
                     info.progress = JobProgress::Running;
                     return true;
                 }
             }
+            ChurtenNotification::Pause { .. } => {
+                if let Some(info) = self.jobs.get_mut(&global_id)
+                    && info.progress == JobProgress::Running
+                {
+                    info.progress = JobProgress::Pending;
+                    info.action = None;
+                    info.byte_progress = None;
+                    // The job starts counting again once restarted.
+                    info.notification_index = 0;
+                    return true;
+                }
+            }
+            ChurtenNotification::Block { reason, .. } => {
+                if let Some(info) = self.jobs.get_mut(&global_id)
+                    && info.progress == JobProgress::Pending
+                {
+                    info.progress = JobProgress::Blocked(reason.clone());
+                    return true;
+                }
+            }
             ChurtenNotification::Finish { progress, .. } => {
                 if let Some(info) = self.jobs.get_mut(&global_id)
                     && !info.progress.is_finished()
@@ -251,11 +295,21 @@ mod tests {
                     arena: self.arena,
                     job_id: self.job_id,
                     job: Arc::clone(&self.job),
+                    priority: JobPriority::new(&self.job, 100),
                 },
                 "start" => ChurtenNotification::Start {
                     arena: self.arena,
                     job_id: self.job_id,
                 },
+                "pause" => ChurtenNotification::Pause {
+                    arena: self.arena,
+                    job_id: self.job_id,
+                },
+                "block" => ChurtenNotification::Block {
+                    arena: self.arena,
+                    job_id: self.job_id,
+                    reason: "no space".to_string(),
+                },
                 "finish" => ChurtenNotification::Finish {
                     arena: self.arena,
                     job_id: self.job_id,
@@ -874,6 +928,60 @@ mod tests {
         assert_eq!(job_info.byte_progress, None); // Should be None
     }
 
+    #[test]
+    fn test_pause_resets_job() {
+        let mut tracker = JobInfoTracker::new(10);
+        let fixture = Fixture::new();
+
+        // Pausing a pending job does nothing
+        tracker.update(&fixture.create_notification("new"));
+        assert!(!tracker.update(&fixture.create_notification("pause")));
+
+        tracker.update(&fixture.create_notification("start"));
+        tracker.update(&fixture.create_notification("update_action(2)"));
+        tracker.update(&fixture.create_notification("update_byte_count(3)"));
+
+        assert!(tracker.update(&fixture.create_notification("pause")));
+        let job_info = tracker.get(&fixture.global_job_id()).unwrap();
+        assert_eq!(job_info.progress, JobProgress::Pending);
+        assert_eq!(job_info.action, None);
+        assert_eq!(job_info.byte_progress, None);
+        assert_eq!(1, tracker.active_len());
+
+        // Once restarted, the job reports progress from the beginning
+        assert!(tracker.update(&fixture.create_notification("start")));
+        assert!(tracker.update(&fixture.create_notification("update_action(2)")));
+        let job_info = tracker.get(&fixture.global_job_id()).unwrap();
+        assert_eq!(job_info.progress, JobProgress::Running);
+        assert_eq!(job_info.action, Some(JobAction::Download));
+    }
+
+    #[test]
+    fn test_block_pending_job() {
+        let mut tracker = JobInfoTracker::new(10);
+        let fixture = Fixture::new();
+
+        tracker.update(&fixture.create_notification("new"));
+        assert!(tracker.update(&fixture.create_notification("block")));
+        let job_info = tracker.get(&fixture.global_job_id()).unwrap();
+        assert_eq!(
+            job_info.progress,
+            JobProgress::Blocked("no space".to_string())
+        );
+        assert_eq!(1, tracker.active_len());
+
+        // Blocking a blocked job does nothing
+        assert!(!tracker.update(&fixture.create_notification("block")));
+
+        // The job can be started once unblocked
+        assert!(tracker.update(&fixture.create_notification("start")));
+        let job_info = tracker.get(&fixture.global_job_id()).unwrap();
+        assert_eq!(job_info.progress, JobProgress::Running);
+
+        // Blocking a running job does nothing
+        assert!(!tracker.update(&fixture.create_notification("block")));
+    }
+
     #[test]
     fn test_update_action_resets_byte_progress() {
         let mut tracker = JobInfoTracker::new(10);
@@ -924,4 +1032,51 @@ mod tests {
         assert_eq!(job1_info.notification_index, 2);
         assert_eq!(job2_info.notification_index, 1);
     }
+
+    #[test]
+    fn test_ordered() {
+        let mut tracker = JobInfoTracker::new(10);
+        let arena = Arena::from("test-arena");
+        let new = |id: u64, job: Job, size: u64| {
+            let job = Arc::new(job);
+            ChurtenNotification::New {
+                arena,
+                job_id: JobId::new(id),
+                priority: JobPriority::new(&job, size),
+                job,
+            }
+        };
+        let path = Path::parse("test-path").unwrap();
+        tracker.update(&new(1, Job::Download(path.clone(), Hash::zero()), 10));
+        tracker.update(&new(2, Job::Download(path.clone(), Hash::zero()), 1000));
+        tracker.update(&new(3, Job::Download(path.clone(), Hash::zero()), 100));
+        tracker.update(&new(
+            4,
+            Job::Realize(path.clone(), Hash::zero(), None),
+            1000,
+        ));
+        tracker.update(&new(5, Job::Download(path.clone(), Hash::zero()), 1));
+        tracker.update(&ChurtenNotification::Start {
+            arena,
+            job_id: JobId::new(2),
+        });
+        tracker.update(&ChurtenNotification::Start {
+            arena,
+            job_id: JobId::new(5),
+        });
+        tracker.update(&ChurtenNotification::Finish {
+            arena,
+            job_id: JobId::new(5),
+            progress: JobProgress::Done,
+        });
+
+        assert_eq!(
+            vec![2, 4, 1, 3, 5],
+            tracker
+                .ordered()
+                .into_iter()
+                .map(|j| j.id.as_u64())
+                .collect::<Vec<_>>()
+        );
+    }
 }
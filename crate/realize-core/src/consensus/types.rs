@@ -1,4 +1,4 @@
-use realize_storage::{Job, JobId};
+use realize_storage::{Job, JobId, JobKind, JobOutcome};
 use realize_types::Arena;
 use std::sync::Arc;
 
@@ -10,11 +10,35 @@ pub enum ChurtenNotification {
         arena: Arena,
         job_id: JobId,
         job: Arc<Job>,
+
+        /// Determines when the job is started, compared to other
+        /// pending jobs.
+        priority: JobPriority,
     },
 
     /// Start a pending job, which enters state [JobProgress::Running].
     Start { arena: Arena, job_id: JobId },
 
+    /// Pause a running job, which goes back to state
+    /// [JobProgress::Pending] until it can be started again.
+    ///
+    /// This happens to jobs that download file content when the
+    /// download schedule ends.
+    Pause { arena: Arena, job_id: JobId },
+
+    /// Block a pending job, which enters state [JobProgress::Blocked]
+    /// until it can be started.
+    ///
+    /// This happens to jobs that write file content when there isn't
+    /// enough free disk space for the file.
+    Block {
+        arena: Arena,
+        job_id: JobId,
+
+        /// Why the job can't be started.
+        reason: String,
+    },
+
     /// Finish a job, successfully or not.
     Finish {
         arena: Arena,
@@ -73,6 +97,8 @@ impl ChurtenNotification {
         match self {
             ChurtenNotification::New { arena, .. } => *arena,
             ChurtenNotification::Start { arena, .. } => *arena,
+            ChurtenNotification::Pause { arena, .. } => *arena,
+            ChurtenNotification::Block { arena, .. } => *arena,
             ChurtenNotification::Finish { arena, .. } => *arena,
             ChurtenNotification::UpdateByteCount { arena, .. } => *arena,
             ChurtenNotification::UpdateAction { arena, .. } => *arena,
@@ -82,6 +108,8 @@ impl ChurtenNotification {
         match self {
             ChurtenNotification::New { job_id, .. } => *job_id,
             ChurtenNotification::Start { job_id, .. } => *job_id,
+            ChurtenNotification::Pause { job_id, .. } => *job_id,
+            ChurtenNotification::Block { job_id, .. } => *job_id,
             ChurtenNotification::Finish { job_id, .. } => *job_id,
             ChurtenNotification::UpdateByteCount { job_id, .. } => *job_id,
             ChurtenNotification::UpdateAction { job_id, .. } => *job_id,
@@ -95,6 +123,12 @@ pub enum JobProgress {
     /// The job has been created, but not yet started.
     Pending,
 
+    /// The job is waiting for enough disk space to become available
+    /// before it can be started.
+    ///
+    /// The string describes the space that is missing.
+    Blocked(String),
+
     /// The job is running.
     Running,
 
@@ -117,6 +151,7 @@ impl JobProgress {
     pub fn is_finished(&self) -> bool {
         match self {
             JobProgress::Pending => false,
+            JobProgress::Blocked(_) => false,
             JobProgress::Running => false,
             JobProgress::Done => true,
             JobProgress::Abandoned => true,
@@ -124,6 +159,30 @@ impl JobProgress {
             JobProgress::Failed(_) => true,
         }
     }
+
+    /// Return the outcome of a finished job, as kept in the job
+    /// history, and its error message, if it failed.
+    pub fn outcome(&self) -> Option<(JobOutcome, Option<String>)> {
+        match self {
+            JobProgress::Pending => None,
+            JobProgress::Blocked(_) => None,
+            JobProgress::Running => None,
+            JobProgress::Done => Some((JobOutcome::Done, None)),
+            JobProgress::Abandoned => Some((JobOutcome::Abandoned, None)),
+            JobProgress::Cancelled => Some((JobOutcome::Cancelled, None)),
+            JobProgress::Failed(msg) => Some((JobOutcome::Failed, Some(msg.clone()))),
+        }
+    }
+
+    /// Build the progress of a finished job from the job history.
+    pub fn from_outcome(outcome: JobOutcome, error: Option<String>) -> Self {
+        match outcome {
+            JobOutcome::Done => JobProgress::Done,
+            JobOutcome::Abandoned => JobProgress::Abandoned,
+            JobOutcome::Cancelled => JobProgress::Cancelled,
+            JobOutcome::Failed => JobProgress::Failed(error.unwrap_or_default()),
+        }
+    }
 }
 
 /// An specific action taken by a job.
@@ -134,3 +193,101 @@ pub enum JobAction {
     Repair,
     Move,
 }
+
+/// Type of a [Job].
+///
+/// Variants are declared in the order in which jobs of that type are
+/// started by [Churten](super::churten::Churten).
+#[derive(
+    Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum JobType {
+    Realize,
+    Unrealize,
+    Download,
+}
+
+impl JobType {
+    /// Return the type of the given job.
+    pub fn of(job: &Job) -> Self {
+        match job {
+            Job::Download(_, _) => JobType::Download,
+            Job::Realize(_, _, _) => JobType::Realize,
+            Job::Unrealize(_, _) => JobType::Unrealize,
+        }
+    }
+
+    /// Check whether jobs of this type may download file content
+    /// from peers.
+    pub fn may_download(self) -> bool {
+        match self {
+            JobType::Realize => true,
+            JobType::Unrealize => false,
+            JobType::Download => true,
+        }
+    }
+}
+
+impl From<JobType> for JobKind {
+    fn from(value: JobType) -> Self {
+        match value {
+            JobType::Realize => JobKind::Realize,
+            JobType::Unrealize => JobKind::Unrealize,
+            JobType::Download => JobKind::Download,
+        }
+    }
+}
+
+impl From<JobKind> for JobType {
+    fn from(value: JobKind) -> Self {
+        match value {
+            JobKind::Realize => JobType::Realize,
+            JobKind::Unrealize => JobType::Unrealize,
+            JobKind::Download => JobType::Download,
+        }
+    }
+}
+
+/// Order in which pending jobs are started.
+///
+/// Lower values are started first: jobs are ordered by type, then
+/// jobs on files that were accessed recently go first, then jobs on
+/// smaller files.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct JobPriority {
+    pub job_type: JobType,
+
+    /// Whether the file was read recently.
+    pub recently_accessed: bool,
+
+    /// Size of the file, in bytes, or [u64::MAX] if unknown.
+    pub size: u64,
+}
+
+impl JobPriority {
+    /// Priority of a job for a file of the given size that wasn't
+    /// accessed recently.
+    pub fn new(job: &Job, size: u64) -> Self {
+        Self {
+            job_type: JobType::of(job),
+            recently_accessed: false,
+            size,
+        }
+    }
+}
+
+impl Ord for JobPriority {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.job_type
+            .cmp(&other.job_type)
+            .then(other.recently_accessed.cmp(&self.recently_accessed))
+            .then(self.size.cmp(&other.size))
+    }
+}
+
+impl PartialOrd for JobPriority {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
@@ -3,11 +3,15 @@ use futures::Future;
 use realize_storage::{Blob, Inode, StorageError, UnrealCacheAsync};
 use realize_types::{Arena, ByteRange, ByteRanges, Path, Peer};
 use std::cmp::min;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io::{ErrorKind, SeekFrom};
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite as _, ReadBuf};
+use tokio::io::{
+    AsyncRead, AsyncReadExt as _, AsyncSeek, AsyncSeekExt as _, AsyncWrite as _, ReadBuf,
+};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 use tokio_stream::StreamExt;
 
 /// Minimum size of a chunk read from a remote peer.
@@ -21,6 +25,12 @@ const MIN_CHUNK_SIZE: u64 = 8 * 1024;
 /// This should be a multiple of MIN_CHUNK_SIZE.
 const MAX_CHUNK_SIZE: u64 = 4 * MIN_CHUNK_SIZE;
 
+/// Maximum number of bytes to download past the requested range when
+/// the file is read sequentially.
+///
+/// This should be a multiple of MIN_CHUNK_SIZE.
+const MAX_READAHEAD: u64 = 256 * MIN_CHUNK_SIZE;
+
 #[derive(Clone)]
 pub struct Downloader {
     household: Household,
@@ -33,6 +43,84 @@ impl Downloader {
     }
 
     pub async fn reader(&self, inode: Inode) -> Result<Download, StorageError> {
+        let download = self.open(inode).await?;
+        self.cache.record_access(inode);
+
+        Ok(download)
+    }
+
+    /// Make the first `head` and the last `tail` bytes of the file
+    /// available locally, downloading whatever is missing.
+    ///
+    /// Unlike [Downloader::reader], this doesn't count as an access
+    /// to the file.
+    pub async fn prefetch(&self, inode: Inode, head: u64, tail: u64) -> anyhow::Result<()> {
+        let mut download = self.open(inode).await?;
+        let size = download.size;
+        let wanted = ByteRanges::from_ranges([
+            ByteRange::new(0, min(head, size)),
+            ByteRange::new(size.saturating_sub(tail), size),
+        ]);
+        let missing = wanted.subtraction(download.local_availability());
+        let mut buf = vec![0; MAX_CHUNK_SIZE as usize];
+        for range in missing.iter() {
+            download.seek(SeekFrom::Start(range.start)).await?;
+            let mut remaining = range.bytecount();
+            while remaining > 0 {
+                let n = download
+                    .read(&mut buf[0..min(remaining, MAX_CHUNK_SIZE) as usize])
+                    .await?;
+                if n == 0 {
+                    break;
+                }
+                remaining = remaining.saturating_sub(n as u64);
+            }
+        }
+        download.update_db().await?;
+
+        Ok(())
+    }
+
+    /// Prefetch the head and tail of the files reported by `rx` in
+    /// the background, with the number of bytes given for their
+    /// arena by `head_tail_per_arena`.
+    ///
+    /// Files are prefetched one at a time. Files of arenas missing
+    /// from `head_tail_per_arena` are skipped.
+    pub fn prefetch_new_files(
+        &self,
+        mut rx: broadcast::Receiver<(Arena, Path)>,
+        head_tail_per_arena: HashMap<Arena, u64>,
+    ) -> JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let (arena, path) = match rx.recv().await {
+                    Ok(new_file) => new_file,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::debug!("Prefetch skipped {n} files");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return;
+                    }
+                };
+                let Some(bytes) = head_tail_per_arena.get(&arena) else {
+                    continue;
+                };
+                log::debug!("Prefetch [{arena}]/{path}");
+                let result = match this.cache.lookup_path(arena, &path).await {
+                    Ok((inode, _)) => this.prefetch(inode, *bytes, *bytes).await,
+                    Err(err) => Err(err.into()),
+                };
+                if let Err(err) = result {
+                    log::debug!("Failed to prefetch [{arena}]/{path}: {err}");
+                }
+            }
+        })
+    }
+
+    async fn open(&self, inode: Inode) -> Result<Download, StorageError> {
         let avail = self.cache.file_availability(inode).await?;
         let blob = self.cache.open_file(inode).await?;
 
@@ -68,6 +156,42 @@ pub struct Download {
     avail: VecDeque<(ByteRange, Vec<u8>)>,
     read: ReadState,
     blob: Blob,
+    readahead: ReadAhead,
+}
+
+/// Decide how much to download past the requested range.
+///
+/// The window doubles every time data is downloaded right after the
+/// previous read, up to [MAX_READAHEAD], and shrinks on random access,
+/// so streaming a file doesn't cost a round trip to the peer per read
+/// while reads scattered across a file download little more than what
+/// was asked for.
+#[derive(Default)]
+struct ReadAhead {
+    window: u64,
+
+    /// Offset right after the last read, if any.
+    last_end: Option<u64>,
+}
+
+impl ReadAhead {
+    /// Adapt the window to a download starting at `offset` and return
+    /// it.
+    fn adapt(&mut self, offset: u64) -> u64 {
+        if self.last_end == Some(offset) {
+            self.window = (self.window * 2).clamp(MAX_CHUNK_SIZE, MAX_READAHEAD);
+        } else {
+            self.window /= 4;
+            self.window -= self.window % MIN_CHUNK_SIZE;
+        }
+
+        self.window
+    }
+
+    /// Record that the last read ended at `offset`.
+    fn read_until(&mut self, offset: u64) {
+        self.last_end = Some(offset);
+    }
 }
 
 /// States for AsyncRead::poll_read.
@@ -123,6 +247,7 @@ impl Download {
             avail: VecDeque::new(),
             read: ReadState::Default,
             blob,
+            readahead: ReadAhead::default(),
         }
     }
 
@@ -193,8 +318,21 @@ impl Download {
         if bufsize > MAX_CHUNK_SIZE {
             bufsize = MAX_CHUNK_SIZE;
         }
-
-        let range = ByteRange::new(next, min(end, next + bufsize));
+        bufsize += self.readahead.adapt(offset);
+
+        let mut range = ByteRange::new(next, min(end, next + bufsize));
+
+        // Don't download again what's already available locally.
+        if let Some(local) = self
+            .blob
+            .local_availability()
+            .intersection(&ByteRanges::for_range(range.clone()))
+            .iter()
+            .next()
+            && local.start > range.start
+        {
+            range = ByteRange::new(range.start, local.start);
+        }
 
         log::debug!("Download [{}]/{} {range}", self.arena, self.path);
 
@@ -430,6 +568,10 @@ impl AsyncRead for Download {
             let (next, ret) = self.handle_poll_read(prev, cx, buf);
             self.read = next;
             if let Some(ret) = ret {
+                if let Poll::Ready(Ok(())) = ret {
+                    let offset = self.offset;
+                    self.readahead.read_until(offset);
+                }
                 return ret;
             }
         }
@@ -791,6 +933,117 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn readahead_grows_on_sequential_reads() {
+        let mut readahead = ReadAhead::default();
+        assert_eq!(0, readahead.adapt(0));
+        readahead.read_until(100);
+        assert_eq!(MAX_CHUNK_SIZE, readahead.adapt(100));
+        readahead.read_until(200);
+        assert_eq!(2 * MAX_CHUNK_SIZE, readahead.adapt(200));
+        for offset in 3..100 {
+            readahead.read_until(offset);
+            readahead.adapt(offset);
+        }
+        assert_eq!(MAX_READAHEAD, readahead.window);
+    }
+
+    #[test]
+    fn readahead_shrinks_on_random_reads() {
+        let mut readahead = ReadAhead {
+            window: MAX_READAHEAD,
+            last_end: Some(100),
+        };
+        assert_eq!(MAX_READAHEAD / 4, readahead.adapt(5000));
+        readahead.read_until(5100);
+        assert_eq!(MAX_READAHEAD / 16, readahead.adapt(0));
+        for offset in 1..10 {
+            readahead.adapt(offset * 10000);
+        }
+        assert_eq!(0, readahead.window);
+    }
+
+    #[tokio::test]
+    async fn read_sequentially_downloads_ahead() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        fixture
+            .inner
+            .with_two_peers()
+            .await?
+            .run(async |household_a, _household_b| {
+                let a = HouseholdFixture::a();
+                let b = HouseholdFixture::b();
+                testing::connect(&household_a, b).await?;
+
+                let b_dir = fixture.inner.arena_root(b);
+                std::fs::write(b_dir.join("large_file"), vec![1u8; 1024 * 1024])?;
+                fixture
+                    .inner
+                    .wait_for_file_in_cache(a, "large_file")
+                    .await?;
+
+                let downloader =
+                    Downloader::new(household_a.clone(), fixture.inner.cache(a)?.clone());
+                let mut reader = fixture.reader(&downloader, "large_file").await?;
+                let mut buf = [0u8; MIN_CHUNK_SIZE as usize];
+                for _ in 0..4 {
+                    reader.read_exact(&mut buf).await?;
+                }
+
+                // More than what was read is available.
+                assert!(reader.local_availability().bytecount() > 4 * MIN_CHUNK_SIZE);
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefetch_head_and_tail() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        fixture
+            .inner
+            .with_two_peers()
+            .await?
+            .run(async |household_a, _household_b| {
+                let a = HouseholdFixture::a();
+                let b = HouseholdFixture::b();
+                testing::connect(&household_a, b).await?;
+
+                let b_dir = fixture.inner.arena_root(b);
+                let size = 16 * MIN_CHUNK_SIZE;
+                std::fs::write(b_dir.join("large_file"), vec![1u8; size as usize])?;
+                fixture
+                    .inner
+                    .wait_for_file_in_cache(a, "large_file")
+                    .await?;
+
+                let cache = fixture.inner.cache(a)?;
+                let downloader = Downloader::new(household_a.clone(), cache.clone());
+                let (inode, _) = cache
+                    .lookup_path(HouseholdFixture::test_arena(), &Path::parse("large_file")?)
+                    .await?;
+                downloader
+                    .prefetch(inode, MIN_CHUNK_SIZE, MIN_CHUNK_SIZE)
+                    .await?;
+
+                let reader = fixture.reader(&downloader, "large_file").await?;
+                let avail = reader.local_availability();
+                let head = ByteRanges::single(0, MIN_CHUNK_SIZE);
+                let tail = ByteRanges::single(size - MIN_CHUNK_SIZE, size);
+                assert_eq!(head, avail.intersection(&head));
+                assert_eq!(tail, avail.intersection(&tail));
+                assert!(avail.bytecount() < size);
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn read_large_file_small_buffer() -> anyhow::Result<()> {
         let mut fixture = HouseholdFixture::setup().await?;
@@ -229,7 +229,10 @@ impl NFSFileSystem for UnrealFs {
             .map_err(|e| unreal_to_nfsstat3(e.as_ref()))?
             .into_value();
 
-        Ok(self.do_read(reader, offset, count).await?)
+        let (data, eof) = self.do_read(reader, offset, count).await?;
+        self.cache.record_read(Inode(id), data.len() as u64);
+
+        Ok((data, eof))
     }
 
     async fn readdir(
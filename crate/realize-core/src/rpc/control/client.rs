@@ -1,14 +1,20 @@
 use crate::consensus::{tracker::JobInfo, types::ChurtenNotification};
 use capnp::capability::Promise;
+use realize_network::PendingKey;
+use realize_network::capnp::PeerInfo;
+use realize_network::security::KeyRotation;
 use realize_network::unixsocket;
+use realize_types::Peer;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
 use super::{
     control_capnp::{
         self,
         churten::subscriber::{NotifyParams, ResetParams},
+        peer_subscriber::UpdateParams,
     },
-    convert::{self, parse_job_info},
+    convert::{self, parse_job_info, parse_peer_info},
 };
 
 /// Connect to a running daemon through the given socket path.
@@ -111,6 +117,163 @@ impl control_capnp::churten::subscriber::Server for TxChurtenSubscriber {
     }
 }
 
+/// Get the connection information of the peers.
+pub async fn get_peers(c: &control_capnp::control::Client) -> Result<Vec<PeerInfo>, capnp::Error> {
+    let reply = c.peers_request().send().promise.await?;
+
+    parse_peer_list(reply.get()?.get_res()?)
+}
+
+/// Limit the bandwidth used by the connections to and from a peer,
+/// in bytes per second; 0 for unlimited.
+pub async fn set_peer_limit(
+    c: &control_capnp::control::Client,
+    peer: Peer,
+    upload_bytes_per_second: u64,
+    download_bytes_per_second: u64,
+) -> Result<(), capnp::Error> {
+    let mut request = c.set_peer_limit_request();
+    let mut params = request.get();
+    params.set_peer(peer.as_str());
+    params.set_upload_bytes_per_second(upload_bytes_per_second);
+    params.set_download_bytes_per_second(download_bytes_per_second);
+    request.send().promise.await?;
+
+    Ok(())
+}
+
+/// Send a new public key of the daemon's peer to the connected
+/// peers.
+///
+/// Returns the peers that received the key.
+pub async fn rotate_key(
+    c: &control_capnp::control::Client,
+    rotation: &KeyRotation,
+) -> Result<Vec<Peer>, capnp::Error> {
+    let mut request = c.rotate_key_request();
+    let mut params = request.get();
+    params.set_pubkey(rotation.pubkey.as_slice());
+    params.set_signature(rotation.signature.as_slice());
+    let reply = request.send().promise.await?;
+
+    reply
+        .get()?
+        .get_peers()?
+        .iter()
+        .map(|p| Ok::<_, capnp::Error>(Peer::from(p?.to_str()?)))
+        .collect()
+}
+
+/// Get the keys proposed by other peers, waiting for approval.
+pub async fn pending_keys(
+    c: &control_capnp::control::Client,
+) -> Result<Vec<PendingKey>, capnp::Error> {
+    let reply = c.pending_keys_request().send().promise.await?;
+
+    reply
+        .get()?
+        .get_res()?
+        .iter()
+        .map(convert::parse_pending_key)
+        .collect()
+}
+
+/// Approve a key proposed by a peer.
+///
+/// Returns false if no such key is pending.
+pub async fn approve_key(
+    c: &control_capnp::control::Client,
+    peer: Peer,
+    pubkey: &[u8],
+) -> Result<bool, capnp::Error> {
+    let mut request = c.approve_key_request();
+    let mut params = request.get();
+    params.set_peer(peer.as_str());
+    params.set_pubkey(pubkey);
+    let reply = request.send().promise.await?;
+
+    Ok(reply.get()?.get_approved())
+}
+
+/// Create a one-time invitation for a new peer, valid for `ttl`.
+///
+/// `address` is where the new peer can reach the daemon, as
+/// host:port. Returns the invitation token.
+pub async fn invite(
+    c: &control_capnp::control::Client,
+    address: &str,
+    ttl: Duration,
+) -> Result<String, capnp::Error> {
+    let mut request = c.invite_request();
+    let mut params = request.get();
+    params.set_address(address);
+    params.set_ttl_secs(ttl.as_secs());
+    let reply = request.send().promise.await?;
+
+    Ok(reply.get()?.get_token()?.to_str()?.to_string())
+}
+
+/// Join the household of the peer that created the invitation token.
+///
+/// `peer` is the ID of the daemon's peer, if not configured, and
+/// `address` where it can be reached, if anywhere. Returns the ID of
+/// the inviting peer.
+pub async fn join(
+    c: &control_capnp::control::Client,
+    token: &str,
+    peer: Option<Peer>,
+    address: Option<&str>,
+) -> Result<Peer, capnp::Error> {
+    let mut request = c.join_request();
+    let mut params = request.get();
+    params.set_token(token);
+    params.set_peer(peer.as_ref().map(|p| p.as_str()).unwrap_or(""));
+    params.set_address(address.unwrap_or(""));
+    let reply = request.send().promise.await?;
+
+    Ok(Peer::from(reply.get()?.get_peer()?.to_str()?))
+}
+
+/// Watch the connection information of the peers.
+///
+/// The current information is sent first, then new information is
+/// sent every time a peer connects or disconnects.
+pub async fn watch_peers(
+    c: &control_capnp::control::Client,
+) -> Result<mpsc::Receiver<Vec<PeerInfo>>, capnp::Error> {
+    let (tx, rx) = mpsc::channel(10);
+    let mut request = c.watch_peers_request();
+    request
+        .get()
+        .set_subscriber(capnp_rpc::new_client(TxPeerSubscriber { tx }));
+    request.send().promise.await?;
+
+    Ok(rx)
+}
+
+/// A peer subscriber server that forwards updates to a channel.
+struct TxPeerSubscriber {
+    tx: mpsc::Sender<Vec<PeerInfo>>,
+}
+
+impl control_capnp::peer_subscriber::Server for TxPeerSubscriber {
+    fn update(&mut self, params: UpdateParams) -> Promise<(), capnp::Error> {
+        let tx = self.tx.clone();
+        Promise::from_future(async move {
+            let peers = parse_peer_list(params.get()?.get_peers()?)?;
+            tx.send(peers).await.map_err(channel_closed)?;
+
+            Ok(())
+        })
+    }
+}
+
+fn parse_peer_list(
+    list: capnp::struct_list::Reader<'_, control_capnp::peer_info::Owned>,
+) -> Result<Vec<PeerInfo>, capnp::Error> {
+    list.iter().map(parse_peer_info).collect()
+}
+
 fn channel_closed<T>(_: mpsc::error::SendError<T>) -> capnp::Error {
     capnp::Error::failed("channel closed".to_string())
 }
@@ -3,9 +3,12 @@ use std::sync::Arc;
 use super::control_capnp;
 use super::control_capnp::churten_notification;
 use crate::consensus::tracker::JobInfo;
-use crate::consensus::types::{ChurtenNotification, JobAction, JobProgress};
-use realize_storage::{Job, JobId};
-use realize_types::{Arena, Hash, Path};
+use crate::consensus::types::{ChurtenNotification, JobAction, JobPriority, JobProgress, JobType};
+use realize_network::PendingKey;
+use realize_network::capnp::{PeerInfo, PeerStatus};
+use realize_network::config::BandwidthLimit;
+use realize_storage::{Job, JobHistoryFilter, JobHistoryTableEntry, JobId, JobOutcome, PlannedJob};
+use realize_types::{Arena, Hash, Path, Peer, UnixTime};
 
 /// Convert capnp ChurtenNotification to rust.
 pub(crate) fn parse_notification(
@@ -16,16 +19,24 @@ pub(crate) fn parse_notification(
 
     match reader.which()? {
         churten_notification::Which::New(new_reader) => {
-            let job_reader = new_reader?.get_job()?;
-            let job = parse_job(job_reader)?;
+            let new_reader = new_reader?;
+            let job = parse_job(new_reader.get_job()?)?;
+            let priority = parse_priority(new_reader.get_priority()?)?;
 
             Ok(ChurtenNotification::New {
                 arena,
                 job_id,
                 job: std::sync::Arc::new(job),
+                priority,
             })
         }
         churten_notification::Which::Start(_) => Ok(ChurtenNotification::Start { arena, job_id }),
+        churten_notification::Which::Pause(_) => Ok(ChurtenNotification::Pause { arena, job_id }),
+        churten_notification::Which::Block(reader) => Ok(ChurtenNotification::Block {
+            arena,
+            job_id,
+            reason: reader?.get_reason()?.to_str()?.to_string(),
+        }),
         churten_notification::Which::Finish(update_reader_result) => {
             let update_reader = update_reader_result?;
             let progress_reader = update_reader.get_progress()?;
@@ -75,12 +86,20 @@ pub(crate) fn fill_notification(
     dest.set_arena(&arena.as_str());
     dest.set_job_id(job_id.as_u64());
     match &source {
-        ChurtenNotification::New { job, .. } => {
-            fill_job(job, dest.reborrow().init_new().init_job());
+        ChurtenNotification::New { job, priority, .. } => {
+            let mut new = dest.reborrow().init_new();
+            fill_job(job, new.reborrow().init_job());
+            fill_priority(priority, new.init_priority());
         }
         ChurtenNotification::Start { .. } => {
             dest.reborrow().init_start();
         }
+        ChurtenNotification::Pause { .. } => {
+            dest.reborrow().init_pause();
+        }
+        ChurtenNotification::Block { reason, .. } => {
+            dest.reborrow().init_block().set_reason(reason);
+        }
         ChurtenNotification::Finish { progress, .. } => {
             fill_progress(progress, dest.reborrow().init_finish().init_progress());
         }
@@ -111,6 +130,7 @@ pub(crate) fn fill_job_info(source: &JobInfo, mut dest: control_capnp::job_info:
     fill_progress(&source.progress, dest.reborrow().init_progress());
     dest.set_action(to_capnp_action(source.action.as_ref()));
     dest.set_notification_index(source.notification_index);
+    fill_priority(&source.priority, dest.reborrow().init_priority());
     if let Some((current, total)) = source.byte_progress {
         let mut byte_progress = dest.init_byte_progress();
         byte_progress.set_current(current);
@@ -138,6 +158,203 @@ fn fill_job(job: &Arc<Job>, mut builder: control_capnp::job::Builder<'_>) {
     }
 }
 
+/// Convert a job history entry to capnp.
+pub(crate) fn fill_job_history_entry(
+    arena: Arena,
+    source: &JobHistoryTableEntry,
+    mut dest: control_capnp::job_history_entry::Builder<'_>,
+) {
+    dest.set_arena(arena.as_str());
+    dest.set_path(source.path.as_str());
+    dest.set_hash(&source.hash.0);
+    dest.set_job_type(to_capnp_job_type(source.kind.into()));
+    dest.set_start_secs(source.start.as_secs());
+    dest.set_end_secs(source.end.as_secs());
+    dest.set_bytes(source.bytes);
+    fill_progress(
+        &JobProgress::from_outcome(source.outcome, source.error.clone()),
+        dest.init_progress(),
+    );
+}
+
+pub(crate) fn fill_planned_job(
+    source: &PlannedJob,
+    mut dest: control_capnp::planned_job::Builder<'_>,
+) {
+    dest.set_arena(source.arena.as_str());
+    dest.set_path(source.job.path().as_str());
+    dest.set_hash(&source.job.hash().0);
+    dest.set_job_type(to_capnp_job_type(JobType::of(&source.job)));
+    dest.set_size(source.size.unwrap_or(0));
+    dest.set_local_bytes(source.local_bytes);
+    let mut peers = dest.init_peers(source.peers.len() as u32);
+    for (i, peer) in source.peers.iter().enumerate() {
+        peers.set(i as u32, peer.as_str());
+    }
+    dest.set_backoff_until_secs(source.backoff_until.map(|t| t.as_secs()).unwrap_or(0));
+}
+
+pub(crate) fn fill_peer_info(source: &PeerInfo, mut dest: control_capnp::peer_info::Builder<'_>) {
+    dest.set_peer(source.status.peer().as_str());
+    dest.set_status(match source.status {
+        PeerStatus::Disconnected(_) => control_capnp::peer_info::Status::Disconnected,
+        PeerStatus::Connected(_) => control_capnp::peer_info::Status::Connected,
+        PeerStatus::Registered(_) => control_capnp::peer_info::Status::Registered,
+    });
+    if let Some(addr) = &source.address {
+        dest.set_address(addr.to_string());
+    }
+    dest.set_connected_since_secs(source.connected_since.map(|t| t.as_secs()).unwrap_or(0));
+    dest.set_incoming(source.incoming);
+    dest.set_bytes_sent(source.bytes_sent);
+    dest.set_bytes_received(source.bytes_received);
+    dest.set_send_rate(source.send_rate);
+    dest.set_receive_rate(source.receive_rate);
+    if let Some(err) = &source.last_error {
+        dest.set_last_error(err.as_str());
+    }
+    dest.set_failed_attempts(source.failed_attempts);
+    dest.set_next_attempt_secs(source.next_attempt.map(|t| t.as_secs()).unwrap_or(0));
+    dest.set_upload_limit(source.limit.upload_bytes_per_second.unwrap_or(0));
+    dest.set_download_limit(source.limit.download_bytes_per_second.unwrap_or(0));
+}
+
+pub(crate) fn parse_peer_info(
+    reader: control_capnp::peer_info::Reader<'_>,
+) -> Result<PeerInfo, capnp::Error> {
+    let peer = Peer::from(reader.get_peer()?.to_str()?);
+    let address = reader.get_address()?.to_str()?;
+    let last_error = reader.get_last_error()?.to_str()?;
+    let secs = |secs: u64| {
+        if secs == 0 {
+            None
+        } else {
+            Some(UnixTime::from_secs(secs))
+        }
+    };
+    let limit = |bytes_per_second: u64| {
+        if bytes_per_second == 0 {
+            None
+        } else {
+            Some(bytes_per_second)
+        }
+    };
+
+    Ok(PeerInfo {
+        status: match reader.get_status()? {
+            control_capnp::peer_info::Status::Disconnected => PeerStatus::Disconnected(peer),
+            control_capnp::peer_info::Status::Connected => PeerStatus::Connected(peer),
+            control_capnp::peer_info::Status::Registered => PeerStatus::Registered(peer),
+        },
+        address: if address.is_empty() {
+            None
+        } else {
+            Some(
+                address
+                    .parse()
+                    .map_err(|_| capnp::Error::failed(format!("invalid address: {address}")))?,
+            )
+        },
+        connected_since: secs(reader.get_connected_since_secs()),
+        incoming: reader.get_incoming(),
+        bytes_sent: reader.get_bytes_sent(),
+        bytes_received: reader.get_bytes_received(),
+        send_rate: reader.get_send_rate(),
+        receive_rate: reader.get_receive_rate(),
+        last_error: if last_error.is_empty() {
+            None
+        } else {
+            Some(last_error.to_string())
+        },
+        failed_attempts: reader.get_failed_attempts(),
+        next_attempt: secs(reader.get_next_attempt_secs()),
+        limit: BandwidthLimit {
+            upload_bytes_per_second: limit(reader.get_upload_limit()),
+            download_bytes_per_second: limit(reader.get_download_limit()),
+        },
+    })
+}
+
+pub(crate) fn fill_pending_key(
+    source: &PendingKey,
+    mut dest: control_capnp::pending_key::Builder<'_>,
+) {
+    dest.set_peer(source.peer.as_str());
+    dest.set_pubkey(source.pubkey.as_slice());
+    dest.set_received_secs(source.received.as_secs());
+}
+
+pub(crate) fn parse_pending_key(
+    reader: control_capnp::pending_key::Reader<'_>,
+) -> Result<PendingKey, capnp::Error> {
+    Ok(PendingKey {
+        peer: Peer::from(reader.get_peer()?.to_str()?),
+        pubkey: reader.get_pubkey()?.to_vec(),
+        received: UnixTime::from_secs(reader.get_received_secs()),
+    })
+}
+
+/// Convert a capnp job history request to an arena and a filter.
+pub(crate) fn parse_job_history_request(
+    reader: control_capnp::job_history_request::Reader<'_>,
+) -> Result<(Option<Arena>, JobHistoryFilter), capnp::Error> {
+    let arena = reader.get_arena()?.to_str()?;
+    let path_prefix = reader.get_path_prefix()?.to_str()?;
+    let secs = |secs: u64| {
+        if secs == 0 {
+            None
+        } else {
+            Some(UnixTime::from_secs(secs))
+        }
+    };
+
+    Ok((
+        if arena.is_empty() {
+            None
+        } else {
+            Some(Arena::from(arena))
+        },
+        JobHistoryFilter {
+            path_prefix: if path_prefix.is_empty() {
+                None
+            } else {
+                Some(Path::parse(path_prefix).map_err(|e| capnp::Error::failed(e.to_string()))?)
+            },
+            outcome: match reader.get_outcome()? {
+                control_capnp::job_history_request::Outcome::Any => None,
+                control_capnp::job_history_request::Outcome::Done => Some(JobOutcome::Done),
+                control_capnp::job_history_request::Outcome::Abandoned => {
+                    Some(JobOutcome::Abandoned)
+                }
+                control_capnp::job_history_request::Outcome::Cancelled => {
+                    Some(JobOutcome::Cancelled)
+                }
+                control_capnp::job_history_request::Outcome::Failed => Some(JobOutcome::Failed),
+            },
+            since: secs(reader.get_since_secs()),
+            until: secs(reader.get_until_secs()),
+            limit: match reader.get_limit() {
+                0 => None,
+                limit => Some(limit as usize),
+            },
+        },
+    ))
+}
+
+fn to_capnp_job_type(job_type: JobType) -> control_capnp::JobType {
+    match job_type {
+        JobType::Realize => control_capnp::JobType::Realize,
+        JobType::Unrealize => control_capnp::JobType::Unrealize,
+        JobType::Download => control_capnp::JobType::Download,
+    }
+}
+
+fn fill_priority(source: &JobPriority, mut dest: control_capnp::job_priority::Builder<'_>) {
+    dest.set_job_type(to_capnp_job_type(source.job_type));
+    dest.set_recently_accessed(source.recently_accessed);
+    dest.set_size(source.size);
+}
+
 fn fill_progress(
     source: &JobProgress,
     mut progress_builder: control_capnp::job_progress::Builder<'_>,
@@ -146,6 +363,10 @@ fn fill_progress(
         JobProgress::Pending => {
             progress_builder.set_type(control_capnp::job_progress::Type::Pending);
         }
+        JobProgress::Blocked(msg) => {
+            progress_builder.set_type(control_capnp::job_progress::Type::Blocked);
+            progress_builder.set_message(msg);
+        }
         JobProgress::Running => {
             progress_builder.set_type(control_capnp::job_progress::Type::Running);
         }
@@ -187,6 +408,7 @@ pub(crate) fn parse_job_info(
     let progress_reader = reader.get_progress()?;
     let progress = parse_progress(progress_reader)?;
     let action = parse_action(reader.get_action()?)?;
+    let priority = parse_priority(reader.get_priority()?)?;
     let byte_progress = if reader.has_byte_progress() {
         let byte_progress_reader = reader.get_byte_progress()?;
         Some((
@@ -205,6 +427,7 @@ pub(crate) fn parse_job_info(
         action,
         byte_progress,
         notification_index,
+        priority,
     })
 }
 
@@ -230,6 +453,20 @@ fn parse_job(job_reader: control_capnp::job::Reader<'_>) -> Result<Job, capnp::E
     }
 }
 
+fn parse_priority(
+    reader: control_capnp::job_priority::Reader<'_>,
+) -> Result<JobPriority, capnp::Error> {
+    Ok(JobPriority {
+        job_type: match reader.get_job_type()? {
+            control_capnp::JobType::Realize => JobType::Realize,
+            control_capnp::JobType::Unrealize => JobType::Unrealize,
+            control_capnp::JobType::Download => JobType::Download,
+        },
+        recently_accessed: reader.get_recently_accessed(),
+        size: reader.get_size(),
+    })
+}
+
 fn parse_progress(
     progress_reader: control_capnp::job_progress::Reader<'_>,
 ) -> Result<JobProgress, capnp::Error> {
@@ -243,6 +480,10 @@ fn parse_progress(
             let message = progress_reader.get_message()?.to_str()?.to_string();
             Ok(JobProgress::Failed(message))
         }
+        control_capnp::job_progress::Type::Blocked => {
+            let message = progress_reader.get_message()?.to_str()?.to_string();
+            Ok(JobProgress::Blocked(message))
+        }
     }
 }
 
@@ -288,6 +529,7 @@ mod tests {
         ChurtenNotification::New {
             arena,
             job_id,
+            priority: JobPriority::new(&job, 100),
             job: std::sync::Arc::new(job),
         }
     }
@@ -349,6 +591,7 @@ mod tests {
         ChurtenNotification::New {
             arena,
             job_id,
+            priority: JobPriority::new(&job, 100),
             job: std::sync::Arc::new(job),
         }
     }
@@ -363,6 +606,7 @@ mod tests {
         ChurtenNotification::New {
             arena,
             job_id,
+            priority: JobPriority::new(&job, 100),
             job: std::sync::Arc::new(job),
         }
     }
@@ -399,6 +643,23 @@ mod tests {
         round_trip_test(create_test_unrealize_notification());
     }
 
+    #[test]
+    fn test_parse_pause_notification() {
+        round_trip_test(ChurtenNotification::Pause {
+            arena: Arena::from("test-arena"),
+            job_id: JobId(123),
+        });
+    }
+
+    #[test]
+    fn test_parse_block_notification() {
+        round_trip_test(ChurtenNotification::Block {
+            arena: Arena::from("test-arena"),
+            job_id: JobId(1),
+            reason: "Not enough space".to_string(),
+        });
+    }
+
     #[test]
     fn test_parse_update_notification() {
         round_trip_test(create_test_update_notification());
@@ -497,6 +758,7 @@ mod tests {
         JobInfo {
             arena,
             id,
+            priority: JobPriority::new(&job, 100),
             job: std::sync::Arc::new(job),
             progress: JobProgress::Running,
             action: Some(JobAction::Download),
@@ -505,6 +767,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_new_notification_recently_accessed() {
+        let job = Job::Download(Path::parse("test/file.txt").unwrap(), Hash([0x42; 32]));
+        let mut priority = JobPriority::new(&job, u64::MAX);
+        priority.recently_accessed = true;
+
+        round_trip_test(ChurtenNotification::New {
+            arena: Arena::from("test-arena"),
+            job_id: JobId(123),
+            job: std::sync::Arc::new(job),
+            priority,
+        });
+    }
+
     fn create_test_realize_job_info() -> JobInfo {
         let arena = Arena::from("test-arena");
         let id = JobId(456);
@@ -515,6 +791,7 @@ mod tests {
         JobInfo {
             arena,
             id,
+            priority: JobPriority::new(&job, 100),
             job: std::sync::Arc::new(job),
             progress: JobProgress::Done,
             action: Some(JobAction::Verify),
@@ -533,6 +810,7 @@ mod tests {
         JobInfo {
             arena,
             id,
+            priority: JobPriority::new(&job, 100),
             job: std::sync::Arc::new(job),
             progress: JobProgress::Failed("Test error".to_string()),
             action: None,
@@ -583,6 +861,7 @@ mod tests {
 
         for progress in [
             JobProgress::Pending,
+            JobProgress::Blocked("Not enough space".to_string()),
             JobProgress::Running,
             JobProgress::Done,
             JobProgress::Abandoned,
@@ -593,6 +872,7 @@ mod tests {
                 arena,
                 id,
                 job: std::sync::Arc::new(job.clone()),
+                priority: JobPriority::new(&job, 100),
                 progress,
                 action: Some(JobAction::Download),
                 byte_progress: Some((42, 100)),
@@ -620,6 +900,7 @@ mod tests {
                 arena,
                 id,
                 job: std::sync::Arc::new(job.clone()),
+                priority: JobPriority::new(&job, 100),
                 progress: JobProgress::Running,
                 action: Some(action),
                 byte_progress: Some((42, 100)),
@@ -640,6 +921,7 @@ mod tests {
         let job_info = JobInfo {
             arena,
             id,
+            priority: JobPriority::new(&job, 100),
             job: std::sync::Arc::new(job),
             progress: JobProgress::Pending,
             action: None,
@@ -648,4 +930,96 @@ mod tests {
         };
         job_info_round_trip_test(job_info);
     }
+
+    #[test]
+    fn test_parse_job_history_request() -> anyhow::Result<()> {
+        let mut message = Builder::new_default();
+        let mut builder = message.init_root::<control_capnp::job_history_request::Builder>();
+        builder.set_arena("myarena");
+        builder.set_path_prefix("foo/bar");
+        builder.set_outcome(control_capnp::job_history_request::Outcome::Failed);
+        builder.set_since_secs(1000);
+        builder.set_limit(10);
+
+        let msg_reader = message.into_reader();
+        let reader = msg_reader.get_root::<control_capnp::job_history_request::Reader>()?;
+        assert_eq!(
+            (
+                Some(Arena::from("myarena")),
+                JobHistoryFilter {
+                    path_prefix: Some(Path::parse("foo/bar")?),
+                    outcome: Some(JobOutcome::Failed),
+                    since: Some(UnixTime::from_secs(1000)),
+                    until: None,
+                    limit: Some(10),
+                }
+            ),
+            parse_job_history_request(reader)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_empty_job_history_request() -> anyhow::Result<()> {
+        let mut message = Builder::new_default();
+        message.init_root::<control_capnp::job_history_request::Builder>();
+
+        let msg_reader = message.into_reader();
+        let reader = msg_reader.get_root::<control_capnp::job_history_request::Reader>()?;
+        assert_eq!(
+            (None, JobHistoryFilter::default()),
+            parse_job_history_request(reader)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_peer_info_round_trip() -> anyhow::Result<()> {
+        let connected = PeerInfo {
+            status: PeerStatus::Registered(Peer::from("a")),
+            address: Some("192.168.1.2:7000".parse()?),
+            connected_since: Some(UnixTime::from_secs(1000)),
+            incoming: true,
+            bytes_sent: 100,
+            bytes_received: 200,
+            send_rate: 10,
+            receive_rate: 20,
+            last_error: Some("connection lost".to_string()),
+            failed_attempts: 0,
+            next_attempt: None,
+            limit: BandwidthLimit {
+                upload_bytes_per_second: Some(1024),
+                download_bytes_per_second: None,
+            },
+        };
+        let disconnected = PeerInfo {
+            status: PeerStatus::Disconnected(Peer::from("b")),
+            address: None,
+            connected_since: None,
+            incoming: false,
+            bytes_sent: 0,
+            bytes_received: 0,
+            send_rate: 0,
+            receive_rate: 0,
+            last_error: None,
+            failed_attempts: 3,
+            next_attempt: Some(UnixTime::from_secs(2000)),
+            limit: BandwidthLimit::UNLIMITED,
+        };
+        for info in [connected, disconnected] {
+            let mut message = Builder::new_default();
+            fill_peer_info(
+                &info,
+                message.init_root::<control_capnp::peer_info::Builder>(),
+            );
+
+            let msg_reader = message.into_reader();
+            let reader = msg_reader.get_root::<control_capnp::peer_info::Reader>()?;
+            assert_eq!(info, parse_peer_info(reader)?);
+        }
+
+        Ok(())
+    }
 }
@@ -2,33 +2,59 @@
 
 use super::control_capnp;
 use super::control_capnp::churten::{
-    self, IsRunningParams, IsRunningResults, RecentJobsParams, RecentJobsResults, ShutdownParams,
-    ShutdownResults, StartParams, StartResults, SubscribeParams, SubscribeResults,
+    self, CancelJobParams, CancelJobResults, IsRunningParams, IsRunningResults, RecentJobsParams,
+    RecentJobsResults, SetBandwidthLimitParams, SetBandwidthLimitResults, ShutdownParams,
+    ShutdownResults, StartParams, StartResults, StatusParams, StatusResults, SubscribeParams,
+    SubscribeResults,
 };
 use super::control_capnp::control::{
-    self, ChurtenParams, ChurtenResults, GetMarkParams, GetMarkResults, SetArenaMarkParams,
-    SetArenaMarkResults, SetMarkParams, SetMarkResults,
+    self, AddMarkRuleParams, AddMarkRuleResults, ApproveKeyParams, ApproveKeyResults,
+    ChurtenParams, ChurtenResults, ClearMarkParams, ClearMarkResults, CorruptionsParams,
+    CorruptionsResults, ExplainMarkParams, ExplainMarkResults, FailedJobsParams, FailedJobsResults,
+    GetMarkParams, GetMarkResults, IgnorePathParams, IgnorePathResults, InviteParams,
+    InviteResults, JobHistoryParams, JobHistoryResults, JobPlanParams, JobPlanResults, JoinParams,
+    JoinResults, ListMarksParams, ListMarksResults, MarkImpactParams, MarkImpactResults,
+    MarkRulesParams, MarkRulesResults, PeersParams, PeersResults, PendingKeysParams,
+    PendingKeysResults, RemoveMarkRuleParams, RemoveMarkRuleResults, RetryJobParams,
+    RetryJobResults, RotateKeyParams, RotateKeyResults, SetArenaMarkParams, SetArenaMarkResults,
+    SetMarkParams, SetMarkResults, SetPeerLimitParams, SetPeerLimitResults, WatchPeersParams,
+    WatchPeersResults,
 };
 use super::convert;
 use crate::consensus::churten::{Churten, JobHandler};
+use crate::consensus::diskspace;
+use crate::rpc::Household;
 use capnp::capability::Promise;
-use realize_storage::{Mark, Storage, StorageError};
-use realize_types::{Arena, Hash, Path};
+use capnp_rpc::pry;
+use realize_network::config::{BandwidthLimit, PeerLimits};
+use realize_network::pairing::Invitation;
+use realize_network::security::KeyRotation;
+use realize_storage::{
+    CorruptionLocation, JobId, Mark, MarkRule, MarkSource, Storage, StorageError,
+};
+use realize_types::{Arena, Hash, Path, Peer};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub(crate) struct ControlServer<H: JobHandler + 'static> {
     storage: Arc<Storage>,
     churten: Rc<RefCell<Churten<H>>>,
+    household: Household,
 }
 
 impl<H: JobHandler + 'static> ControlServer<H> {
-    pub(crate) fn new(storage: Arc<Storage>, churten: Churten<H>) -> ControlServer<H> {
+    pub(crate) fn new(
+        storage: Arc<Storage>,
+        churten: Churten<H>,
+        household: Household,
+    ) -> ControlServer<H> {
         Self {
             storage,
             churten: Rc::new(RefCell::new(churten)),
+            household,
         }
     }
 
@@ -110,6 +136,532 @@ impl<H: JobHandler + 'static> control::Server for ControlServer<H> {
             Ok(())
         })
     }
+
+    fn corruptions(
+        &mut self,
+        _: CorruptionsParams,
+        mut results: CorruptionsResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let corruptions = storage.corruptions().await.map_err(from_storage_err)?;
+
+            let mut list = results.get().init_res(corruptions.len() as u32);
+            for (i, (arena, path, entry)) in corruptions.into_iter().enumerate() {
+                let mut builder = list.reborrow().get(i as u32);
+                builder.set_arena(arena.as_str());
+                builder.set_path(path.as_str());
+                builder.set_location(match entry.location {
+                    CorruptionLocation::Index => control_capnp::corruption::Location::Index,
+                    CorruptionLocation::Blob => control_capnp::corruption::Location::Blob,
+                });
+                builder.set_expected(&entry.expected.0);
+                builder.set_actual(&entry.actual.0);
+                builder.set_detected_secs(entry.detected.as_secs());
+            }
+
+            Ok(())
+        })
+    }
+
+    fn job_history(
+        &mut self,
+        params: JobHistoryParams,
+        mut results: JobHistoryResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let (arena, filter) = convert::parse_job_history_request(params.get()?.get_req()?)?;
+            let history = storage
+                .job_history(arena, filter)
+                .await
+                .map_err(from_storage_err)?;
+
+            let mut list = results.get().init_res(history.len() as u32);
+            for (i, (arena, entry)) in history.iter().enumerate() {
+                convert::fill_job_history_entry(*arena, entry, list.reborrow().get(i as u32));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn job_plan(
+        &mut self,
+        params: JobPlanParams,
+        mut results: JobPlanResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = params.get_arena()?.to_str()?;
+            let arena = if arena.is_empty() {
+                None
+            } else {
+                Some(Arena::from(arena))
+            };
+            let path_prefix = params.get_path_prefix()?.to_str()?;
+            let path_prefix = if path_prefix.is_empty() {
+                None
+            } else {
+                Some(Path::parse(path_prefix).map_err(|e| capnp::Error::failed(e.to_string()))?)
+            };
+            let plan = storage
+                .job_plan(arena, path_prefix)
+                .await
+                .map_err(from_storage_err)?;
+
+            let mut list = results.get().init_res(plan.len() as u32);
+            for (i, planned) in plan.iter().enumerate() {
+                convert::fill_planned_job(planned, list.reborrow().get(i as u32));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn failed_jobs(
+        &mut self,
+        params: FailedJobsParams,
+        mut results: FailedJobsResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let arena = params.get()?.get_arena()?.to_str()?;
+            let arena = if arena.is_empty() {
+                None
+            } else {
+                Some(Arena::from(arena))
+            };
+            let failed = storage.failed_jobs(arena).await.map_err(from_storage_err)?;
+
+            let mut list = results.get().init_res(failed.len() as u32);
+            for (i, (arena, job_id, path, entry)) in failed.into_iter().enumerate() {
+                let mut builder = list.reborrow().get(i as u32);
+                builder.set_arena(arena.as_str());
+                builder.set_job_id(job_id.as_u64());
+                builder.set_path(path.as_str());
+                builder.set_failure_count(entry.failure_count);
+                builder.set_backoff_until_secs(entry.backoff_until.as_secs());
+            }
+
+            Ok(())
+        })
+    }
+
+    fn retry_job(
+        &mut self,
+        params: RetryJobParams,
+        mut results: RetryJobResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = parse_arena(params.get_arena()?)?;
+            let job_id = JobId(params.get_job_id());
+
+            let retried = storage
+                .retry_job(arena, job_id)
+                .await
+                .map_err(from_storage_err)?;
+            results.get().set_retried(retried);
+
+            Ok(())
+        })
+    }
+
+    fn ignore_path(
+        &mut self,
+        params: IgnorePathParams,
+        mut results: IgnorePathResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = parse_arena(params.get_arena()?)?;
+            let path = parse_path(params.get_path()?)?;
+
+            let ignored = storage
+                .ignore_path(arena, &path)
+                .await
+                .map_err(from_storage_err)?;
+            results.get().set_ignored(ignored.is_some());
+
+            Ok(())
+        })
+    }
+
+    fn add_mark_rule(
+        &mut self,
+        params: AddMarkRuleParams,
+        mut results: AddMarkRuleResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = parse_arena(params.get_arena()?)?;
+            let (rule, mark) = parse_mark_rule(params.get_rule()?)?;
+
+            let id = storage
+                .add_mark_rule(arena, rule, mark)
+                .await
+                .map_err(from_storage_err)?;
+            results.get().set_id(id);
+
+            Ok(())
+        })
+    }
+
+    fn mark_rules(
+        &mut self,
+        params: MarkRulesParams,
+        mut results: MarkRulesResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let arena = parse_arena(params.get()?.get_arena()?)?;
+            let rules = storage.mark_rules(arena).await.map_err(from_storage_err)?;
+
+            let mut list = results.get().init_res(rules.len() as u32);
+            for (i, (id, rule, mark)) in rules.into_iter().enumerate() {
+                fill_mark_rule(id, &rule, mark, list.reborrow().get(i as u32));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn remove_mark_rule(
+        &mut self,
+        params: RemoveMarkRuleParams,
+        mut results: RemoveMarkRuleResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = parse_arena(params.get_arena()?)?;
+
+            let removed = storage
+                .remove_mark_rule(arena, params.get_id())
+                .await
+                .map_err(from_storage_err)?;
+            results.get().set_removed(removed);
+
+            Ok(())
+        })
+    }
+
+    fn clear_mark(
+        &mut self,
+        params: ClearMarkParams,
+        _: ClearMarkResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = parse_arena(params.get_arena()?)?;
+            let path = parse_path(params.get_path()?)?;
+
+            storage
+                .clear_mark(arena, &path)
+                .await
+                .map_err(from_storage_err)?;
+
+            Ok(())
+        })
+    }
+
+    fn list_marks(
+        &mut self,
+        params: ListMarksParams,
+        mut results: ListMarksResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let arena = parse_arena(params.get()?.get_arena()?)?;
+            let marks = storage.list_marks(arena).await.map_err(from_storage_err)?;
+
+            let mut list = results.get().init_res(marks.len() as u32);
+            for (i, (path, mark)) in marks.into_iter().enumerate() {
+                let mut builder = list.reborrow().get(i as u32);
+                builder.set_path(path.as_ref().map(|p| p.as_str()).unwrap_or(""));
+                builder.set_mark(mark_to_capnp(mark));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn mark_impact(
+        &mut self,
+        params: MarkImpactParams,
+        mut results: MarkImpactResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = parse_arena(params.get_arena()?)?;
+            let path = params.get_path()?.to_str()?;
+            let path = if path.is_empty() {
+                None
+            } else {
+                Some(Path::parse(path).map_err(|e| capnp::Error::failed(e.to_string()))?)
+            };
+            let mark = parse_mark(params.get_mark()?);
+
+            let impact = storage
+                .mark_impact(arena, path.as_ref(), mark)
+                .await
+                .map_err(from_storage_err)?;
+            let warnings = diskspace::mark_impact_warnings(&storage, arena, &impact);
+
+            let mut res = results.get().init_res();
+            res.set_download_files(impact.download_files);
+            res.set_download_bytes(impact.download_bytes);
+            res.set_realize_files(impact.realize_files);
+            res.set_realize_bytes(impact.realize_bytes);
+            res.set_unrealize_files(impact.unrealize_files);
+            res.set_unrealize_bytes(impact.unrealize_bytes);
+            let mut list = res.init_warnings(warnings.len() as u32);
+            for (i, warning) in warnings.iter().enumerate() {
+                list.set(i as u32, warning.as_str());
+            }
+
+            Ok(())
+        })
+    }
+
+    fn explain_mark(
+        &mut self,
+        params: ExplainMarkParams,
+        mut results: ExplainMarkResults,
+    ) -> Promise<(), capnp::Error> {
+        let storage = Arc::clone(&self.storage);
+        Promise::from_future(async move {
+            let params = params.get()?;
+            let arena = parse_arena(params.get_arena()?)?;
+            let path = parse_path(params.get_path()?)?;
+
+            let (mark, source) = storage
+                .explain_mark(arena, &path)
+                .await
+                .map_err(from_storage_err)?;
+
+            let mut res = results.get().init_res();
+            res.set_mark(mark_to_capnp(mark));
+            let mut source_builder = res.init_source();
+            match source {
+                MarkSource::Path(path) => source_builder.set_path(path.as_str()),
+                MarkSource::Rule(id, rule) => {
+                    fill_mark_rule(id, &rule, mark, source_builder.init_rule())
+                }
+                MarkSource::Arena => source_builder.set_arena(()),
+                MarkSource::Default => source_builder.set_default(()),
+            }
+
+            Ok(())
+        })
+    }
+
+    fn peers(&mut self, _: PeersParams, mut results: PeersResults) -> Promise<(), capnp::Error> {
+        let household = self.household.clone();
+        Promise::from_future(async move {
+            let peers = household
+                .peers()
+                .await
+                .map_err(|err| capnp::Error::failed(err.to_string()))?;
+
+            let mut list = results.get().init_res(peers.len() as u32);
+            for (i, info) in peers.iter().enumerate() {
+                convert::fill_peer_info(info, list.reborrow().get(i as u32));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn set_peer_limit(
+        &mut self,
+        params: SetPeerLimitParams,
+        _: SetPeerLimitResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let peer = Peer::from(pry!(pry!(params.get_peer()).to_str()));
+        let limit = |bytes_per_second: u64| {
+            if bytes_per_second == 0 {
+                None
+            } else {
+                Some(bytes_per_second)
+            }
+        };
+        let limits = PeerLimits {
+            default: BandwidthLimit {
+                upload_bytes_per_second: limit(params.get_upload_bytes_per_second()),
+                download_bytes_per_second: limit(params.get_download_bytes_per_second()),
+            },
+            schedule: vec![],
+        };
+        pry!(
+            self.household
+                .set_peer_limits(peer, limits)
+                .map_err(|err| capnp::Error::failed(err.to_string()))
+        );
+
+        Promise::ok(())
+    }
+
+    fn rotate_key(
+        &mut self,
+        params: RotateKeyParams,
+        mut results: RotateKeyResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let rotation = KeyRotation {
+            pubkey: pry!(params.get_pubkey()).to_vec(),
+            signature: pry!(params.get_signature()).to_vec(),
+        };
+        if !rotation.verify() {
+            return Promise::err(capnp::Error::failed(
+                "invalid key rotation signature".to_string(),
+            ));
+        }
+        let household = self.household.clone();
+        Promise::from_future(async move {
+            let peers = household
+                .propose_key(rotation)
+                .await
+                .map_err(|err| capnp::Error::failed(err.to_string()))?;
+
+            let mut list = results.get().init_peers(peers.len() as u32);
+            for (i, peer) in peers.iter().enumerate() {
+                list.set(i as u32, peer.as_str());
+            }
+
+            Ok(())
+        })
+    }
+
+    fn pending_keys(
+        &mut self,
+        _: PendingKeysParams,
+        mut results: PendingKeysResults,
+    ) -> Promise<(), capnp::Error> {
+        let pending = self.household.pending_keys();
+        let mut list = results.get().init_res(pending.len() as u32);
+        for (i, key) in pending.iter().enumerate() {
+            convert::fill_pending_key(key, list.reborrow().get(i as u32));
+        }
+
+        Promise::ok(())
+    }
+
+    fn approve_key(
+        &mut self,
+        params: ApproveKeyParams,
+        mut results: ApproveKeyResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let peer = Peer::from(pry!(pry!(params.get_peer()).to_str()));
+        let pubkey = pry!(params.get_pubkey());
+        results
+            .get()
+            .set_approved(self.household.approve_key(peer, pubkey));
+
+        Promise::ok(())
+    }
+
+    fn invite(
+        &mut self,
+        params: InviteParams,
+        mut results: InviteResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let address = pry!(pry!(params.get_address()).to_str());
+        let ttl = Duration::from_secs(params.get_ttl_secs());
+        let invitation = pry!(
+            self.household
+                .invite(address, ttl)
+                .map_err(|err| capnp::Error::failed(err.to_string()))
+        );
+        let token = pry!(
+            invitation
+                .encode()
+                .map_err(|err| capnp::Error::failed(err.to_string()))
+        );
+        results.get().set_token(token);
+
+        Promise::ok(())
+    }
+
+    fn join(&mut self, params: JoinParams, mut results: JoinResults) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let invitation = pry!(
+            Invitation::decode(pry!(pry!(params.get_token()).to_str()))
+                .map_err(|err| capnp::Error::failed(err.to_string()))
+        );
+        let peer = pry!(pry!(params.get_peer()).to_str());
+        let peer = if peer.is_empty() {
+            None
+        } else {
+            Some(Peer::from(peer))
+        };
+        let address = pry!(pry!(params.get_address()).to_str());
+        let address = if address.is_empty() {
+            None
+        } else {
+            Some(address.to_string())
+        };
+        let household = self.household.clone();
+        Promise::from_future(async move {
+            let paired = household
+                .join(&invitation, peer, address)
+                .await
+                .map_err(|err| capnp::Error::failed(err.to_string()))?;
+            results.get().set_peer(paired.peer.as_str());
+
+            Ok(())
+        })
+    }
+
+    fn watch_peers(
+        &mut self,
+        params: WatchPeersParams,
+        _: WatchPeersResults,
+    ) -> Promise<(), capnp::Error> {
+        let household = self.household.clone();
+        let mut rx = household.peer_status();
+
+        Promise::from_future(async move {
+            let subscriber = params.get()?.get_subscriber()?;
+
+            // First send the current state of the peers.
+            send_peers(&household, &subscriber).await?;
+
+            // Send it again every time the status of a peer changes,
+            // in the background.
+            //
+            // This task remains as long as sending to the subscriber
+            // succeeds and the channel hasn't been closed.
+            tokio::task::spawn_local(async move {
+                use tokio::sync::broadcast::error::RecvError;
+
+                loop {
+                    match rx.recv().await {
+                        Ok(_) | Err(RecvError::Lagged(_)) => {
+                            if send_peers(&household, &subscriber).await.is_err() {
+                                return;
+                            }
+                        }
+                        Err(RecvError::Closed) => {
+                            return;
+                        }
+                    }
+                }
+            });
+
+            Ok::<_, capnp::Error>(())
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -213,18 +765,86 @@ impl<H: JobHandler + 'static> churten::Server for ChurtenServer<H> {
             Ok(())
         })
     }
-}
 
-async fn send_active_jobs<H: JobHandler + 'static>(
-    churten: &Rc<RefCell<Churten<H>>>,
-    subscriber: &control_capnp::churten::subscriber::Client,
-) -> Result<(), capnp::Error> {
-    let jobs = churten.borrow().active_jobs().await;
+    fn status(&mut self, _: StatusParams, mut results: StatusResults) -> Promise<(), capnp::Error> {
+        let churten = self.churten.borrow();
+        let schedules = churten.download_schedules();
+
+        let mut res = results.get().init_res();
+        res.set_running(churten.is_running());
+        let mut list = res.init_download_schedules(schedules.len() as u32);
+        for (i, (arena, state)) in schedules.into_iter().enumerate() {
+            let mut builder = list.reborrow().get(i as u32);
+            builder.set_arena(arena.map(|a| a.as_str()).unwrap_or(""));
+            builder.set_open(state.open);
+            builder.set_next_change_secs(state.next_change.map(|t| t.as_secs()).unwrap_or(0));
+        }
+        res.set_bandwidth_limit(churten.max_bytes_per_second().unwrap_or(0));
 
-    let mut request = subscriber.reset_request();
-    let mut builder = request.get().init_jobs(jobs.len() as u32);
-    for (i, job_info) in jobs.into_iter().enumerate() {
-        convert::fill_job_info(&job_info, builder.reborrow().get(i as u32));
+        Promise::ok(())
+    }
+
+    fn set_bandwidth_limit(
+        &mut self,
+        params: SetBandwidthLimitParams,
+        _: SetBandwidthLimitResults,
+    ) -> Promise<(), capnp::Error> {
+        let bytes_per_second = pry!(params.get()).get_bytes_per_second();
+        self.churten
+            .borrow()
+            .set_max_bytes_per_second(if bytes_per_second == 0 {
+                None
+            } else {
+                Some(bytes_per_second)
+            });
+
+        Promise::ok(())
+    }
+
+    fn cancel_job(
+        &mut self,
+        params: CancelJobParams,
+        mut results: CancelJobResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let arena = pry!(parse_arena(pry!(params.get_arena())));
+        let job_id = JobId(params.get_job_id());
+        let cancelled = self.churten.borrow().cancel_job(arena, job_id);
+        results.get().set_cancelled(cancelled);
+
+        Promise::ok(())
+    }
+}
+
+async fn send_active_jobs<H: JobHandler + 'static>(
+    churten: &Rc<RefCell<Churten<H>>>,
+    subscriber: &control_capnp::churten::subscriber::Client,
+) -> Result<(), capnp::Error> {
+    let jobs = churten.borrow().active_jobs().await;
+
+    let mut request = subscriber.reset_request();
+    let mut builder = request.get().init_jobs(jobs.len() as u32);
+    for (i, job_info) in jobs.into_iter().enumerate() {
+        convert::fill_job_info(&job_info, builder.reborrow().get(i as u32));
+    }
+    request.send().await?;
+
+    Ok(())
+}
+
+async fn send_peers(
+    household: &Household,
+    subscriber: &control_capnp::peer_subscriber::Client,
+) -> Result<(), capnp::Error> {
+    let peers = household
+        .peers()
+        .await
+        .map_err(|err| capnp::Error::failed(err.to_string()))?;
+
+    let mut request = subscriber.update_request();
+    let mut list = request.get().init_peers(peers.len() as u32);
+    for (i, info) in peers.iter().enumerate() {
+        convert::fill_peer_info(info, list.reborrow().get(i as u32));
     }
     request.send().await?;
 
@@ -265,6 +885,36 @@ fn parse_mark(mark: control_capnp::Mark) -> Mark {
     }
 }
 
+fn parse_mark_rule(
+    reader: control_capnp::mark_rule::Reader<'_>,
+) -> Result<(MarkRule, Mark), capnp::Error> {
+    let pattern = reader.get_pattern()?.to_str()?;
+    let rule = MarkRule {
+        pattern: if pattern.is_empty() {
+            None
+        } else {
+            Some(pattern.to_string())
+        },
+        min_size: Some(reader.get_min_size()).filter(|s| *s > 0),
+        max_size: Some(reader.get_max_size()).filter(|s| *s > 0),
+    };
+
+    Ok((rule, parse_mark(reader.get_mark()?)))
+}
+
+fn fill_mark_rule(
+    id: u64,
+    rule: &MarkRule,
+    mark: Mark,
+    mut builder: control_capnp::mark_rule::Builder<'_>,
+) {
+    builder.set_id(id);
+    builder.set_pattern(rule.pattern.as_deref().unwrap_or(""));
+    builder.set_min_size(rule.min_size.unwrap_or(0));
+    builder.set_max_size(rule.max_size.unwrap_or(0));
+    builder.set_mark(mark_to_capnp(mark));
+}
+
 fn mark_to_capnp(mark: Mark) -> control_capnp::Mark {
     match mark {
         Mark::Own => control_capnp::Mark::Own,
@@ -278,12 +928,17 @@ mod tests {
     use super::*;
     use crate::consensus::churten::{JobHandler, JobHandlerImpl};
     use crate::consensus::progress::{ByteCountProgress, TxByteCountProgress};
-    use crate::consensus::types::{ChurtenNotification, JobAction, JobProgress};
+    use crate::consensus::types::{ChurtenNotification, JobAction, JobPriority, JobProgress};
     use crate::rpc::control::client::{self, ChurtenUpdates, TxChurtenSubscriber};
-    use crate::rpc::testing::HouseholdFixture;
+    use crate::rpc::testing::{self, HouseholdFixture};
     use assert_fs::TempDir;
+    use futures::StreamExt;
+    use realize_network::Server;
+    use realize_network::capnp::PeerStatus;
     use realize_network::unixsocket;
-    use realize_storage::{Job, JobId, JobStatus, Mark, Notification};
+    use realize_storage::{
+        Job, JobHistoryTableEntry, JobId, JobKind, JobOutcome, JobStatus, Mark, Notification,
+    };
     use realize_types::{Peer, UnixTime};
     use std::path::PathBuf;
     use std::time::Duration;
@@ -364,10 +1019,23 @@ mod tests {
             local: &LocalSet,
             peer: Peer,
             handler: H,
+        ) -> anyhow::Result<PathBuf> {
+            let household = self.inner.create_household(local, peer)?;
+
+            self.bind_server_with_household(local, peer, handler, household)
+                .await
+        }
+
+        async fn bind_server_with_household<H: JobHandler + 'static>(
+            &self,
+            local: &LocalSet,
+            peer: Peer,
+            handler: H,
+            household: Household,
         ) -> anyhow::Result<PathBuf> {
             let storage = self.inner.storage(peer)?;
             let churten = Churten::with_handler(Arc::clone(storage), handler);
-            let server = ControlServer::new(Arc::clone(storage), churten);
+            let server = ControlServer::new(Arc::clone(storage), churten, household);
 
             let sockpath = self
                 .tempdir
@@ -514,6 +1182,706 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn mark_rules() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let arena = HouseholdFixture::test_arena();
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let household = fixture.inner.create_household(&local, peer)?;
+        let storage = fixture.inner.storage(peer)?;
+        let sockpath = fixture
+            .bind_server(
+                &local,
+                peer,
+                JobHandlerImpl::new(Arc::clone(storage), household.clone()),
+            )
+            .await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                let mut request = control.add_mark_rule_request();
+                let mut params = request.get();
+                params.set_arena(arena.as_str());
+                let mut rule = params.init_rule();
+                rule.set_pattern("*.psd");
+                rule.set_mark(control_capnp::Mark::Own);
+                let id = request.send().promise.await?.get()?.get_id();
+
+                assert_eq!(
+                    Mark::Own,
+                    storage
+                        .get_mark(arena, &Path::parse("foo/bar.psd")?)
+                        .await?
+                );
+
+                let mut request = control.mark_rules_request();
+                request.get().set_arena(arena.as_str());
+                let reply = request.send().promise.await?;
+                let rules = reply.get()?.get_res()?;
+                assert_eq!(1, rules.len());
+                assert_eq!(id, rules.get(0).get_id());
+                assert_eq!("*.psd", rules.get(0).get_pattern()?.to_str()?);
+                assert_eq!(0, rules.get(0).get_max_size());
+                assert_eq!(control_capnp::Mark::Own, rules.get(0).get_mark()?);
+
+                let mut request = control.remove_mark_rule_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_id(id);
+                assert!(request.send().promise.await?.get()?.get_removed());
+                assert!(storage.mark_rules(arena).await?.is_empty());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_explain_and_clear_marks() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let arena = HouseholdFixture::test_arena();
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let household = fixture.inner.create_household(&local, peer)?;
+        let storage = fixture.inner.storage(peer)?;
+        let sockpath = fixture
+            .bind_server(
+                &local,
+                peer,
+                JobHandlerImpl::new(Arc::clone(storage), household.clone()),
+            )
+            .await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+                storage
+                    .set_mark(arena, &Path::parse("foo")?, Mark::Own)
+                    .await?;
+
+                let mut request = control.list_marks_request();
+                request.get().set_arena(arena.as_str());
+                let reply = request.send().promise.await?;
+                let marks = reply.get()?.get_res()?;
+                assert_eq!(1, marks.len());
+                assert_eq!("foo", marks.get(0).get_path()?.to_str()?);
+                assert_eq!(control_capnp::Mark::Own, marks.get(0).get_mark()?);
+
+                let mut request = control.explain_mark_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_path("foo/bar");
+                let reply = request.send().promise.await?;
+                let res = reply.get()?.get_res()?;
+                assert_eq!(control_capnp::Mark::Own, res.get_mark()?);
+                match res.get_source().which()? {
+                    control_capnp::mark_explanation::source::Path(path) => {
+                        assert_eq!("foo", path?.to_str()?);
+                    }
+                    _ => panic!("unexpected source"),
+                }
+
+                let mut request = control.clear_mark_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_path("foo");
+                request.send().promise.await?;
+                assert_eq!(
+                    Mark::Watch,
+                    storage.get_mark(arena, &Path::parse("foo/bar")?).await?
+                );
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_impact() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let arena = HouseholdFixture::test_arena();
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let household = fixture.inner.create_household(&local, peer)?;
+        let storage = fixture.inner.storage(peer)?;
+        let sockpath = fixture
+            .bind_server(
+                &local,
+                peer,
+                JobHandlerImpl::new(Arc::clone(storage), household.clone()),
+            )
+            .await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+                storage
+                    .update(
+                        HouseholdFixture::b(),
+                        realize_storage::Notification::Add {
+                            arena,
+                            index: 1,
+                            path: Path::parse("foo/bar")?,
+                            mtime: realize_types::UnixTime::from_secs(1234567890),
+                            size: 100,
+                            hash: Hash([1; 32]),
+                        },
+                    )
+                    .await?;
+
+                let mut request = control.mark_impact_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_path("foo");
+                request.get().set_mark(control_capnp::Mark::Keep);
+                let reply = request.send().promise.await?;
+                let res = reply.get()?.get_res()?;
+                assert_eq!(1, res.get_download_files());
+                assert_eq!(100, res.get_download_bytes());
+                assert_eq!(0, res.get_realize_files());
+                assert!(res.get_warnings()?.is_empty());
+
+                // The mark wasn't changed.
+                assert_eq!(
+                    Mark::Watch,
+                    storage.get_mark(arena, &Path::parse("foo/bar")?).await?
+                );
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn corruptions() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let household = fixture.inner.create_household(&local, peer)?;
+        let storage = fixture.inner.storage(peer)?;
+        let sockpath = fixture
+            .bind_server(
+                &local,
+                peer,
+                JobHandlerImpl::new(Arc::clone(storage), household.clone()),
+            )
+            .await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                let result = control.corruptions_request().send().promise.await?;
+                assert_eq!(0, result.get()?.get_res()?.len());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn job_history() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let arena = HouseholdFixture::test_arena();
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let storage = fixture.inner.storage(peer)?;
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture.bind_server(&local, peer, handler).await?;
+
+        for (path, outcome, error) in [
+            ("foo/a", JobOutcome::Done, None),
+            ("bar/b", JobOutcome::Failed, Some("no peers".to_string())),
+        ] {
+            storage
+                .record_job(
+                    arena,
+                    JobHistoryTableEntry {
+                        path: Path::parse(path)?,
+                        hash: Hash([1; 32]),
+                        kind: JobKind::Download,
+                        start: UnixTime::from_secs(1000),
+                        end: UnixTime::from_secs(1010),
+                        bytes: 42,
+                        outcome,
+                        error,
+                    },
+                )
+                .await?;
+        }
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                let result = control.job_history_request().send().promise.await?;
+                let res = result.get()?.get_res()?;
+                assert_eq!(2, res.len());
+                assert_eq!("bar/b", res.get(0).get_path()?.to_str()?);
+                assert_eq!("foo/a", res.get(1).get_path()?.to_str()?);
+
+                let mut request = control.job_history_request();
+                let mut req = request.get().init_req();
+                req.set_arena(arena.as_str());
+                req.set_outcome(control_capnp::job_history_request::Outcome::Failed);
+                let result = request.send().promise.await?;
+                let res = result.get()?.get_res()?;
+                assert_eq!(1, res.len());
+                let entry = res.get(0);
+                assert_eq!(arena.as_str(), entry.get_arena()?.to_str()?);
+                assert_eq!("bar/b", entry.get_path()?.to_str()?);
+                assert_eq!(control_capnp::JobType::Download, entry.get_job_type()?);
+                assert_eq!(1000, entry.get_start_secs());
+                assert_eq!(1010, entry.get_end_secs());
+                assert_eq!(42, entry.get_bytes());
+                let progress = entry.get_progress()?;
+                assert_eq!(
+                    control_capnp::job_progress::Type::Failed,
+                    progress.get_type()?
+                );
+                assert_eq!("no peers", progress.get_message()?.to_str()?);
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn job_plan() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let arena = HouseholdFixture::test_arena();
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let storage = fixture.inner.storage(peer)?;
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture.bind_server(&local, peer, handler).await?;
+
+        storage.set_arena_mark(arena, Mark::Keep).await?;
+        for path in ["foo/a", "bar/b"] {
+            fixture
+                .inner
+                .cache(peer)?
+                .update(
+                    Peer::from("other"),
+                    Notification::Add {
+                        arena,
+                        index: 1,
+                        path: Path::parse(path)?,
+                        mtime: UnixTime::from_secs(1234567890),
+                        size: 100,
+                        hash: Hash([1; 32]),
+                    },
+                )
+                .await?;
+        }
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                let result = control.job_plan_request().send().promise.await?;
+                let res = result.get()?.get_res()?;
+                assert_eq!(2, res.len());
+                assert_eq!("foo/a", res.get(0).get_path()?.to_str()?);
+                assert_eq!("bar/b", res.get(1).get_path()?.to_str()?);
+
+                let mut request = control.job_plan_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_path_prefix("foo");
+                let result = request.send().promise.await?;
+                let res = result.get()?.get_res()?;
+                assert_eq!(1, res.len());
+                let planned = res.get(0);
+                assert_eq!(arena.as_str(), planned.get_arena()?.to_str()?);
+                assert_eq!("foo/a", planned.get_path()?.to_str()?);
+                assert_eq!(control_capnp::JobType::Download, planned.get_job_type()?);
+                assert_eq!(100, planned.get_size());
+                assert_eq!(0, planned.get_local_bytes());
+                let peers = planned.get_peers()?;
+                assert_eq!(1, peers.len());
+                assert_eq!("other", peers.get(0)?.to_str()?);
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn churten_status() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture.bind_server(&local, peer, handler).await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+                let churten = control
+                    .churten_request()
+                    .send()
+                    .promise
+                    .await?
+                    .get()?
+                    .get_churten()?;
+
+                let result = churten.status_request().send().promise.await?;
+                let status = result.get()?.get_res()?;
+                assert!(!status.get_running());
+                let schedules = status.get_download_schedules()?;
+                assert_eq!(1, schedules.len());
+                let default = schedules.get(0);
+                assert_eq!("", default.get_arena()?.to_str()?);
+                assert!(default.get_open());
+                assert_eq!(0, default.get_next_change_secs());
+                assert_eq!(0, status.get_bandwidth_limit());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn peers() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let a = HouseholdFixture::a();
+        let b = HouseholdFixture::b();
+        let c = HouseholdFixture::c();
+        let addr_b = fixture.inner.pick_port(b)?;
+        let local = LocalSet::new();
+
+        let household_b = fixture.inner.create_household(&local, b)?;
+        let mut server_b = Server::new(fixture.inner.peers.networking(b)?);
+        household_b.register(&mut server_b);
+
+        let household_a = fixture.inner.create_household(&local, a)?;
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture
+            .bind_server_with_household(&local, a, handler, household_a.clone())
+            .await?;
+
+        local
+            .run_until(async move {
+                server_b.listen(&addr_b).await?;
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                let mut updates = client::watch_peers(&control).await?;
+                let initial = updates.recv().await.unwrap();
+                assert_eq!(
+                    vec![PeerStatus::Disconnected(b), PeerStatus::Disconnected(c)],
+                    initial.into_iter().map(|i| i.status).collect::<Vec<_>>()
+                );
+
+                testing::connect(&household_a, b).await?;
+
+                let peers = client::get_peers(&control).await?;
+                assert_eq!(
+                    vec![PeerStatus::Registered(b), PeerStatus::Disconnected(c)],
+                    peers.iter().map(|i| i.status.clone()).collect::<Vec<_>>()
+                );
+                assert_eq!(Some(addr_b.addr()), peers[0].address);
+                assert!(peers[0].connected_since.is_some());
+                assert!(peers[0].bytes_received > 0);
+
+                // Updates are sent as the status of B changes.
+                while updates.recv().await.unwrap()[0].status != PeerStatus::Registered(b) {}
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_peer_limit() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let a = HouseholdFixture::a();
+        let b = HouseholdFixture::b();
+        let local = LocalSet::new();
+
+        let household_a = fixture.inner.create_household(&local, a)?;
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture
+            .bind_server_with_household(&local, a, handler, household_a.clone())
+            .await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                client::set_peer_limit(&control, b, 1024, 0).await?;
+                let peers = client::get_peers(&control).await?;
+                assert_eq!(PeerStatus::Disconnected(b), peers[0].status);
+                assert_eq!(
+                    BandwidthLimit {
+                        upload_bytes_per_second: Some(1024),
+                        download_bytes_per_second: None,
+                    },
+                    peers[0].limit
+                );
+
+                client::set_peer_limit(&control, b, 0, 0).await?;
+                let peers = client::get_peers(&control).await?;
+                assert_eq!(BandwidthLimit::UNLIMITED, peers[0].limit);
+
+                assert!(
+                    client::set_peer_limit(&control, Peer::from("unknown"), 1024, 0)
+                        .await
+                        .is_err()
+                );
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn approve_pending_key() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let a = HouseholdFixture::a();
+        let b = HouseholdFixture::b();
+        let local = LocalSet::new();
+
+        let household_a = fixture.inner.create_household(&local, a)?;
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture
+            .bind_server_with_household(&local, a, handler, household_a.clone())
+            .await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+                assert!(client::pending_keys(&control).await?.is_empty());
+
+                // Nothing to approve.
+                assert!(!client::approve_key(&control, b, b"key").await?);
+
+                // Rotations must be signed with the new key.
+                assert!(
+                    client::rotate_key(
+                        &control,
+                        &KeyRotation {
+                            pubkey: realize_network::testing::other_public_key().to_vec(),
+                            signature: vec![0; 64],
+                        }
+                    )
+                    .await
+                    .is_err()
+                );
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invite() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let a = HouseholdFixture::a();
+        let local = LocalSet::new();
+
+        let household_a = fixture.inner.create_household(&local, a)?;
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture
+            .bind_server_with_household(&local, a, handler, household_a.clone())
+            .await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                let token =
+                    client::invite(&control, "a.example.com:9771", Duration::from_secs(600))
+                        .await?;
+                let invitation = Invitation::decode(&token)?;
+                assert_eq!(a, invitation.peer);
+                assert_eq!("a.example.com:9771", invitation.address);
+                assert!(!invitation.is_expired(UnixTime::now()));
+
+                assert!(
+                    client::join(&control, "bad token", None, None)
+                        .await
+                        .is_err()
+                );
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn churten_set_bandwidth_limit() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture.bind_server(&local, peer, handler).await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+                let churten = control
+                    .churten_request()
+                    .send()
+                    .promise
+                    .await?
+                    .get()?
+                    .get_churten()?;
+
+                let mut request = churten.set_bandwidth_limit_request();
+                request.get().set_bytes_per_second(1024 * 1024);
+                request.send().promise.await?;
+
+                let result = churten.status_request().send().promise.await?;
+                assert_eq!(1024 * 1024, result.get()?.get_res()?.get_bandwidth_limit());
+
+                let mut request = churten.set_bandwidth_limit_request();
+                request.get().set_bytes_per_second(0);
+                request.send().promise.await?;
+
+                let result = churten.status_request().send().promise.await?;
+                assert_eq!(0, result.get()?.get_res()?.get_bandwidth_limit());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_jobs_retry_and_ignore() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let arena = HouseholdFixture::test_arena();
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let storage = Arc::clone(fixture.inner.storage(peer)?);
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture.bind_server(&local, peer, handler).await?;
+
+        storage.set_arena_mark(arena, Mark::Keep).await?;
+        fixture
+            .inner
+            .cache(peer)?
+            .update(
+                Peer::from("other"),
+                Notification::Add {
+                    arena,
+                    index: 1,
+                    path: Path::parse("foo/a")?,
+                    mtime: UnixTime::from_secs(1234567890),
+                    size: 100,
+                    hash: Hash([1; 32]),
+                },
+            )
+            .await?;
+        let (_, job_id, _) = {
+            let mut job_stream = std::pin::pin!(storage.job_stream());
+            tokio::time::timeout(Duration::from_secs(3), job_stream.next())
+                .await?
+                .unwrap()
+        };
+        storage.job_finished(arena, job_id, Err(anyhow::anyhow!("fake")))?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+
+                let result = control.failed_jobs_request().send().promise.await?;
+                let res = result.get()?.get_res()?;
+                assert_eq!(1, res.len());
+                let failed = res.get(0);
+                assert_eq!(arena.as_str(), failed.get_arena()?.to_str()?);
+                assert_eq!(job_id.as_u64(), failed.get_job_id());
+                assert_eq!("foo/a", failed.get_path()?.to_str()?);
+                assert_eq!(1, failed.get_failure_count());
+                assert!(failed.get_backoff_until_secs() > 0);
+
+                let mut request = control.retry_job_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_job_id(job_id.as_u64());
+                assert!(request.send().promise.await?.get()?.get_retried());
+
+                let result = control.failed_jobs_request().send().promise.await?;
+                assert_eq!(0, result.get()?.get_res()?.len());
+
+                let mut request = control.retry_job_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_job_id(job_id.as_u64());
+                assert!(!request.send().promise.await?.get()?.get_retried());
+
+                let mut request = control.ignore_path_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_path("foo/a");
+                assert!(request.send().promise.await?.get()?.get_ignored());
+
+                let result = control.job_plan_request().send().promise.await?;
+                assert_eq!(0, result.get()?.get_res()?.len());
+
+                let mut request = control.ignore_path_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_path("doesnotexist");
+                assert!(!request.send().promise.await?.get()?.get_ignored());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn churten_cancel_job_not_running() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let arena = HouseholdFixture::test_arena();
+        let peer = HouseholdFixture::a();
+        let local = LocalSet::new();
+        let handler = FakeJobHandler::new(|| Ok(JobStatus::Done));
+        let sockpath = fixture.bind_server(&local, peer, handler).await?;
+
+        local
+            .run_until(async move {
+                let control = unixsocket::connect::<control::Client>(&sockpath).await?;
+                let churten = control
+                    .churten_request()
+                    .send()
+                    .promise
+                    .await?
+                    .get()?
+                    .get_churten()?;
+
+                let mut request = churten.cancel_job_request();
+                request.get().set_arena(arena.as_str());
+                request.get().set_job_id(1);
+                assert!(!request.send().promise.await?.get()?.get_cancelled());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn churten_rpc_job_succeeds() -> anyhow::Result<()> {
         let fixture = Fixture::setup().await?;
@@ -580,7 +1948,8 @@ mod tests {
                     ChurtenUpdates::Notify(ChurtenNotification::New {
                         arena,
                         job_id: JobId(1),
-                        job: Arc::new(Job::Download(foo.clone(), hash.clone()))
+                        job: Arc::new(Job::Download(foo.clone(), hash.clone())),
+                        priority: JobPriority::new(&Job::Download(foo.clone(), hash.clone()), 100),
                     }),
                     tokio::time::timeout(Duration::from_secs(3), rx.recv())
                         .await?
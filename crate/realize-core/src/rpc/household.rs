@@ -1,4 +1,6 @@
-use super::peer_capnp::connected_peer;
+use super::peer_capnp::connected_peer::{
+    self, ProposeKeyParams, ProposeKeyResults, RelayParams, RelayResults,
+};
 use super::result_capnp;
 use super::store_capnp::read_callback::{ChunkParams, FinishParams, FinishResults};
 use super::store_capnp::store::{
@@ -9,8 +11,13 @@ use super::store_capnp::subscriber::{self, NotifyParams, NotifyResults};
 use super::store_capnp::{io_error, notification, read_callback};
 use capnp::capability::Promise;
 use capnp_rpc::pry;
-use realize_network::capnp::{ConnectionHandler, ConnectionManager, PeerStatus};
-use realize_network::{Networking, Server};
+use realize_network::capnp::{
+    ConnectionHandler, ConnectionManager, PeerClients, PeerInfo, PeerStatus,
+};
+use realize_network::config::PeerLimits;
+use realize_network::pairing::{Invitation, PairedPeer};
+use realize_network::security::KeyRotation;
+use realize_network::{Networking, PendingKey, Server};
 use realize_storage::utils::holder::ByteConversionError;
 use realize_storage::{Notification, Progress, Storage, StorageError};
 use realize_types::{self, Arena, ByteRange, Delta, Hash, Path, Peer, Signature, UnixTime};
@@ -18,6 +25,7 @@ use std::collections::{HashMap, HashSet};
 use std::io::{self, SeekFrom};
 use std::pin;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::AsyncSeekExt;
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::sync::{broadcast, mpsc, oneshot};
@@ -38,6 +46,7 @@ const TAG: &[u8; 4] = b"PEER";
 #[derive(Clone)]
 pub struct Household {
     manager: Arc<ConnectionManager<HouseholdOperation>>,
+    networking: Networking,
 }
 
 impl Household {
@@ -52,11 +61,15 @@ impl Household {
         networking: Networking,
         storage: Arc<Storage>,
     ) -> anyhow::Result<Self> {
-        let manager =
-            ConnectionManager::spawn(local, networking, PeerConnectionHandler::new(storage))?;
+        let manager = ConnectionManager::spawn(
+            local,
+            networking.clone(),
+            PeerConnectionHandler::new(storage, networking.clone()),
+        )?;
 
         Ok(Self {
             manager: Arc::new(manager),
+            networking,
         })
     }
 
@@ -87,6 +100,92 @@ impl Household {
         self.manager.peer_status()
     }
 
+    /// Report the connection information of the peers.
+    pub async fn peers(&self) -> anyhow::Result<Vec<PeerInfo>> {
+        self.manager.peers().await
+    }
+
+    /// Change the bandwidth limits of a configured peer.
+    ///
+    /// See [Networking::set_limits].
+    pub fn set_peer_limits(&self, peer: Peer, limits: PeerLimits) -> anyhow::Result<()> {
+        if !self.networking.known_peers().any(|p| p == peer) {
+            anyhow::bail!("unknown peer: {peer}");
+        }
+        self.networking.set_limits(peer, limits);
+
+        Ok(())
+    }
+
+    /// Send a new public key of the current peer to all connected
+    /// peers.
+    ///
+    /// Each peer keeps the key as pending until its operator
+    /// approves it. See [Networking::propose_key].
+    ///
+    /// Returns the peers that received the key.
+    pub async fn propose_key(&self, rotation: KeyRotation) -> anyhow::Result<Vec<Peer>> {
+        let mut replies = vec![];
+        for peer in self.networking.known_peers() {
+            let (tx, rx) = oneshot::channel();
+            self.manager.with_peer_client(
+                peer,
+                HouseholdOperation::ProposeKey {
+                    rotation: rotation.clone(),
+                    tx,
+                },
+            )?;
+            replies.push((peer, rx));
+        }
+
+        let mut sent = vec![];
+        for (peer, rx) in replies {
+            match rx.await? {
+                Ok(()) => sent.push(peer),
+                Err(err) => log::warn!("{peer}: failed to send new key: {err}"),
+            }
+        }
+        sent.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        Ok(sent)
+    }
+
+    /// Keys proposed by other peers, waiting for approval.
+    pub fn pending_keys(&self) -> Vec<PendingKey> {
+        self.networking.pending_keys()
+    }
+
+    /// Accept a key proposed by a peer, until restart.
+    ///
+    /// See [Networking::approve_key].
+    pub fn approve_key(&self, peer: Peer, pubkey: &[u8]) -> bool {
+        self.networking.approve_key(peer, pubkey)
+    }
+
+    /// Invite a new peer to join the household.
+    ///
+    /// See [Networking::invite].
+    pub fn invite(&self, address: &str, ttl: Duration) -> anyhow::Result<Invitation> {
+        self.networking.invite(address, ttl)
+    }
+
+    /// Join the household of the peer that created the invitation.
+    ///
+    /// `local` defaults to the ID of the current peer, if it's
+    /// configured with its own key. See [Networking::join].
+    pub async fn join(
+        &self,
+        invitation: &Invitation,
+        local: Option<Peer>,
+        address: Option<String>,
+    ) -> anyhow::Result<PairedPeer> {
+        let local = local
+            .or_else(|| self.networking.local_peer())
+            .ok_or_else(|| anyhow::anyhow!("the ID of the current peer is unknown"))?;
+
+        self.networking.join(invitation, local, address).await
+    }
+
     /// Register peer connections to the given server.
     ///
     /// With this call, the server answers to PEER calls as Cap'n Proto
@@ -95,7 +194,26 @@ impl Household {
         self.manager.register(server)
     }
 
+    /// Peers to try, in order, to reach one of the given peers.
+    ///
+    /// This is the given peers followed by the peers that can relay
+    /// connections to them, which are tried in turn if a relay fails.
+    /// See [Household::read].
+    fn candidates(&self, peers: &[Peer]) -> Vec<Peer> {
+        let mut candidates = peers.to_vec();
+        for relay in self.networking.relay_peers() {
+            if !candidates.contains(&relay) {
+                candidates.push(relay);
+            }
+        }
+
+        candidates
+    }
+
     /// Read a file from a connected peer.
+    ///
+    /// If none of the peers is connected, the file is read through a
+    /// connected peer that accepts to relay the connection.
     pub fn read<T>(
         &self,
         peers: T,
@@ -108,10 +226,12 @@ impl Household {
         T: IntoIterator<Item = Peer>,
     {
         let (tx, rx) = mpsc::channel(10);
+        let peers = peers.into_iter().collect::<Vec<_>>();
         self.manager
             .with_any_peer_client(
-                peers,
+                self.candidates(&peers),
                 HouseholdOperation::Read {
+                    peers,
                     arena,
                     path,
                     offset,
@@ -125,6 +245,8 @@ impl Household {
     }
 
     /// Generate a rsync patch to apply to a given byte range of a remote file.
+    ///
+    /// Falls back to relayed connections, as [Household::read].
     pub async fn rsync<T>(
         &self,
         peers: T,
@@ -137,9 +259,11 @@ impl Household {
         T: IntoIterator<Item = Peer>,
     {
         let (tx, rx) = oneshot::channel();
+        let peers = peers.into_iter().collect::<Vec<_>>();
         self.manager.with_any_peer_client(
-            peers,
+            self.candidates(&peers),
             HouseholdOperation::Rsync {
+                peers,
                 tx,
                 arena,
                 path: path.clone(),
@@ -152,8 +276,13 @@ impl Household {
     }
 }
 
+/// Operations executed on a connected peer.
+///
+/// `peers` are the peers the operation targets. If the connected peer
+/// is not one of them, it's used as a relay.
 enum HouseholdOperation {
     Read {
+        peers: Vec<Peer>,
         tx: mpsc::Sender<Result<(u64, Vec<u8>), io::Error>>,
         arena: Arena,
         path: realize_types::Path,
@@ -161,20 +290,29 @@ enum HouseholdOperation {
         limit: Option<u64>,
     },
     Rsync {
+        peers: Vec<Peer>,
         tx: oneshot::Sender<anyhow::Result<Delta>>,
         arena: Arena,
         path: realize_types::Path,
         range: ByteRange,
         sig: Signature,
     },
+    ProposeKey {
+        rotation: KeyRotation,
+        tx: oneshot::Sender<anyhow::Result<()>>,
+    },
 }
 struct PeerConnectionHandler {
     storage: Arc<Storage>,
+    networking: Networking,
 }
 
 impl PeerConnectionHandler {
-    fn new(storage: Arc<Storage>) -> Self {
-        Self { storage }
+    fn new(storage: Arc<Storage>, networking: Networking) -> Self {
+        Self {
+            storage,
+            networking,
+        }
     }
 }
 
@@ -183,8 +321,14 @@ impl ConnectionHandler<connected_peer::Client, HouseholdOperation> for PeerConne
         TAG
     }
 
-    fn server(&self, peer: Peer) -> capnp::capability::Client {
+    fn server(
+        &self,
+        peer: Peer,
+        clients: PeerClients<connected_peer::Client>,
+    ) -> capnp::capability::Client {
         ConnectedPeerServer::new(peer, self.storage.clone())
+            .with_relay(self.networking.clone(), clients)
+            .with_key_rotation(self.networking.clone())
             .into_connected_peer()
             .client
     }
@@ -207,11 +351,12 @@ impl ConnectionHandler<connected_peer::Client, HouseholdOperation> for PeerConne
 
     async fn execute(
         &self,
-        client: Option<(Peer, connected_peer::Client)>,
+        clients: Vec<(Peer, connected_peer::Client)>,
         operation: HouseholdOperation,
     ) {
         match operation {
             HouseholdOperation::Read {
+                peers,
                 tx,
                 arena,
                 path,
@@ -219,41 +364,113 @@ impl ConnectionHandler<connected_peer::Client, HouseholdOperation> for PeerConne
                 limit,
             } => {
                 let tx_clone = tx.clone();
-                if let Err(err) = execute_read(client, arena, path, offset, limit, tx).await {
+                if let Err(err) =
+                    execute_read(clients, &peers, arena, path, offset, limit, tx).await
+                {
                     let _ = tx_clone.send(Err(err)).await;
                 }
             }
             HouseholdOperation::Rsync {
+                peers,
                 tx,
                 arena,
                 path,
                 range,
                 sig,
             } => {
-                let res = execute_rsync(client, arena, &path, &range, sig).await;
+                let res = execute_rsync(clients, &peers, arena, &path, &range, sig).await;
+                let _ = tx.send(res);
+            }
+            HouseholdOperation::ProposeKey { rotation, tx } => {
+                let res = execute_propose_key(clients.into_iter().next(), &rotation).await;
                 let _ = tx.send(res);
             }
         }
     }
 }
 
-async fn execute_read(
+/// Send a new key of the current peer to the connected peer.
+async fn execute_propose_key(
     client: Option<(Peer, connected_peer::Client)>,
+    rotation: &KeyRotation,
+) -> anyhow::Result<()> {
+    let (_, client) = client.ok_or_else(|| anyhow::anyhow!("not connected"))?;
+    let mut request = client.propose_key_request();
+    let mut builder = request.get();
+    builder.set_pubkey(rotation.pubkey.as_slice());
+    builder.set_signature(rotation.signature.as_slice());
+    request.send().promise.await?;
+
+    Ok(())
+}
+
+/// Get the store of one of `peers` from the first connected peer
+/// that can provide it.
+///
+/// Connected peers that aren't one of `peers` are asked to relay the
+/// connection. If a relay fails, the next one is tried.
+async fn any_target_store(
+    clients: Vec<(Peer, connected_peer::Client)>,
+    peers: &[Peer],
+) -> anyhow::Result<(Peer, store::Client)> {
+    for (peer, client) in clients {
+        match target_store(peer, &client, peers).await {
+            Ok(ret) => return Ok(ret),
+            Err(err) => log::debug!("Cannot reach {peers:?} through {peer}: {err}"),
+        }
+    }
+
+    anyhow::bail!("No available peer");
+}
+
+/// Get the store of one of `peers` from the connected peer.
+///
+/// If the connected peer isn't one of `peers`, ask it to relay the
+/// connection to each of `peers` in turn.
+async fn target_store(
+    peer: Peer,
+    client: &connected_peer::Client,
+    peers: &[Peer],
+) -> anyhow::Result<(Peer, store::Client)> {
+    if peers.contains(&peer) {
+        return Ok((peer, client.store_request().send().pipeline.get_store()));
+    }
+    for target in peers {
+        let mut request = client.relay_request();
+        request.get().set_peer(target.as_str());
+        match request
+            .send()
+            .promise
+            .await
+            .and_then(|reply| reply.get()?.get_store())
+        {
+            Ok(store) => {
+                log::debug!("Reaching {target} through {peer}");
+                return Ok((*target, store));
+            }
+            Err(err) => {
+                log::debug!("{peer} failed to relay to {target}: {err}");
+            }
+        }
+    }
+
+    anyhow::bail!("No available peer");
+}
+
+async fn execute_read(
+    clients: Vec<(Peer, connected_peer::Client)>,
+    peers: &[Peer],
     arena: Arena,
     path: realize_types::Path,
     offset: u64,
     limit: Option<u64>,
     tx: mpsc::Sender<io::Result<(u64, Vec<u8>)>>,
 ) -> io::Result<()> {
-    let (peer, client) = match client {
-        Some(c) => c,
-        None => {
-            return Err(io::Error::new(io::ErrorKind::NotFound, "No available peer"));
-        }
-    };
+    let (peer, store) = any_target_store(clients, peers)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::NotFound, err))?;
     log::debug!("Reading [{arena}]/{path} from {peer}");
 
-    let store = client.store_request().send().pipeline.get_store();
     let mut request = store.read_request();
     let mut builder = request.get();
     builder.set_cb(capnp_rpc::new_client(ReadCallbackServer { tx: Some(tx) }));
@@ -275,24 +492,19 @@ async fn execute_read(
 }
 
 async fn execute_rsync(
-    client: Option<(Peer, connected_peer::Client)>,
+    clients: Vec<(Peer, connected_peer::Client)>,
+    peers: &[Peer],
     arena: Arena,
     path: &realize_types::Path,
     range: &ByteRange,
     sig: Signature,
 ) -> anyhow::Result<Delta> {
-    let (peer, client) = match client {
-        Some(c) => c,
-        None => {
-            anyhow::bail!("No available peer");
-        }
-    };
+    let (peer, store) = any_target_store(clients, peers).await?;
     log::debug!(
         "Rsyncing [{arena}]/{path} {range} with {peer} ({} bytes in)",
         sig.0.len()
     );
 
-    let store = client.store_request().send().pipeline.get_store();
     let mut request = store.rsync_request();
     let mut req = request.get().init_req();
     req.set_arena(arena.as_str());
@@ -456,11 +668,36 @@ async fn subscribe_self(
 struct ConnectedPeerServer {
     peer: Peer,
     storage: Arc<Storage>,
+    relay: Option<(Networking, PeerClients<connected_peer::Client>)>,
+    key_rotation: Option<Networking>,
 }
 
 impl ConnectedPeerServer {
     fn new(peer: Peer, storage: Arc<Storage>) -> Self {
-        Self { peer, storage }
+        Self {
+            peer,
+            storage,
+            relay: None,
+            key_rotation: None,
+        }
+    }
+
+    /// Allow relaying connections to other peers, if configured.
+    fn with_relay(
+        mut self,
+        networking: Networking,
+        clients: PeerClients<connected_peer::Client>,
+    ) -> Self {
+        self.relay = Some((networking, clients));
+
+        self
+    }
+
+    /// Accept new keys proposed by the peer, pending approval.
+    fn with_key_rotation(mut self, networking: Networking) -> Self {
+        self.key_rotation = Some(networking);
+
+        self
     }
 
     fn into_connected_peer(self) -> connected_peer::Client {
@@ -500,8 +737,9 @@ impl ConnectedPeerServer {
 
         if let Err(err) = tokio::spawn({
             let storage = self.storage.clone();
+            let peer = self.peer;
             async move {
-                storage.subscribe(arena, tx, progress).await?;
+                storage.subscribe(arena, peer, tx, progress).await?;
 
                 Ok::<(), anyhow::Error>(())
             }
@@ -537,6 +775,55 @@ impl ConnectedPeerServer {
         Ok(())
     }
 
+    async fn do_relay(
+        &self,
+        params: RelayParams,
+        mut results: RelayResults,
+    ) -> Result<(), capnp::Error> {
+        let target = Peer::from(params.get()?.get_peer()?.to_str()?);
+        let clients = match &self.relay {
+            Some((networking, clients))
+                if target != self.peer
+                    && networking.is_relay(self.peer)
+                    && networking.is_relay(target) =>
+            {
+                clients
+            }
+            _ => {
+                return Err(capnp::Error::failed(format!(
+                    "Relaying from {} to {target} not allowed",
+                    self.peer
+                )));
+            }
+        };
+        let (_, mut client) = clients
+            .reachable([target])
+            .ok_or_else(|| capnp::Error::failed(format!("{target} not connected")))?;
+        let store = get_connected_peer_store(&mut client)
+            .await
+            .map_err(|err| capnp::Error::failed(err.to_string()))?;
+        log::debug!("Relaying from {} to {target}", self.peer);
+        results.get().set_store(store);
+
+        Ok(())
+    }
+
+    fn do_propose_key(&self, params: ProposeKeyParams) -> Result<(), capnp::Error> {
+        let params = params.get()?;
+        let rotation = KeyRotation {
+            pubkey: params.get_pubkey()?.to_vec(),
+            signature: params.get_signature()?.to_vec(),
+        };
+        let networking = self
+            .key_rotation
+            .as_ref()
+            .ok_or_else(|| capnp::Error::failed("Key rotation not supported".to_string()))?;
+
+        networking
+            .propose_key(self.peer, &rotation)
+            .map_err(|err| capnp::Error::failed(err.to_string()))
+    }
+
     async fn do_read(&self, params: ReadParams) -> Result<(), capnp::Error> {
         let params = params.get()?;
         let req = params.get_req()?;
@@ -666,6 +953,7 @@ fn read_errno(err: StorageError) -> io_error::Errno {
         StorageError::InvalidRsyncSignature => InvalidInput,
         StorageError::UnknownArena(_) => NotFound,
         StorageError::NoLocalStorage(_) => NotFound,
+        StorageError::InvalidMarkRule(_) => InvalidInput,
     }
 }
 
@@ -679,6 +967,21 @@ impl connected_peer::Server for ConnectedPeerServer {
 
         Promise::ok(())
     }
+
+    fn relay(&mut self, params: RelayParams, results: RelayResults) -> Promise<(), capnp::Error> {
+        let this = self.clone();
+        Promise::from_future(async move { this.do_relay(params, results).await })
+    }
+
+    fn propose_key(
+        &mut self,
+        params: ProposeKeyParams,
+        _: ProposeKeyResults,
+    ) -> Promise<(), capnp::Error> {
+        pry!(self.do_propose_key(params));
+
+        Promise::ok(())
+    }
 }
 
 impl store::Server for ConnectedPeerServer {
@@ -779,6 +1082,22 @@ async fn do_notify(
                     old_hash: parse_hash(drop.get_old_hash()?)?,
                 }
             }
+            notification::Which::Link(link) => {
+                let link = link?;
+                let target = link.get_target()?;
+
+                Notification::Link {
+                    arena: parse_arena(link.get_arena()?)?,
+                    index: link.get_index(),
+                    path: parse_path(link.get_path()?)?,
+                    hash: parse_hash(link.get_hash()?)?,
+                    target: if target.to_str()?.is_empty() {
+                        None
+                    } else {
+                        Some(parse_path(target)?)
+                    },
+                }
+            }
             notification::Which::CatchupStart(start) => {
                 Notification::CatchupStart(parse_arena(start?.get_arena()?)?)
             }
@@ -885,6 +1204,21 @@ async fn send_notifications(
                 old_hash,
             } => fill_drop(notif_builder.init_drop(), *arena, *index, path, old_hash),
 
+            Notification::Link {
+                arena,
+                index,
+                path,
+                hash,
+                target,
+            } => fill_link(
+                notif_builder.init_link(),
+                *arena,
+                *index,
+                path,
+                hash,
+                target.as_ref(),
+            ),
+
             Notification::Catchup {
                 arena,
                 path,
@@ -995,6 +1329,23 @@ fn fill_drop(
     builder.set_old_hash(&old_hash.0);
 }
 
+fn fill_link(
+    mut builder: super::store_capnp::link::Builder<'_>,
+    arena: Arena,
+    index: u64,
+    path: &realize_types::Path,
+    hash: &realize_types::Hash,
+    target: Option<&realize_types::Path>,
+) {
+    builder.set_arena(arena.as_str());
+    builder.set_index(index);
+    builder.set_path(path.as_str());
+    builder.set_hash(&hash.0);
+    if let Some(target) = target {
+        builder.set_target(target.as_str());
+    }
+}
+
 fn fill_catchup(
     mut builder: super::store_capnp::catchup::Builder<'_>,
     arena: Arena,
@@ -1071,10 +1422,12 @@ fn storage_to_capnp_err(err: StorageError) -> capnp::Error {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::rpc::testing::HouseholdFixture;
+    use crate::rpc::testing::{HouseholdFixture, connect};
     use fast_rsync::SignatureOptions;
     use futures::TryStreamExt as _;
+    use std::time::Duration;
     use tokio::fs;
+    use tokio::time::timeout;
 
     #[tokio::test]
     async fn household_subscribes() -> anyhow::Result<()> {
@@ -1154,6 +1507,104 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn read_through_relay() -> anyhow::Result<()> {
+        let mut fixture = HouseholdFixture::setup().await?;
+        let a = HouseholdFixture::a();
+        let b = HouseholdFixture::b();
+        let c = HouseholdFixture::c();
+        fixture.peers.allow_relay(a)?;
+        fixture.peers.allow_relay(b)?;
+
+        // A and C can't be connected to, but they can both connect to B.
+        let addr_b = fixture.pick_port(b)?;
+        let local = LocalSet::new();
+        let household_a = fixture.create_household(&local, a)?;
+        let household_b = fixture.create_household(&local, b)?;
+        let household_c = fixture.create_household(&local, c)?;
+        let mut server_b = Server::new(fixture.peers.networking(b)?);
+        household_b.register(&mut server_b);
+
+        local
+            .run_until(async move {
+                server_b.listen(&addr_b).await?;
+                connect(&household_a, b).await?;
+                connect(&household_c, b).await?;
+
+                fs::write(&fixture.arena_root(c).join("bar.txt"), b"test").await?;
+
+                // Retry until C has indexed the file.
+                timeout(Duration::from_secs(3), async {
+                    loop {
+                        let stream = household_a.read(
+                            vec![c],
+                            HouseholdFixture::test_arena(),
+                            realize_types::Path::parse("bar.txt")?,
+                            0,
+                            None,
+                        )?;
+                        if let Ok(collected) = stream.try_collect::<Vec<_>>().await
+                            && collected == vec![(0, b"test".to_vec())]
+                        {
+                            return Ok::<(), anyhow::Error>(());
+                        }
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                    }
+                })
+                .await??;
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn propose_and_approve_key() -> anyhow::Result<()> {
+        let mut fixture = HouseholdFixture::setup().await?;
+        let a = HouseholdFixture::a();
+        let b = HouseholdFixture::b();
+
+        // Only A and B are configured, so the key of C is free.
+        fixture.peers = realize_network::testing::TestingPeers::empty();
+        fixture.peers.add(
+            a,
+            realize_network::testing::CLIENT_PUBLIC_KEY_PEM,
+            realize_network::testing::client_private_key(),
+        )?;
+        fixture.peers.add(
+            b,
+            realize_network::testing::SERVER_PUBLIC_KEY_PEM,
+            realize_network::testing::server_private_key(),
+        )?;
+
+        fixture
+            .with_two_peers()
+            .await?
+            .interconnected()
+            .run(async |household_a, household_b| {
+                let new_key = realize_network::security::RawPublicKeyResolver::from_private_key(
+                    realize_network::testing::other_private_key(),
+                )?;
+                let rotation = KeyRotation::sign(&new_key)?;
+                assert_eq!(vec![b], household_a.propose_key(rotation.clone()).await?);
+
+                let pending = household_b.pending_keys();
+                assert_eq!(1, pending.len());
+                assert_eq!(a, pending[0].peer);
+                assert_eq!(rotation.pubkey, pending[0].pubkey);
+
+                assert!(household_b.approve_key(a, &rotation.pubkey));
+                assert!(household_b.pending_keys().is_empty());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn rsync_from_peer() -> anyhow::Result<()> {
         let mut fixture = HouseholdFixture::setup().await?;
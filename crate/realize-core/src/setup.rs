@@ -1,14 +1,16 @@
 use anyhow::Context;
 use tarpc::tokio_util::sync::CancellationToken;
 use tokio::fs;
+use tokio::sync::broadcast;
 use tokio::task::LocalSet;
 
-use super::config::Config;
+use super::config::{self, ChurtenConfig, Config};
 use crate::consensus::churten::Churten;
 use crate::fs::downloader::Downloader;
 use crate::fs::nfs;
 use crate::rpc::control::server::ControlServer;
 use crate::rpc::{Household, realstore};
+use crate::utils::async_utils::AbortOnDrop;
 use realize_network::{Networking, Server, unixsocket};
 use realize_storage::Storage;
 use realize_storage::config::ArenaConfig;
@@ -23,6 +25,11 @@ pub struct SetupHelper {
     pub networking: Networking,
     pub storage: Arc<Storage>,
     pub household: Household,
+    pub churten_config: ChurtenConfig,
+
+    /// Number of bytes at the head and tail of new files to
+    /// prefetch, for the arenas that have it configured.
+    pub prefetch_head_tail_per_arena: HashMap<Arena, u64>,
 }
 
 impl SetupHelper {
@@ -33,7 +40,8 @@ impl SetupHelper {
     ) -> anyhow::Result<Self> {
         check_directory_access(&config.storage.arenas)?;
 
-        let networking = Networking::from_config(&config.network.peers, privkey)?;
+        let networking = Networking::from_config(&config.network.peers, privkey)?
+            .with_revoked_keys(&config.network.revoked_keys)?;
         let storage = Storage::from_config(&config.storage).await?;
         log::debug!(
             "Storage: Cached {:?}",
@@ -49,20 +57,68 @@ impl SetupHelper {
             networking,
             storage,
             household,
+            churten_config: config.churten,
+            prefetch_head_tail_per_arena: config
+                .storage
+                .arenas
+                .iter()
+                .filter_map(|(arena, c)| c.prefetch_head_tail.map(|bytes| (*arena, bytes)))
+                .collect(),
         })
     }
 
     /// Export NFS at the given address.
     ///
     /// A local cache must be configured.
-    pub async fn export_nfs(&self, addr: SocketAddr) -> anyhow::Result<()> {
+    ///
+    /// Returns the task that prefetches new files, if any arena has
+    /// prefetching configured. The task is aborted once the returned
+    /// value is dropped.
+    pub async fn export_nfs(&self, addr: SocketAddr) -> anyhow::Result<Option<AbortOnDrop<()>>> {
         let cache = self.storage.cache();
 
         let downloader = Downloader::new(self.household.clone(), cache.clone());
+        let prefetch = if self.prefetch_head_tail_per_arena.is_empty() {
+            None
+        } else {
+            Some(AbortOnDrop::new(downloader.prefetch_new_files(
+                self.storage.new_files(),
+                self.prefetch_head_tail_per_arena.clone(),
+            )))
+        };
 
         nfs::export(cache.clone(), downloader, addr).await?;
 
-        Ok(())
+        Ok(prefetch)
+    }
+
+    /// Add peers paired while the daemon runs to the configuration
+    /// file at `path`, so they're still known after a restart.
+    pub fn save_paired_peers(&self, path: &std::path::Path) {
+        let mut rx = self.networking.paired_peers();
+        let path = path.to_path_buf();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(paired) => {
+                        let peer = paired.peer;
+                        let result = tokio::task::spawn_blocking({
+                            let path = path.clone();
+                            move || config::append_peer_config(&path, paired.peer, &paired.config)
+                        })
+                        .await
+                        .map_err(anyhow::Error::from)
+                        .and_then(|res| res);
+                        match result {
+                            Ok(()) => log::info!("Added {peer} to {path:?}"),
+                            Err(err) => log::warn!("Failed to add {peer} to {path:?}: {err:#}"),
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
     }
 
     /// Bind to a UNIX socket that allows the owning user to control the server
@@ -85,8 +141,13 @@ impl SetupHelper {
         };
 
         let token = CancellationToken::new();
-        let churten = Churten::new(Arc::clone(&self.storage), self.household.clone());
-        let control_server = ControlServer::new(Arc::clone(&self.storage), churten);
+        let churten = Churten::new(
+            Arc::clone(&self.storage),
+            self.household.clone(),
+            self.churten_config.clone(),
+        );
+        let control_server =
+            ControlServer::new(Arc::clone(&self.storage), churten, self.household.clone());
         unixsocket::bind(
             local,
             path.as_ref(),
@@ -109,6 +170,7 @@ impl SetupHelper {
             networking,
             storage,
             household,
+            ..
         } = self;
 
         let mut server = Server::new(networking.clone());
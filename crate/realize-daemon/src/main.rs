@@ -8,6 +8,7 @@ use realize_core::config::Config;
 use realize_core::rpc::realstore::metrics;
 use realize_core::setup::SetupHelper;
 use realize_core::utils::logging;
+use realize_network::discovery::Discovery;
 use realize_network::hostport::HostPort;
 use signal_hook_tokio::Signals;
 use std::path::{Path, PathBuf};
@@ -80,8 +81,11 @@ async fn execute(cli: Cli) -> anyhow::Result<()> {
     let config = parse_config(&cli.config)
         .with_context(|| format!("{}: failed to read TOML config file", cli.config.display()))?;
 
+    let discovery_config = config.network.discovery.clone();
     let local = LocalSet::new();
     let setup = SetupHelper::setup(config, &cli.privkey, &local).await?;
+    let networking = setup.networking.clone();
+    setup.save_paired_peers(&cli.config);
 
     if let Some(addr) = &cli.metrics_addr {
         metrics::export_metrics(addr)
@@ -95,16 +99,19 @@ async fn execute(cli: Cli) -> anyhow::Result<()> {
         .with_context(|| format!("Failed to parse --address {}", cli.address))?;
     log::debug!("Starting server on {}/{:?}...", hostport, hostport.addr());
 
-    if let Some(addr) = &cli.nfs {
-        setup
-            .export_nfs(
-                HostPort::parse(addr)
-                    .await
-                    .with_context(|| format!("Failed to parse --nfs {addr}"))?
-                    .addr(),
-            )
-            .await?;
-    }
+    let _prefetch = match &cli.nfs {
+        Some(addr) => {
+            setup
+                .export_nfs(
+                    HostPort::parse(addr)
+                        .await
+                        .with_context(|| format!("Failed to parse --nfs {addr}"))?
+                        .addr(),
+                )
+                .await?
+        }
+        None => None,
+    };
 
     setup
         .bind_control_socket(&local, cli.socket.as_deref())
@@ -117,6 +124,17 @@ async fn execute(cli: Cli) -> anyhow::Result<()> {
         .await
         .with_context(|| format!("Failed to start server on {hostport}"))?;
 
+    let _discovery = match &discovery_config {
+        Some(discovery_config) => Some(
+            Discovery::spawn(networking, discovery_config, addr.port())
+                .await
+                .with_context(|| {
+                    format!("Failed to start discovery on {}", discovery_config.group)
+                })?,
+        ),
+        None => None,
+    };
+
     local
         .run_until(async move {
             let mut signals = Signals::new([
@@ -97,14 +97,20 @@ impl Fixture {
         config.network.peers.insert(
             Peer::from("a"),
             PeerConfig {
-                address: None,
+                addresses: vec![],
+                relay: false,
+                pubkeys: vec![],
+                limits: Default::default(),
                 pubkey: std::fs::read_to_string(resources.join("a-spki.pem"))?,
             },
         );
         config.network.peers.insert(
             Peer::from("b"),
             PeerConfig {
-                address: None,
+                addresses: vec![],
+                relay: false,
+                pubkeys: vec![],
+                limits: Default::default(),
                 pubkey: std::fs::read_to_string(resources.join("b-spki.pem"))?,
             },
         );
@@ -278,7 +284,7 @@ async fn daemon_starts_and_lists_files() -> anyhow::Result<()> {
 
     let a = Peer::from("a");
     let mut peers = fixture.config.network.peers.clone();
-    peers.get_mut(&a).expect("PeerConfig of a").address = Some(fixture.server_address.clone());
+    peers.get_mut(&a).expect("PeerConfig of a").addresses = vec![fixture.server_address.clone()];
 
     let networking = Networking::from_config(&peers, &fixture.resources.join("b.key"))?;
     let client = realstore::client::connect(&networking, a, ClientOptions::default()).await?;
@@ -562,7 +568,7 @@ async fn daemon_updates_cache() -> anyhow::Result<()> {
         .peers
         .get_mut(&Peer::from("a"))
         .expect("peer a")
-        .address = Some(fixture_a.server_address);
+        .addresses = vec![fixture_a.server_address];
 
     let nfs_port = portpicker::pick_unused_port().expect("No ports free");
     let nfs_addr = format!("127.0.0.1:{nfs_port}");
@@ -0,0 +1,113 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Bytes sent and received over a set of streams.
+#[derive(Clone, Default)]
+pub(crate) struct ByteCount {
+    sent: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+}
+
+impl ByteCount {
+    /// Total number of bytes written to the streams.
+    pub(crate) fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Total number of bytes read from the streams.
+    pub(crate) fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Count the bytes going through the given stream.
+    pub(crate) fn count<S>(&self, inner: S) -> CountedStream<S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        CountedStream {
+            inner,
+            count: self.clone(),
+        }
+    }
+}
+
+/// A stream whose bytes are added to a [ByteCount].
+pub(crate) struct CountedStream<S> {
+    inner: S,
+    count: ByteCount,
+}
+
+impl<S> AsyncRead for CountedStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &res {
+            let len = (buf.filled().len() - before) as u64;
+            self.count.received.fetch_add(len, Ordering::Relaxed);
+        }
+
+        res
+    }
+}
+
+impl<S> AsyncWrite for CountedStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = &res {
+            self.count.sent.fetch_add(*len as u64, Ordering::Relaxed);
+        }
+
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn count_bytes() -> anyhow::Result<()> {
+        let (a, mut b) = tokio::io::duplex(64);
+        let count = ByteCount::default();
+        let mut a = count.count(a);
+
+        a.write_all(b"hello").await?;
+        let mut buf = [0; 5];
+        b.read_exact(&mut buf).await?;
+
+        b.write_all(b"hi").await?;
+        let mut buf = [0; 2];
+        a.read_exact(&mut buf).await?;
+
+        assert_eq!(5, count.sent());
+        assert_eq!(2, count.received());
+
+        Ok(())
+    }
+}
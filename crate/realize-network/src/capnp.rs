@@ -3,22 +3,26 @@ use capnp_rpc::twoparty::VatNetwork;
 use capnp_rpc::{RpcSystem, VatNetwork as _};
 use futures::AsyncReadExt;
 use futures::io::{BufReader, BufWriter};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
-use std::rc::Rc;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio::sync::broadcast;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::task::{JoinHandle, LocalSet};
+use tokio::time::MissedTickBehavior;
 use tokio_retry::strategy::ExponentialBackoff;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 use tokio_util::sync::CancellationToken;
 
-use realize_types::Peer;
+use realize_types::{Peer, UnixTime};
 
+use super::byte_count::ByteCount;
 use super::rate_limit::RateLimitedStream;
+use crate::config::BandwidthLimit;
 use crate::{Networking, Server};
 
 /// Connection status of a peer, broadcast by [ConnectionManager].
@@ -29,6 +33,64 @@ pub enum PeerStatus {
     Disconnected(Peer),
 }
 
+impl PeerStatus {
+    /// The peer whose status this is.
+    pub fn peer(&self) -> Peer {
+        match self {
+            PeerStatus::Connected(peer)
+            | PeerStatus::Registered(peer)
+            | PeerStatus::Disconnected(peer) => *peer,
+        }
+    }
+}
+
+/// How often transfer rates are computed.
+const RATE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the schedules of the bandwidth limits are checked.
+const LIMIT_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Connection information of a peer, returned by
+/// [ConnectionManager::peers].
+#[derive(Clone, PartialEq, Debug)]
+pub struct PeerInfo {
+    /// Status of the connection to the peer.
+    pub status: PeerStatus,
+
+    /// Address of the connection to the peer, if connected.
+    pub address: Option<SocketAddr>,
+
+    /// When the connection to the peer was established, if connected.
+    pub connected_since: Option<UnixTime>,
+
+    /// True if the peer has a connection open to us.
+    pub incoming: bool,
+
+    /// Bytes sent to and received from the peer, on all connections.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+
+    /// Bytes per second sent to and received from the peer
+    /// recently.
+    pub send_rate: u64,
+    pub receive_rate: u64,
+
+    /// The last error encountered connecting to the peer since the
+    /// last successful connection, if any.
+    pub last_error: Option<String>,
+
+    /// Number of connection attempts that failed since the last
+    /// successful one.
+    pub failed_attempts: u32,
+
+    /// When the next connection attempt is due, if waiting to
+    /// reconnect.
+    pub next_attempt: Option<UnixTime>,
+
+    /// Bandwidth limits currently applied to the peer.
+    pub limit: BandwidthLimit,
+}
+
 /// Messages used to communicate with capnp on the main thread.
 enum ConnectionMessage<O> {
     /// Send incoming (server) TCP connections to the capnp threads to
@@ -40,6 +102,8 @@ enum ConnectionMessage<O> {
     },
     /// Connect to all peers that have an address and attempt to keep
     /// the connection up.
+    ///
+    /// This includes peers whose address is discovered later on.
     KeepAllConnected,
 
     /// Disconnect for all peers. This disables KeepConnected.
@@ -61,15 +125,22 @@ enum ConnectionMessage<O> {
     /// Operations usually include a channel to use to send a reply
     /// back.
     PeerOperation { peers: Vec<Peer>, operation: O },
+
+    /// Report the connection information of all peers.
+    Peers(oneshot::Sender<Vec<PeerInfo>>),
 }
 
 #[allow(async_fn_in_trait)]
 pub trait ConnectionHandler<C, O>: Send {
     fn tag(&self) -> &'static [u8; 4];
-    fn server(&self, peer: Peer) -> capnp::capability::Client;
+    fn server(&self, peer: Peer, clients: PeerClients<C>) -> capnp::capability::Client;
     async fn check_connection(&self, peer: Peer, client: &mut C) -> anyhow::Result<()>;
     async fn register(&self, peer: Peer, client: C) -> anyhow::Result<()>;
-    async fn execute(&self, client: Option<(Peer, C)>, operation: O);
+
+    /// Execute an operation with the clients of the connected peers
+    /// among those passed to [ConnectionManager::with_any_peer_client],
+    /// in the same order.
+    async fn execute(&self, clients: Vec<(Peer, C)>, operation: O);
 }
 
 pub struct ConnectionManager<O> {
@@ -101,8 +172,31 @@ where
             let broadcast_tx = broadcast_tx.clone();
 
             async move {
+                let mut discovered_rx = networking.discovered_peers();
                 let ctx = AppContext::new(networking, handler, broadcast_tx);
-                while let Some(conn) = rx.recv().await {
+                let mut rate_interval = tokio::time::interval(RATE_SAMPLE_INTERVAL);
+                rate_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                let mut limit_interval = tokio::time::interval(LIMIT_UPDATE_INTERVAL);
+                limit_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    let conn = tokio::select!(
+                        conn = rx.recv() => match conn {
+                            Some(conn) => conn,
+                            None => break,
+                        },
+                        Ok(peer) = discovered_rx.recv() => {
+                            ctx.peer_discovered(peer);
+                            continue;
+                        }
+                        _ = rate_interval.tick() => {
+                            ctx.sample_rates();
+                            continue;
+                        }
+                        _ = limit_interval.tick() => {
+                            ctx.networking.update_limits();
+                            continue;
+                        }
+                    );
                     match conn {
                         ConnectionMessage::Incoming {
                             peer,
@@ -120,6 +214,9 @@ where
                         ConnectionMessage::PeerOperation { peers, operation } => {
                             ctx.with_any_peer_client(peers, operation);
                         }
+                        ConnectionMessage::Peers(tx) => {
+                            let _ = tx.send(ctx.peers());
+                        }
                     }
                 }
             }
@@ -156,6 +253,15 @@ where
         Ok(())
     }
 
+    /// Report the connection information of the configured peers
+    /// and of any other peer with a connection, ordered by peer.
+    pub async fn peers(&self) -> anyhow::Result<Vec<PeerInfo>> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(ConnectionMessage::Peers(tx))?;
+
+        Ok(rx.await?)
+    }
+
     /// Keep a client connection up to all peers for which an address is known.
     pub fn keep_all_connected(&self) -> anyhow::Result<()> {
         self.tx.send(ConnectionMessage::KeepAllConnected)?;
@@ -210,7 +316,13 @@ where
     networking: Networking,
     handler: H,
     broadcast_tx: broadcast::Sender<PeerStatus>,
-    connections: RefCell<HashMap<Peer, TrackedPeerConnections<C>>>,
+    connections: Rc<RefCell<HashMap<Peer, TrackedPeerConnections<C>>>>,
+    /// True between KeepAllConnected and DisconnectAll.
+    keep_all: Cell<bool>,
+    /// Id of the last incoming connection.
+    last_incoming_id: Cell<u64>,
+    /// When transfer rates were last computed.
+    last_rate_sample: Cell<Instant>,
     _phantom1: PhantomData<C>,
     _phantom2: PhantomData<O>,
 }
@@ -219,6 +331,26 @@ struct TrackedPeerConnections<C> {
     tracked_client: Option<C>,
     tracker: Option<JoinHandle<()>>,
     cancel: CancellationToken,
+
+    /// Client served by the peer on the last connection it opened to
+    /// us, with an id identifying that connection.
+    incoming_client: Option<(u64, C)>,
+
+    /// True once the tracked client has been registered.
+    registered: bool,
+    address: Option<SocketAddr>,
+    connected_since: Option<UnixTime>,
+    last_error: Option<String>,
+    failed_attempts: u32,
+    next_attempt: Option<UnixTime>,
+
+    /// Bytes sent and received on all connections to and from the
+    /// peer.
+    bytes: ByteCount,
+    /// Byte counts at the time of the last rate sample.
+    sampled: (u64, u64),
+    /// Bytes per seconds sent and received, as of the last rate sample.
+    rate: (u64, u64),
 }
 
 impl<C> TrackedPeerConnections<C> {
@@ -227,8 +359,50 @@ impl<C> TrackedPeerConnections<C> {
             tracked_client: None,
             tracker: None,
             cancel: CancellationToken::new(),
+            incoming_client: None,
+            registered: false,
+            address: None,
+            connected_since: None,
+            last_error: None,
+            failed_attempts: 0,
+            next_attempt: None,
+            bytes: ByteCount::default(),
+            sampled: (0, 0),
+            rate: (0, 0),
         }
     }
+
+    fn info(&self, peer: Peer, limit: BandwidthLimit) -> PeerInfo {
+        PeerInfo {
+            status: match (&self.tracked_client, self.registered) {
+                (Some(_), true) => PeerStatus::Registered(peer),
+                (Some(_), false) => PeerStatus::Connected(peer),
+                (None, _) => PeerStatus::Disconnected(peer),
+            },
+            address: self.address,
+            connected_since: self.connected_since,
+            incoming: self.incoming_client.is_some(),
+            bytes_sent: self.bytes.sent(),
+            bytes_received: self.bytes.received(),
+            send_rate: self.rate.0,
+            receive_rate: self.rate.1,
+            last_error: self.last_error.clone(),
+            failed_attempts: self.failed_attempts,
+            next_attempt: self.next_attempt,
+            limit,
+        }
+    }
+
+    /// Compute transfer rates from the bytes transferred since the
+    /// last sample, `elapsed` seconds ago.
+    fn sample_rate(&mut self, elapsed: f64) {
+        let current = (self.bytes.sent(), self.bytes.received());
+        self.rate = (
+            ((current.0 - self.sampled.0) as f64 / elapsed) as u64,
+            ((current.1 - self.sampled.1) as f64 / elapsed) as u64,
+        );
+        self.sampled = current;
+    }
 }
 impl<H, C, O> AppContext<H, C, O>
 where
@@ -245,7 +419,10 @@ where
             networking,
             handler,
             broadcast_tx,
-            connections: RefCell::new(HashMap::new()),
+            connections: Rc::new(RefCell::new(HashMap::new())),
+            keep_all: Cell::new(false),
+            last_incoming_id: Cell::new(0),
+            last_rate_sample: Cell::new(Instant::now()),
             _phantom1: PhantomData,
             _phantom2: PhantomData,
         })
@@ -259,6 +436,9 @@ where
     ) {
         let this = Rc::clone(self);
         tokio::task::spawn_local(async move {
+            let stream = this
+                .update_connection(peer, |c| c.bytes.clone())
+                .count(stream);
             let (r, w) = TokioAsyncReadCompatExt::compat(stream).split();
             let mut net = Box::new(VatNetwork::new(
                 BufReader::new(r),
@@ -267,10 +447,26 @@ where
                 Default::default(),
             ));
             let until_shutdown = net.drive_until_shutdown();
-            let system = RpcSystem::new(net, Some(this.handler.server(peer)));
+            let mut system = RpcSystem::new(net, Some(this.handler.server(peer, this.clients())));
+            let client: C = system.bootstrap(Side::Client);
             let disconnector = system.get_disconnector();
             tokio::task::spawn_local(system);
 
+            let id = this.last_incoming_id.get() + 1;
+            this.last_incoming_id.set(id);
+            this.connections
+                .borrow_mut()
+                .entry(peer)
+                .or_insert_with(TrackedPeerConnections::new)
+                .incoming_client = Some((id, client));
+            scopeguard::defer!({
+                if let Some(conn) = this.connections.borrow_mut().get_mut(&peer)
+                    && conn.incoming_client.as_ref().map(|(i, _)| *i) == Some(id)
+                {
+                    conn.incoming_client = None;
+                }
+            });
+
             tokio::select!(
                 _ = shutdown_rx.recv() => {
                     let _ = disconnector.await;
@@ -287,6 +483,7 @@ where
     }
 
     fn keep_all_connected(self: &Rc<Self>) {
+        self.keep_all.set(true);
         for peer in self.networking.connectable_peers() {
             self.keep_peer_connected(peer);
         }
@@ -327,12 +524,21 @@ where
     }
 
     fn disconnect_all(self: &Rc<Self>) {
+        self.keep_all.set(false);
         let borrow = self.connections.borrow();
         for conn in borrow.values() {
             conn.cancel.cancel();
         }
     }
 
+    /// Connect to newly discovered peers, if all peers should be
+    /// kept connected.
+    fn peer_discovered(self: &Rc<Self>, peer: Peer) {
+        if self.keep_all.get() {
+            self.keep_peer_connected(peer);
+        }
+    }
+
     async fn track_peer(self: &Rc<Self>, peer: Peer, cancel: CancellationToken) {
         let retry_strategy =
             ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(5 * 60));
@@ -345,6 +551,10 @@ where
                         return;
                     }
                     Some(delay) => {
+                        self.update_connection(peer, |c| {
+                            c.next_attempt = Some(UnixTime::now().plus(delay))
+                        });
+                        scopeguard::defer!(self.update_connection(peer, |c| c.next_attempt = None));
                         tokio::select!(
                         _ = cancel.cancelled() => { return },
                         _ = tokio::time::sleep(delay) => {});
@@ -367,11 +577,16 @@ where
                     },
                     Err(err) => {
                         log::debug!("Failed to connect to {peer}: {err}; Will retry.");
+                        self.connection_failed(peer, &err);
                         continue;
                     }
                 }
             );
 
+            let address = stream.get_ref().0.get_ref().peer_addr().ok();
+            let stream = self
+                .update_connection(peer, |c| c.bytes.clone())
+                .count(stream);
             let (r, w) = TokioAsyncReadCompatExt::compat(stream).split();
             let mut net = Box::new(VatNetwork::new(
                 BufReader::new(r),
@@ -380,7 +595,14 @@ where
                 Default::default(),
             ));
             let until_shutdown = net.drive_until_shutdown();
-            let mut system = RpcSystem::new(net, None);
+            // If relaying is allowed for the peer, serve it on this
+            // connection as well, so it can reach us, or relay
+            // connections to us, even if it can't connect to us.
+            let server = self
+                .networking
+                .is_relay(peer)
+                .then(|| self.handler.server(peer, self.clients()));
+            let mut system = RpcSystem::new(net, server);
             let mut client: C = system.bootstrap(Side::Server);
             let disconnector = system.get_disconnector();
             scopeguard::defer!({
@@ -390,19 +612,35 @@ where
 
             if let Err(err) = self.handler.check_connection(peer, &mut client).await {
                 log::debug!("Bad connection to {peer}; will retry: {err}");
+                self.connection_failed(peer, &err);
                 continue;
             }
 
             self.set_tracked_client(peer, Some(client.clone()));
+            self.update_connection(peer, |c| {
+                c.address = address;
+                c.connected_since = Some(UnixTime::now());
+                c.failed_attempts = 0;
+                c.last_error = None;
+            });
             let _ = self.broadcast_tx.send(PeerStatus::Connected(peer));
             scopeguard::defer!({
                 self.set_tracked_client(peer, None);
+                self.update_connection(peer, |c| {
+                    c.registered = false;
+                    c.address = None;
+                    c.connected_since = None;
+                });
                 let _ = self.broadcast_tx.send(PeerStatus::Disconnected(peer));
             });
 
             if let Err(err) = self.handler.register(peer, client).await {
                 log::debug!("Registration on {peer} failed; Keeping connection: {err}");
+                self.update_connection(peer, |c| {
+                    c.last_error = Some(format!("registration failed: {err:#}"))
+                });
             } else {
+                self.update_connection(peer, |c| c.registered = true);
                 let _ = self.broadcast_tx.send(PeerStatus::Registered(peer));
             }
 
@@ -414,7 +652,10 @@ where
                     return;
                 }
                 res = until_shutdown => if let Err(err) = res {
-                    log::debug!("Connection to {peer} was shutdown: {err}; Will reconnect.")
+                    log::debug!("Connection to {peer} was shutdown: {err}; Will reconnect.");
+                    self.update_connection(peer, |c| {
+                        c.last_error = Some(format!("connection lost: {err}"))
+                    });
                 },
             );
         }
@@ -429,28 +670,153 @@ where
             .tracked_client = client;
     }
 
-    /// Find a connected client from the given peer list and send it to the handler
-    /// together with the operation for execution.
+    /// Find the connected clients from the given peer list and send
+    /// them to the handler together with the operation for execution.
     fn with_any_peer_client(self: &Rc<Self>, peers: Vec<Peer>, operation: O) {
-        let client = self.first_connected_peer(peers);
+        let clients = self.clients().all_connected(peers);
         let this = Rc::clone(self);
         tokio::task::spawn_local(async move {
-            this.handler.execute(client, operation).await;
+            this.handler.execute(clients, operation).await;
         });
     }
 
-    fn first_connected_peer(&self, peers: Vec<Peer>) -> Option<(Peer, C)> {
-        let tracked = self.connections.borrow();
+    /// Apply `f` to the connections of the given peer.
+    fn update_connection<R>(
+        &self,
+        peer: Peer,
+        f: impl FnOnce(&mut TrackedPeerConnections<C>) -> R,
+    ) -> R {
+        f(self
+            .connections
+            .borrow_mut()
+            .entry(peer)
+            .or_insert_with(TrackedPeerConnections::new))
+    }
 
-        for peer in peers {
-            if let Some(conn) = tracked.get(&peer) {
-                if let Some(client) = &conn.tracked_client {
-                    return Some((peer, client.clone()));
+    /// Remember that an attempt to connect to the peer failed.
+    fn connection_failed(&self, peer: Peer, err: &anyhow::Error) {
+        self.update_connection(peer, |c| {
+            c.last_error = Some(format!("{err:#}"));
+            c.failed_attempts += 1;
+        });
+    }
+
+    /// Compute the transfer rates of all peers.
+    fn sample_rates(&self) {
+        let now = Instant::now();
+        let elapsed = now
+            .duration_since(self.last_rate_sample.replace(now))
+            .as_secs_f64();
+        if elapsed <= 0.0 {
+            return;
+        }
+        for conn in self.connections.borrow_mut().values_mut() {
+            conn.sample_rate(elapsed);
+        }
+    }
+
+    /// Connection information of the known peers and of any peer
+    /// with a connection, ordered by peer.
+    fn peers(&self) -> Vec<PeerInfo> {
+        let tracked = self.connections.borrow();
+        let mut peers = self
+            .networking
+            .known_peers()
+            .chain(tracked.keys().copied())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        peers
+            .into_iter()
+            .map(|peer| {
+                let limit = self.networking.current_limit(peer);
+                match tracked.get(&peer) {
+                    Some(conn) => conn.info(peer, limit),
+                    None => TrackedPeerConnections::<C>::new().info(peer, limit),
                 }
-            }
+            })
+            .collect()
+    }
+
+    fn clients(&self) -> PeerClients<C> {
+        PeerClients {
+            connections: Rc::downgrade(&self.connections),
         }
+    }
+}
 
-        None
+/// Give access to the clients of connected peers.
+///
+/// This is passed to [ConnectionHandler::server] and must only be
+/// used on the [LocalSet] of the [ConnectionManager].
+pub struct PeerClients<C> {
+    connections: Weak<RefCell<HashMap<Peer, TrackedPeerConnections<C>>>>,
+}
+
+impl<C> Clone for PeerClients<C> {
+    fn clone(&self) -> Self {
+        Self {
+            connections: Weak::clone(&self.connections),
+        }
+    }
+}
+
+impl<C: Clone> PeerClients<C> {
+    /// Return the client of the first peer from the list that we're
+    /// connected to.
+    pub fn connected(&self, peers: impl IntoIterator<Item = Peer>) -> Option<(Peer, C)> {
+        let connections = self.connections.upgrade()?;
+        let tracked = connections.borrow();
+
+        peers.into_iter().find_map(|peer| {
+            tracked
+                .get(&peer)
+                .and_then(|conn| conn.tracked_client.as_ref())
+                .map(|client| (peer, client.clone()))
+        })
+    }
+
+    /// Return the clients of all peers from the list that we're
+    /// connected to, in the same order.
+    pub fn all_connected(&self, peers: impl IntoIterator<Item = Peer>) -> Vec<(Peer, C)> {
+        let Some(connections) = self.connections.upgrade() else {
+            return vec![];
+        };
+        let tracked = connections.borrow();
+
+        peers
+            .into_iter()
+            .filter_map(|peer| {
+                tracked
+                    .get(&peer)
+                    .and_then(|conn| conn.tracked_client.as_ref())
+                    .map(|client| (peer, client.clone()))
+            })
+            .collect()
+    }
+
+    /// Return the client of the first peer from the list that we're
+    /// connected to or, if there are none, that is connected to us.
+    ///
+    /// Peers that connect to us make their own server available on
+    /// the connection. This allows reaching peers that can't be
+    /// connected to.
+    pub fn reachable(&self, peers: impl IntoIterator<Item = Peer>) -> Option<(Peer, C)> {
+        let peers = peers.into_iter().collect::<Vec<_>>();
+        if let Some(ret) = self.connected(peers.iter().copied()) {
+            return Some(ret);
+        }
+        let connections = self.connections.upgrade()?;
+        let tracked = connections.borrow();
+
+        peers.into_iter().find_map(|peer| {
+            tracked
+                .get(&peer)
+                .and_then(|conn| conn.incoming_client.as_ref())
+                .map(|(_, client)| (peer, client.clone()))
+        })
     }
 }
 
@@ -503,10 +869,15 @@ mod tests {
             b"HELO"
         }
 
-        fn server(&self, peer: Peer) -> capnp::capability::Client {
+        fn server(
+            &self,
+            peer: Peer,
+            clients: PeerClients<hello::Client>,
+        ) -> capnp::capability::Client {
             let c: hello::Client = capnp_rpc::new_client(HelloServer {
                 this_peer: self.peer,
                 other_peer: peer,
+                clients,
             });
 
             c.client
@@ -539,10 +910,10 @@ mod tests {
             Ok(())
         }
 
-        async fn execute(&self, client: Option<(Peer, hello::Client)>, op: HelloOperation) {
+        async fn execute(&self, clients: Vec<(Peer, hello::Client)>, op: HelloOperation) {
             match op {
                 HelloOperation::Hello(name, tx) => {
-                    let _ = tx.send(execute_hello(&name, client).await);
+                    let _ = tx.send(execute_hello(&name, clients.into_iter().next()).await);
                 }
             }
         }
@@ -570,6 +941,7 @@ mod tests {
     struct HelloServer {
         this_peer: Peer,
         other_peer: Peer,
+        clients: PeerClients<hello::Client>,
     }
 
     impl hello::Server for HelloServer {
@@ -579,6 +951,25 @@ mod tests {
             mut results: HelloResults,
         ) -> Promise<(), capnp::Error> {
             let name = pry!(pry!(pry!(params.get()).get_name()).to_str());
+            if let Some(target) = name.strip_prefix("relay:") {
+                // Forward hello to another peer, as the other peer.
+                let target = Peer::from(target);
+                let (_, client) = pry!(
+                    self.clients
+                        .reachable([target])
+                        .ok_or_else(|| capnp::Error::failed(format!("{target} not reachable")))
+                );
+                let mut request = client.hello_request();
+                request.get().set_name(self.other_peer.as_str());
+                return Promise::from_future(async move {
+                    let reply = request.send().promise.await?;
+                    results
+                        .get()
+                        .set_result(reply.get()?.get_result()?.to_str()?);
+
+                    Ok(())
+                });
+            }
             results.get().set_result(format!(
                 "Hello {name} -- From {} to {}",
                 self.this_peer, self.other_peer,
@@ -723,6 +1114,94 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn peer_info() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let local = LocalSet::new();
+
+        let a = a();
+        let b = b();
+        let addr_b = fixture.peers.pick_port(b)?;
+        // Nothing listens to C's address.
+        let c = c();
+        fixture.peers.pick_port(c)?;
+
+        let manager_a = fixture.manager(&local, a)?;
+        let manager_b = fixture.manager(&local, b)?;
+        let _server_b = fixture.launch_server(b, &manager_b).await?;
+
+        local
+            .run_until(async {
+                let mut status_a = manager_a.peer_status();
+                manager_a.keep_peer_connected(b)?;
+                assert_eq!(PeerStatus::Connected(b), status_a.recv().await?);
+                assert_eq!(PeerStatus::Registered(b), status_a.recv().await?);
+                manager_a.keep_peer_connected(c)?;
+
+                let info = manager_a.peers().await?;
+                assert_eq!(
+                    vec![b, c],
+                    info.iter().map(|i| i.status.peer()).collect::<Vec<_>>()
+                );
+                let info_b = &info[0];
+                assert_eq!(PeerStatus::Registered(b), info_b.status);
+                assert_eq!(Some(addr_b.addr()), info_b.address);
+                assert!(info_b.connected_since.is_some());
+                assert!(!info_b.incoming);
+                assert!(info_b.bytes_sent > 0);
+                assert!(info_b.bytes_received > 0);
+                assert_eq!(0, info_b.failed_attempts);
+                assert!(info_b.last_error.is_none());
+
+                // B sees the connection from A.
+                let info = manager_b.peers().await?;
+                let info_a = info.iter().find(|i| i.status.peer() == a).unwrap();
+                assert_eq!(PeerStatus::Disconnected(a), info_a.status);
+                assert!(info_a.incoming);
+                assert!(info_a.bytes_received > 0);
+
+                // Connecting to C fails and is retried later.
+                let info_c = loop {
+                    let info = manager_a.peers().await?;
+                    let info_c = info.into_iter().find(|i| i.status.peer() == c).unwrap();
+                    if info_c.next_attempt.is_some() {
+                        break info_c;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                };
+                assert_eq!(PeerStatus::Disconnected(c), info_c.status);
+                assert_eq!(1, info_c.failed_attempts);
+                assert!(info_c.last_error.is_some());
+                assert!(info_c.address.is_none());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        // Once C is up, the next attempt succeeds and the error is
+        // forgotten.
+        let manager_c = fixture.manager(&local, c)?;
+        let _server_c = fixture.launch_server(c, &manager_c).await?;
+        local
+            .run_until(async move {
+                let info_c = loop {
+                    let info = manager_a.peers().await?;
+                    let info_c = info.into_iter().find(|i| i.status.peer() == c).unwrap();
+                    if info_c.connected_since.is_some() {
+                        break info_c;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                };
+                assert_eq!(0, info_c.failed_attempts);
+                assert!(info_c.last_error.is_none());
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn manager_reconnects() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup().await?;
@@ -848,6 +1327,100 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn reach_peer_through_incoming_connection() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let local = LocalSet::new();
+
+        let a = a();
+        let b = b();
+        let c = c();
+        fixture.peers.pick_port(b)?;
+        fixture.peers.allow_relay(b)?;
+
+        // A and C can't be connected to.
+        let manager_a = fixture.manager(&local, a)?;
+        let manager_c = fixture.manager(&local, c)?;
+
+        let manager_b = fixture.manager(&local, b)?;
+        let _server_b = fixture.launch_server(b, &manager_b).await?;
+
+        local
+            .run_until(async move {
+                let mut status_a = manager_a.peer_status();
+                manager_a.keep_peer_connected(b)?;
+                assert_eq!(PeerStatus::Connected(b), status_a.recv().await?);
+                assert_eq!(PeerStatus::Registered(b), status_a.recv().await?);
+
+                let mut status_c = manager_c.peer_status();
+                manager_c.keep_peer_connected(b)?;
+                assert_eq!(PeerStatus::Connected(b), status_c.recv().await?);
+                assert_eq!(PeerStatus::Registered(b), status_c.recv().await?);
+
+                // A has no connection to C...
+                assert!(say_hello_to_peers(&manager_a, vec![c], "1").await.is_err());
+
+                // ...but B can use the connection from C.
+                assert_eq!(
+                    "Hello a -- From c to b",
+                    say_hello_to_peers(&manager_a, vec![b], "relay:c").await?
+                );
+
+                manager_a.disconnect_peer(b)?;
+                assert_eq!(PeerStatus::Disconnected(b), status_a.recv().await?);
+                manager_c.disconnect_peer(b)?;
+                assert_eq!(PeerStatus::Disconnected(b), status_c.recv().await?);
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn serve_outgoing_connection_only_to_relays() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let local = LocalSet::new();
+
+        let a = a();
+        let b = b();
+        let c = c();
+        fixture.peers.pick_port(b)?;
+
+        // A and C can't be connected to and don't allow relaying.
+        let manager_a = fixture.manager(&local, a)?;
+        let manager_c = fixture.manager(&local, c)?;
+
+        let manager_b = fixture.manager(&local, b)?;
+        let _server_b = fixture.launch_server(b, &manager_b).await?;
+
+        local
+            .run_until(async move {
+                let mut status_a = manager_a.peer_status();
+                manager_a.keep_peer_connected(b)?;
+                assert_eq!(PeerStatus::Connected(b), status_a.recv().await?);
+                assert_eq!(PeerStatus::Registered(b), status_a.recv().await?);
+
+                let mut status_c = manager_c.peer_status();
+                manager_c.keep_peer_connected(b)?;
+                assert_eq!(PeerStatus::Connected(b), status_c.recv().await?);
+                assert_eq!(PeerStatus::Registered(b), status_c.recv().await?);
+
+                // C doesn't serve B on its connection.
+                assert!(
+                    say_hello_to_peers(&manager_a, vec![b], "relay:c")
+                        .await
+                        .is_err()
+                );
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await?;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn hello_operation() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup().await?;
@@ -1,9 +1,25 @@
 use realize_types::Peer;
+use realize_types::schedule::{TimeWindow, WeekMinute};
+use serde::Deserialize as _;
 use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 #[derive(Clone, serde::Deserialize, serde::Serialize, Debug)]
 pub struct NetworkConfig {
+    #[serde(default)]
     pub peers: HashMap<Peer, PeerConfig>,
+
+    /// Announce this peer and discover other peers on the local
+    /// network, if set.
+    #[serde(default)]
+    pub discovery: Option<DiscoveryConfig>,
+
+    /// Public keys that must never be accepted, whatever peer they
+    /// belong to.
+    ///
+    /// Each key must be a PEM-encoded ED25519 public key.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revoked_keys: Vec<String>,
 }
 
 impl Default for NetworkConfig {
@@ -16,6 +32,8 @@ impl NetworkConfig {
     pub fn new() -> Self {
         NetworkConfig {
             peers: HashMap::new(),
+            discovery: None,
+            revoked_keys: vec![],
         }
     }
 }
@@ -25,15 +43,426 @@ impl NetworkConfig {
 /// A peer is identified by [realize_types::Peer].
 #[derive(Clone, serde::Deserialize, serde::Serialize, Debug)]
 pub struct PeerConfig {
-    /// Address of the peer, if available.
+    /// Addresses of the peer, if available, as host:port.
     ///
     /// Not all peers can be connected to. Peers that can should have
-    /// their address listed here.
-    pub address: Option<String>,
+    /// their addresses listed here. When there are more than one
+    /// address, or when an address resolves to more than one IP,
+    /// all of them are tried, see [crate::Networking::connect].
+    ///
+    /// The configuration also accepts a single string, under the
+    /// name `address`.
+    #[serde(
+        default,
+        alias = "address",
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub addresses: Vec<String>,
 
     /// Specify the peer's public key that'll be used to identify
     /// the peer during connection.
     ///
-    /// Must be a PEM-encoded ED25519 public key.
+    /// Must be a PEM-encoded ED25519 public key. Can be left out if
+    /// the keys of the peer are listed in `pubkeys`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub pubkey: String,
+
+    /// Other public keys of the peer, possibly only valid for a
+    /// period of time.
+    ///
+    /// A connection is accepted if it uses any valid key of the peer,
+    /// which allows replacing the key of a peer without updating the
+    /// configuration of all other peers at the same time.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pubkeys: Vec<PeerKey>,
+
+    /// Allow relaying connections through or for this peer.
+    ///
+    /// When set, the current peer accepts to forward connections
+    /// from this peer to other peers it's connected to, and asks this
+    /// peer to do the same when it can't reach a peer directly.
+    /// Relaying only works if both peers allow it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub relay: bool,
+
+    /// Bandwidth limits shared by all connections to and from this
+    /// peer.
+    #[serde(default, skip_serializing_if = "PeerLimits::is_unlimited")]
+    pub limits: PeerLimits,
+}
+
+impl PeerConfig {
+    /// All keys of the peer, `pubkey` first, if set.
+    pub fn keys(&self) -> impl Iterator<Item = PeerKey> {
+        let main = if self.pubkey.is_empty() {
+            None
+        } else {
+            Some(PeerKey {
+                pubkey: self.pubkey.clone(),
+                not_before: None,
+                not_after: None,
+            })
+        };
+
+        main.into_iter().chain(self.pubkeys.iter().cloned())
+    }
+}
+
+/// A public key of a peer.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct PeerKey {
+    /// PEM-encoded ED25519 public key.
+    pub pubkey: String,
+
+    /// The key isn't accepted before that time, written as
+    /// "2025-01-31T00:00:00Z".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<jiff::Timestamp>,
+
+    /// The key isn't accepted after that time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<jiff::Timestamp>,
+}
+
+/// Upload and download limits, in bytes per second.
+///
+/// A limit that isn't set means unlimited.
+#[derive(Clone, Copy, Default, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct BandwidthLimit {
+    /// Limit on the data sent to the peer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_bytes_per_second: Option<u64>,
+
+    /// Limit on the data received from the peer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_bytes_per_second: Option<u64>,
+}
+
+impl BandwidthLimit {
+    /// No limit in either direction.
+    pub const UNLIMITED: BandwidthLimit = BandwidthLimit {
+        upload_bytes_per_second: None,
+        download_bytes_per_second: None,
+    };
+}
+
+/// Bandwidth limits of a peer, possibly depending on the time of day.
+///
+/// For example, to throttle a peer during the day on weekdays:
+///
+/// ```toml
+/// [peers.phone.limits]
+/// upload_bytes_per_second = 1048576
+///
+/// [[peers.phone.limits.schedule]]
+/// days = ["mon", "tue", "wed", "thu", "fri"]
+/// start = "08:00"
+/// end = "20:00"
+/// upload_bytes_per_second = 65536
+/// download_bytes_per_second = 262144
+/// ```
+#[derive(Clone, Default, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct PeerLimits {
+    /// Limits outside of the windows of `schedule`.
+    #[serde(flatten)]
+    pub default: BandwidthLimit,
+
+    /// Limits that replace `default` within a time window.
+    ///
+    /// If windows overlap, the first one wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduledLimit>,
+}
+
+impl PeerLimits {
+    /// Limits that never limit anything.
+    pub fn is_unlimited(&self) -> bool {
+        self.default == BandwidthLimit::UNLIMITED
+            && self
+                .schedule
+                .iter()
+                .all(|s| s.limit == BandwidthLimit::UNLIMITED)
+    }
+
+    /// Limits that apply at time `now`.
+    pub fn at(&self, now: WeekMinute) -> BandwidthLimit {
+        self.schedule
+            .iter()
+            .find(|s| s.window.contains(now))
+            .map(|s| s.limit)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Bandwidth limits that apply within a time window.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct ScheduledLimit {
+    #[serde(flatten)]
+    pub window: TimeWindow,
+
+    #[serde(flatten)]
+    pub limit: BandwidthLimit,
+}
+
+/// Configure LAN peer discovery.
+///
+/// See [crate::discovery].
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct DiscoveryConfig {
+    /// Address and port announcements are sent to and received from.
+    ///
+    /// This is normally a multicast group, but it can also be a
+    /// broadcast address or, for tests, a unicast address.
+    #[serde(default = "default_discovery_group")]
+    pub group: SocketAddr,
+
+    /// Time between two announcements, in seconds. Must not be 0.
+    ///
+    /// Addresses discovered from the announcements of a peer are
+    /// forgotten after three intervals without announcements.
+    #[serde(
+        default = "default_discovery_interval_secs",
+        deserialize_with = "non_zero"
+    )]
+    pub interval_secs: u64,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            group: default_discovery_group(),
+            interval_secs: default_discovery_interval_secs(),
+        }
+    }
+}
+
+fn default_discovery_group() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 97, 71)), 9772)
+}
+
+fn default_discovery_interval_secs() -> u64 {
+    30
+}
+
+/// Deserialize either a single string or a list of strings.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+/// Deserialize a number that must not be 0.
+fn non_zero<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = <u64 as serde::Deserialize>::deserialize(deserializer)?;
+    if value == 0 {
+        return Err(serde::de::Error::custom("must not be 0"));
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use realize_types::schedule::{TimeOfDay, Weekday};
+
+    #[test]
+    fn parse_single_address() -> anyhow::Result<()> {
+        let config: PeerConfig = toml::from_str(
+            r#"
+address = "localhost:1234"
+pubkey = "key"
+"#,
+        )?;
+        assert_eq!(vec!["localhost:1234".to_string()], config.addresses);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_address_list() -> anyhow::Result<()> {
+        let config: PeerConfig = toml::from_str(
+            r#"
+addresses = ["localhost:1234", "192.168.1.2:1234"]
+pubkey = "key"
+"#,
+        )?;
+        assert_eq!(
+            vec!["localhost:1234".to_string(), "192.168.1.2:1234".to_string()],
+            config.addresses
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_discovery() -> anyhow::Result<()> {
+        let config: NetworkConfig = toml::from_str(
+            r#"
+[peers]
+
+[discovery]
+"#,
+        )?;
+        assert_eq!(Some(DiscoveryConfig::default()), config.discovery);
+
+        let config: NetworkConfig = toml::from_str(
+            r#"
+[peers]
+
+[discovery]
+group = "127.0.0.1:1234"
+interval_secs = 5
+"#,
+        )?;
+        assert_eq!(
+            Some(DiscoveryConfig {
+                group: "127.0.0.1:1234".parse()?,
+                interval_secs: 5,
+            }),
+            config.discovery
+        );
+
+        let config: NetworkConfig = toml::from_str("[peers]\n")?;
+        assert_eq!(None, config.discovery);
+
+        assert!(
+            toml::from_str::<NetworkConfig>(
+                r#"
+[peers]
+
+[discovery]
+interval_secs = 0
+"#,
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_limits() -> anyhow::Result<()> {
+        let config: PeerConfig = toml::from_str(
+            r#"
+pubkey = "key"
+
+[limits]
+upload_bytes_per_second = 1000
+
+[[limits.schedule]]
+days = ["mon"]
+start = "08:00"
+end = "20:00"
+download_bytes_per_second = 50
+"#,
+        )?;
+        let limits = &config.limits;
+        assert!(!limits.is_unlimited());
+
+        let monday_noon = WeekMinute::new(Weekday::Mon, TimeOfDay::new(12, 0).unwrap());
+        assert_eq!(
+            BandwidthLimit {
+                upload_bytes_per_second: None,
+                download_bytes_per_second: Some(50),
+            },
+            limits.at(monday_noon)
+        );
+
+        let tuesday_noon = WeekMinute::new(Weekday::Tue, TimeOfDay::new(12, 0).unwrap());
+        assert_eq!(
+            BandwidthLimit {
+                upload_bytes_per_second: Some(1000),
+                download_bytes_per_second: None,
+            },
+            limits.at(tuesday_noon)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_no_limits() -> anyhow::Result<()> {
+        let config: PeerConfig = toml::from_str(
+            r#"
+pubkey = "key"
+"#,
+        )?;
+        assert!(config.limits.is_unlimited());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_pubkeys() -> anyhow::Result<()> {
+        let config: NetworkConfig = toml::from_str(
+            r#"
+revoked_keys = ["revoked"]
+
+[peers.a]
+pubkey = "key1"
+
+[[peers.a.pubkeys]]
+pubkey = "key2"
+not_before = "2025-01-01T00:00:00Z"
+not_after = "2026-01-01T00:00:00Z"
+
+[peers.b]
+pubkeys = [{ pubkey = "key3" }]
+"#,
+        )?;
+        assert_eq!(vec!["revoked".to_string()], config.revoked_keys);
+
+        let a = config.peers.get(&Peer::from("a")).unwrap();
+        assert_eq!(
+            vec![
+                PeerKey {
+                    pubkey: "key1".to_string(),
+                    not_before: None,
+                    not_after: None,
+                },
+                PeerKey {
+                    pubkey: "key2".to_string(),
+                    not_before: Some("2025-01-01T00:00:00Z".parse()?),
+                    not_after: Some("2026-01-01T00:00:00Z".parse()?),
+                },
+            ],
+            a.keys().collect::<Vec<_>>()
+        );
+
+        let b = config.peers.get(&Peer::from("b")).unwrap();
+        assert_eq!(
+            vec!["key3".to_string()],
+            b.keys().map(|k| k.pubkey).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_no_address() -> anyhow::Result<()> {
+        let config: PeerConfig = toml::from_str(
+            r#"
+pubkey = "key"
+"#,
+        )?;
+        assert!(config.addresses.is_empty());
+
+        Ok(())
+    }
 }
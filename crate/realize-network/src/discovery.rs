@@ -0,0 +1,425 @@
+//! Discover peers on the local network.
+//!
+//! Peers that have discovery enabled periodically send a small
+//! announcement over UDP to a multicast group. The announcement
+//! contains the [Peer] id, the port the peer listens to and a
+//! timestamp, signed with the peer's ED25519 private key.
+//!
+//! Peers that receive an announcement check the signature against
+//! the public key configured for that peer. If it matches, the
+//! address the announcement came from, with the announced port, is
+//! added to the addresses of the peer in [Networking].
+//!
+//! The address the announcement came from isn't signed, so an
+//! announcement is only accepted if it is more recent than the last
+//! one accepted for the same peer; a captured announcement can't be
+//! replayed from another address. Discovered addresses are forgotten
+//! if the peer stops announcing them.
+
+use crate::Networking;
+use crate::config::DiscoveryConfig;
+use crate::security::{PeerVerifier, RawPublicKeyResolver};
+use realize_types::Peer;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+/// Marks the start of an announcement.
+const MAGIC: &[u8; 4] = b"RLZA";
+
+/// Version of the announcement format.
+const VERSION: u8 = 1;
+
+/// Size of the fixed part of an announcement: magic, version,
+/// timestamp, port and length of the peer name.
+const HEADER_LEN: usize = 4 + 1 + 8 + 2 + 1;
+
+/// Maximum size of an announcement.
+const MAX_ANNOUNCEMENT_LEN: usize = 512;
+
+/// Announcements whose timestamp is further away from the current
+/// time are ignored.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// A discovered address is forgotten after that many announcement
+/// intervals without a new announcement.
+const MISSED_ANNOUNCEMENTS_BEFORE_EXPIRY: u32 = 3;
+
+/// Time to wait before receiving again after an error.
+const RECEIVE_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// LAN discovery, started by [Discovery::spawn].
+///
+/// Announcements are sent and received until the instance is
+/// dropped.
+pub struct Discovery {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Discovery {
+    /// Announce the current peer, listening on `port`, and listen to
+    /// announcements of other peers.
+    ///
+    /// The current peer must be part of the configured peers, as
+    /// other peers know it by the id it has there.
+    pub async fn spawn(
+        networking: Networking,
+        config: &DiscoveryConfig,
+        port: u16,
+    ) -> anyhow::Result<Self> {
+        // Tasks already started are aborted if a later step fails.
+        let mut discovery = Self { tasks: vec![] };
+        discovery
+            .tasks
+            .push(start_announcer(networking.clone(), config, port).await?);
+        discovery
+            .tasks
+            .push(start_listener(networking, config).await?);
+
+        Ok(discovery)
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Send announcements to the group every `config.interval_secs`.
+async fn start_announcer(
+    networking: Networking,
+    config: &DiscoveryConfig,
+    port: u16,
+) -> anyhow::Result<JoinHandle<()>> {
+    let local = networking.local_peer().ok_or_else(|| {
+        anyhow::anyhow!("discovery requires the current peer to be in the peer list")
+    })?;
+    let group = config.group;
+    if config.interval_secs == 0 {
+        anyhow::bail!("discovery interval must not be 0");
+    }
+    let interval = Duration::from_secs(config.interval_secs);
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified(group.ip()), 0)).await?;
+    socket.set_broadcast(true)?;
+    log::debug!("Announcing {local} on port {port} to {group}");
+
+    Ok(tokio::spawn(async move {
+        loop {
+            match encode(networking.resolver(), local, port, unix_now()) {
+                Ok(announcement) => {
+                    if let Err(err) = socket.send_to(&announcement, group).await {
+                        log::debug!("Failed to send announcement to {group}: {err}");
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Cannot announce {local}: {err}");
+                    return;
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }))
+}
+
+/// Receive announcements sent to the group and add the addresses of
+/// the announced peers to `networking`.
+async fn start_listener(
+    networking: Networking,
+    config: &DiscoveryConfig,
+) -> anyhow::Result<JoinHandle<()>> {
+    let socket = bind_group(config.group)?;
+    let local = networking.local_peer();
+    let ttl = Duration::from_secs(config.interval_secs) * MISSED_ANNOUNCEMENTS_BEFORE_EXPIRY;
+    log::debug!("Listening to announcements on {}", config.group);
+
+    Ok(tokio::spawn(async move {
+        let mut buf = [0u8; MAX_ANNOUNCEMENT_LEN];
+        // Timestamp of the last announcement accepted for each peer.
+        let mut last_accepted: HashMap<Peer, u64> = HashMap::new();
+        loop {
+            let (len, from) = match socket.recv_from(&mut buf).await {
+                Ok(ret) => ret,
+                Err(err) => {
+                    log::debug!("Failed to receive announcement: {err}");
+                    tokio::time::sleep(RECEIVE_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            match decode(networking.verifier(), &buf[..len], unix_now()) {
+                Ok((peer, port, timestamp)) => {
+                    if Some(peer) == local {
+                        continue;
+                    }
+                    if !accept_timestamp(&mut last_accepted, peer, timestamp) {
+                        log::debug!("Ignored announcement of {peer} from {from}: replayed");
+                        continue;
+                    }
+                    networking.add_discovered_address(
+                        peer,
+                        SocketAddr::new(from.ip(), port),
+                        Some(ttl),
+                    );
+                }
+                Err(err) => log::debug!("Ignored announcement from {from}: {err}"),
+            }
+        }
+    }))
+}
+
+/// Check whether an announcement of `peer` sent at `timestamp` is
+/// more recent than the last one accepted and, if it is, remember it
+/// as the last one accepted.
+fn accept_timestamp(last_accepted: &mut HashMap<Peer, u64>, peer: Peer, timestamp: u64) -> bool {
+    if let Some(last) = last_accepted.get(&peer)
+        && timestamp <= *last
+    {
+        return false;
+    }
+    last_accepted.insert(peer, timestamp);
+
+    true
+}
+
+/// Bind a socket that receives packets sent to the group.
+fn bind_group(group: SocketAddr) -> anyhow::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(group), Type::DGRAM, Some(Protocol::UDP))?;
+    // Allow more than one process on the same host to listen.
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::new(unspecified(group.ip()), group.port()).into())?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    match group.ip() {
+        IpAddr::V4(ip) if ip.is_multicast() => {
+            socket.join_multicast_v4(ip, Ipv4Addr::UNSPECIFIED)?;
+        }
+        IpAddr::V6(ip) if ip.is_multicast() => {
+            socket.join_multicast_v6(&ip, 0)?;
+        }
+        _ => {}
+    }
+
+    Ok(socket)
+}
+
+/// The unspecified address of the same family as `ip`.
+fn unspecified(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+/// Current time, in seconds since the epoch.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Build the part of an announcement that is signed.
+fn signed_part(peer: Peer, port: u16, timestamp: u64) -> anyhow::Result<Vec<u8>> {
+    let name = peer.as_str().as_bytes();
+    let name_len = u8::try_from(name.len())
+        .map_err(|_| anyhow::anyhow!("peer name too long to be announced: {peer}"))?;
+    let mut buf = Vec::with_capacity(HEADER_LEN + name.len());
+    buf.extend_from_slice(MAGIC);
+    buf.push(VERSION);
+    buf.extend_from_slice(&timestamp.to_be_bytes());
+    buf.extend_from_slice(&port.to_be_bytes());
+    buf.push(name_len);
+    buf.extend_from_slice(name);
+
+    Ok(buf)
+}
+
+/// Build a signed announcement for `peer`, listening on `port`.
+fn encode(
+    resolver: &RawPublicKeyResolver,
+    peer: Peer,
+    port: u16,
+    timestamp: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buf = signed_part(peer, port, timestamp)?;
+    let signature = resolver.sign(&buf)?;
+    buf.extend_from_slice(&signature);
+
+    Ok(buf)
+}
+
+/// Parse an announcement and check its signature and timestamp.
+///
+/// Returns the announced peer, port and timestamp.
+fn decode(verifier: &PeerVerifier, data: &[u8], now: u64) -> anyhow::Result<(Peer, u16, u64)> {
+    if data.len() < HEADER_LEN || &data[0..4] != MAGIC {
+        anyhow::bail!("not an announcement");
+    }
+    if data[4] != VERSION {
+        anyhow::bail!("unsupported announcement version {}", data[4]);
+    }
+    let timestamp = u64::from_be_bytes(data[5..13].try_into()?);
+    let port = u16::from_be_bytes(data[13..15].try_into()?);
+    let signed_len = HEADER_LEN + data[15] as usize;
+    if data.len() <= signed_len {
+        anyhow::bail!("truncated announcement");
+    }
+    let name = std::str::from_utf8(&data[HEADER_LEN..signed_len])?;
+    let peer = verifier
+        .known_peer(name)
+        .ok_or_else(|| anyhow::anyhow!("unknown peer {name}"))?;
+    if !verifier.verify_peer_signature(peer, &data[..signed_len], &data[signed_len..]) {
+        anyhow::bail!("bad signature for {peer}");
+    }
+    if timestamp.abs_diff(now) > MAX_CLOCK_SKEW.as_secs() {
+        anyhow::bail!("announcement of {peer} is too old or too far in the future");
+    }
+
+    Ok((peer, port, timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TestingPeers};
+    use rustls::pki_types::PrivateKeyDer;
+    use std::sync::Arc;
+
+    fn verifier() -> PeerVerifier {
+        let mut verifier = PeerVerifier::new();
+        verifier.add_peer(TestingPeers::a(), testing::client_public_key());
+        verifier.add_peer(TestingPeers::b(), testing::server_public_key());
+
+        verifier
+    }
+
+    fn networking(private_key: PrivateKeyDer<'static>) -> anyhow::Result<Networking> {
+        Ok(Networking::new(
+            vec![],
+            RawPublicKeyResolver::from_private_key(private_key)?,
+            Arc::new(verifier()),
+        ))
+    }
+
+    #[test]
+    fn encode_and_decode() -> anyhow::Result<()> {
+        let resolver = RawPublicKeyResolver::from_private_key(testing::client_private_key())?;
+        let announcement = encode(&resolver, TestingPeers::a(), 1234, 1000)?;
+
+        assert_eq!(
+            (TestingPeers::a(), 1234, 1000),
+            decode(&verifier(), &announcement, 1000)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_rejects_bad_announcements() -> anyhow::Result<()> {
+        let verifier = verifier();
+        let resolver = RawPublicKeyResolver::from_private_key(testing::client_private_key())?;
+        let announcement = encode(&resolver, TestingPeers::a(), 1234, 1000)?;
+
+        // Not an announcement
+        assert!(decode(&verifier, b"hello, world", 1000).is_err());
+        assert!(decode(&verifier, &announcement[..HEADER_LEN], 1000).is_err());
+
+        // Modified port
+        let mut modified = announcement.clone();
+        modified[14] += 1;
+        assert!(decode(&verifier, &modified, 1000).is_err());
+
+        // Signed with the key of another peer
+        let other = RawPublicKeyResolver::from_private_key(testing::other_private_key())?;
+        let forged = encode(&other, TestingPeers::a(), 1234, 1000)?;
+        assert!(decode(&verifier, &forged, 1000).is_err());
+
+        // Unknown peer
+        let unknown = encode(&resolver, TestingPeers::c(), 1234, 1000)?;
+        assert!(decode(&verifier, &unknown, 1000).is_err());
+
+        // Too old or too far in the future
+        assert!(decode(&verifier, &announcement, 1000 + 3600).is_err());
+        assert!(decode(&verifier, &announcement, 1000 - 900).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_replayed_announcements() {
+        let mut last_accepted = HashMap::new();
+        let a = TestingPeers::a();
+        let b = TestingPeers::b();
+
+        assert!(accept_timestamp(&mut last_accepted, a, 1000));
+        assert!(!accept_timestamp(&mut last_accepted, a, 1000));
+        assert!(!accept_timestamp(&mut last_accepted, a, 999));
+        assert!(accept_timestamp(&mut last_accepted, b, 999));
+        assert!(accept_timestamp(&mut last_accepted, a, 1030));
+    }
+
+    #[tokio::test]
+    async fn discover_peer_over_loopback() -> anyhow::Result<()> {
+        let _ = env_logger::try_init();
+
+        let port = portpicker::pick_unused_port().expect("No ports free");
+        let config = DiscoveryConfig {
+            group: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+            interval_secs: 1,
+        };
+        let a = networking(testing::client_private_key())?;
+        let b = networking(testing::server_private_key())?;
+        assert!(!b.is_connectable(TestingPeers::a()));
+
+        let mut discovered = b.discovered_peers();
+        let _discovery = Discovery {
+            tasks: vec![
+                start_listener(b.clone(), &config).await?,
+                start_announcer(a, &config, 1234).await?,
+            ],
+        };
+
+        let peer = tokio::time::timeout(Duration::from_secs(5), discovered.recv()).await??;
+        assert_eq!(TestingPeers::a(), peer);
+        assert_eq!(
+            Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1234)),
+            b.discovered_address(TestingPeers::a())
+        );
+        assert!(b.is_connectable(TestingPeers::a()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn announce_requires_local_peer() -> anyhow::Result<()> {
+        let port = portpicker::pick_unused_port().expect("No ports free");
+        let config = DiscoveryConfig {
+            group: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+            interval_secs: 1,
+        };
+        let c = networking(testing::other_private_key())?;
+
+        assert!(Discovery::spawn(c, &config, 1234).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn announce_rejects_zero_interval() -> anyhow::Result<()> {
+        let port = portpicker::pick_unused_port().expect("No ports free");
+        let config = DiscoveryConfig {
+            group: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+            interval_secs: 0,
+        };
+        let a = networking(testing::client_private_key())?;
+
+        assert!(start_announcer(a, &config, 1234).await.is_err());
+
+        Ok(())
+    }
+}
@@ -12,32 +12,36 @@ pub struct HostPort {
 
 impl HostPort {
     /// Parse a host:port string, resolve DNS, and return a HostPort.
+    ///
+    /// If the host resolves to more than one address, the first one
+    /// is used. Use [HostPort::resolve_all] to get all of them.
     pub async fn parse(s: &str) -> anyhow::Result<Self> {
-        // Handle [::1]:port for IPv6
-        let (host, port) = if let Some(idx) = s.rfind(':') {
-            let (host, port_str) = s.split_at(idx);
-            let port = port_str[1..]
-                .parse::<u16>()
-                .map_err(|_| anyhow::anyhow!("Invalid port in address: {s}"))?;
-            let host = if host.starts_with('[') && host.ends_with(']') {
-                &host[1..host.len() - 1]
-            } else {
-                host
-            };
-            (host.to_string(), port)
-        } else {
-            return Err(anyhow::anyhow!("Missing port in address: {s}"));
-        };
-        // DNS resolution
-        let mut addrs = tokio::net::lookup_host(s)
-            .await
-            .with_context(|| format!("DNS lookup failed for {s}"))?;
-        let addr = addrs
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("DNS lookup failed for {s}"))?;
+        let (host, port) = split_host_port(s)?;
+        let addr = lookup(s).await?.remove(0);
+
         Ok(HostPort { host, port, addr })
     }
 
+    /// Parse a host:port string, resolve DNS, and return one
+    /// HostPort per resolved address.
+    ///
+    /// Addresses are sorted as described in RFC 8305, section 4:
+    /// IPv6 and IPv4 addresses are interleaved, starting with IPv6,
+    /// so that trying them in order quickly falls back from one
+    /// family to the other.
+    pub async fn resolve_all(s: &str) -> anyhow::Result<Vec<Self>> {
+        let (host, port) = split_host_port(s)?;
+
+        Ok(interleave_families(lookup(s).await?)
+            .into_iter()
+            .map(|addr| HostPort {
+                host: host.clone(),
+                port,
+                addr,
+            })
+            .collect())
+    }
+
     /// Create a hostport on 127.0.0.1 with the given port.
     pub fn localhost(port: u16) -> Self {
         SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port).into()
@@ -54,6 +58,60 @@ impl HostPort {
     }
 }
 
+/// Split a host:port string into its host and port.
+fn split_host_port(s: &str) -> anyhow::Result<(String, u16)> {
+    let idx = s
+        .rfind(':')
+        .ok_or_else(|| anyhow::anyhow!("Missing port in address: {s}"))?;
+    let (host, port_str) = s.split_at(idx);
+    let port = port_str[1..]
+        .parse::<u16>()
+        .map_err(|_| anyhow::anyhow!("Invalid port in address: {s}"))?;
+    // Handle [::1]:port for IPv6
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    };
+
+    Ok((host.to_string(), port))
+}
+
+/// Resolve a host:port string into a non-empty list of addresses.
+async fn lookup(s: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs = tokio::net::lookup_host(s)
+        .await
+        .with_context(|| format!("DNS lookup failed for {s}"))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(anyhow::anyhow!("DNS lookup failed for {s}"));
+    }
+
+    Ok(addrs)
+}
+
+/// Interleave IPv6 and IPv4 addresses, starting with IPv6.
+///
+/// The relative order of addresses of the same family is kept.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let len = addrs.len();
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|a| a.is_ipv6());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut ret = Vec::with_capacity(len);
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => {
+                ret.extend(a);
+                ret.extend(b);
+            }
+        }
+    }
+
+    ret
+}
+
 impl fmt::Display for HostPort {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}:{}", self.host, self.port)
@@ -110,6 +168,38 @@ mod tests {
         assert!(HostPort::parse("doesnotexist:1000").await.is_err());
     }
 
+    #[tokio::test]
+    async fn test_resolve_all() {
+        let all = HostPort::resolve_all("127.0.0.1:8000").await.unwrap();
+        assert_eq!(vec![HostPort::localhost(8000)], all);
+
+        let all = HostPort::resolve_all("localhost:1234").await.unwrap();
+        assert!(!all.is_empty());
+        for hp in all {
+            assert_eq!(hp.host(), "localhost");
+            assert_eq!(hp.port(), 1234);
+            assert!(hp.addr().ip().is_loopback());
+        }
+
+        assert!(HostPort::resolve_all("myhost").await.is_err());
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let v4a: SocketAddr = "10.0.0.1:1".parse().unwrap();
+        let v4b: SocketAddr = "10.0.0.2:1".parse().unwrap();
+        let v4c: SocketAddr = "10.0.0.3:1".parse().unwrap();
+        let v6a: SocketAddr = "[fd00::1]:1".parse().unwrap();
+        let v6b: SocketAddr = "[fd00::2]:1".parse().unwrap();
+
+        assert_eq!(
+            vec![v6a, v4a, v6b, v4b, v4c],
+            interleave_families(vec![v4a, v4b, v6a, v4c, v6b])
+        );
+        assert_eq!(vec![v4a, v4b], interleave_families(vec![v4a, v4b]));
+        assert!(interleave_families(vec![]).is_empty());
+    }
+
     #[tokio::test]
     async fn test_from_socketaddr() {
         let hp1 = HostPort::parse("127.0.0.1:8000").await.unwrap();
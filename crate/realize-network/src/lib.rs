@@ -1,8 +1,11 @@
+mod byte_count;
 pub mod capnp;
 pub mod config;
+pub mod discovery;
 pub mod hostport;
 mod metrics;
 mod network;
+pub mod pairing;
 mod rate_limit;
 pub mod reconnect;
 pub mod security;
@@ -10,4 +13,4 @@ pub mod security;
 pub mod testing;
 pub mod unixsocket;
 
-pub use network::{Networking, Server};
+pub use network::{Networking, PendingKey, Server};
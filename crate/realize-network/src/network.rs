@@ -1,17 +1,20 @@
-use crate::config::PeerConfig;
+use crate::config::{BandwidthLimit, PeerConfig, PeerLimits};
 use crate::hostport::HostPort;
-use crate::rate_limit::RateLimitedStream;
-use crate::security::{PeerVerifier, RawPublicKeyResolver};
+use crate::pairing::{self, Invitation, PairedPeer, PairingRequest};
+use crate::rate_limit::{PeerLimiter, RateLimitedStream};
+use crate::security::{KeyRotation, PeerVerifier, RawPublicKeyResolver};
 use anyhow::Context as _;
 use async_speed_limit::Limiter;
 use async_speed_limit::clock::StandardClock;
 use futures::prelude::*;
-use realize_types::Peer;
-use rustls::pki_types::ServerName;
-use std::collections::HashMap;
+use realize_types::schedule::WeekMinute;
+use realize_types::{Peer, UnixTime};
+use rustls::pki_types::{ServerName, SubjectPublicKeyInfoDer, pem::PemObject as _};
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tarpc::tokio_serde::formats::Bincode;
 use tarpc::tokio_util::codec::length_delimited::LengthDelimitedCodec;
 use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
@@ -19,9 +22,23 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::broadcast;
 use tokio_rustls::{TlsAcceptor, TlsConnector};
 
+/// Delay before starting the next connection attempt while the
+/// previous ones are still pending, as recommended by RFC 8305,
+/// section 5.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
 #[derive(Clone)]
 pub struct Networking {
-    addresses: Arc<HashMap<Peer, String>>,
+    addresses: Arc<HashMap<Peer, Vec<String>>>,
+    last_working: Arc<Mutex<HashMap<Peer, SocketAddr>>>,
+    /// Discovered addresses, with the time they expire, if they do.
+    discovered: Arc<Mutex<HashMap<Peer, (SocketAddr, Option<Instant>)>>>,
+    discovered_tx: broadcast::Sender<Peer>,
+    paired_tx: broadcast::Sender<PairedPeer>,
+    relays: Arc<HashSet<Peer>>,
+    bandwidth: Arc<Mutex<HashMap<Peer, PeerBandwidth>>>,
+    pending_keys: Arc<Mutex<Vec<PendingKey>>>,
+    resolver: Arc<RawPublicKeyResolver>,
     verifier: Arc<PeerVerifier>,
     acceptor: Arc<TlsAcceptor>,
     connector: Arc<TlsConnector>,
@@ -36,16 +53,22 @@ impl Networking {
         let resolver = RawPublicKeyResolver::from_private_key_file(privkey)?;
         Ok(Self::new(
             peers.iter().flat_map(|(p, c)| {
-                c.address.as_ref().map(|addr| {
+                c.addresses.iter().map(|addr| {
                     let leaked: &'static str = addr.to_string().leak() as &'static str;
                     (p.clone(), leaked)
                 })
             }),
             resolver,
             verifier,
-        ))
+        )
+        .with_relays(peers.iter().filter(|(_, c)| c.relay).map(|(p, _)| *p))
+        .with_limits(peers.iter().map(|(p, c)| (*p, c.limits.clone()))))
     }
 
+    /// Create a new networking instance.
+    ///
+    /// A peer may appear more than once in `addresses`, if it can be
+    /// reached at more than one address.
     pub fn new<'a, T>(
         addresses: T,
         resolver: Arc<RawPublicKeyResolver>,
@@ -54,13 +77,22 @@ impl Networking {
     where
         T: IntoIterator<Item = (Peer, &'a str)>,
     {
+        let mut map: HashMap<Peer, Vec<String>> = HashMap::new();
+        for (peer, addr) in addresses {
+            map.entry(peer).or_default().push(addr.to_string());
+        }
+        let (discovered_tx, _) = broadcast::channel(16);
+        let (paired_tx, _) = broadcast::channel(16);
         Self {
-            addresses: Arc::new(
-                addresses
-                    .into_iter()
-                    .map(|(p, addr)| (p, addr.to_string()))
-                    .collect(),
-            ),
+            addresses: Arc::new(map),
+            last_working: Arc::new(Mutex::new(HashMap::new())),
+            discovered: Arc::new(Mutex::new(HashMap::new())),
+            discovered_tx,
+            paired_tx,
+            relays: Arc::new(HashSet::new()),
+            bandwidth: Arc::new(Mutex::new(HashMap::new())),
+            pending_keys: Arc::new(Mutex::new(vec![])),
+            resolver: Arc::clone(&resolver),
             verifier: Arc::clone(&verifier),
             acceptor: Arc::new(crate::security::make_tls_acceptor(
                 Arc::clone(&verifier),
@@ -70,16 +102,431 @@ impl Networking {
         }
     }
 
+    /// Allow relaying connections through or for the given peers.
+    ///
+    /// See [PeerConfig::relay].
+    pub fn with_relays(mut self, peers: impl IntoIterator<Item = Peer>) -> Self {
+        self.relays = Arc::new(peers.into_iter().collect());
+
+        self
+    }
+
+    /// Check whether relaying through or for the peer is allowed.
+    pub fn is_relay(&self, peer: Peer) -> bool {
+        self.relays.contains(&peer)
+    }
+
+    /// Peers relaying is allowed through or for.
+    pub fn relay_peers(&self) -> impl Iterator<Item = Peer> {
+        self.relays.iter().copied().collect::<Vec<_>>().into_iter()
+    }
+
+    /// Set the bandwidth limits of the given peers.
+    ///
+    /// See [PeerConfig::limits].
+    pub fn with_limits(self, limits: impl IntoIterator<Item = (Peer, PeerLimits)>) -> Self {
+        for (peer, limits) in limits {
+            self.set_limits(peer, limits);
+        }
+
+        self
+    }
+
+    /// Change the bandwidth limits of the peer.
+    ///
+    /// This takes effect immediately, including on connections that
+    /// are already open.
+    pub fn set_limits(&self, peer: Peer, limits: PeerLimits) {
+        let now = WeekMinute::now();
+        let mut bandwidth = self.bandwidth.lock().unwrap();
+        let entry = bandwidth.entry(peer).or_insert_with(PeerBandwidth::new);
+        entry.limits = limits;
+        entry.apply(now);
+    }
+
+    /// Bandwidth limits of the peer, as configured or last set by
+    /// [Networking::set_limits].
+    pub fn limits(&self, peer: Peer) -> PeerLimits {
+        self.bandwidth
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .map(|b| b.limits.clone())
+            .unwrap_or_default()
+    }
+
+    /// Bandwidth limits currently applied to the connections to and
+    /// from the peer.
+    pub fn current_limit(&self, peer: Peer) -> BandwidthLimit {
+        self.bandwidth
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .map(|b| b.current)
+            .unwrap_or_default()
+    }
+
+    /// Apply the limits of the time windows that just started or
+    /// ended.
+    ///
+    /// This must be called regularly for the schedules of
+    /// [PeerLimits] to have any effect. [crate::capnp::ConnectionManager]
+    /// takes care of it.
+    pub fn update_limits(&self) {
+        let now = WeekMinute::now();
+        for bandwidth in self.bandwidth.lock().unwrap().values_mut() {
+            bandwidth.apply(now);
+        }
+    }
+
+    /// The limiter shared by all connections to and from the peer.
+    fn peer_limiter(&self, peer: Peer) -> PeerLimiter {
+        self.bandwidth
+            .lock()
+            .unwrap()
+            .entry(peer)
+            .or_insert_with(PeerBandwidth::new)
+            .limiter
+            .clone()
+    }
+
+    /// Never accept the given PEM-encoded public keys.
+    ///
+    /// See [crate::config::NetworkConfig::revoked_keys].
+    pub fn with_revoked_keys(self, pems: &[String]) -> anyhow::Result<Self> {
+        for pem in pems {
+            let spki = SubjectPublicKeyInfoDer::from_pem_slice(pem.as_bytes())
+                .context("Failed to parse revoked public key")?;
+            self.verifier.revoke_key(spki);
+        }
+
+        Ok(self)
+    }
+
+    /// Record a new key proposed by a peer, until the operator
+    /// approves it with [Networking::approve_key].
+    ///
+    /// Only the latest key proposed by each peer is kept; it replaces
+    /// any key the peer proposed before.
+    ///
+    /// `peer` must be the peer the key rotation was received from,
+    /// over an authenticated connection.
+    pub fn propose_key(&self, peer: Peer, rotation: &KeyRotation) -> anyhow::Result<()> {
+        if !rotation.verify() {
+            anyhow::bail!("{peer}: invalid key rotation signature");
+        }
+        if self.verifier.is_revoked(&rotation.pubkey) {
+            anyhow::bail!("{peer}: proposed key was revoked");
+        }
+        match self.verifier.peer_for_key(&rotation.pubkey) {
+            Some(owner) if owner == peer => return Ok(()),
+            Some(owner) => anyhow::bail!("{peer}: proposed key belongs to {owner}"),
+            None => {}
+        }
+
+        let mut pending = self.pending_keys.lock().unwrap();
+        if pending
+            .iter()
+            .any(|k| k.peer == peer && k.pubkey == rotation.pubkey)
+        {
+            return Ok(());
+        }
+        log::info!("{peer}: proposed a new key, waiting for approval");
+        pending.retain(|k| k.peer != peer);
+        pending.push(PendingKey {
+            peer,
+            pubkey: rotation.pubkey.clone(),
+            received: UnixTime::now(),
+        });
+
+        Ok(())
+    }
+
+    /// Keys proposed by peers that haven't been approved yet.
+    pub fn pending_keys(&self) -> Vec<PendingKey> {
+        self.pending_keys.lock().unwrap().clone()
+    }
+
+    /// Accept the pending key proposed by the peer for new
+    /// connections.
+    ///
+    /// The key is only accepted until the next restart; it should be
+    /// added to the configuration of the peer to be kept.
+    ///
+    /// Returns false if no such key is pending.
+    pub fn approve_key(&self, peer: Peer, pubkey: &[u8]) -> bool {
+        let mut pending = self.pending_keys.lock().unwrap();
+        let Some(pos) = pending
+            .iter()
+            .position(|k| k.peer == peer && k.pubkey == pubkey)
+        else {
+            return false;
+        };
+        let key = pending.remove(pos);
+        log::info!("{peer}: approved new key");
+        self.verifier
+            .add_key(peer, SubjectPublicKeyInfoDer::from(key.pubkey), None, None);
+
+        true
+    }
+
     /// Set of peers that have a known address.
+    ///
+    /// This includes peers whose address was discovered.
     pub fn connectable_peers(&self) -> impl Iterator<Item = Peer> {
-        self.addresses.keys().cloned()
+        let mut peers = self.addresses.keys().cloned().collect::<HashSet<_>>();
+        let now = Instant::now();
+        peers.extend(
+            self.discovered
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, (_, expires))| !is_expired(expires, now))
+                .map(|(peer, _)| *peer),
+        );
+
+        peers.into_iter()
     }
 
     /// Check whether the peer is connectable.
     pub fn is_connectable(&self, peer: Peer) -> bool {
-        self.addresses.contains_key(&peer)
+        self.addresses.contains_key(&peer) || self.discovered_address(peer).is_some()
+    }
+
+    /// Configured peers, not including the current peer.
+    pub fn known_peers(&self) -> impl Iterator<Item = Peer> {
+        let local = self.local_peer();
+        self.verifier
+            .peers()
+            .filter(|p| Some(*p) != local)
+            .collect::<HashSet<_>>()
+            .into_iter()
+    }
+
+    /// The current peer, if it is part of the configured peers.
+    pub fn local_peer(&self) -> Option<Peer> {
+        self.verifier.peer_for_key(self.resolver.public_key())
+    }
+
+    /// Add an address discovered for the peer.
+    ///
+    /// The discovered address is tried after the configured
+    /// addresses, if any. It replaces any address previously
+    /// discovered for that peer. It is forgotten after `ttl`, unless
+    /// added again, or kept until restart if `ttl` is `None`.
+    ///
+    /// Returns true if the address is new.
+    pub fn add_discovered_address(
+        &self,
+        peer: Peer,
+        addr: SocketAddr,
+        ttl: Option<Duration>,
+    ) -> bool {
+        let now = Instant::now();
+        let previous = self
+            .discovered
+            .lock()
+            .unwrap()
+            .insert(peer, (addr, ttl.map(|ttl| now + ttl)));
+        if let Some((previous, expires)) = previous
+            && previous == addr
+            && !is_expired(&expires, now)
+        {
+            return false;
+        }
+        log::info!("Discovered {peer} at {addr}");
+        let _ = self.discovered_tx.send(peer);
+
+        true
+    }
+
+    /// Address discovered for the peer, if any and if it hasn't
+    /// expired.
+    pub fn discovered_address(&self, peer: Peer) -> Option<SocketAddr> {
+        let mut discovered = self.discovered.lock().unwrap();
+        let (addr, expires) = discovered.get(&peer)?;
+        if is_expired(expires, Instant::now()) {
+            log::debug!("Discovered address of {peer} expired");
+            discovered.remove(&peer);
+
+            return None;
+        }
+
+        Some(*addr)
     }
 
+    /// Report peers whose address was discovered.
+    ///
+    /// A peer is reported every time a new address is discovered
+    /// for it.
+    pub fn discovered_peers(&self) -> broadcast::Receiver<Peer> {
+        self.discovered_tx.subscribe()
+    }
+
+    /// Invite a new peer to join the household.
+    ///
+    /// `address` is the address the new peer should connect to, as
+    /// host:port. Until the invitation is used or expires, after
+    /// `ttl`, unknown peers are allowed to connect, but can't do
+    /// anything but pair.
+    ///
+    /// The current peer must be configured with its own key, so it
+    /// knows its ID.
+    pub fn invite(&self, address: &str, ttl: Duration) -> anyhow::Result<Invitation> {
+        let local = self.local_peer().ok_or_else(|| {
+            anyhow::anyhow!("the current peer must be configured with its own public key")
+        })?;
+        let invitation = Invitation::new(local, address, self.resolver.public_key(), ttl)?;
+        self.verifier
+            .open_invitation(invitation.secret(), &invitation.expires);
+        log::info!("Invitation open for {}s", ttl.as_secs());
+
+        Ok(invitation)
+    }
+
+    /// Join the household of the peer that created `invitation`.
+    ///
+    /// `local` is the ID the current peer is to have on the inviting
+    /// peer and `address` an address it can be reached at, if any.
+    ///
+    /// Once this returns, both peers accept each other's key. The
+    /// inviting peer is reported by [Networking::paired_peers].
+    pub async fn join(
+        &self,
+        invitation: &Invitation,
+        local: Peer,
+        address: Option<String>,
+    ) -> anyhow::Result<PairedPeer> {
+        let peer = invitation.peer;
+        if invitation.is_expired(UnixTime::now()) {
+            anyhow::bail!("invitation from {peer} has expired");
+        }
+        if self.verifier.known_peer(peer.as_str()).is_some() {
+            anyhow::bail!("{peer} is already known");
+        }
+        let candidates = HostPort::resolve_all(&invitation.address).await?;
+        let (hostport, stream) = connect_staggered(peer, candidates).await?;
+        let domain = ServerName::try_from(hostport.host().to_string())?;
+        stream.set_nodelay(true)?;
+
+        let connector = crate::security::make_tls_connector(
+            Arc::new(pairing::FingerprintVerifier::new(invitation.fingerprint)),
+            Arc::clone(&self.resolver),
+        );
+        let mut tls_stream = connector.connect(domain, stream).await?;
+        let spki = tls_stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| cert.to_vec())
+            .ok_or_else(|| anyhow::anyhow!("{peer} sent no key"))?;
+
+        tokio::time::timeout(pairing::PAIRING_TIMEOUT, async {
+            tls_stream.write_all(pairing::TAG).await?;
+            PairingRequest {
+                secret: *invitation.secret(),
+                peer: local,
+                address,
+            }
+            .write(&mut tls_stream)
+            .await?;
+
+            pairing::read_response(&mut tls_stream).await
+        })
+        .await
+        .with_context(|| format!("pairing with {peer} timed out"))??;
+        let _ = tls_stream.shutdown().await;
+
+        self.verifier.add_key(
+            peer,
+            SubjectPublicKeyInfoDer::from(spki.clone()),
+            None,
+            None,
+        );
+        self.add_discovered_address(peer, hostport.addr(), None);
+        log::info!("Paired with {peer} at {}", invitation.address);
+        let paired = PairedPeer::new(peer, &spki, Some(invitation.address.clone()));
+        let _ = self.paired_tx.send(paired.clone());
+
+        Ok(paired)
+    }
+
+    /// Report peers added by pairing, on either side.
+    ///
+    /// The peers are only known until restart; their configuration
+    /// must be saved to keep them.
+    pub fn paired_peers(&self) -> broadcast::Receiver<PairedPeer> {
+        self.paired_tx.subscribe()
+    }
+
+    /// Answer the pairing request of an unknown peer.
+    async fn serve_pairing<T>(
+        &self,
+        spki: Vec<u8>,
+        tls_stream: &mut tokio_rustls::server::TlsStream<T>,
+    ) -> anyhow::Result<()>
+    where
+        T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        let request = PairingRequest::read(tls_stream).await?;
+        let peer = request.peer;
+        let rejection = if !self.verifier.has_invitation(&request.secret) {
+            Some("invalid or expired invitation")
+        } else if self.verifier.known_peer(peer.as_str()).is_some() {
+            Some("peer ID already in use")
+        } else if !self.verifier.close_invitation(&request.secret) {
+            Some("invitation already used")
+        } else {
+            None
+        };
+        if let Some(reason) = rejection {
+            pairing::write_response(tls_stream, Err(reason)).await?;
+            anyhow::bail!("rejected pairing of {peer}: {reason}");
+        }
+
+        self.verifier.add_key(
+            peer,
+            SubjectPublicKeyInfoDer::from(spki.clone()),
+            None,
+            None,
+        );
+        if let Some(address) = &request.address {
+            match HostPort::parse(address).await {
+                Ok(hostport) => {
+                    self.add_discovered_address(peer, hostport.addr(), None);
+                }
+                Err(err) => log::warn!("{peer}: invalid address {address}: {err}"),
+            }
+        }
+        pairing::write_response(tls_stream, Ok(())).await?;
+        log::info!("Paired with {peer}");
+        let _ = self
+            .paired_tx
+            .send(PairedPeer::new(peer, &spki, request.address));
+
+        Ok(())
+    }
+
+    pub(crate) fn resolver(&self) -> &RawPublicKeyResolver {
+        &self.resolver
+    }
+
+    pub(crate) fn verifier(&self) -> &PeerVerifier {
+        &self.verifier
+    }
+
+    /// Address last used to successfully connect to the peer, if any.
+    pub fn last_working_address(&self, peer: Peer) -> Option<SocketAddr> {
+        self.last_working.lock().unwrap().get(&peer).copied()
+    }
+
+    /// Connect to the given peer.
+    ///
+    /// All addresses of the peer are resolved and tried, following
+    /// RFC 8305: attempts are started [CONNECTION_ATTEMPT_DELAY]
+    /// apart and the first one to succeed is used. The address that
+    /// last worked is tried first.
     pub async fn connect<Req, Resp>(
         &self,
         peer: Peer,
@@ -114,53 +561,238 @@ impl Networking {
         tag: &[u8; 4],
         limiter: Option<Limiter>,
     ) -> anyhow::Result<tokio_rustls::client::TlsStream<RateLimitedStream<TcpStream>>> {
-        let addr = self
+        let addresses = self
             .addresses
             .get(&peer)
-            .ok_or(anyhow::anyhow!("no address known for {peer}"))?;
-        let addr = HostPort::parse(addr)
-            .await
-            .with_context(|| format!("address for {peer} is invalid"))?;
-        let domain = ServerName::try_from(addr.host().to_string())?;
-        let stream = TcpStream::connect(addr.addr()).await?;
+            .map(|v| v.as_slice())
+            .unwrap_or(&[]);
+        let discovered = self.discovered_address(peer);
+        if addresses.is_empty() && discovered.is_none() {
+            return Err(anyhow::anyhow!("no address known for {peer}"));
+        }
+        let mut candidates = vec![];
+        let mut last_err = None;
+        for (addr, result) in addresses
+            .iter()
+            .zip(future::join_all(addresses.iter().map(|addr| HostPort::resolve_all(addr))).await)
+        {
+            match result {
+                Ok(hostports) => candidates.extend(hostports),
+                Err(err) => {
+                    log::debug!("{peer}: cannot resolve {addr}: {err}");
+                    last_err = Some(err);
+                }
+            }
+        }
+        if let Some(addr) = discovered
+            && !candidates.iter().any(|hp| hp.addr() == addr)
+        {
+            candidates.push(HostPort::from(addr));
+        }
+        if candidates.is_empty() {
+            return Err(last_err
+                .unwrap_or_else(|| anyhow::anyhow!("no address known for {peer}"))
+                .context(format!("address for {peer} is invalid")));
+        }
+        if let Some(last) = self.last_working_address(peer)
+            && let Some(pos) = candidates.iter().position(|hp| hp.addr() == last)
+        {
+            let hostport = candidates.remove(pos);
+            candidates.insert(0, hostport);
+        }
+
+        let (hostport, stream) = connect_staggered(peer, candidates).await?;
+        let domain = ServerName::try_from(hostport.host().to_string())?;
         stream.set_nodelay(true)?;
 
-        let stream = RateLimitedStream::new(
+        let mut stream = RateLimitedStream::new(
             stream,
             limiter.unwrap_or_else(|| Limiter::<StandardClock>::new(f64::INFINITY)),
         );
+        stream.limit_peer(self.peer_limiter(peer));
         let mut tls_stream = self.connector.connect(domain, stream).await?;
+        let spki = tls_stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| cert.to_vec());
+        self.close_when_key_expires(tls_stream.get_mut().0, spki.as_deref());
         tls_stream.write_all(tag).await?;
+        self.last_working
+            .lock()
+            .unwrap()
+            .insert(peer, hostport.addr());
 
         Ok(tls_stream)
     }
 
+    /// Close the connection once `spki`, the key the peer used to
+    /// connect, stops being valid.
+    ///
+    /// Keys are only checked during the TLS handshake, so without
+    /// this, connections would outlive the key.
+    fn close_when_key_expires(
+        &self,
+        stream: &mut RateLimitedStream<TcpStream>,
+        spki: Option<&[u8]>,
+    ) {
+        let Some(not_after) = spki.and_then(|spki| self.verifier.key_not_after(spki)) else {
+            return;
+        };
+        // Keys are valid until the end of the not_after second.
+        let remaining = (not_after.as_secs() + 1).saturating_sub(UnixTime::now().as_secs());
+        stream.close_at(tokio::time::Instant::now() + Duration::from_secs(remaining));
+    }
+
+    /// Accept a connection from a peer.
+    ///
+    /// Returns [None] if the connection came from an unknown peer
+    /// that was allowed to pair, and it's been handled.
     async fn accept(
         &self,
         stream: TcpStream,
         limiter: Limiter,
     ) -> Result<
-        (
+        Option<(
             Peer,
             [u8; 4],
             tokio_rustls::server::TlsStream<RateLimitedStream<TcpStream>>,
-        ),
+        )>,
         anyhow::Error,
     > {
         let peer_addr = stream.peer_addr()?;
         let stream = RateLimitedStream::new(stream, limiter);
         let mut tls_stream = self.acceptor.accept(stream).await?;
-        // Guards against bugs in the auth code; worth dying
-        let peer = self
-            .verifier
-            .connection_peer_id(&tls_stream)
-            .expect("Peer must be known at this point")
-            .clone();
+        let Some(peer) = self.verifier.connection_peer_id(&tls_stream) else {
+            // Unknown keys are only accepted while an invitation is
+            // open, and only to pair.
+            let spki = PeerVerifier::connection_key(&tls_stream)
+                .ok_or_else(|| anyhow::anyhow!("{peer_addr} sent no key"))?;
+            let result = tokio::time::timeout(pairing::PAIRING_TIMEOUT, async {
+                let mut tag = [0u8; 4];
+                tls_stream.read_exact(&mut tag).await?;
+                if &tag != pairing::TAG {
+                    anyhow::bail!("unknown peer from {peer_addr}");
+                }
+
+                self.serve_pairing(spki, &mut tls_stream).await
+            })
+            .await
+            .with_context(|| format!("pairing with {peer_addr} timed out"))?;
+            let _ = tls_stream.shutdown().await;
+            result?;
+
+            return Ok(None);
+        };
+        tls_stream.get_mut().0.limit_peer(self.peer_limiter(peer));
+        let spki = PeerVerifier::connection_key(&tls_stream);
+        self.close_when_key_expires(tls_stream.get_mut().0, spki.as_deref());
         log::info!("Accepted peer {peer} from {peer_addr}");
         let mut tag = [0u8; 4];
         tls_stream.read_exact(&mut tag).await?;
 
-        Ok((peer, tag, tls_stream))
+        Ok(Some((peer, tag, tls_stream)))
+    }
+}
+
+/// A key proposed by a peer, waiting for approval.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingKey {
+    /// The peer that proposed the key.
+    pub peer: Peer,
+
+    /// The proposed public key, as SPKI.
+    pub pubkey: Vec<u8>,
+
+    /// When the key was proposed.
+    pub received: UnixTime,
+}
+
+/// Bandwidth limits of a peer and the limiter that enforces them.
+struct PeerBandwidth {
+    limits: PeerLimits,
+    current: BandwidthLimit,
+    limiter: PeerLimiter,
+}
+
+impl PeerBandwidth {
+    fn new() -> Self {
+        Self {
+            limits: PeerLimits::default(),
+            current: BandwidthLimit::UNLIMITED,
+            limiter: PeerLimiter::new(),
+        }
+    }
+
+    /// Apply the limits of time `now`, if they changed.
+    fn apply(&mut self, now: WeekMinute) {
+        let limit = self.limits.at(now);
+        if limit != self.current {
+            self.limiter.set_limit(&limit);
+            self.current = limit;
+        }
+    }
+}
+
+/// Check whether a discovered address has expired at `now`.
+fn is_expired(expires: &Option<Instant>, now: Instant) -> bool {
+    expires.map(|t| t <= now).unwrap_or(false)
+}
+
+/// Connect to the first of the given addresses that answers.
+///
+/// Attempts are started in order, either [CONNECTION_ATTEMPT_DELAY]
+/// after the previous attempt or as soon as it fails, and race
+/// against each other. The first successful connection is returned
+/// and the other attempts are dropped.
+async fn connect_staggered(
+    peer: Peer,
+    candidates: Vec<HostPort>,
+) -> anyhow::Result<(HostPort, TcpStream)> {
+    let mut candidates = candidates.into_iter();
+    let mut attempts = stream::FuturesUnordered::new();
+    let mut last_err = None;
+    loop {
+        if attempts.is_empty() {
+            match candidates.next() {
+                Some(hostport) => attempts.push(connect_tcp(hostport)),
+                None => break,
+            }
+        }
+        tokio::select!(
+            Some(result) = attempts.next() => {
+                match result {
+                    Ok(ret) => return Ok(ret),
+                    Err((hostport, err)) => {
+                        log::debug!("{peer}: failed to connect to {hostport} ({}): {err}", hostport.addr());
+                        last_err = Some(err);
+                        if let Some(hostport) = candidates.next() {
+                            attempts.push(connect_tcp(hostport));
+                        }
+                    }
+                }
+            }
+            _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if !candidates.as_slice().is_empty() => {
+                if let Some(hostport) = candidates.next() {
+                    attempts.push(connect_tcp(hostport));
+                }
+            }
+        );
+    }
+
+    Err(last_err
+        .map(anyhow::Error::from)
+        .unwrap_or_else(|| anyhow::anyhow!("no address known for {peer}")))
+}
+
+/// Open a TCP connection to the given address.
+async fn connect_tcp(
+    hostport: HostPort,
+) -> Result<(HostPort, TcpStream), (HostPort, std::io::Error)> {
+    match TcpStream::connect(hostport.addr()).await {
+        Ok(stream) => Ok((hostport, stream)),
+        Err(err) => Err((hostport, err)),
     }
 }
 
@@ -303,10 +935,14 @@ impl Server {
                     Ok((stream, peer)) = listener.accept() => {
                         match weak_self.upgrade() {
                             Some(strong_self) => {
-                                match strong_self.accept(stream).await {
-                                    Ok(_) => {}
-                                    Err(err) => log::debug!("{peer}: connection rejected: {err}"),
-                                };
+                                // The TLS handshake and, for unknown
+                                // peers, pairing take time; don't
+                                // hold back other connections.
+                                tokio::spawn(async move {
+                                    if let Err(err) = strong_self.accept(stream).await {
+                                        log::debug!("{peer}: connection rejected: {err}");
+                                    }
+                                });
                             }
                             None => {
                                 // Server has been dropped; Shutdown listener.
@@ -327,7 +963,11 @@ impl Server {
 
         let shutdown_rx = self.shutdown_tx.subscribe();
         let limiter = Limiter::<StandardClock>::new(f64::INFINITY);
-        let (peer, tag, mut tls_stream) = self.networking.accept(stream, limiter.clone()).await?;
+        let Some((peer, tag, mut tls_stream)) =
+            self.networking.accept(stream, limiter.clone()).await?
+        else {
+            return Ok(());
+        };
         if let Some(handler) = self.handlers.get(&tag) {
             (*handler)(peer, tls_stream, limiter, shutdown_rx);
         } else {
@@ -408,6 +1048,20 @@ mod tests {
                 verifier,
             ))
         }
+
+        fn client_networking_with_addresses(
+            &self,
+            peer: Peer,
+            addresses: Vec<String>,
+        ) -> anyhow::Result<Networking> {
+            Ok(Networking::new(
+                addresses
+                    .into_iter()
+                    .map(|addr| (peer, addr.leak() as &'static str)),
+                RawPublicKeyResolver::from_private_key(testing::client_private_key())?,
+                Arc::clone(&self.verifier),
+            ))
+        }
     }
 
     // Helper to create a PeerVerifier with only the server key
@@ -445,6 +1099,313 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn connect_tries_all_addresses() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let addr = fixture.start_server().await?;
+        let closed_port = portpicker::pick_unused_port().expect("No ports free");
+
+        let peer = Peer::from("server");
+        let networking = fixture.client_networking_with_addresses(
+            peer,
+            vec![
+                "doesnotexist:1000".to_string(),
+                format!("127.0.0.1:{closed_port}"),
+                addr.to_string(),
+            ],
+        )?;
+        assert_eq!(None, networking.last_working_address(peer));
+        let client = connect_ping(&networking, peer).await?;
+        assert_eq!("pong", client.ping(context::current()).await?);
+        assert_eq!(Some(addr), networking.last_working_address(peer));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_to_localhost() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let addr = fixture.start_server().await?;
+
+        // The server only listens to 127.0.0.1; if localhost also
+        // resolves to ::1, that attempt fails and IPv4 is used.
+        let peer = Peer::from("server");
+        let networking = fixture
+            .client_networking_with_addresses(peer, vec![format!("localhost:{}", addr.port())])?;
+        let client = connect_ping(&networking, peer).await?;
+        assert_eq!("pong", client.ping(context::current()).await?);
+        assert_eq!(Some(addr), networking.last_working_address(peer));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn set_peer_limits() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let addr = fixture.start_server().await?;
+
+        let peer = Peer::from("server");
+        let networking = fixture.client_networking(peer, addr)?;
+        assert_eq!(BandwidthLimit::UNLIMITED, networking.current_limit(peer));
+
+        let limit = BandwidthLimit {
+            upload_bytes_per_second: Some(1024 * 1024),
+            download_bytes_per_second: Some(2 * 1024 * 1024),
+        };
+        networking.set_limits(
+            peer,
+            PeerLimits {
+                default: limit,
+                schedule: vec![],
+            },
+        );
+        assert_eq!(limit, networking.current_limit(peer));
+        assert_eq!(limit, networking.limits(peer).default);
+
+        let client = connect_ping(&networking, peer).await?;
+        assert_eq!("pong", client.ping(context::current()).await?);
+
+        networking.set_limits(peer, PeerLimits::default());
+        assert_eq!(BandwidthLimit::UNLIMITED, networking.current_limit(peer));
+        assert_eq!("pong", client.ping(context::current()).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn discovered_address_expires() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let networking = Networking::new(
+            vec![],
+            Arc::clone(&fixture.resolver),
+            Arc::clone(&fixture.verifier),
+        );
+        let peer = Peer::from("client");
+        let addr: SocketAddr = "192.0.2.1:1234".parse()?;
+        let mut discovered = networking.discovered_peers();
+
+        assert!(networking.add_discovered_address(peer, addr, Some(Duration::ZERO)));
+        assert_eq!(peer, discovered.try_recv()?);
+        assert_eq!(None, networking.discovered_address(peer));
+        assert!(!networking.is_connectable(peer));
+
+        // An expired address is new again.
+        assert!(networking.add_discovered_address(peer, addr, Some(Duration::from_secs(3600))));
+        assert_eq!(Some(addr), networking.discovered_address(peer));
+        assert!(networking.is_connectable(peer));
+        assert!(!networking.add_discovered_address(peer, addr, None));
+        assert_eq!(Some(addr), networking.discovered_address(peer));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn approve_proposed_key() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let peer = Peer::from("client");
+        let networking = Networking::new(
+            vec![],
+            Arc::clone(&fixture.resolver),
+            Arc::clone(&fixture.verifier),
+        );
+
+        let new_key = RawPublicKeyResolver::from_private_key(testing::other_private_key())?;
+        let rotation = KeyRotation::sign(&new_key)?;
+        networking.propose_key(peer, &rotation)?;
+        networking.propose_key(peer, &rotation)?;
+        assert_eq!(
+            vec![(peer, rotation.pubkey.clone())],
+            networking
+                .pending_keys()
+                .into_iter()
+                .map(|k| (k.peer, k.pubkey))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(None, networking.verifier().peer_for_key(&rotation.pubkey));
+
+        assert!(networking.approve_key(peer, &rotation.pubkey));
+        assert!(networking.pending_keys().is_empty());
+        assert_eq!(
+            Some(peer),
+            networking.verifier().peer_for_key(&rotation.pubkey)
+        );
+        assert!(!networking.approve_key(peer, &rotation.pubkey));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keep_latest_proposed_key() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let peer = Peer::from("client");
+        let networking = Networking::new(
+            vec![],
+            Arc::clone(&fixture.resolver),
+            Arc::clone(&fixture.verifier),
+        );
+
+        let first = KeyRotation::sign(&RawPublicKeyResolver::from_private_key(
+            testing::other_private_key(),
+        )?)?;
+        let second = KeyRotation::sign(&RawPublicKeyResolver::from_private_key(
+            rustls::pki_types::PrivateKeyDer::from_pem_slice(
+                crate::security::generate_private_key_pem()?.as_bytes(),
+            )?,
+        )?)?;
+        networking.propose_key(peer, &first)?;
+        networking.propose_key(peer, &second)?;
+        assert_eq!(
+            vec![(peer, second.pubkey.clone())],
+            networking
+                .pending_keys()
+                .into_iter()
+                .map(|k| (k.peer, k.pubkey))
+                .collect::<Vec<_>>()
+        );
+        assert!(!networking.approve_key(peer, &first.pubkey));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_bad_proposed_key() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let peer = Peer::from("client");
+        let networking = Networking::new(
+            vec![],
+            Arc::clone(&fixture.resolver),
+            Arc::clone(&fixture.verifier),
+        );
+
+        let new_key = RawPublicKeyResolver::from_private_key(testing::other_private_key())?;
+        let rotation = KeyRotation::sign(&new_key)?;
+        let forged = KeyRotation {
+            pubkey: testing::other_public_key().to_vec(),
+            signature: rotation.signature.iter().map(|b| b ^ 1).collect(),
+        };
+        assert!(networking.propose_key(peer, &forged).is_err());
+
+        let networking = networking
+            .with_revoked_keys(
+                &[std::str::from_utf8(testing::OTHER_PUBLIC_KEY_PEM)?.to_string()],
+            )?;
+        assert!(networking.propose_key(peer, &rotation).is_err());
+        assert!(networking.pending_keys().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pair_with_invitation() -> anyhow::Result<()> {
+        let _ = env_logger::try_init();
+        let server_peer = Peer::from("server");
+        let client_peer = Peer::from("client");
+
+        let inviter = Networking::new(
+            vec![],
+            RawPublicKeyResolver::from_private_key(testing::server_private_key())?,
+            verifier_server_only(),
+        );
+        let mut server = Server::new(inviter.clone());
+        register_ping(&mut server);
+        let server = Arc::new(server);
+        let addr = Arc::clone(&server).listen(&HostPort::localhost(0)).await?;
+        let mut paired_rx = inviter.paired_peers();
+
+        let joiner = Networking::new(
+            vec![],
+            RawPublicKeyResolver::from_private_key(testing::client_private_key())?,
+            verifier_client_only(),
+        );
+
+        // The new peer only accepts the key of the invitation.
+        let invitation = inviter.invite(&addr.to_string(), Duration::from_secs(60))?;
+        let mut wrong_key = invitation.clone();
+        wrong_key.fingerprint = pairing::fingerprint(testing::other_public_key().as_ref());
+        assert!(joiner.join(&wrong_key, client_peer, None).await.is_err());
+
+        let invitation = Invitation::decode(&invitation.encode()?)?;
+        let paired = joiner
+            .join(
+                &invitation,
+                client_peer,
+                Some("client.example.com:9771".to_string()),
+            )
+            .await?;
+        assert_eq!(server_peer, paired.peer);
+        assert_eq!(vec![addr.to_string()], paired.config.addresses);
+        assert_eq!(
+            crate::security::public_key_pem(testing::server_public_key().as_ref()),
+            paired.config.pubkey
+        );
+
+        let paired = paired_rx.recv().await?;
+        assert_eq!(client_peer, paired.peer);
+        assert_eq!(
+            vec!["client.example.com:9771".to_string()],
+            paired.config.addresses
+        );
+        assert_eq!(
+            crate::security::public_key_pem(testing::client_public_key().as_ref()),
+            paired.config.pubkey
+        );
+        assert!(inviter.known_peers().any(|p| p == client_peer));
+
+        // Both peers now know each other.
+        let client = connect_ping(&joiner, server_peer).await?;
+        assert_eq!("pong", client.ping(context::current()).await?);
+
+        // The invitation can only be used once.
+        let other = Networking::new(
+            vec![],
+            RawPublicKeyResolver::from_private_key(testing::other_private_key())?,
+            Arc::new(PeerVerifier::new()),
+        );
+        assert!(
+            other
+                .join(&invitation, Peer::from("other"), None)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stalled_connection_does_not_block_others() -> anyhow::Result<()> {
+        let mut fixture = Fixture::setup().await?;
+        let addr = fixture.start_server().await?;
+
+        // This connection never starts the TLS handshake.
+        let _stalled = TcpStream::connect(addr).await?;
+
+        let peer = Peer::from("server");
+        let networking = fixture.client_networking(peer, addr)?;
+        let client =
+            tokio::time::timeout(Duration::from_secs(3), connect_ping(&networking, peer)).await??;
+        assert_eq!("pong", client.ping(context::current()).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn connect_fails_if_no_address_works() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let closed_port = portpicker::pick_unused_port().expect("No ports free");
+
+        let peer = Peer::from("server");
+        let networking = fixture
+            .client_networking_with_addresses(peer, vec![format!("127.0.0.1:{closed_port}")])?;
+        let ret = connect_ping(&networking, peer).await;
+        assert_eq!(
+            Some(std::io::ErrorKind::ConnectionRefused),
+            testing::io_error_kind(ret.err())
+        );
+        assert_eq!(None, networking.last_working_address(peer));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn tarpc_tcp_reject_bad_tag() -> anyhow::Result<()> {
         let mut fixture = Fixture::setup().await?;
@@ -0,0 +1,413 @@
+//! Add peers to the household with one-time invitations.
+//!
+//! A peer creates an [Invitation] that contains its ID, an address
+//! it can be reached at, the fingerprint of its public key and a
+//! one-time secret. The new peer connects to that address: it only
+//! accepts the key matching the fingerprint and proves it got the
+//! invitation by sending the secret. Both peers then know each
+//! other's public key.
+
+use crate::config::PeerConfig;
+use crate::security::{default_provider, public_key_pem};
+use base64::Engine as _;
+use realize_types::{Peer, UnixTime};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::{CertificateDer, ServerName, SubjectPublicKeyInfoDer};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+
+/// Identifies pairing connections.
+pub(crate) const TAG: &[u8; 4] = b"PAIR";
+
+/// Maximum time a pairing exchange can take, once connected.
+pub(crate) const PAIRING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Length of the one-time secret of an invitation.
+const SECRET_LEN: usize = 16;
+
+/// Version of the token format, see [Invitation::encode].
+const TOKEN_VERSION: u8 = 1;
+
+/// An invitation to join the household, created by [crate::Networking::invite].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Invitation {
+    /// The peer that created the invitation.
+    pub peer: Peer,
+
+    /// Address the inviting peer can be reached at, as host:port.
+    pub address: String,
+
+    /// SHA-256 of the public key of the inviting peer, as SPKI.
+    pub fingerprint: [u8; 32],
+
+    /// When the invitation stops being accepted.
+    pub expires: UnixTime,
+
+    secret: [u8; SECRET_LEN],
+}
+
+impl Invitation {
+    /// Create a new invitation, with a random secret, valid for `ttl`.
+    pub(crate) fn new(
+        peer: Peer,
+        address: &str,
+        spki: &[u8],
+        ttl: Duration,
+    ) -> anyhow::Result<Self> {
+        let mut secret = [0u8; SECRET_LEN];
+        default_provider()
+            .secure_random
+            .fill(&mut secret)
+            .map_err(|_| anyhow::anyhow!("failed to generate secret"))?;
+
+        Ok(Self {
+            peer,
+            address: address.to_string(),
+            fingerprint: fingerprint(spki),
+            expires: UnixTime::from_secs(UnixTime::now().as_secs() + ttl.as_secs()),
+            secret,
+        })
+    }
+
+    /// The one-time secret of the invitation.
+    pub(crate) fn secret(&self) -> &[u8; SECRET_LEN] {
+        &self.secret
+    }
+
+    /// Encode the invitation as a token that can be copied to
+    /// another machine.
+    ///
+    /// Fails if the peer name or the address are too long.
+    pub fn encode(&self) -> anyhow::Result<String> {
+        let mut buf = vec![TOKEN_VERSION];
+        buf.extend_from_slice(&self.secret);
+        buf.extend_from_slice(&self.fingerprint);
+        buf.extend_from_slice(&self.expires.as_secs().to_be_bytes());
+        push_str(&mut buf, self.peer.as_str())?;
+        push_str(&mut buf, &self.address)?;
+
+        Ok(base64::prelude::BASE64_URL_SAFE_NO_PAD.encode(buf))
+    }
+
+    /// Decode a token created by [Invitation::encode].
+    pub fn decode(token: &str) -> anyhow::Result<Self> {
+        let buf = base64::prelude::BASE64_URL_SAFE_NO_PAD
+            .decode(token.trim())
+            .map_err(|_| anyhow::anyhow!("invalid invitation"))?;
+        let mut reader = buf.as_slice();
+        let version = take(&mut reader, 1)?[0];
+        if version != TOKEN_VERSION {
+            anyhow::bail!("unsupported invitation version: {version}");
+        }
+        let secret = take(&mut reader, SECRET_LEN)?.try_into()?;
+        let fingerprint = take(&mut reader, 32)?.try_into()?;
+        let expires = UnixTime::from_secs(u64::from_be_bytes(take(&mut reader, 8)?.try_into()?));
+        let peer = Peer::from(take_str(&mut reader)?);
+        let address = take_str(&mut reader)?.to_string();
+        if !reader.is_empty() {
+            anyhow::bail!("invalid invitation");
+        }
+
+        Ok(Self {
+            peer,
+            address,
+            fingerprint,
+            expires,
+            secret,
+        })
+    }
+
+    /// Check whether the invitation has expired at time `now`.
+    pub fn is_expired(&self, now: UnixTime) -> bool {
+        now >= self.expires
+    }
+}
+
+/// A peer added by pairing, and the configuration to keep it.
+#[derive(Clone, Debug)]
+pub struct PairedPeer {
+    pub peer: Peer,
+    pub config: PeerConfig,
+}
+
+impl PairedPeer {
+    pub(crate) fn new(peer: Peer, spki: &[u8], address: Option<String>) -> Self {
+        Self {
+            peer,
+            config: PeerConfig {
+                addresses: address.into_iter().collect(),
+                pubkey: public_key_pem(spki),
+                pubkeys: vec![],
+                relay: false,
+                limits: Default::default(),
+            },
+        }
+    }
+}
+
+/// SHA-256 of a public key, given as SPKI.
+pub fn fingerprint(spki: &[u8]) -> [u8; 32] {
+    aws_lc_rs::digest::digest(&aws_lc_rs::digest::SHA256, spki)
+        .as_ref()
+        .try_into()
+        .expect("SHA-256 is 32 bytes")
+}
+
+/// Request sent by the new peer, once connected.
+pub(crate) struct PairingRequest {
+    pub(crate) secret: [u8; SECRET_LEN],
+
+    /// ID of the new peer.
+    pub(crate) peer: Peer,
+
+    /// Address the new peer can be reached at, if any.
+    pub(crate) address: Option<String>,
+}
+
+impl PairingRequest {
+    pub(crate) async fn write<W: AsyncWrite + Unpin>(&self, stream: &mut W) -> anyhow::Result<()> {
+        let mut buf = self.secret.to_vec();
+        push_str(&mut buf, self.peer.as_str())?;
+        push_str(&mut buf, self.address.as_deref().unwrap_or(""))?;
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn read<R: AsyncRead + Unpin>(stream: &mut R) -> anyhow::Result<Self> {
+        let mut secret = [0u8; SECRET_LEN];
+        stream.read_exact(&mut secret).await?;
+        let peer = read_str(stream).await?;
+        if peer.is_empty() {
+            anyhow::bail!("missing peer ID");
+        }
+        let address = read_str(stream).await?;
+
+        Ok(Self {
+            secret,
+            peer: Peer::from(peer.as_str()),
+            address: if address.is_empty() {
+                None
+            } else {
+                Some(address)
+            },
+        })
+    }
+}
+
+/// Answer of the inviting peer: OK or the reason for the rejection.
+pub(crate) async fn write_response<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    result: Result<(), &str>,
+) -> anyhow::Result<()> {
+    let mut buf = vec![];
+    match result {
+        Ok(()) => buf.push(1),
+        Err(reason) => {
+            buf.push(0);
+            push_str(&mut buf, reason)?;
+        }
+    }
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+
+    Ok(())
+}
+
+pub(crate) async fn read_response<R: AsyncRead + Unpin>(stream: &mut R) -> anyhow::Result<()> {
+    if stream.read_u8().await? == 1 {
+        return Ok(());
+    }
+    let reason = read_str(stream).await?;
+
+    Err(anyhow::anyhow!("invitation rejected: {reason}"))
+}
+
+/// Only accept the server key with the given fingerprint.
+///
+/// Used by the new peer, which doesn't know the key of the inviting
+/// peer yet.
+#[derive(Debug)]
+pub(crate) struct FingerprintVerifier {
+    fingerprint: [u8; 32],
+    algos: WebPkiSupportedAlgorithms,
+}
+
+impl FingerprintVerifier {
+    pub(crate) fn new(fingerprint: [u8; 32]) -> Self {
+        Self {
+            fingerprint,
+            algos: default_provider().signature_verification_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: rustls::pki_types::UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity.as_ref()) != self.fingerprint {
+            return Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _: &[u8],
+        _: &CertificateDer<'_>,
+        _: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::PeerIncompatible(
+            rustls::PeerIncompatible::Tls12NotOffered,
+        ))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature_with_raw_key(
+            message,
+            &SubjectPublicKeyInfoDer::from(cert.as_ref()),
+            dss,
+            &self.algos,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        vec![rustls::SignatureScheme::ED25519]
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+/// Append `s` to `buf`, prefixed with its length.
+///
+/// Fails if `s` is longer than 255 bytes.
+fn push_str(buf: &mut Vec<u8>, s: &str) -> anyhow::Result<()> {
+    let bytes = s.as_bytes();
+    let len = u8::try_from(bytes.len())
+        .map_err(|_| anyhow::anyhow!("string too long to be encoded: {s}"))?;
+    buf.push(len);
+    buf.extend_from_slice(bytes);
+
+    Ok(())
+}
+
+fn take<'a>(reader: &mut &'a [u8], len: usize) -> anyhow::Result<&'a [u8]> {
+    if reader.len() < len {
+        anyhow::bail!("invalid invitation");
+    }
+    let (head, tail) = reader.split_at(len);
+    *reader = tail;
+
+    Ok(head)
+}
+
+fn take_str<'a>(reader: &mut &'a [u8]) -> anyhow::Result<&'a str> {
+    let len = take(reader, 1)?[0] as usize;
+
+    Ok(std::str::from_utf8(take(reader, len)?)?)
+}
+
+async fn read_str<R: AsyncRead + Unpin>(stream: &mut R) -> anyhow::Result<String> {
+    let len = stream.read_u8().await? as usize;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+
+    Ok(String::from_utf8(buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn encode_and_decode_invitation() -> anyhow::Result<()> {
+        let invitation = Invitation::new(
+            Peer::from("server"),
+            "server.example.com:9771",
+            testing::server_public_key().as_ref(),
+            Duration::from_secs(600),
+        )?;
+
+        let decoded = Invitation::decode(&invitation.encode()?)?;
+        assert_eq!(invitation, decoded);
+        assert_eq!(
+            fingerprint(testing::server_public_key().as_ref()),
+            decoded.fingerprint
+        );
+        assert!(!decoded.is_expired(UnixTime::now()));
+        assert!(decoded.is_expired(UnixTime::from_secs(decoded.expires.as_secs() + 1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_invitation() -> anyhow::Result<()> {
+        assert!(Invitation::decode("not a token").is_err());
+
+        let invitation = Invitation::new(
+            Peer::from("server"),
+            "localhost:9771",
+            testing::server_public_key().as_ref(),
+            Duration::from_secs(600),
+        )?;
+        let token = invitation.encode()?;
+        assert!(Invitation::decode(&token[..token.len() - 4]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_address_too_long() -> anyhow::Result<()> {
+        let invitation = Invitation::new(
+            Peer::from("server"),
+            &format!("{}:9771", "é".repeat(200)),
+            testing::server_public_key().as_ref(),
+            Duration::from_secs(600),
+        )?;
+        assert!(invitation.encode().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exchange_pairing_request() -> anyhow::Result<()> {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let request = PairingRequest {
+            secret: [7; SECRET_LEN],
+            peer: Peer::from("client"),
+            address: Some("client.example.com:9771".to_string()),
+        };
+        request.write(&mut a).await?;
+
+        let received = PairingRequest::read(&mut b).await?;
+        assert_eq!(request.secret, received.secret);
+        assert_eq!(request.peer, received.peer);
+        assert_eq!(request.address, received.address);
+
+        write_response(&mut b, Err("no")).await?;
+        assert!(read_response(&mut a).await.is_err());
+        write_response(&mut b, Ok(())).await?;
+        read_response(&mut a).await?;
+
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::config::BandwidthLimit;
 use async_speed_limit::Limiter;
 use async_speed_limit::clock::{Clock, StandardClock};
 use async_speed_limit::limiter::Consume;
@@ -5,8 +6,50 @@ use futures::prelude::*;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{Instant, Sleep};
+
+/// Upload and download limiters shared by all streams to and from a
+/// peer.
+#[derive(Clone)]
+pub(crate) struct PeerLimiter<C: Clock = StandardClock> {
+    upload: Limiter<C>,
+    download: Limiter<C>,
+}
+
+impl<C: Clock> PeerLimiter<C> {
+    /// Create a limiter that doesn't limit anything until
+    /// [PeerLimiter::set_limit] is called.
+    pub(crate) fn new() -> Self {
+        Self {
+            upload: Limiter::new(f64::INFINITY),
+            download: Limiter::new(f64::INFINITY),
+        }
+    }
+
+    /// Change the limits, in bytes per second.
+    ///
+    /// This takes effect immediately on all streams.
+    pub(crate) fn set_limit(&self, limit: &BandwidthLimit) {
+        self.upload
+            .set_speed_limit(as_speed_limit(limit.upload_bytes_per_second));
+        self.download
+            .set_speed_limit(as_speed_limit(limit.download_bytes_per_second));
+    }
+}
+
+/// Convert a limit into a speed limit for a [Limiter].
+fn as_speed_limit(bytes_per_second: Option<u64>) -> f64 {
+    bytes_per_second
+        .map(|bps| bps as f64)
+        .unwrap_or(f64::INFINITY)
+}
 
 /// Apply the given bps limit on writes to this stream.
+///
+/// Once the peer is known, reads and writes also go through the
+/// limiter of that peer, see [RateLimitedStream::limit_peer], and
+/// stop working once the key of the peer expires, see
+/// [RateLimitedStream::close_at].
 pub struct RateLimitedStream<S, C = StandardClock>
 where
     S: AsyncRead + AsyncWrite + Unpin,
@@ -15,6 +58,10 @@ where
     inner: S,
     limiter: Limiter<C>,
     waiter: Option<Consume<C, ()>>,
+    peer_limiter: Option<PeerLimiter<C>>,
+    upload_waiter: Option<Consume<C, ()>>,
+    download_waiter: Option<Consume<C, ()>>,
+    deadline: Option<Pin<Box<Sleep>>>,
 }
 
 impl<S, C> RateLimitedStream<S, C>
@@ -27,8 +74,46 @@ where
             inner,
             limiter,
             waiter: None,
+            peer_limiter: None,
+            upload_waiter: None,
+            download_waiter: None,
+            deadline: None,
         }
     }
+
+    /// Also apply the limits of the peer at the other end of the
+    /// stream.
+    pub(crate) fn limit_peer(&mut self, peer_limiter: PeerLimiter<C>) {
+        self.peer_limiter = Some(peer_limiter);
+    }
+
+    /// Fail all reads and writes from `deadline` on.
+    ///
+    /// Pending reads fail as soon as the deadline is reached, so the
+    /// connection is closed even if idle.
+    pub(crate) fn close_at(&mut self, deadline: Instant) {
+        self.deadline = Some(Box::pin(tokio::time::sleep_until(deadline)));
+    }
+
+    /// The underlying stream.
+    pub(crate) fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Check whether the deadline set by [RateLimitedStream::close_at]
+    /// has been reached.
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Error> {
+        if let Some(deadline) = &mut self.deadline
+            && deadline.as_mut().poll(cx).is_ready()
+        {
+            return Poll::Ready(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "key of peer expired",
+            ));
+        }
+
+        Poll::Pending
+    }
 }
 
 impl<S, C> AsyncRead for RateLimitedStream<S, C>
@@ -41,7 +126,26 @@ where
         cx: &mut Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.inner).poll_read(cx, buf)
+        if let Poll::Ready(err) = self.poll_deadline(cx) {
+            return Poll::Ready(Err(err));
+        }
+        if let Some(waiter) = &mut self.download_waiter {
+            if Pin::new(waiter).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.download_waiter = None;
+        }
+
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &res {
+            let len = buf.filled().len() - before;
+            if len > 0 {
+                self.download_waiter = self.peer_limiter.as_ref().map(|l| l.download.consume(len));
+            }
+        }
+
+        res
     }
 }
 
@@ -55,6 +159,9 @@ where
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
+        if let Poll::Ready(err) = self.poll_deadline(cx) {
+            return Poll::Ready(Err(err));
+        }
         if let Some(waiter) = &mut self.waiter {
             let res = Pin::new(waiter).poll(cx);
             if res.is_pending() {
@@ -62,11 +169,18 @@ where
             }
             self.waiter = None;
         }
+        if let Some(waiter) = &mut self.upload_waiter {
+            if Pin::new(waiter).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.upload_waiter = None;
+        }
 
         let res = Pin::new(&mut self.inner).poll_write(cx, buf);
         if let Poll::Ready(Ok(len)) = &res {
             if *len > 0 {
                 self.waiter = Some(self.limiter.consume(*len));
+                self.upload_waiter = self.peer_limiter.as_ref().map(|l| l.upload.consume(*len));
             }
         }
 
@@ -165,4 +279,63 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn peer_limiter_limits_reads() -> anyhow::Result<()> {
+        let (a, mut b) = tokio::io::duplex(64);
+        let peer_limiter = PeerLimiter::<ManualClock>::new();
+        peer_limiter.set_limit(&BandwidthLimit {
+            upload_bytes_per_second: None,
+            download_bytes_per_second: Some(3),
+        });
+        let mut limited_a = RateLimitedStream::new(a, Limiter::<ManualClock>::new(f64::INFINITY));
+        limited_a.limit_peer(peer_limiter.clone());
+
+        b.write_all(b"123456").await?;
+
+        // First read goes through immediately.
+        let mut buf = [0; 3];
+        limited_a.read_exact(&mut buf).await?;
+        assert_eq!(b"123", &buf);
+
+        // Second read has to wait for the clock to advance.
+        let mut buf = [0; 3];
+        let mut read = Box::pin(limited_a.read_exact(&mut buf));
+        assert!(futures::poll!(&mut read).is_pending());
+        peer_limiter
+            .download
+            .clock()
+            .set_time(Nanoseconds(1_000_000_000));
+        read.await?;
+        assert_eq!(b"456", &buf);
+
+        assert_eq!(6, peer_limiter.download.total_bytes_consumed());
+        assert_eq!(0, peer_limiter.upload.total_bytes_consumed());
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn close_at_deadline() -> anyhow::Result<()> {
+        let (a, mut b) = tokio::io::duplex(64);
+        let mut limited_a = RateLimitedStream::new(a, Limiter::<ManualClock>::new(f64::INFINITY));
+        limited_a.close_at(Instant::now() + std::time::Duration::from_millis(50));
+
+        b.write_all(b"123").await?;
+        let mut buf = [0; 3];
+        limited_a.read_exact(&mut buf).await?;
+        assert_eq!(b"123", &buf);
+
+        // A pending read fails once the deadline is reached, even
+        // though nothing is sent.
+        let err = tokio::time::timeout(
+            std::time::Duration::from_secs(3),
+            limited_a.read_exact(&mut buf),
+        )
+        .await?
+        .unwrap_err();
+        assert_eq!(std::io::ErrorKind::ConnectionAborted, err.kind());
+        assert!(limited_a.write_all(b"456").await.is_err());
+
+        Ok(())
+    }
 }
@@ -1,6 +1,7 @@
 use super::config::PeerConfig;
 use anyhow::Context as _;
-use realize_types::Peer;
+use base64::Engine as _;
+use realize_types::{Peer, UnixTime};
 use rustls::client::Resumption;
 use rustls::client::danger::ServerCertVerifier;
 use rustls::crypto::WebPkiSupportedAlgorithms;
@@ -10,9 +11,9 @@ use rustls::pki_types::{PrivateKeyDer, SubjectPublicKeyInfoDer};
 use rustls::sign::CertifiedKey;
 use rustls::version::TLS13;
 use rustls::{ClientConfig, Error, ServerConfig};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio_rustls::rustls::server::danger::ClientCertVerifier;
 use tokio_rustls::rustls::{self};
 use tokio_rustls::{TlsAcceptor, TlsConnector};
@@ -31,7 +32,7 @@ pub(crate) fn make_tls_acceptor(
 
 /// Create af TlsConnector (client-side) for the given peer and private key.
 pub(crate) fn make_tls_connector(
-    verifier: Arc<PeerVerifier>,
+    verifier: Arc<dyn ServerCertVerifier>,
     resolver: Arc<RawPublicKeyResolver>,
 ) -> TlsConnector {
     let mut config = ClientConfig::builder_with_protocol_versions(&[&TLS13])
@@ -48,12 +49,56 @@ pub(crate) fn make_tls_connector(
 ///
 /// Client and server connections are only accepted for known peers.
 /// Add public keys to the verifier before using it.
+///
+/// A peer can have more than one key, each possibly only valid for a
+/// period of time. Revoked keys are never accepted.
+///
+/// While an invitation is open, clients with unknown keys are
+/// accepted too, so they can pair; see [crate::pairing].
 #[derive(Debug)]
 pub struct PeerVerifier {
-    allowed_peers: BTreeMap<Vec<u8>, Peer>,
+    keys: RwLock<Keys>,
     algos: WebPkiSupportedAlgorithms,
 }
 
+/// Public keys known to a [PeerVerifier], as SPKI.
+#[derive(Debug, Default)]
+struct Keys {
+    allowed: BTreeMap<Vec<u8>, AllowedKey>,
+    revoked: BTreeSet<Vec<u8>>,
+
+    /// Secrets of the open invitations and when they expire, in
+    /// seconds since the epoch.
+    invitations: Vec<(Vec<u8>, u64)>,
+}
+
+impl Keys {
+    /// Return the allowed, non-revoked key, if any.
+    fn get(&self, spki: &[u8]) -> Option<&AllowedKey> {
+        if self.revoked.contains(spki) {
+            return None;
+        }
+
+        self.allowed.get(spki)
+    }
+}
+
+/// A public key of a peer and the period within which it's valid.
+#[derive(Debug, Clone, Copy)]
+struct AllowedKey {
+    peer: Peer,
+    not_before: Option<u64>,
+    not_after: Option<u64>,
+}
+
+impl AllowedKey {
+    /// Check whether the key is valid at the given time, in seconds
+    /// since the epoch.
+    fn is_valid_at(&self, secs: u64) -> bool {
+        self.not_before.is_none_or(|t| secs >= t) && self.not_after.is_none_or(|t| secs <= t)
+    }
+}
+
 impl Default for PeerVerifier {
     fn default() -> Self {
         Self::new()
@@ -66,7 +111,7 @@ impl PeerVerifier {
         {
             let crypto = Arc::new(default_provider());
             Self {
-                allowed_peers: BTreeMap::new(),
+                keys: RwLock::new(Keys::default()),
                 algos: crypto.signature_verification_algorithms,
             }
         }
@@ -74,11 +119,18 @@ impl PeerVerifier {
 
     /// Create a verifier and fill it from [PeerConfig] instances.
     pub fn from_config(peers: &HashMap<Peer, PeerConfig>) -> anyhow::Result<Arc<Self>> {
-        let mut verifier = Self::new();
+        let verifier = Self::new();
         for (peer, config) in peers {
-            let spki = SubjectPublicKeyInfoDer::from_pem_slice(config.pubkey.as_bytes())
-                .with_context(|| format!("Failed to parse public key for peer {peer}"))?;
-            verifier.add_peer(*peer, spki);
+            for key in config.keys() {
+                let spki = SubjectPublicKeyInfoDer::from_pem_slice(key.pubkey.as_bytes())
+                    .with_context(|| format!("Failed to parse public key for peer {peer}"))?;
+                verifier.add_key(
+                    *peer,
+                    spki,
+                    key.not_before.map(timestamp_to_unix_time),
+                    key.not_after.map(timestamp_to_unix_time),
+                );
+            }
         }
 
         Ok(Arc::new(verifier))
@@ -90,12 +142,161 @@ impl PeerVerifier {
     ///
     /// The SPKI must be the public part of a ED25519 key.
     pub fn add_peer(&mut self, peer: Peer, spki: rustls::pki_types::SubjectPublicKeyInfoDer) {
-        self.allowed_peers.insert(spki.to_vec(), peer);
+        self.add_key(peer, spki, None, None);
+    }
+
+    /// Accept connections to the peer with the given public key,
+    /// within the given period of time.
+    ///
+    /// This can be called while the verifier is in use; the key is
+    /// accepted for new connections.
+    pub fn add_key(
+        &self,
+        peer: Peer,
+        spki: rustls::pki_types::SubjectPublicKeyInfoDer,
+        not_before: Option<UnixTime>,
+        not_after: Option<UnixTime>,
+    ) {
+        self.keys.write().unwrap().allowed.insert(
+            spki.to_vec(),
+            AllowedKey {
+                peer,
+                not_before: not_before.map(|t| t.as_secs()),
+                not_after: not_after.map(|t| t.as_secs()),
+            },
+        );
+    }
+
+    /// Never accept the given public key, whatever peer it belongs
+    /// to.
+    pub fn revoke_key(&self, spki: rustls::pki_types::SubjectPublicKeyInfoDer) {
+        self.keys.write().unwrap().revoked.insert(spki.to_vec());
+    }
+
+    /// Check whether the key was revoked.
+    pub(crate) fn is_revoked(&self, spki: &[u8]) -> bool {
+        self.keys.read().unwrap().revoked.contains(spki)
+    }
+
+    /// Accept clients with unknown keys until `expires`, so they can
+    /// pair using the given one-time secret.
+    pub(crate) fn open_invitation(&self, secret: &[u8], expires: &UnixTime) {
+        let mut keys = self.keys.write().unwrap();
+        let now = UnixTime::now().as_secs();
+        keys.invitations.retain(|(_, expires)| *expires > now);
+        keys.invitations.push((secret.to_vec(), expires.as_secs()));
+    }
+
+    /// Check whether `secret` is the secret of an open invitation.
+    pub(crate) fn has_invitation(&self, secret: &[u8]) -> bool {
+        let now = UnixTime::now().as_secs();
+        self.keys
+            .read()
+            .unwrap()
+            .invitations
+            .iter()
+            .any(|(s, expires)| s == secret && *expires > now)
+    }
+
+    /// Close the invitation with the given secret, once used.
+    ///
+    /// Returns false if the invitation was already closed.
+    pub(crate) fn close_invitation(&self, secret: &[u8]) -> bool {
+        let mut keys = self.keys.write().unwrap();
+        let len = keys.invitations.len();
+        keys.invitations.retain(|(s, _)| s != secret);
+
+        keys.invitations.len() != len
+    }
+
+    /// Check whether unknown, non-revoked keys are accepted at time
+    /// `now`, because an invitation is open.
+    fn accept_guest(
+        &self,
+        spki: &rustls::pki_types::SubjectPublicKeyInfoDer,
+        now: rustls::pki_types::UnixTime,
+    ) -> bool {
+        let keys = self.keys.read().unwrap();
+
+        !keys.revoked.contains(spki.as_ref())
+            && keys
+                .invitations
+                .iter()
+                .any(|(_, expires)| *expires > now.as_secs())
+    }
+
+    /// Return all known peers.
+    pub(crate) fn peers(&self) -> impl Iterator<Item = Peer> {
+        self.keys
+            .read()
+            .unwrap()
+            .allowed
+            .values()
+            .map(|k| k.peer)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    /// Return the known peer with the given name, if any.
+    pub(crate) fn known_peer(&self, name: &str) -> Option<Peer> {
+        self.keys
+            .read()
+            .unwrap()
+            .allowed
+            .values()
+            .find(|k| k.peer.as_str() == name)
+            .map(|k| k.peer)
+    }
+
+    /// Return the peer with the given public key, if any.
+    ///
+    /// Revoked keys have no peer.
+    pub(crate) fn peer_for_key(&self, spki: &[u8]) -> Option<Peer> {
+        self.keys.read().unwrap().get(spki).map(|k| k.peer)
+    }
+
+    /// Return the time after which the given key stops being valid,
+    /// if it ever does.
+    pub(crate) fn key_not_after(&self, spki: &[u8]) -> Option<UnixTime> {
+        self.keys
+            .read()
+            .unwrap()
+            .get(spki)
+            .and_then(|k| k.not_after)
+            .map(UnixTime::from_secs)
+    }
+
+    /// Check an ED25519 signature of `message` made by `peer`.
+    ///
+    /// Returns true if the signature was made with the private key
+    /// matching any public key of the peer.
+    pub(crate) fn verify_peer_signature(
+        &self,
+        peer: Peer,
+        message: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        let now = UnixTime::now().as_secs();
+        let keys = self.keys.read().unwrap();
+        keys.allowed
+            .iter()
+            .filter(|(spki, k)| {
+                k.peer == peer && k.is_valid_at(now) && !keys.revoked.contains(*spki)
+            })
+            .any(|(spki, _)| verify_ed25519(&self.algos, spki, message, signature))
     }
 
-    /// Check whether the given spki is authorized.
-    fn accept_peer(&self, spki: &rustls::pki_types::SubjectPublicKeyInfoDer) -> bool {
-        self.allowed_peers.contains_key(spki.as_ref())
+    /// Check whether the given spki is authorized at time `now`.
+    fn accept_peer(
+        &self,
+        spki: &rustls::pki_types::SubjectPublicKeyInfoDer,
+        now: rustls::pki_types::UnixTime,
+    ) -> bool {
+        self.keys
+            .read()
+            .unwrap()
+            .get(spki.as_ref())
+            .is_some_and(|k| k.is_valid_at(now.as_secs()))
     }
 
     /// Return the ID of the stream's peer.
@@ -106,20 +307,35 @@ impl PeerVerifier {
         let (_, conn) = stream.get_ref();
         if let Some(cert) = conn.peer_certificates() {
             if !cert.is_empty() {
-                return self.allowed_peers.get(cert[0].as_ref()).copied();
+                return self.peer_for_key(cert[0].as_ref());
             }
         }
 
         None
     }
 
+    /// Return the public key of the stream's peer, as SPKI.
+    pub(crate) fn connection_key<T>(
+        stream: &tokio_rustls::server::TlsStream<T>,
+    ) -> Option<Vec<u8>> {
+        let (_, conn) = stream.get_ref();
+
+        conn.peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| cert.to_vec())
+    }
+
     fn verify_peer(
         &self,
         end_entity: &rustls::pki_types::CertificateDer<'_>,
+        now: rustls::pki_types::UnixTime,
+        allow_guest: bool,
     ) -> Result<(), rustls::Error> {
         let end_entity_as_spki =
             rustls::pki_types::SubjectPublicKeyInfoDer::from(end_entity.as_ref());
-        if !self.accept_peer(&end_entity_as_spki) {
+        if !self.accept_peer(&end_entity_as_spki, now)
+            && !(allow_guest && self.accept_guest(&end_entity_as_spki, now))
+        {
             return Err(rustls::Error::InvalidCertificate(
                 rustls::CertificateError::ApplicationVerificationFailure,
             ));
@@ -164,9 +380,9 @@ impl ClientCertVerifier for PeerVerifier {
         &self,
         end_entity: &rustls::pki_types::CertificateDer<'_>,
         _intermediates: &[rustls::pki_types::CertificateDer<'_>],
-        _now: rustls::pki_types::UnixTime,
+        now: rustls::pki_types::UnixTime,
     ) -> Result<rustls::server::danger::ClientCertVerified, rustls::Error> {
-        self.verify_peer(end_entity)?;
+        self.verify_peer(end_entity, now, true)?;
 
         Ok(rustls::server::danger::ClientCertVerified::assertion())
     }
@@ -211,9 +427,9 @@ impl ServerCertVerifier for PeerVerifier {
         _: &[rustls::pki_types::CertificateDer<'_>],
         _: &rustls::pki_types::ServerName<'_>,
         _: &[u8],
-        _: rustls::pki_types::UnixTime,
+        now: rustls::pki_types::UnixTime,
     ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
-        self.verify_peer(end_entity)?;
+        self.verify_peer(end_entity, now, false)?;
 
         Ok(rustls::client::danger::ServerCertVerified::assertion())
     }
@@ -289,6 +505,144 @@ impl RawPublicKeyResolver {
     fn create_internal(certified_key: Arc<CertifiedKey>) -> Arc<Self> {
         Arc::new(Self { certified_key })
     }
+
+    /// The public key of the current peer, as SPKI.
+    pub(crate) fn public_key(&self) -> &[u8] {
+        self.certified_key.cert[0].as_ref()
+    }
+
+    /// The public key of the current peer, encoded as PEM.
+    pub fn public_key_pem(&self) -> String {
+        public_key_pem(self.public_key())
+    }
+
+    /// Sign `message` with the private key of the current peer.
+    ///
+    /// The signature can be checked with
+    /// [PeerVerifier::verify_peer_signature].
+    pub(crate) fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        self.certified_key
+            .key
+            .choose_scheme(&[rustls::SignatureScheme::ED25519])
+            .ok_or(Error::General("private key is not ED25519".to_string()))?
+            .sign(message)
+    }
+}
+
+/// DER prefix of the SPKI of an ED25519 public key (RFC 8410).
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Extract the raw ED25519 public key from a SPKI.
+fn ed25519_public_key(spki: &[u8]) -> Option<&[u8]> {
+    spki.strip_prefix(ED25519_SPKI_PREFIX)
+        .filter(|key| key.len() == 32)
+}
+
+/// Check an ED25519 signature of `message` made with the private key
+/// matching `spki`.
+fn verify_ed25519(
+    algos: &WebPkiSupportedAlgorithms,
+    spki: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    let pubkey = match ed25519_public_key(spki) {
+        Some(pubkey) => pubkey,
+        None => return false,
+    };
+    algos
+        .mapping
+        .iter()
+        .filter(|(scheme, _)| *scheme == rustls::SignatureScheme::ED25519)
+        .flat_map(|(_, algs)| algs.iter())
+        .any(|alg| alg.verify_signature(pubkey, message, signature).is_ok())
+}
+
+/// Convert a time from the configuration.
+pub(crate) fn timestamp_to_unix_time(timestamp: jiff::Timestamp) -> UnixTime {
+    UnixTime::from_secs(timestamp.as_second().max(0) as u64)
+}
+
+/// Encode a public key, given as SPKI, as PEM.
+///
+/// This is the format of the public keys in the configuration.
+pub fn public_key_pem(spki: &[u8]) -> String {
+    encode_pem("PUBLIC KEY", spki)
+}
+
+/// Generate a new ED25519 private key, encoded as PKCS#8 PEM.
+///
+/// This is the format expected by
+/// [RawPublicKeyResolver::from_private_key_file].
+pub fn generate_private_key_pem() -> anyhow::Result<String> {
+    let keypair = aws_lc_rs::signature::Ed25519KeyPair::generate()
+        .map_err(|_| anyhow::anyhow!("failed to generate ED25519 key"))?;
+    let pkcs8 = keypair
+        .to_pkcs8v1()
+        .map_err(|_| anyhow::anyhow!("failed to encode ED25519 key"))?;
+
+    Ok(encode_pem("PRIVATE KEY", pkcs8.as_ref()))
+}
+
+fn encode_pem(label: &str, der: &[u8]) -> String {
+    let encoded = base64::prelude::BASE64_STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+
+    pem
+}
+
+/// Context of the signature of a [KeyRotation].
+const KEY_ROTATION_CONTEXT: &[u8] = b"realize key rotation\0";
+
+/// A new public key, proposed by a peer to replace its current key.
+///
+/// The message is signed with the new private key, which proves that
+/// the sender holds it. It must be sent over a connection
+/// authenticated with a current key of the peer, so the receiver
+/// knows which peer the new key belongs to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRotation {
+    /// The new public key, as SPKI.
+    pub pubkey: Vec<u8>,
+
+    /// ED25519 signature of the new public key, made with the new
+    /// private key.
+    pub signature: Vec<u8>,
+}
+
+impl KeyRotation {
+    /// Propose the public key of `new_key`, signed with it.
+    pub fn sign(new_key: &RawPublicKeyResolver) -> Result<Self, rustls::Error> {
+        let pubkey = new_key.public_key().to_vec();
+        let signature = new_key.sign(&Self::signed_part(&pubkey))?;
+
+        Ok(Self { pubkey, signature })
+    }
+
+    /// Check that the message was signed with the private key
+    /// matching the proposed public key.
+    pub fn verify(&self) -> bool {
+        verify_ed25519(
+            &default_provider().signature_verification_algorithms,
+            &self.pubkey,
+            &Self::signed_part(&self.pubkey),
+            &self.signature,
+        )
+    }
+
+    fn signed_part(pubkey: &[u8]) -> Vec<u8> {
+        let mut buf = KEY_ROTATION_CONTEXT.to_vec();
+        buf.extend_from_slice(pubkey);
+
+        buf
+    }
 }
 
 impl rustls::client::ResolvesClientCert for RawPublicKeyResolver {
@@ -524,6 +878,182 @@ mod tests {
         super::default_provider().key_provider.load_private_key(key)
     }
 
+    #[test]
+    fn sign_and_verify() -> anyhow::Result<()> {
+        let verifier = complete_verifier();
+        let resolver = RawPublicKeyResolver::from_private_key(testing::client_private_key())?;
+        let client = Peer::from("client");
+        let server = Peer::from("server");
+
+        let signature = resolver.sign(b"message")?;
+        assert!(verifier.verify_peer_signature(client, b"message", &signature));
+        assert!(!verifier.verify_peer_signature(client, b"other message", &signature));
+        assert!(!verifier.verify_peer_signature(server, b"message", &signature));
+
+        assert_eq!(Some(client), verifier.peer_for_key(resolver.public_key()));
+        assert_eq!(Some(server), verifier.known_peer("server"));
+        assert_eq!(None, verifier.known_peer("unknown"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn accept_any_key_of_peer() -> anyhow::Result<()> {
+        let mut verifier = complete_verifier();
+        verifier.add_peer(Peer::from("client"), testing::other_public_key());
+
+        assert_eq!(
+            Some(Peer::from("client")),
+            verifier.peer_for_key(testing::other_public_key().as_ref())
+        );
+        assert_eq!(
+            std::collections::HashSet::from([Peer::from("client"), Peer::from("server")]),
+            verifier.peers().collect::<std::collections::HashSet<_>>()
+        );
+        test_connect(verifier).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_key_outside_of_validity_period() -> anyhow::Result<()> {
+        let now = UnixTime::now();
+
+        let verifier = PeerVerifier::new();
+        verifier.add_key(
+            Peer::from("client"),
+            testing::client_public_key(),
+            None,
+            Some(UnixTime::from_secs(now.as_secs() - 3600)),
+        );
+        verifier.add_key(
+            Peer::from("server"),
+            testing::server_public_key(),
+            None,
+            None,
+        );
+        assert!(test_connect(verifier).await.is_err());
+
+        let verifier = PeerVerifier::new();
+        verifier.add_key(
+            Peer::from("client"),
+            testing::client_public_key(),
+            Some(UnixTime::from_secs(now.as_secs() + 3600)),
+            None,
+        );
+        verifier.add_key(
+            Peer::from("server"),
+            testing::server_public_key(),
+            None,
+            None,
+        );
+        assert!(test_connect(verifier).await.is_err());
+
+        let verifier = PeerVerifier::new();
+        verifier.add_key(
+            Peer::from("client"),
+            testing::client_public_key(),
+            Some(UnixTime::from_secs(now.as_secs() - 3600)),
+            Some(UnixTime::from_secs(now.as_secs() + 3600)),
+        );
+        verifier.add_key(
+            Peer::from("server"),
+            testing::server_public_key(),
+            None,
+            None,
+        );
+        test_connect(verifier).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_revoked_key() -> anyhow::Result<()> {
+        let verifier = complete_verifier();
+        verifier.revoke_key(testing::client_public_key());
+
+        assert!(verifier.is_revoked(testing::client_public_key().as_ref()));
+        assert_eq!(
+            None,
+            verifier.peer_for_key(testing::client_public_key().as_ref())
+        );
+        assert!(test_connect(verifier).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_and_verify_key_rotation() -> anyhow::Result<()> {
+        let new_key = RawPublicKeyResolver::from_private_key(testing::other_private_key())?;
+
+        let rotation = KeyRotation::sign(&new_key)?;
+        assert_eq!(
+            testing::other_public_key().as_ref(),
+            rotation.pubkey.as_slice()
+        );
+        assert!(rotation.verify());
+
+        // The key must match the signature.
+        let forged = KeyRotation {
+            pubkey: testing::client_public_key().to_vec(),
+            signature: rotation.signature.clone(),
+        };
+        assert!(!forged.verify());
+
+        Ok(())
+    }
+
+    #[test]
+    fn generate_private_key() -> anyhow::Result<()> {
+        let pem = generate_private_key_pem()?;
+        let resolver =
+            RawPublicKeyResolver::from_private_key(PrivateKeyDer::from_pem_slice(pem.as_bytes())?)?;
+        assert_eq!(44, resolver.public_key().len());
+
+        let other = generate_private_key_pem()?;
+        assert_ne!(pem, other);
+
+        Ok(())
+    }
+
+    #[test]
+    fn accept_unknown_client_while_invitation_is_open() -> anyhow::Result<()> {
+        let mut verifier = PeerVerifier::new();
+        verifier.add_peer(Peer::from("server"), testing::server_public_key());
+        let unknown = rustls::pki_types::CertificateDer::from(testing::other_public_key().to_vec());
+        let now = rustls::pki_types::UnixTime::now();
+        assert!(verifier.verify_peer(&unknown, now, true).is_err());
+
+        let expires = UnixTime::from_secs(UnixTime::now().as_secs() + 60);
+        verifier.open_invitation(b"secret", &expires);
+        assert!(verifier.has_invitation(b"secret"));
+        assert!(!verifier.has_invitation(b"other"));
+        assert!(verifier.verify_peer(&unknown, now, true).is_ok());
+
+        // Servers must always be known.
+        assert!(verifier.verify_peer(&unknown, now, false).is_err());
+
+        // Revoked keys are never accepted.
+        verifier.revoke_key(testing::other_public_key());
+        assert!(verifier.verify_peer(&unknown, now, true).is_err());
+
+        assert!(verifier.close_invitation(b"secret"));
+        assert!(!verifier.close_invitation(b"secret"));
+        assert!(!verifier.has_invitation(b"secret"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_public_key_pem() -> anyhow::Result<()> {
+        assert_eq!(
+            std::str::from_utf8(testing::CLIENT_PUBLIC_KEY_PEM)?.trim(),
+            public_key_pem(testing::client_public_key().as_ref()).trim()
+        );
+
+        Ok(())
+    }
+
     fn complete_verifier() -> PeerVerifier {
         let mut verifier = PeerVerifier::new();
         verifier.add_peer(Peer::from("client"), testing::client_public_key());
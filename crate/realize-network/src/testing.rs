@@ -15,14 +15,14 @@ MCowBQYDK2VwAyEA/CMSGfePPViYEUoHMNTrywE+mwTmB0poO0A1ATNIJGo=
 "#;
 
 /// Public key for test servers, in PEM format.
-const SERVER_PUBLIC_KEY_PEM: &[u8] = br#"
+pub const SERVER_PUBLIC_KEY_PEM: &[u8] = br#"
 -----BEGIN PUBLIC KEY-----
 MCowBQYDK2VwAyEAIckr1J3xrgglc6pseuCDWDAupSMzA1TJyitkgJi/SPg=
 -----END PUBLIC KEY-----
 "#;
 
 /// Another test public key, in PEM format.
-const OTHER_PUBLIC_KEY_PEM: &[u8] = br#"
+pub const OTHER_PUBLIC_KEY_PEM: &[u8] = br#"
 -----BEGIN PUBLIC KEY-----
 MCowBQYDK2VwAyEAJ7KIbhdPS2ESzYMeQXoqHJv8Vdmi+pJlkFChY8K+IVg=
 -----END PUBLIC KEY-----
@@ -46,6 +46,14 @@ pub fn server_public_key() -> SubjectPublicKeyInfoDer<'static> {
         .expect("Invalid test server public key")
 }
 
+/// Another test public key.
+///
+/// Generated from [other_private_key].
+pub fn other_public_key() -> SubjectPublicKeyInfoDer<'static> {
+    SubjectPublicKeyInfoDer::from_pem_slice(OTHER_PUBLIC_KEY_PEM)
+        .expect("Invalid other test public key")
+}
+
 /// Private key for test servers.
 ///
 /// Generated with:
@@ -155,7 +163,10 @@ impl TestingPeers {
             peer,
             PeerConfig {
                 pubkey: String::from_utf8(pubkey.to_vec())?,
-                address: None,
+                addresses: vec![],
+                relay: false,
+                pubkeys: vec![],
+                limits: Default::default(),
             },
         );
         self.private_keys.insert(peer, private_key);
@@ -163,6 +174,18 @@ impl TestingPeers {
         Ok(())
     }
 
+    /// Allow relaying through or for the given peer.
+    ///
+    /// See [PeerConfig::relay].
+    pub fn allow_relay(&mut self, peer: Peer) -> anyhow::Result<()> {
+        self.peers
+            .get_mut(&peer)
+            .ok_or(anyhow::anyhow!("Unknown peer {peer}"))?
+            .relay = true;
+
+        Ok(())
+    }
+
     /// Choose an address for the given peer, store it and return it.
     pub fn pick_port(&mut self, peer: Peer) -> anyhow::Result<HostPort> {
         let port = portpicker::pick_unused_port().ok_or(anyhow::anyhow!("No free port"))?;
@@ -214,7 +237,9 @@ impl TestingPeers {
             .iter()
             .map(|(p, s)| (*p, s.clone().leak() as &'static str))
             .collect();
-        Ok(crate::Networking::new(leaked_vec, resolver, verifier))
+        Ok(crate::Networking::new(leaked_vec, resolver, verifier)
+            .with_relays(others.iter().filter(|(_, c)| c.relay).map(|(p, _)| *p))
+            .with_limits(others.iter().map(|(p, c)| (*p, c.limits.clone()))))
     }
 }
 
@@ -24,6 +24,7 @@ fn capnpc() -> anyhow::Result<()> {
         .file("capnp/arena/mark.capnp")
         .file("capnp/arena/index.capnp")
         .file("capnp/arena/blob.capnp")
+        .file("capnp/arena/scrub.capnp")
         .file("capnp/global/cache.capnp")
         // keep files sorted
         .run()?;
@@ -6,8 +6,10 @@ use arena_cache::ArenaCache;
 use db::ArenaDatabase;
 use engine::{DirtyPaths, Engine};
 use index::RealIndexAsync;
+use job_history::JobHistory;
 use mark::PathMarks;
 use realize_types::{Arena, Hash};
+use scrubber::Scrubber;
 use std::time::Duration;
 use std::{path::PathBuf, sync::Arc};
 use tokio::task;
@@ -20,8 +22,10 @@ pub mod engine;
 pub mod hasher;
 pub mod index;
 pub mod indexed_store;
+pub mod job_history;
 pub mod mark;
 pub mod notifier;
+pub mod scrubber;
 pub mod store;
 pub mod types;
 pub mod watcher;
@@ -34,12 +38,16 @@ pub(crate) struct ArenaStorage {
     pub(crate) pathmarks: PathMarks,
     pub(crate) engine: Arc<Engine>,
     pub(crate) indexed: Option<IndexedArenaStorage>,
+    pub(crate) job_history: JobHistory,
+    pub(crate) blob_dir: PathBuf,
+    _scrubber: Scrubber,
 }
 
 /// Indexed (FS-based) local storage.
 pub(crate) struct IndexedArenaStorage {
     pub(crate) root: PathBuf,
     pub(crate) index: RealIndexAsync,
+    pub(crate) history: config::HistoryConfig,
     _watcher: RealWatcher,
 }
 
@@ -72,6 +80,7 @@ impl ArenaStorage {
                 log::debug!("Watch {root:?}, excluding {exclude:?}");
                 let watcher = RealWatcher::builder(root, index.clone())
                     .with_catchup()
+                    .with_hasher_config(&arena_config.hasher)
                     .exclude_all(exclude.iter())
                     .spawn()
                     .await?;
@@ -79,6 +88,7 @@ impl ArenaStorage {
                 Some(IndexedArenaStorage {
                     root: root.to_path_buf(),
                     index,
+                    history: arena_config.history.clone(),
                     _watcher: watcher,
                 })
             }
@@ -89,9 +99,20 @@ impl ArenaStorage {
             Arc::clone(&db),
             Arc::clone(&dirty_paths),
             arena_root,
+            &arena_config.unrealize,
             job_retry_strategy,
         );
         let pathmarks = PathMarks::new(Arc::clone(&db), arena_root, Arc::clone(&dirty_paths))?;
+        let scrubber = Scrubber::spawn(
+            arena,
+            Arc::clone(&db),
+            Arc::clone(&arena_cache),
+            indexed
+                .as_ref()
+                .map(|indexed| (indexed.root.clone(), indexed.index.clone())),
+            &arena_config.scrub,
+        );
+        let job_history = JobHistory::new(Arc::clone(&db), &arena_config.job_history);
 
         Ok(ArenaStorage {
             arena,
@@ -100,6 +121,9 @@ impl ArenaStorage {
             engine,
             pathmarks,
             indexed,
+            job_history,
+            blob_dir: arena_config.blob_dir.clone(),
+            _scrubber: scrubber,
         })
     }
 
@@ -148,6 +172,73 @@ impl ArenaStorage {
         Ok(done)
     }
 
+    /// Create a file as a hard link of another file in the
+    /// filesystem, when peers reported it as such.
+    ///
+    /// This is used instead of downloading the file, so the file
+    /// isn't stored twice.
+    ///
+    /// Gives up and returns false if `path` isn't known to be a
+    /// link of a file with `cache_hash`, if that file isn't in the
+    /// index with that version, if the current version of `path` in
+    /// the index doesn't match `index_hash` or if the filesystem
+    /// can't create the link, for example because the two files
+    /// would be on different filesystems. The caller should then
+    /// download the file instead.
+    ///
+    /// A `index_hash` value of `None` means that the file must not
+    /// exist.
+    pub(crate) async fn link(
+        &self,
+        path: &realize_types::Path,
+        cache_hash: &Hash,
+        index_hash: Option<&Hash>,
+    ) -> Result<bool, StorageError> {
+        let indexed = match &self.indexed {
+            None => return Err(StorageError::NoLocalStorage(self.arena)),
+            Some(indexed) => indexed,
+        };
+
+        let arena = self.arena;
+        let cache = self.cache.clone();
+        let root = indexed.root.clone();
+        let path = path.clone();
+        let cache_hash = cache_hash.clone();
+        let index_hash = index_hash.cloned();
+        let db = Arc::clone(&self.db);
+        let done = task::spawn_blocking(move || {
+            let txn = db.begin_write()?;
+            let target = match cache.link_target_if_matches(&txn, &path, &cache_hash)? {
+                Some(target) => target,
+                None => return Ok(false),
+            };
+            let targetpath = match index::get_indexed_file(&txn, &root, &target, Some(&cache_hash))?
+            {
+                Some(p) => p,
+                None => return Ok(false),
+            };
+            let realpath = match index::get_indexed_file(&txn, &root, &path, index_hash.as_ref())? {
+                Some(p) => p,
+                None => return Ok(false),
+            };
+            if let Err(err) = hard_link(&targetpath, &realpath, index_hash.is_some()) {
+                log::debug!(
+                    "[{arena}]/{path}: cannot link to {target}, falling back to download: {err}"
+                );
+                return Ok(false);
+            }
+            cache.drop_blob_if_matches(&txn, &path, &cache_hash)?;
+            txn.commit()?;
+            log::debug!("Realized [{arena}]/{path} {cache_hash} as a link of {target}");
+
+            // The watcher adds the new file to the index.
+            Ok::<bool, StorageError>(true)
+        })
+        .await??;
+
+        Ok(done)
+    }
+
     /// Move a file from the filesystem to the cache.
     ///
     /// Gives up and returns false if the current versions in the
@@ -226,6 +317,45 @@ fn job_retry_strategy(attempt: u32) -> Option<Duration> {
     Some(duration.max(MAX_JOB_RETRY_DURATION))
 }
 
+/// Destinations for which [hard_link] fails as if they were on
+/// another filesystem.
+#[cfg(test)]
+static FAIL_HARD_LINK: std::sync::Mutex<Vec<std::path::PathBuf>> =
+    std::sync::Mutex::new(Vec::new());
+
+/// Create `dest` as a hard link of `target`, creating its parent
+/// directories as needed.
+///
+/// If `replace` is true, `dest` is replaced in one step, through a
+/// temporary link created next to it.
+fn hard_link(
+    target: &std::path::Path,
+    dest: &std::path::Path,
+    replace: bool,
+) -> std::io::Result<()> {
+    #[cfg(test)]
+    if FAIL_HARD_LINK.lock().unwrap().iter().any(|p| p == dest) {
+        return Err(std::io::ErrorKind::CrossesDevices.into());
+    }
+    let parent = dest.parent().ok_or(std::io::ErrorKind::InvalidInput)?;
+    std::fs::create_dir_all(parent)?;
+    if !replace {
+        return std::fs::hard_link(target, dest);
+    }
+
+    let mut name = std::ffi::OsString::from(".");
+    name.push(dest.file_name().ok_or(std::io::ErrorKind::InvalidInput)?);
+    name.push(".link");
+    let tmppath = parent.join(name);
+    std::fs::hard_link(target, &tmppath)?;
+    if let Err(err) = std::fs::rename(&tmppath, dest) {
+        let _ = std::fs::remove_file(&tmppath);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::index::RealIndexBlocking;
@@ -274,6 +404,12 @@ mod tests {
                 } else {
                     None
                 },
+                hasher: config::HasherConfig::default(),
+                history: config::HistoryConfig::default(),
+                scrub: config::ScrubConfig::default(),
+                job_history: config::JobHistoryConfig::default(),
+                unrealize: config::UnrealizeConfig::default(),
+                prefetch_head_tail: None,
             };
             let storage = ArenaStorage::from_config(arena, &config, &vec![], &allocator).await?;
 
@@ -322,6 +458,26 @@ mod tests {
             Ok(())
         }
 
+        fn add_link_to_cache(
+            &self,
+            path_str: &str,
+            hash: &Hash,
+            target_str: &str,
+        ) -> anyhow::Result<()> {
+            self.storage.cache.update(
+                Peer::from("peer"),
+                Notification::Link {
+                    arena: self.arena,
+                    index: 2,
+                    path: Path::parse(path_str)?,
+                    hash: hash.clone(),
+                    target: Some(Path::parse(target_str)?),
+                },
+            )?;
+
+            Ok(())
+        }
+
         /// Check if a file exists in the filesystem
         fn file_exists(&self, path: &str) -> bool {
             self.root.join(path).exists()
@@ -430,4 +586,98 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn link_success() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let hash = fixture.create_indexed_file("foo", "foobar").await?;
+        fixture.add_to_cache("bar", &hash, 6)?;
+        fixture.add_link_to_cache("bar", &hash, "foo")?;
+
+        let path = Path::parse("bar")?;
+        assert!(fixture.storage.link(&path, &hash, None).await?);
+
+        assert_eq!(
+            std::fs::metadata(fixture.root.join("foo"))?.ino(),
+            std::fs::metadata(fixture.root.join("bar"))?.ino()
+        );
+
+        // The cache entry is kept after the commit.
+        assert!(fixture.find_in_cache("bar")?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn link_replaces_file() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let hash = fixture.create_indexed_file("foo", "foobar").await?;
+        let old_hash = fixture.create_indexed_file("bar", "old").await?;
+        fixture.add_to_cache("bar", &hash, 6)?;
+        fixture.add_link_to_cache("bar", &hash, "foo")?;
+
+        let path = Path::parse("bar")?;
+        assert!(fixture.storage.link(&path, &hash, Some(&old_hash)).await?);
+
+        assert_eq!("foobar", std::fs::read_to_string(fixture.root.join("bar"))?);
+        assert_eq!(
+            vec![
+                std::ffi::OsString::from("bar"),
+                std::ffi::OsString::from("foo")
+            ],
+            {
+                let mut names = std::fs::read_dir(fixture.root.path())?
+                    .map(|e| e.map(|e| e.file_name()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                names.sort();
+                names
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn link_missing_parent_dir() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let hash = fixture.create_indexed_file("foo", "foobar").await?;
+        fixture.add_to_cache("a/b/bar", &hash, 6)?;
+        fixture.add_link_to_cache("a/b/bar", &hash, "foo")?;
+
+        let path = Path::parse("a/b/bar")?;
+        assert!(fixture.storage.link(&path, &hash, None).await?);
+
+        assert_eq!(
+            "foobar",
+            std::fs::read_to_string(fixture.root.join("a/b/bar"))?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn link_across_filesystems() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let hash = fixture.create_indexed_file("foo", "foobar").await?;
+        fixture.add_to_cache("other/bar", &hash, 6)?;
+        fixture.add_link_to_cache("other/bar", &hash, "foo")?;
+
+        // Linking fails, as it would if other/bar was on another
+        // filesystem.
+        let dest = fixture.root.join("other/bar");
+        FAIL_HARD_LINK.lock().unwrap().push(dest.clone());
+
+        let path = Path::parse("other/bar")?;
+        assert!(!fixture.storage.link(&path, &hash, None).await?);
+
+        // The file can still be downloaded.
+        assert!(fixture.find_in_cache("other/bar")?.is_some());
+        assert!(!dest.exists());
+
+        Ok(())
+    }
 }
@@ -1,7 +1,8 @@
 use super::blob::{self, Blobstore};
 use super::db::{ArenaDatabase, ArenaReadTransaction, ArenaWriteTransaction};
-use super::types::LocalAvailability;
-use crate::arena::engine::DirtyPaths;
+use super::scrubber;
+use super::types::{CorruptionLocation, CorruptionTableEntry, LocalAvailability};
+use crate::arena::engine::{self, DirtyPaths};
 use crate::arena::notifier::{Notification, Progress};
 use crate::global::types::{
     DirTableEntry, FileAvailability, FileContent, FileMetadata, FileTableEntry, InodeAssignment,
@@ -146,6 +147,8 @@ impl ArenaCache {
                             file_inode,
                             &entry,
                         )?;
+                    } else {
+                        self.mark_dirty_if_indexed(&txn, &entry)?;
                     }
                 }
             }
@@ -178,6 +181,7 @@ impl ArenaCache {
                     // If it overwrites the peer's entry, we want to
                     // keep that.
                     self.do_write_file_entry(&mut file_table, file_inode, peer, &entry)?;
+                    self.mark_dirty_if_indexed(&txn, &entry)?;
                 }
             }
             Notification::Remove {
@@ -196,6 +200,27 @@ impl ArenaCache {
 
                 let root = self.arena_root;
                 self.do_unlink(&txn, peer, root, &path, old_hash)?;
+                txn.cache_link_table()?.remove(path.as_str())?;
+            }
+            Notification::Link {
+                index,
+                path,
+                hash,
+                target,
+                ..
+            } => {
+                do_update_last_seen_notification(&txn, peer, index)?;
+
+                let mut link_table = txn.cache_link_table()?;
+                match target {
+                    Some(target) => {
+                        link_table.insert(path.as_str(), (target.as_str(), hash.0.as_slice()))?;
+                    }
+                    None => {
+                        link_table.remove(path.as_str())?;
+                    }
+                }
+                self.dirty_paths.mark_dirty(&txn, &path)?;
             }
             Notification::CatchupStart(_) => {
                 do_mark_peer_files(&txn, peer)?;
@@ -213,10 +238,15 @@ impl ArenaCache {
                 do_unmark_peer_file(&txn, peer, file_inode)?;
 
                 let mut file_table = txn.cache_file_table()?;
+                let known = get_file_entry(&file_table, file_inode, Some(peer))?
+                    .map(|e| e.content.hash == hash)
+                    .unwrap_or(false);
                 let entry = FileTableEntry::new(path, size, mtime, hash, parent_inode);
                 self.do_write_file_entry(&mut file_table, file_inode, peer, &entry)?;
                 if !get_file_entry(&file_table, file_inode, None)?.is_some() {
                     self.do_write_default_file_entry(&txn, &mut file_table, file_inode, &entry)?;
+                } else if !known {
+                    self.mark_dirty_if_indexed(&txn, &entry)?;
                 }
             }
             Notification::CatchupComplete { index, .. } => {
@@ -317,6 +347,139 @@ impl ArenaCache {
         Ok(true)
     }
 
+    /// Return the file `path` was reported to be a hard link of, if
+    /// the link was reported for version `hash` of `path`.
+    pub(crate) fn link_target_if_matches(
+        &self,
+        txn: &ArenaWriteTransaction,
+        path: &Path,
+        hash: &Hash,
+    ) -> Result<Option<Path>, StorageError> {
+        let link_table = txn.cache_link_table()?;
+        if let Some(entry) = link_table.get(path.as_str())? {
+            let (target, link_hash) = entry.value();
+            if link_hash == hash.0.as_slice() {
+                return Ok(Some(Path::parse(target)?));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Drop the blob of `path` once version `hash` of the file has
+    /// been created in the filesystem some other way, such as a
+    /// hard link.
+    ///
+    /// The cache entry itself is kept. Does nothing if the version
+    /// of `path` in the cache isn't `hash`.
+    pub(crate) fn drop_blob_if_matches(
+        &self,
+        txn: &ArenaWriteTransaction,
+        path: &Path,
+        hash: &Hash,
+    ) -> Result<(), StorageError> {
+        let inode = match do_lookup_path(&txn.cache_directory_table()?, self.arena_root, Some(path))
+        {
+            Ok((inode, _)) => inode,
+            Err(StorageError::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let mut file_table = txn.cache_file_table()?;
+        let mut file_entry = match get_default_entry(&file_table, inode) {
+            Ok(entry) => entry,
+            Err(StorageError::NotFound) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if file_entry.content.hash != *hash {
+            return Ok(());
+        }
+        if let Some(blob_id) = file_entry.content.blob.take() {
+            self.blobstore.delete_blob(txn, blob_id)?;
+            file_table.insert((inode, ""), Holder::with_content(file_entry)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Return all blobs whose content has been verified, with the
+    /// path of the file they contain, the expected hash and the path
+    /// of the blob file.
+    pub(crate) fn verified_blobs(
+        &self,
+    ) -> Result<Vec<(Path, BlobId, Hash, PathBuf)>, StorageError> {
+        let txn = self.db.begin_read()?;
+        let file_table = txn.cache_file_table()?;
+        let mut ret = vec![];
+        for entry in file_table.iter()? {
+            let (k, v) = entry?;
+            if !k.value().1.is_empty() {
+                continue;
+            }
+            let file_entry: FileTableEntry = v.value().parse()?;
+            if let Some(blob_id) = file_entry.content.blob
+                && blob::local_availability(&txn, &file_entry)? == LocalAvailability::Verified
+            {
+                ret.push((
+                    file_entry.content.path,
+                    blob_id,
+                    file_entry.content.hash,
+                    self.blobstore.blob_path(blob_id),
+                ));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Record that the content of blob `blob_id` of `path` was found
+    /// to hash to `actual` instead of `expected`.
+    ///
+    /// The blob isn't considered verified anymore and `path` is
+    /// marked dirty, so it can be downloaded again.
+    ///
+    /// Gives up and returns false if `path` isn't stored in a blob
+    /// `blob_id` verified as `expected` anymore.
+    pub(crate) fn mark_blob_corrupted(
+        &self,
+        path: &Path,
+        blob_id: BlobId,
+        expected: &Hash,
+        actual: &Hash,
+    ) -> Result<bool, StorageError> {
+        let txn = self.db.begin_write()?;
+        let inode = match do_lookup_path(&txn.cache_directory_table()?, self.arena_root, Some(path))
+        {
+            Ok((inode, _)) => inode,
+            Err(StorageError::NotFound) => {
+                return Ok(false);
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        };
+        let file_entry = get_default_entry(&txn.cache_file_table()?, inode)?;
+        if file_entry.content.blob != Some(blob_id)
+            || file_entry.content.hash != *expected
+            || !self.blobstore.clear_content_hash(&txn, blob_id, expected)?
+        {
+            return Ok(false);
+        }
+        scrubber::record_corruption(
+            &txn,
+            path,
+            CorruptionTableEntry {
+                location: CorruptionLocation::Blob,
+                expected: expected.clone(),
+                actual: actual.clone(),
+                detected: UnixTime::now(),
+            },
+        )?;
+        self.dirty_paths.mark_dirty(&txn, path)?;
+        txn.commit()?;
+
+        Ok(true)
+    }
+
     /// Prepare the database and return the path to write into to move some
     /// file into the blob, replacing any existing ones.
     ///
@@ -374,6 +537,26 @@ impl ArenaCache {
         Ok(())
     }
 
+    /// Mark the path of `entry` dirty if its version is the one in
+    /// the index.
+    ///
+    /// This tells the engine that one more peer has the local version
+    /// of the file, which might now be unrealized.
+    fn mark_dirty_if_indexed(
+        &self,
+        txn: &ArenaWriteTransaction,
+        entry: &FileTableEntry,
+    ) -> Result<(), StorageError> {
+        let path = &entry.content.path;
+        if let Some(indexed) = txn.index_file_table()?.get(path.as_str())?
+            && indexed.value().parse()?.hash == entry.content.hash
+        {
+            self.dirty_paths.mark_dirty(txn, path)?;
+        }
+
+        Ok(())
+    }
+
     /// Write an entry in the file table, overwriting any existing one.
     fn do_write_default_file_entry(
         &self,
@@ -393,6 +576,7 @@ impl ArenaCache {
         // This entry is the outside world view of the file, so
         // changes should be reported.
         self.dirty_paths.mark_dirty(txn, &entry.content.path)?;
+        engine::unignore_if_changed(txn, &entry.content.path, Some(&entry.content.hash))?;
 
         file_table.insert((file_inode, key), Holder::new(entry)?)?;
 
@@ -455,6 +639,7 @@ impl ArenaCache {
 
             if let Some(path) = path {
                 self.dirty_paths.mark_dirty(&txn, &path)?;
+                engine::unignore_if_changed(txn, &path, None)?;
             }
 
             // Check if the default entry has a blob and delete it
@@ -626,6 +811,64 @@ pub(crate) fn mark_dirty_recursive(
     }
 }
 
+/// List the files of the cache that are at `path` or within `path`,
+/// or all files if `path` is None.
+pub(crate) fn files_within(
+    txn: &ArenaReadTransaction,
+    arena_root: Inode,
+    path: Option<&Path>,
+) -> Result<Vec<Path>, StorageError> {
+    let dir_table = txn.cache_directory_table()?;
+    let file_table = txn.cache_file_table()?;
+    let mut ret = vec![];
+    match do_lookup_path(&dir_table, arena_root, path) {
+        Ok((inode, InodeAssignment::File)) => {
+            collect_file(&file_table, inode, &mut ret)?;
+        }
+        Ok((inode, InodeAssignment::Directory)) => {
+            collect_dir(&dir_table, &file_table, inode, &mut ret)?;
+        }
+        Err(StorageError::NotFound) => {}
+        Err(err) => return Err(err),
+    }
+
+    Ok(ret)
+}
+
+fn collect_file(
+    file_table: &impl ReadableTable<(Inode, &'static str), Holder<'static, FileTableEntry>>,
+    inode: Inode,
+    ret: &mut Vec<Path>,
+) -> Result<(), StorageError> {
+    if let Some(entry) = file_table.get((inode, ""))? {
+        ret.push(entry.value().parse()?.content.path);
+    }
+
+    Ok(())
+}
+
+fn collect_dir(
+    dir_table: &impl ReadableTable<(Inode, &'static str), Holder<'static, DirTableEntry>>,
+    file_table: &impl ReadableTable<(Inode, &'static str), Holder<'static, FileTableEntry>>,
+    inode: Inode,
+    ret: &mut Vec<Path>,
+) -> Result<(), StorageError> {
+    for item in dir_table.range((inode, "")..(inode.plus(1), ""))? {
+        let (key, value) = item?;
+        if key.value().0 != inode {
+            break;
+        }
+        if let DirTableEntry::Regular(entry) = value.value().parse()? {
+            match entry.assignment {
+                InodeAssignment::File => collect_file(file_table, entry.inode, ret)?,
+                InodeAssignment::Directory => collect_dir(dir_table, file_table, entry.inode, ret)?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn mark_file_dirty(
     txn: &ArenaWriteTransaction,
     file_table: &redb::Table<'_, (Inode, &str), Holder<FileTableEntry>>,
@@ -771,6 +1014,44 @@ pub(crate) fn get_file_entry_for_path(
     get_file_entry(&file_table, inode, None)?.ok_or(StorageError::NotFound)
 }
 
+/// Return the peers that have version `hash` of the file at `path`.
+pub(crate) fn peers_with_version(
+    txn: &ArenaReadTransaction,
+    root: Inode,
+    path: &Path,
+    hash: &Hash,
+) -> Result<Vec<Peer>, StorageError> {
+    let dir_table = txn.cache_directory_table()?;
+    let (inode, assignment) = do_lookup_path(&dir_table, root, Some(path))?;
+    if assignment != InodeAssignment::File {
+        return Err(StorageError::IsADirectory);
+    }
+
+    do_peers_with_version(&txn.cache_file_table()?, inode, hash)
+}
+
+/// Return the peers that have version `hash` of the file `inode`.
+fn do_peers_with_version(
+    file_table: &impl redb::ReadableTable<(Inode, &'static str), Holder<'static, FileTableEntry>>,
+    inode: Inode,
+    hash: &Hash,
+) -> Result<Vec<Peer>, StorageError> {
+    let mut peers = vec![];
+    for entry in file_table.range((inode, "")..(inode.plus(1), ""))? {
+        let entry = entry?;
+        let peer = entry.0.value().1;
+        if peer.is_empty() {
+            continue;
+        }
+        let file_entry: FileTableEntry = entry.1.value().parse()?;
+        if file_entry.content.hash == *hash {
+            peers.push(Peer::from(peer));
+        }
+    }
+
+    Ok(peers)
+}
+
 /// Get a [FileTableEntry] for a specific peer.
 fn get_file_entry(
     file_table: &impl redb::ReadableTable<(Inode, &'static str), Holder<'static, FileTableEntry>>,
@@ -806,27 +1087,17 @@ fn do_file_availability(
 ) -> Result<FileAvailability, StorageError> {
     let file_table = txn.cache_file_table()?;
 
-    let mut range = file_table.range((inode, "")..(inode.plus(1), ""))?;
-    let (default_key, default_entry) = range.next().ok_or(StorageError::NotFound)??;
-    if default_key.value().1 != "" {
-        log::warn!("File table entry without a default peer: {inode}");
-        return Err(StorageError::NotFound);
-    }
-    let FileTableEntry {
+    let Some(FileTableEntry {
         metadata,
         content: FileContent { path, hash, .. },
         ..
-    } = default_entry.value().parse()?;
+    }) = get_file_entry(&file_table, inode, None)?
+    else {
+        log::warn!("File table entry without a default peer: {inode}");
+        return Err(StorageError::NotFound);
+    };
 
-    let mut peers = vec![];
-    for entry in range {
-        let entry = entry?;
-        let file_entry: FileTableEntry = entry.1.value().parse()?;
-        if file_entry.content.hash == hash {
-            let peer = Peer::from(entry.0.value().1);
-            peers.push(peer);
-        }
-    }
+    let peers = do_peers_with_version(&file_table, inode, &hash)?;
     if peers.is_empty() {
         log::warn!("No peer has hash {hash} for {inode}");
         return Err(StorageError::NotFound);
@@ -1194,6 +1465,90 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn link_records_target() -> anyhow::Result<()> {
+        let fixture = Fixture::setup_with_arena(test_arena()).await?;
+        let acache = &fixture.acache;
+        let foo = Path::parse("foo")?;
+        let bar = Path::parse("bar")?;
+        fixture.add_file(&foo, 100, &test_time())?;
+        fixture.add_file(&bar, 100, &test_time())?;
+        fixture.clear_dirty()?;
+
+        acache.update(
+            test_peer(),
+            Notification::Link {
+                arena: fixture.arena,
+                index: 2,
+                path: bar.clone(),
+                hash: test_hash(),
+                target: Some(foo.clone()),
+            },
+        )?;
+        assert!(engine::is_dirty(&fixture.begin_read()?, &bar)?);
+        {
+            let txn = fixture.begin_write()?;
+            assert_eq!(
+                Some(foo.clone()),
+                acache.link_target_if_matches(&txn, &bar, &test_hash())?
+            );
+            assert_eq!(
+                None,
+                acache.link_target_if_matches(&txn, &bar, &Hash([0xff; 32]))?
+            );
+        }
+
+        acache.update(
+            test_peer(),
+            Notification::Link {
+                arena: fixture.arena,
+                index: 3,
+                path: bar.clone(),
+                hash: test_hash(),
+                target: None,
+            },
+        )?;
+        {
+            let txn = fixture.begin_write()?;
+            assert_eq!(
+                None,
+                acache.link_target_if_matches(&txn, &bar, &test_hash())?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_forgets_link() -> anyhow::Result<()> {
+        let fixture = Fixture::setup_with_arena(test_arena()).await?;
+        let acache = &fixture.acache;
+        let foo = Path::parse("foo")?;
+        let bar = Path::parse("bar")?;
+        fixture.add_file(&foo, 100, &test_time())?;
+        fixture.add_file(&bar, 100, &test_time())?;
+        acache.update(
+            test_peer(),
+            Notification::Link {
+                arena: fixture.arena,
+                index: 2,
+                path: bar.clone(),
+                hash: test_hash(),
+                target: Some(foo.clone()),
+            },
+        )?;
+
+        fixture.remove_file(&bar)?;
+
+        let txn = fixture.begin_write()?;
+        assert_eq!(
+            None,
+            acache.link_target_if_matches(&txn, &bar, &test_hash())?
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn replace_existing_file() -> anyhow::Result<()> {
         let arena = test_arena();
@@ -1,11 +1,12 @@
 use super::db::{ArenaDatabase, ArenaReadTransaction, ArenaWriteTransaction};
 use super::hasher::hash_file;
-use super::types::{BlobTableEntry, LocalAvailability};
+use super::scrubber;
+use super::types::{BlobTableEntry, CorruptionLocation, LocalAvailability};
 use crate::StorageError;
 use crate::global::types::FileTableEntry;
 use crate::types::{BlobId, Inode};
 use crate::utils::holder::Holder;
-use realize_types::{ByteRange, ByteRanges, Hash};
+use realize_types::{ByteRange, ByteRanges, Hash, Path};
 use redb::ReadableTable;
 use std::cmp::min;
 use std::io::{SeekFrom, Write};
@@ -122,7 +123,7 @@ impl Blobstore {
     }
 
     /// Return the path of the file for the given blob.
-    fn blob_path(&self, blob_id: BlobId) -> PathBuf {
+    pub(crate) fn blob_path(&self, blob_id: BlobId) -> PathBuf {
         self.blob_dir.join(blob_id.to_string())
     }
 
@@ -165,7 +166,12 @@ impl Blobstore {
         Ok(())
     }
 
-    fn set_content_hash(&self, blob_id: BlobId, hash: Hash) -> Result<(), StorageError> {
+    fn set_content_hash(
+        &self,
+        blob_id: BlobId,
+        path: &Path,
+        hash: Hash,
+    ) -> Result<(), StorageError> {
         let txn = self.db.begin_write()?;
         {
             let mut blob_table = txn.blob_table()?;
@@ -175,11 +181,33 @@ impl Blobstore {
 
             blob_table.insert(blob_id, Holder::with_content(blob_entry)?)?;
         }
+        scrubber::clear_corruption(&txn, path, CorruptionLocation::Blob)?;
         txn.commit()?;
 
         Ok(())
     }
 
+    /// Forget that the content of `blob_id` was verified, if it was
+    /// verified to be `hash`.
+    ///
+    /// Return true if the blob was verified to be `hash`.
+    pub(crate) fn clear_content_hash(
+        &self,
+        txn: &ArenaWriteTransaction,
+        blob_id: BlobId,
+        hash: &Hash,
+    ) -> Result<bool, StorageError> {
+        let mut blob_table = txn.blob_table()?;
+        let mut blob_entry = get_blob_entry(&blob_table, blob_id)?;
+        if blob_entry.content_hash.as_ref() != Some(hash) {
+            return Ok(false);
+        }
+        blob_entry.content_hash = None;
+        blob_table.insert(blob_id, Holder::with_content(blob_entry)?)?;
+
+        Ok(true)
+    }
+
     /// Move the file of `blob_id` to `dest` and delete the entry.
     ///
     /// Does nothing and return false unless the blob content hash is
@@ -325,6 +353,7 @@ pub struct Blob {
     file: tokio::fs::File,
     size: u64,
     hash: Hash,
+    path: Path,
     blobstore: Arc<Blobstore>,
 
     /// Complete available range
@@ -355,6 +384,7 @@ impl Blob {
             pending_ranges: ByteRanges::new(),
             size: file_entry.metadata.size,
             hash: file_entry.content.hash,
+            path: file_entry.content.path,
             offset: 0,
         }
     }
@@ -414,7 +444,9 @@ impl Blob {
         let blob_id = self.blob_id;
         let blobstore = self.blobstore.clone();
         let hash = self.hash.clone();
-        tokio::task::spawn_blocking(move || blobstore.set_content_hash(blob_id, hash)).await?
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || blobstore.set_content_hash(blob_id, &path, hash))
+            .await?
     }
 
     /// Make sure any updated content is stored on disk before
@@ -1,5 +1,6 @@
 use super::types::{
-    BlobTableEntry, FailedJobTableEntry, HistoryTableEntry, IndexedFileTableEntry, MarkTableEntry,
+    BlobTableEntry, CorruptionTableEntry, FailedJobTableEntry, HistoryTableEntry,
+    IndexedFileTableEntry, InodeTableEntry, JobHistoryTableEntry, MarkTableEntry,
 };
 use crate::Inode;
 use crate::global::types::{FileTableEntry, PeerTableEntry};
@@ -24,6 +25,49 @@ const INDEX_FILE_TABLE: TableDefinition<&str, Holder<IndexedFileTableEntry>> =
 const INDEX_HISTORY_TABLE: TableDefinition<u64, Holder<HistoryTableEntry>> =
     TableDefinition::new("index.history");
 
+/// Inode of indexed files, for files whose fingerprint is known.
+///
+/// Each entry in this table has a corresponding entry in
+/// INDEX_FILE_TABLE.
+///
+/// Key: realize_types::Path
+/// Value: (dev, ino), key of INDEX_INODE_TABLE
+const INDEX_FILE_INODE_TABLE: TableDefinition<&str, (u64, u64)> =
+    TableDefinition::new("index.file_inode");
+
+/// Indexed files of each inode, the reverse of
+/// INDEX_FILE_INODE_TABLE.
+///
+/// Files that share an inode are hard links of each other. To find
+/// them, do a range scan.
+///
+/// Key: (dev, ino, realize_types::Path)
+/// Value: ()
+const INDEX_INODE_FILE_TABLE: TableDefinition<(u64, u64, &str), ()> =
+    TableDefinition::new("index.inode_file");
+
+/// Hash of a specific version of an inode.
+///
+/// Entries in this table are kept after the corresponding file has
+/// been removed from INDEX_FILE_TABLE, so files that were moved can
+/// be recognized without being hashed again. Entries not referenced
+/// by INDEX_FILE_INODE_TABLE are eventually pruned.
+///
+/// Key: (dev, ino)
+/// Value: InodeTableEntry
+const INDEX_INODE_TABLE: TableDefinition<(u64, u64), Holder<InodeTableEntry>> =
+    TableDefinition::new("index.inode");
+
+/// History index acknowledged by peers.
+///
+/// A peer acknowledges history entries up to a given index when it
+/// subscribes. Peers not in this table don't prevent history entries
+/// from being pruned.
+///
+/// Key: realize_types::Peer
+/// Value: index of the last history entry the peer has seen
+const INDEX_PEER_TABLE: TableDefinition<&str, u64> = TableDefinition::new("index.peer");
+
 /// Database settings.
 ///
 /// Key: string
@@ -89,6 +133,12 @@ const CACHE_PEER_TABLE: TableDefinition<&str, Holder<PeerTableEntry>> =
 const CACHE_NOTIFICATION_TABLE: TableDefinition<&str, u64> =
     TableDefinition::new("acache.notification");
 
+/// Track files reported by peers as hard links of another file.
+///
+/// Key: &str (Path)
+/// Value: (Path of the link target, hash of the file content)
+const CACHE_LINK_TABLE: TableDefinition<&str, (&str, &[u8])> = TableDefinition::new("acache.link");
+
 /// Track blobs.
 ///
 /// Key: BlodId
@@ -140,6 +190,42 @@ const DIRTY_COUNTER_TABLE: TableDefinition<(), u64> = TableDefinition::new("engi
 const FAILED_JOB_TABLE: TableDefinition<u64, Holder<FailedJobTableEntry>> =
     TableDefinition::new("engine.failed_job");
 
+/// Paths for which no job should be created, as long as the file
+/// has the given version.
+///
+/// Key: &str (path)
+/// Value: &[u8] (hash of the ignored version)
+const IGNORED_JOB_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("engine.ignored_job");
+
+/// Files temporarily kept in the cache, even though they're not
+/// marked Keep, because they're used often.
+///
+/// Key: &str (path)
+/// Value: u64 (time until which the file is pinned, in seconds since the epoch)
+const PINNED_TABLE: TableDefinition<&str, u64> = TableDefinition::new("engine.pinned");
+
+/// Outcome of jobs that were run, kept within the limits set by
+/// [crate::config::JobHistoryConfig].
+///
+/// Key: u64 (increasing)
+/// Value: JobHistoryTableEntry
+const JOB_HISTORY_TABLE: TableDefinition<u64, Holder<JobHistoryTableEntry>> =
+    TableDefinition::new("engine.job_history");
+
+/// Files whose content was found not to match their hash.
+///
+/// Entries are removed once the file or blob content is replaced.
+///
+/// Key: &str (path)
+/// Value: CorruptionTableEntry
+const SCRUB_CORRUPTION_TABLE: TableDefinition<&str, Holder<CorruptionTableEntry>> =
+    TableDefinition::new("scrub.corruption");
+
+/// Time at which the last scrub pass started.
+///
+/// Value: seconds since the beginning of the Unix epoch
+const SCRUB_LAST_PASS_TABLE: TableDefinition<(), u64> = TableDefinition::new("scrub.last_pass");
+
 pub(crate) struct ArenaDatabase {
     db: redb::Database,
 }
@@ -152,12 +238,17 @@ impl ArenaDatabase {
             // transactions in an empty database.
             txn.open_table(INDEX_FILE_TABLE)?;
             txn.open_table(INDEX_HISTORY_TABLE)?;
+            txn.open_table(INDEX_FILE_INODE_TABLE)?;
+            txn.open_table(INDEX_INODE_TABLE)?;
+            txn.open_table(INDEX_INODE_FILE_TABLE)?;
+            txn.open_table(INDEX_PEER_TABLE)?;
             txn.open_table(INDEX_SETTINGS_TABLE)?;
             txn.open_table(CACHE_DIRECTORY_TABLE)?;
             txn.open_table(CACHE_FILE_TABLE)?;
             txn.open_table(CACHE_PENDING_CATCHUP_TABLE)?;
             txn.open_table(CACHE_PEER_TABLE)?;
             txn.open_table(CACHE_NOTIFICATION_TABLE)?;
+            txn.open_table(CACHE_LINK_TABLE)?;
             txn.open_table(CACHE_CURRENT_INODE_RANGE_TABLE)?;
             txn.open_table(BLOB_TABLE)?;
             txn.open_table(MARK_TABLE)?;
@@ -165,6 +256,11 @@ impl ArenaDatabase {
             txn.open_table(DIRTY_LOG_TABLE)?;
             txn.open_table(DIRTY_COUNTER_TABLE)?;
             txn.open_table(FAILED_JOB_TABLE)?;
+            txn.open_table(IGNORED_JOB_TABLE)?;
+            txn.open_table(PINNED_TABLE)?;
+            txn.open_table(JOB_HISTORY_TABLE)?;
+            txn.open_table(SCRUB_CORRUPTION_TABLE)?;
+            txn.open_table(SCRUB_LAST_PASS_TABLE)?;
         }
         txn.commit()?;
 
@@ -231,6 +327,30 @@ impl ArenaWriteTransaction {
         Ok(self.inner.open_table(INDEX_HISTORY_TABLE)?)
     }
 
+    pub fn index_file_inode_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, &'static str, (u64, u64)>, StorageError> {
+        Ok(self.inner.open_table(INDEX_FILE_INODE_TABLE)?)
+    }
+
+    pub fn index_inode_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, (u64, u64), Holder<'static, InodeTableEntry>>, StorageError> {
+        Ok(self.inner.open_table(INDEX_INODE_TABLE)?)
+    }
+
+    pub fn index_inode_file_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, (u64, u64, &'static str), ()>, StorageError> {
+        Ok(self.inner.open_table(INDEX_INODE_FILE_TABLE)?)
+    }
+
+    pub fn index_peer_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, &'static str, u64>, StorageError> {
+        Ok(self.inner.open_table(INDEX_PEER_TABLE)?)
+    }
+
     pub fn index_settings_table<'txn>(
         &'txn self,
     ) -> Result<Table<'txn, &'static str, &'static [u8]>, StorageError> {
@@ -269,6 +389,12 @@ impl ArenaWriteTransaction {
         Ok(self.inner.open_table(CACHE_NOTIFICATION_TABLE)?)
     }
 
+    pub fn cache_link_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, &'static str, (&'static str, &'static [u8])>, StorageError> {
+        Ok(self.inner.open_table(CACHE_LINK_TABLE)?)
+    }
+
     pub fn cache_current_inode_range_table<'txn>(
         &'txn self,
     ) -> Result<Table<'txn, (), (Inode, Inode)>, StorageError> {
@@ -306,6 +432,33 @@ impl ArenaWriteTransaction {
     ) -> Result<Table<'txn, u64, Holder<'static, FailedJobTableEntry>>, StorageError> {
         Ok(self.inner.open_table(FAILED_JOB_TABLE)?)
     }
+
+    pub fn ignored_job_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, &'static str, &'static [u8]>, StorageError> {
+        Ok(self.inner.open_table(IGNORED_JOB_TABLE)?)
+    }
+
+    pub fn pinned_table<'txn>(&'txn self) -> Result<Table<'txn, &'static str, u64>, StorageError> {
+        Ok(self.inner.open_table(PINNED_TABLE)?)
+    }
+
+    pub fn job_history_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, u64, Holder<'static, JobHistoryTableEntry>>, StorageError> {
+        Ok(self.inner.open_table(JOB_HISTORY_TABLE)?)
+    }
+
+    pub fn scrub_corruption_table<'txn>(
+        &'txn self,
+    ) -> Result<Table<'txn, &'static str, Holder<'static, CorruptionTableEntry>>, StorageError>
+    {
+        Ok(self.inner.open_table(SCRUB_CORRUPTION_TABLE)?)
+    }
+
+    pub fn scrub_last_pass_table<'txn>(&'txn self) -> Result<Table<'txn, (), u64>, StorageError> {
+        Ok(self.inner.open_table(SCRUB_LAST_PASS_TABLE)?)
+    }
 }
 
 pub struct ArenaReadTransaction {
@@ -326,6 +479,28 @@ impl ArenaReadTransaction {
         Ok(self.inner.open_table(INDEX_HISTORY_TABLE)?)
     }
 
+    pub fn index_file_inode_table(
+        &self,
+    ) -> Result<ReadOnlyTable<&'static str, (u64, u64)>, StorageError> {
+        Ok(self.inner.open_table(INDEX_FILE_INODE_TABLE)?)
+    }
+
+    pub fn index_inode_table(
+        &self,
+    ) -> Result<ReadOnlyTable<(u64, u64), Holder<'static, InodeTableEntry>>, StorageError> {
+        Ok(self.inner.open_table(INDEX_INODE_TABLE)?)
+    }
+
+    pub fn index_inode_file_table(
+        &self,
+    ) -> Result<ReadOnlyTable<(u64, u64, &'static str), ()>, StorageError> {
+        Ok(self.inner.open_table(INDEX_INODE_FILE_TABLE)?)
+    }
+
+    pub fn index_peer_table(&self) -> Result<ReadOnlyTable<&'static str, u64>, StorageError> {
+        Ok(self.inner.open_table(INDEX_PEER_TABLE)?)
+    }
+
     pub fn cache_directory_table(
         &self,
     ) -> Result<ReadOnlyTable<(Inode, &'static str), Holder<'static, DirTableEntry>>, StorageError>
@@ -346,6 +521,12 @@ impl ArenaReadTransaction {
         Ok(self.inner.open_table(CACHE_PEER_TABLE)?)
     }
 
+    pub fn cache_link_table(
+        &self,
+    ) -> Result<ReadOnlyTable<&'static str, (&'static str, &'static [u8])>, StorageError> {
+        Ok(self.inner.open_table(CACHE_LINK_TABLE)?)
+    }
+
     pub fn cache_notification_table(
         &self,
     ) -> Result<ReadOnlyTable<&'static str, u64>, StorageError> {
@@ -377,4 +558,31 @@ impl ArenaReadTransaction {
     ) -> Result<ReadOnlyTable<u64, Holder<'static, FailedJobTableEntry>>, StorageError> {
         Ok(self.inner.open_table(FAILED_JOB_TABLE)?)
     }
+
+    pub fn ignored_job_table(
+        &self,
+    ) -> Result<ReadOnlyTable<&'static str, &'static [u8]>, StorageError> {
+        Ok(self.inner.open_table(IGNORED_JOB_TABLE)?)
+    }
+
+    pub fn pinned_table(&self) -> Result<ReadOnlyTable<&'static str, u64>, StorageError> {
+        Ok(self.inner.open_table(PINNED_TABLE)?)
+    }
+
+    pub fn job_history_table(
+        &self,
+    ) -> Result<ReadOnlyTable<u64, Holder<'static, JobHistoryTableEntry>>, StorageError> {
+        Ok(self.inner.open_table(JOB_HISTORY_TABLE)?)
+    }
+
+    pub fn scrub_corruption_table(
+        &self,
+    ) -> Result<ReadOnlyTable<&'static str, Holder<'static, CorruptionTableEntry>>, StorageError>
+    {
+        Ok(self.inner.open_table(SCRUB_CORRUPTION_TABLE)?)
+    }
+
+    pub fn scrub_last_pass_table(&self) -> Result<ReadOnlyTable<(), u64>, StorageError> {
+        Ok(self.inner.open_table(SCRUB_LAST_PASS_TABLE)?)
+    }
 }
@@ -2,15 +2,18 @@
 
 use super::db::{ArenaDatabase, ArenaReadTransaction, ArenaWriteTransaction};
 use super::index;
-use super::types::{FailedJobTableEntry, LocalAvailability};
+use super::scrubber;
+use super::types::{CorruptionLocation, FailedJobTableEntry, LocalAvailability};
 use crate::arena::arena_cache;
 use crate::arena::blob;
 use crate::arena::mark;
+use crate::config::UnrealizeConfig;
 use crate::types::JobId;
 use crate::utils::holder::Holder;
 use crate::{Inode, Mark, StorageError};
-use realize_types::{Arena, Hash, Path, UnixTime};
+use realize_types::{Arena, Hash, Path, Peer, UnixTime};
 use redb::ReadableTable;
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
@@ -52,6 +55,53 @@ impl Job {
     }
 }
 
+/// A job that is still to be run, together with what it would cost.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedJob {
+    pub arena: Arena,
+    pub job_id: JobId,
+    pub job: Job,
+
+    /// Size of the file, if known.
+    pub size: Option<u64>,
+
+    /// Number of bytes of the file already available locally.
+    pub local_bytes: u64,
+
+    /// Peers that can serve the file.
+    pub peers: Vec<Peer>,
+
+    /// If the job failed, time until which it waits before being
+    /// retried.
+    pub backoff_until: Option<UnixTime>,
+}
+
+/// What setting a mark would do, computed without changing
+/// anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MarkImpact {
+    /// Number of files that would be downloaded into the cache.
+    pub download_files: u64,
+
+    /// Number of bytes that would be downloaded, not counting data
+    /// already available locally.
+    pub download_bytes: u64,
+
+    /// Number of files that would be moved from the cache to the
+    /// arena root.
+    pub realize_files: u64,
+
+    /// Total size of the files that would be realized.
+    pub realize_bytes: u64,
+
+    /// Number of files that would be moved from the arena root to the
+    /// cache.
+    pub unrealize_files: u64,
+
+    /// Total size of the files that would be unrealized.
+    pub unrealize_bytes: u64,
+}
+
 /// The result of processing a job.
 ///
 /// The full status has type `Result<JobStatus>`, to make it
@@ -134,6 +184,7 @@ pub struct Engine {
     db: Arc<ArenaDatabase>,
     dirty_paths: Arc<DirtyPaths>,
     arena_root: Inode,
+    unrealize: UnrealizeConfig,
     job_retry_strategy: Box<dyn (Fn(u32) -> Option<Duration>) + Sync + Send + 'static>,
 
     /// A channel that triggers whenever a new failed job is created.
@@ -149,6 +200,7 @@ impl Engine {
         db: Arc<ArenaDatabase>,
         dirty_paths: Arc<DirtyPaths>,
         arena_root: Inode,
+        unrealize: &UnrealizeConfig,
         job_retry_strategy: impl (Fn(u32) -> Option<Duration>) + Sync + Send + 'static,
     ) -> Arc<Engine> {
         let (failed_job_tx, _) = watch::channel(());
@@ -158,6 +210,7 @@ impl Engine {
             db,
             dirty_paths,
             arena_root,
+            unrealize: unrealize.clone(),
             job_retry_strategy: Box::new(job_retry_strategy),
             failed_job_tx,
         })
@@ -362,6 +415,12 @@ impl Engine {
                         ret?;
                     }
 
+                    // Expired pins mark their path dirty, which is
+                    // then picked up by the next loop.
+                    ret = self.expire_pins() => {
+                        ret?;
+                    }
+
                     Ok(Some((backoff_until, mut jobs))) = self.wait_until_next_backoff(retry_lower_bound.as_ref()) => {
                         log::debug!("[{arena}] jobs to retry: {jobs:?}");
                         jobs_to_retry.append(&mut jobs);
@@ -452,6 +511,335 @@ impl Engine {
         .await?
     }
 
+    /// List the jobs that are still to be run, without consuming
+    /// them.
+    ///
+    /// If `path_prefix` is set, only jobs on that path or on files
+    /// within that directory are reported.
+    ///
+    /// Jobs that failed come with the time until which they wait
+    /// before being retried.
+    pub(crate) async fn pending_jobs(
+        self: &Arc<Self>,
+        path_prefix: Option<Path>,
+    ) -> Result<Vec<(JobId, Job, Option<UnixTime>)>, StorageError> {
+        let this = Arc::clone(self);
+        let arena = self.arena;
+        task::spawn_blocking(move || {
+            let txn = this.db.begin_read()?;
+            let dirty_log_table = txn.dirty_log_table()?;
+            let failed_job_table = txn.failed_job_table()?;
+            let mut ret = vec![];
+            for entry in dirty_log_table.iter()? {
+                let (key, value) = entry?;
+                let path = match Path::parse(value.value()) {
+                    Ok(p) => p,
+                    Err(err) => {
+                        log::debug!(
+                            "[{arena}] Skipping invalid path in dirty log {:?}: {err}",
+                            value.value()
+                        );
+                        continue;
+                    }
+                };
+                if let Some(prefix) = &path_prefix
+                    && !path.starts_with(prefix)
+                {
+                    continue;
+                }
+                if let Some((job_id, job)) = this.build_job(&txn, path, key.value())? {
+                    let backoff_until = match failed_job_table.get(job_id.as_u64())? {
+                        Some(entry) => {
+                            let entry: FailedJobTableEntry = entry.value().parse()?;
+                            Some(entry.backoff_until)
+                        }
+                        None => None,
+                    };
+                    ret.push((job_id, job, backoff_until));
+                }
+            }
+
+            Ok::<_, StorageError>(ret)
+        })
+        .await?
+    }
+
+    /// Compute what setting `mark` on `path_or_root`, or on the
+    /// arena if it is None, would do, without changing anything.
+    ///
+    /// This looks at the jobs that would be created for the files
+    /// whose mark would change.
+    pub(crate) async fn mark_impact(
+        self: &Arc<Self>,
+        path_or_root: Option<Path>,
+        mark: Mark,
+    ) -> Result<MarkImpact, StorageError> {
+        let this = Arc::clone(self);
+        task::spawn_blocking(move || {
+            let txn = this.db.begin_read()?;
+            let mut paths = BTreeSet::new();
+            paths.extend(arena_cache::files_within(
+                &txn,
+                this.arena_root,
+                path_or_root.as_ref(),
+            )?);
+            paths.extend(index::files_within(&txn, path_or_root.as_ref())?);
+
+            let mut impact = MarkImpact::default();
+            for path in paths {
+                let (before, after) =
+                    mark::mark_change(&txn, this.arena_root, &path, path_or_root.as_ref(), mark)?;
+                if before == after {
+                    continue;
+                }
+                if let Some((_, job)) = this.build_job_for_mark(&txn, path, 0, after)? {
+                    this.add_to_impact(&txn, &job, &mut impact)?;
+                }
+            }
+
+            Ok::<_, StorageError>(impact)
+        })
+        .await?
+    }
+
+    /// Add the cost of `job` to `impact`.
+    fn add_to_impact(
+        &self,
+        txn: &ArenaReadTransaction,
+        job: &Job,
+        impact: &mut MarkImpact,
+    ) -> Result<(), StorageError> {
+        if let Job::Unrealize(path, _) = job {
+            impact.unrealize_files += 1;
+            if let Some(indexed) = index::get_file_entry(txn, path)? {
+                impact.unrealize_bytes += indexed.size;
+            }
+
+            return Ok(());
+        }
+        let Ok(cached) = arena_cache::get_file_entry_for_path(txn, self.arena_root, job.path())
+        else {
+            return Ok(());
+        };
+        let size = cached.metadata.size;
+        if let Job::Realize(_, _, _) = job {
+            impact.realize_files += 1;
+            impact.realize_bytes += size;
+        }
+        let local_bytes = match blob::local_availability(txn, &cached)? {
+            LocalAvailability::Missing => 0,
+            LocalAvailability::Partial(_, ranges) => ranges.bytecount(),
+            LocalAvailability::Complete | LocalAvailability::Verified => size,
+        };
+        if local_bytes < size {
+            // Realize downloads missing data first.
+            impact.download_files += 1;
+            impact.download_bytes += size - local_bytes;
+        }
+
+        Ok(())
+    }
+
+    /// List the jobs that failed and are waiting to be retried, with
+    /// their path and retry information.
+    pub(crate) async fn failed_jobs(
+        self: &Arc<Self>,
+    ) -> Result<Vec<(JobId, Path, FailedJobTableEntry)>, StorageError> {
+        let this = Arc::clone(self);
+        task::spawn_blocking(move || {
+            let txn = this.db.begin_read()?;
+            let dirty_log_table = txn.dirty_log_table()?;
+            let mut ret = vec![];
+            for entry in txn.failed_job_table()?.iter()? {
+                let (key, value) = entry?;
+                let counter = key.value();
+                if let Some(path) = dirty_log_table.get(counter)? {
+                    ret.push((
+                        JobId(counter),
+                        Path::parse(path.value())?,
+                        value.value().parse()?,
+                    ));
+                }
+            }
+
+            Ok::<_, StorageError>(ret)
+        })
+        .await?
+    }
+
+    /// Have a failed job retried right away instead of waiting for
+    /// the end of its backoff period.
+    ///
+    /// The path of the job is marked dirty again, so the job is
+    /// returned by the job streams with a new [JobId] and its failure
+    /// count is reset.
+    ///
+    /// Return false if the job isn't a failed job.
+    pub(crate) async fn retry_job(self: &Arc<Self>, job_id: JobId) -> Result<bool, StorageError> {
+        let this = Arc::clone(self);
+        let counter = job_id.as_u64();
+        task::spawn_blocking(move || {
+            let txn = this.db.begin_write()?;
+            let path = {
+                if txn.failed_job_table()?.get(counter)?.is_none() {
+                    return Ok(false);
+                }
+                match txn.dirty_log_table()?.get(counter)? {
+                    Some(v) => Path::parse(v.value())?,
+                    None => return Ok(false),
+                }
+            };
+            this.dirty_paths.mark_dirty(&txn, &path)?;
+            txn.commit()?;
+
+            Ok::<_, StorageError>(true)
+        })
+        .await?
+    }
+
+    /// Stop creating jobs for `path` until its version in the cache
+    /// changes.
+    ///
+    /// Any pending or failed job on that path is dropped. A running
+    /// job isn't interrupted. Jobs that were already sent by
+    /// [Engine::job_stream] but haven't started yet are dropped when
+    /// checked with [Engine::job_for_path], which returns `None` for
+    /// ignored paths.
+    ///
+    /// The path is forgotten once the version of the file in the
+    /// cache changes.
+    ///
+    /// Return the ignored version or `None` if the file isn't in the
+    /// cache.
+    pub(crate) async fn ignore_path(
+        self: &Arc<Self>,
+        path: &Path,
+    ) -> Result<Option<Hash>, StorageError> {
+        let this = Arc::clone(self);
+        let path = path.clone();
+        task::spawn_blocking(move || {
+            let hash = {
+                let txn = this.db.begin_read()?;
+                match arena_cache::get_file_entry_for_path(&txn, this.arena_root, &path) {
+                    Ok(cached) => cached.content.hash,
+                    Err(_) => return Ok(None),
+                }
+            };
+            let txn = this.db.begin_write()?;
+            {
+                txn.ignored_job_table()?
+                    .insert(path.as_str(), hash.0.as_slice())?;
+                if let Some(counter) = txn.dirty_table()?.remove(path.as_str())? {
+                    let counter = counter.value();
+                    txn.dirty_log_table()?.remove(counter)?;
+                    txn.failed_job_table()?.remove(counter)?;
+                }
+            }
+            txn.commit()?;
+
+            Ok::<_, StorageError>(Some(hash))
+        })
+        .await?
+    }
+
+    /// Keep the file at `path` in the cache until `until`, even if
+    /// it is only marked Watch.
+    ///
+    /// While pinned, the file is downloaded and verified, just like
+    /// files marked Keep, and downloaded again if its content goes
+    /// missing from the cache. Once the pin expires, the job stream
+    /// removes it and marks the path dirty, so the file is treated
+    /// according to its mark again.
+    ///
+    /// Return false if the file isn't in the cache.
+    pub(crate) async fn pin_path(
+        self: &Arc<Self>,
+        path: &Path,
+        until: UnixTime,
+    ) -> Result<bool, StorageError> {
+        let this = Arc::clone(self);
+        let path = path.clone();
+        task::spawn_blocking(move || {
+            {
+                let txn = this.db.begin_read()?;
+                if arena_cache::get_file_entry_for_path(&txn, this.arena_root, &path).is_err() {
+                    return Ok(false);
+                }
+            }
+            let txn = this.db.begin_write()?;
+            this.remove_expired_pins(&txn)?;
+            txn.pinned_table()?.insert(path.as_str(), until.as_secs())?;
+            this.dirty_paths.mark_dirty(&txn, &path)?;
+            txn.commit()?;
+
+            Ok::<_, StorageError>(true)
+        })
+        .await?
+    }
+
+    /// Wait until the next pin expires, then remove the expired pins.
+    ///
+    /// Never returns if there are no pins.
+    async fn expire_pins(self: &Arc<Self>) -> Result<(), StorageError> {
+        let this = Arc::clone(self);
+        let next = task::spawn_blocking(move || {
+            let txn = this.db.begin_read()?;
+            let pinned_table = txn.pinned_table()?;
+            let mut next: Option<u64> = None;
+            for entry in pinned_table.iter()? {
+                let (_, v) = entry?;
+                next = Some(next.map_or(v.value(), |n| n.min(v.value())));
+            }
+
+            Ok::<_, StorageError>(next)
+        })
+        .await??;
+        let Some(until) = next else {
+            return std::future::pending().await;
+        };
+        time::sleep(Duration::from_secs(
+            until.saturating_sub(UnixTime::now().as_secs()),
+        ))
+        .await;
+
+        let this = Arc::clone(self);
+        task::spawn_blocking(move || {
+            let txn = this.db.begin_write()?;
+            this.remove_expired_pins(&txn)?;
+            txn.commit()?;
+
+            Ok::<_, StorageError>(())
+        })
+        .await?
+    }
+
+    /// Remove the pins that have expired and mark their paths dirty,
+    /// so the files are treated according to their mark again.
+    fn remove_expired_pins(&self, txn: &ArenaWriteTransaction) -> Result<(), StorageError> {
+        let now = UnixTime::now().as_secs();
+        let mut expired = vec![];
+        {
+            let mut pinned_table = txn.pinned_table()?;
+            for entry in pinned_table.iter()? {
+                let (k, v) = entry?;
+                if v.value() <= now {
+                    expired.push(k.value().to_string());
+                }
+            }
+            for path in &expired {
+                pinned_table.remove(path.as_str())?;
+            }
+        }
+        for path in expired {
+            if let Ok(path) = Path::parse(&path) {
+                log::debug!("[{}] pin of {path} expired", self.arena);
+                self.dirty_paths.mark_dirty(txn, &path)?;
+            }
+        }
+
+        Ok(())
+    }
+
     /// Build a job from a path, if possible.
     fn build_job(
         &self,
@@ -459,20 +847,61 @@ impl Engine {
         path: Path,
         counter: u64,
     ) -> Result<Option<(JobId, Job)>, StorageError> {
-        match mark::get_mark(txn, &path)? {
+        let mark = mark::get_mark(txn, self.arena_root, &path)?;
+
+        self.build_job_for_mark(txn, path, counter, mark)
+    }
+
+    /// Build a job from a path, if possible, as if the path had the
+    /// given mark.
+    fn build_job_for_mark(
+        &self,
+        txn: &ArenaReadTransaction,
+        path: Path,
+        counter: u64,
+        mark: Mark,
+    ) -> Result<Option<(JobId, Job)>, StorageError> {
+        if let Some(ignored) = txn.ignored_job_table()?.get(path.as_str())?
+            && let Ok(cached) = arena_cache::get_file_entry_for_path(txn, self.arena_root, &path)
+            && ignored.value() == cached.content.hash.0.as_slice()
+        {
+            return Ok(None);
+        }
+        match mark {
             Mark::Watch => {
-                if let (Ok(cached), Ok(Some(indexed))) = (
-                    arena_cache::get_file_entry_for_path(txn, self.arena_root, &path),
-                    index::get_file_entry(txn, &path),
-                ) && cached.content.hash == indexed.hash
+                // Files in the index are only moved to the cache, if
+                // configured, once enough peers have that version, as
+                // they might then be evicted.
+                if let Some(min_peers) = self.unrealize.min_peers
+                    && let (Ok(cached), Ok(Some(indexed))) = (
+                        arena_cache::get_file_entry_for_path(txn, self.arena_root, &path),
+                        index::get_file_entry(txn, &path),
+                    )
+                    && cached.content.hash == indexed.hash
+                    && arena_cache::peers_with_version(txn, self.arena_root, &path, &indexed.hash)?
+                        .len()
+                        >= min_peers
                 {
                     return Ok(Some((
                         JobId(counter),
                         Job::Unrealize(path, cached.content.hash),
                     )));
                 }
+                if is_pinned(txn, &path)?
+                    && let Ok(cached) =
+                        arena_cache::get_file_entry_for_path(txn, self.arena_root, &path)
+                    && blob::local_availability(txn, &cached)? != LocalAvailability::Verified
+                {
+                    return Ok(Some((
+                        JobId(counter),
+                        Job::Download(path, cached.content.hash),
+                    )));
+                }
             }
             Mark::Keep => {
+                if let Some(job) = self.repair_job(txn, &path, counter)? {
+                    return Ok(Some(job));
+                }
                 if let Ok(cached) =
                     arena_cache::get_file_entry_for_path(txn, self.arena_root, &path)
                 {
@@ -494,6 +923,9 @@ impl Engine {
                 }
             }
             Mark::Own => {
+                if let Some(job) = self.repair_job(txn, &path, counter)? {
+                    return Ok(Some(job));
+                }
                 // TODO: treat is as Keep if there is no index.
                 if let Ok(cached) =
                     arena_cache::get_file_entry_for_path(txn, self.arena_root, &path)
@@ -525,6 +957,33 @@ impl Engine {
         Ok(None)
     }
 
+    /// Build a job that replaces a corrupted indexed file with a good
+    /// copy, if the scrubber found the file at `path` to be corrupted.
+    ///
+    /// The good copy is downloaded into the cache, verified, then
+    /// moved over the corrupted file.
+    fn repair_job(
+        &self,
+        txn: &ArenaReadTransaction,
+        path: &Path,
+        counter: u64,
+    ) -> Result<Option<(JobId, Job)>, StorageError> {
+        if let Some(corruption) = scrubber::get_corruption(txn, path)?
+            && corruption.location == CorruptionLocation::Index
+            && let Ok(Some(indexed)) = index::get_file_entry(txn, path)
+            && indexed.hash == corruption.expected
+            && let Ok(cached) = arena_cache::get_file_entry_for_path(txn, self.arena_root, path)
+            && cached.content.hash == indexed.hash
+        {
+            return Ok(Some((
+                JobId(counter),
+                Job::Realize(path.clone(), indexed.hash.clone(), Some(indexed.hash)),
+            )));
+        }
+
+        Ok(None)
+    }
+
     fn delete_range(&self, beg: u64, end: u64) -> Result<(), StorageError> {
         if !(beg < end) {
             return Ok(());
@@ -659,6 +1118,17 @@ pub(crate) fn is_dirty(txn: &ArenaReadTransaction, path: &Path) -> Result<bool,
     Ok(dirty_table.get(path.as_str())?.is_some())
 }
 
+/// Check whether a path was pinned by [Engine::pin_path] and the pin
+/// hasn't expired yet.
+pub(crate) fn is_pinned(txn: &ArenaReadTransaction, path: &Path) -> Result<bool, StorageError> {
+    let pinned_table = txn.pinned_table()?;
+
+    Ok(pinned_table
+        .get(path.as_str())?
+        .map(|until| until.value() > UnixTime::now().as_secs())
+        .unwrap_or(false))
+}
+
 /// Check whether a path is dirty and if it is return its dirty mark counter.
 pub(crate) fn get_dirty(
     txn: &ArenaReadTransaction,
@@ -689,6 +1159,29 @@ pub(crate) fn clear_dirty(txn: &ArenaWriteTransaction, path: &Path) -> Result<()
     Ok(())
 }
 
+/// Stop ignoring jobs on `path`, set by [Engine::ignore_path], if
+/// the version of the file in the cache changed.
+///
+/// `hash` is the new version of the file in the cache or `None` if
+/// the file was removed. This does nothing if jobs on the path aren't
+/// ignored.
+pub(crate) fn unignore_if_changed(
+    txn: &ArenaWriteTransaction,
+    path: &Path,
+    hash: Option<&Hash>,
+) -> Result<(), StorageError> {
+    let mut ignored_job_table = txn.ignored_job_table()?;
+    let changed = match ignored_job_table.get(path.as_str())? {
+        None => false,
+        Some(ignored) => hash.map(|h| h.0.as_slice()) != Some(ignored.value()),
+    };
+    if changed {
+        ignored_job_table.remove(path.as_str())?;
+    }
+
+    Ok(())
+}
+
 /// Get a dirty path from the table for processing.
 ///
 /// The returned path is removed from the dirty table when the
@@ -766,6 +1259,10 @@ mod tests {
     }
     impl EngineFixture {
         async fn setup() -> anyhow::Result<EngineFixture> {
+            Self::setup_with_unrealize(UnrealizeConfig::default()).await
+        }
+
+        async fn setup_with_unrealize(unrealize: UnrealizeConfig) -> anyhow::Result<EngineFixture> {
             let _ = env_logger::try_init();
 
             let tempdir = TempDir::new()?;
@@ -793,6 +1290,7 @@ mod tests {
                 Arc::clone(&db),
                 Arc::clone(&dirty_paths),
                 arena_root,
+                &unrealize,
                 |attempt| {
                     if attempt < 3 {
                         Some(Duration::from_secs(attempt as u64 * 10))
@@ -1257,6 +1755,34 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn job_stream_file_to_own_corrupted_in_index() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let foobar = Path::parse("foo/bar")?;
+
+        fixture.pathmarks.set_arena_mark(Mark::Own)?;
+        fixture.add_file_to_cache_with_version(&foobar, Hash([1; 32]))?;
+        fixture.add_file_to_index_with_version(&foobar, Hash([1; 32]))?;
+        assert!(
+            fixture
+                .index
+                .mark_corrupted(&foobar, &Hash([1; 32]), &Hash([3; 32]))?
+        );
+
+        let mut job_stream = fixture.engine.job_stream();
+
+        let job = next_with_timeout(&mut job_stream).await?.unwrap();
+        assert_eq!(
+            (
+                JobId(3),
+                Job::Realize(foobar, Hash([1; 32]), Some(Hash([1; 32])))
+            ),
+            job
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn job_done() -> anyhow::Result<()> {
         let fixture = EngineFixture::setup().await?;
@@ -1403,6 +1929,359 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn retry_failed_job() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let foodir = Path::parse("foo")?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        let mut job_stream = fixture.engine.job_stream();
+        fixture.pathmarks.set_mark(&foodir, Mark::Keep)?;
+        fixture.add_file_to_cache(&barfile)?;
+
+        let (job_id, _) = next_with_timeout(&mut job_stream).await?.unwrap();
+        fixture
+            .engine
+            .job_finished(job_id, Err(anyhow::anyhow!("fake")))?;
+
+        let failed = fixture.engine.failed_jobs().await?;
+        assert_eq!(1, failed.len());
+        let (failed_id, failed_path, entry) = &failed[0];
+        assert_eq!(job_id, *failed_id);
+        assert_eq!(barfile, *failed_path);
+        assert_eq!(1, entry.failure_count);
+
+        // The job is still pending, waiting out its backoff.
+        assert_eq!(
+            vec![(
+                job_id,
+                Job::Download(barfile.clone(), test_hash()),
+                Some(entry.backoff_until)
+            )],
+            fixture.engine.pending_jobs(None).await?
+        );
+
+        assert!(fixture.engine.retry_job(job_id).await?);
+
+        // The job is returned right away, without waiting for the
+        // backoff period, under a new id.
+        let (new_job_id, job) = next_with_timeout(&mut job_stream).await?.unwrap();
+        assert_eq!(barfile, *job.path());
+        assert!(new_job_id > job_id);
+        assert!(fixture.engine.failed_jobs().await?.is_empty());
+
+        // The old job is gone.
+        assert!(!fixture.engine.retry_job(job_id).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ignore_path_until_version_changes() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let foodir = Path::parse("foo")?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        fixture.pathmarks.set_mark(&foodir, Mark::Keep)?;
+        fixture.add_file_to_cache(&barfile)?;
+        assert_eq!(1, fixture.engine.pending_jobs(None).await?.len());
+
+        assert_eq!(
+            Some(test_hash()),
+            fixture.engine.ignore_path(&barfile).await?
+        );
+        assert!(fixture.engine.pending_jobs(None).await?.is_empty());
+
+        // Marking the path dirty again doesn't bring the job back.
+        fixture.mark_dirty(&barfile)?;
+        assert!(fixture.engine.pending_jobs(None).await?.is_empty());
+
+        // A job that was already sent is dropped before it starts.
+        assert_eq!(None, fixture.engine.job_for_path(&barfile).await?);
+
+        // A new version does.
+        fixture.update_cache(Notification::Replace {
+            arena: fixture.arena,
+            index: 1,
+            path: barfile.clone(),
+            mtime: UnixTime::from_secs(1234567891),
+            size: 4,
+            hash: Hash([2; 32]),
+            old_hash: test_hash(),
+        })?;
+        assert!(
+            fixture
+                .db
+                .begin_read()?
+                .ignored_job_table()?
+                .get(barfile.as_str())?
+                .is_none()
+        );
+        assert_eq!(
+            vec![Job::Download(barfile.clone(), Hash([2; 32]))],
+            fixture
+                .engine
+                .pending_jobs(None)
+                .await?
+                .into_iter()
+                .map(|(_, job, _)| job)
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ignore_path_not_in_cache() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+
+        assert_eq!(
+            None,
+            fixture
+                .engine
+                .ignore_path(&Path::parse("foo/bar.txt")?)
+                .await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pinned_file_is_downloaded() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        fixture.add_file_to_cache(&barfile)?;
+        assert!(fixture.engine.pending_jobs(None).await?.is_empty());
+
+        assert!(
+            fixture
+                .engine
+                .pin_path(&barfile, UnixTime::now().plus(Duration::from_secs(3600)))
+                .await?
+        );
+        assert_eq!(
+            vec![Job::Download(barfile.clone(), test_hash())],
+            fixture
+                .engine
+                .pending_jobs(None)
+                .await?
+                .into_iter()
+                .map(|(_, job, _)| job)
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keep_local_file_by_default() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        fixture.add_file_to_index_with_version(&barfile, test_hash())?;
+        fixture.add_file_to_cache(&barfile)?;
+
+        assert!(fixture.engine.pending_jobs(None).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unrealize_local_file_once_a_peer_has_it() -> anyhow::Result<()> {
+        let fixture =
+            EngineFixture::setup_with_unrealize(UnrealizeConfig { min_peers: Some(1) }).await?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        fixture.add_file_to_index_with_version(&barfile, test_hash())?;
+        fixture.add_file_to_cache(&barfile)?;
+
+        assert_eq!(
+            vec![Job::Unrealize(barfile.clone(), test_hash())],
+            fixture
+                .engine
+                .pending_jobs(None)
+                .await?
+                .into_iter()
+                .map(|(_, job, _)| job)
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unrealize_local_file_once_enough_peers_have_it() -> anyhow::Result<()> {
+        let fixture =
+            EngineFixture::setup_with_unrealize(UnrealizeConfig { min_peers: Some(2) }).await?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        fixture.add_file_to_index_with_version(&barfile, test_hash())?;
+        fixture.add_file_to_cache(&barfile)?;
+        assert!(fixture.engine.pending_jobs(None).await?.is_empty());
+
+        // Another peer with a different version doesn't count.
+        fixture.acache.update(
+            Peer::from("third"),
+            Notification::Add {
+                arena: fixture.arena,
+                index: 1,
+                path: barfile.clone(),
+                mtime: UnixTime::from_secs(1234567890),
+                size: 4,
+                hash: Hash([2; 32]),
+            },
+        )?;
+        assert!(fixture.engine.pending_jobs(None).await?.is_empty());
+
+        fixture.acache.update(
+            Peer::from("fourth"),
+            Notification::Add {
+                arena: fixture.arena,
+                index: 1,
+                path: barfile.clone(),
+                mtime: UnixTime::from_secs(1234567890),
+                size: 4,
+                hash: test_hash(),
+            },
+        )?;
+        assert_eq!(
+            vec![Job::Unrealize(barfile.clone(), test_hash())],
+            fixture
+                .engine
+                .pending_jobs(None)
+                .await?
+                .into_iter()
+                .map(|(_, job, _)| job)
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_impact() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let foo = Path::parse("dir/foo")?;
+        let bar = Path::parse("dir/bar")?;
+        let local = Path::parse("dir/local")?;
+        let other = Path::parse("other/file")?;
+        fixture.add_file_to_cache(&foo)?;
+        fixture.add_file_to_cache(&bar)?;
+        fixture.add_file_to_cache(&other)?;
+        fixture.add_file_to_index_with_version(&local, test_hash())?;
+        fixture.add_file_to_cache(&local)?;
+        let dir = Path::parse("dir")?;
+
+        assert_eq!(
+            MarkImpact {
+                download_files: 2,
+                download_bytes: 8,
+                unrealize_files: 1,
+                unrealize_bytes: 100,
+                ..Default::default()
+            },
+            fixture
+                .engine
+                .mark_impact(Some(dir.clone()), Mark::Keep)
+                .await?
+        );
+        assert_eq!(
+            MarkImpact {
+                download_files: 2,
+                download_bytes: 8,
+                realize_files: 2,
+                realize_bytes: 8,
+                ..Default::default()
+            },
+            fixture
+                .engine
+                .mark_impact(Some(dir.clone()), Mark::Own)
+                .await?
+        );
+
+        // A mark closer to the file wins.
+        fixture.pathmarks.set_mark(&foo, Mark::Keep)?;
+        assert_eq!(
+            MarkImpact {
+                download_files: 1,
+                download_bytes: 4,
+                realize_files: 1,
+                realize_bytes: 4,
+                ..Default::default()
+            },
+            fixture
+                .engine
+                .mark_impact(Some(dir.clone()), Mark::Own)
+                .await?
+        );
+
+        // Nothing was changed.
+        assert_eq!(Mark::Watch, fixture.pathmarks.get_mark(&bar)?);
+
+        // Watch is already the default.
+        assert_eq!(
+            MarkImpact::default(),
+            fixture.engine.mark_impact(None, Mark::Watch).await?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_pin_is_ignored() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        fixture.add_file_to_cache(&barfile)?;
+
+        assert!(
+            fixture
+                .engine
+                .pin_path(&barfile, UnixTime::from_secs(1000))
+                .await?
+        );
+        assert!(fixture.engine.pending_jobs(None).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_pin_is_removed() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        fixture.add_file_to_cache(&barfile)?;
+
+        assert!(
+            fixture
+                .engine
+                .pin_path(&barfile, UnixTime::from_secs(1000))
+                .await?
+        );
+        {
+            let txn = fixture.db.begin_write()?;
+            clear_dirty(&txn, &barfile)?;
+            txn.commit()?;
+        }
+        time::timeout(Duration::from_secs(3), fixture.engine.expire_pins()).await??;
+
+        let txn = fixture.db.begin_read()?;
+        assert!(txn.pinned_table()?.get(barfile.as_str())?.is_none());
+        assert!(is_dirty(&txn, &barfile)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pin_path_not_in_cache() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+
+        assert!(
+            !fixture
+                .engine
+                .pin_path(
+                    &Path::parse("foo/bar.txt")?,
+                    UnixTime::now().plus(Duration::from_secs(3600))
+                )
+                .await?
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn new_stream_delays_failed_job() -> anyhow::Result<()> {
         let fixture = EngineFixture::setup().await?;
@@ -1541,6 +2420,40 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn pending_jobs() -> anyhow::Result<()> {
+        let fixture = EngineFixture::setup().await?;
+        let barfile = Path::parse("foo/bar.txt")?;
+        let bazfile = Path::parse("baz.txt")?;
+        fixture.pathmarks.set_arena_mark(Mark::Keep)?;
+        fixture.add_file_to_cache(&barfile)?;
+        fixture.add_file_to_cache(&bazfile)?;
+
+        assert_eq!(
+            vec![
+                (JobId(1), Job::Download(barfile.clone(), test_hash()), None),
+                (JobId(2), Job::Download(bazfile.clone(), test_hash()), None),
+            ],
+            fixture.engine.pending_jobs(None).await?
+        );
+        assert_eq!(
+            vec![(JobId(1), Job::Download(barfile.clone(), test_hash()), None)],
+            fixture
+                .engine
+                .pending_jobs(Some(Path::parse("foo")?))
+                .await?
+        );
+
+        // The jobs haven't been consumed.
+        let mut job_stream = fixture.engine.job_stream();
+        assert_eq!(
+            Some((JobId(1), Job::Download(barfile, test_hash()))),
+            next_with_timeout(&mut job_stream).await?
+        );
+
+        Ok(())
+    }
+
     async fn check_job_returns_outdated_because_done() -> anyhow::Result<()> {
         let fixture = EngineFixture::setup().await?;
         let foodir = Path::parse("foo")?;
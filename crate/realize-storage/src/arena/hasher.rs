@@ -1,51 +1,271 @@
 use std::path::PathBuf;
 
+use super::types::FileFingerprint;
+use crate::config::HasherConfig;
 use crate::utils::hash::{self};
+use async_speed_limit::Limiter;
 use futures::TryStreamExt as _;
-use realize_types::{self, Hash, UnixTime};
+use realize_types::{self, Hash};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex};
 use tokio::fs::File;
 use tokio::io::AsyncRead;
-use tokio::sync::mpsc;
+use tokio::sync::{Notify, mpsc};
 use tokio_util::io::ReaderStream;
+use tokio_util::sync::CancellationToken;
 
 pub struct HashResult {
     pub hash: Hash,
 
-    /// Modification time taken at the time the file was open, for verification.
-    pub mtime: UnixTime,
-
-    /// Size taken at the time the file was open, for verification.
-    pub size: u64,
+    /// Fingerprint taken at the time the file was open, for
+    /// verification.
+    pub fingerprint: FileFingerprint,
 }
 
+/// Hash files in the background, using a bounded pool of workers.
+///
+/// Queued files are hashed smallest first. Requesting the hash of a
+/// file that's already queued doesn't queue it a second time.
+/// Requesting the hash of a file that's currently being hashed
+/// cancels that hash and queues the file again, as it must have
+/// changed.
+///
+/// Results are sent to the channel passed to [Hasher::new]; cancelled
+/// hashes report nothing.
+///
+/// Workers stop when the hasher is dropped.
 pub struct Hasher {
+    shared: Arc<Shared>,
+}
+
+/// State shared by the hasher and its workers.
+struct Shared {
+    state: Mutex<State>,
+
+    /// Notified whenever a request is queued.
+    queued: Notify,
+
     tx: mpsc::Sender<(realize_types::Path, std::io::Result<HashResult>)>,
+
+    /// Limits the bytes read per second by all workers, if set.
+    limiter: Option<Limiter>,
+
+    /// Cancelled when the hasher is dropped.
+    shutdown: CancellationToken,
 }
 
-impl Hasher {
-    pub fn new(tx: mpsc::Sender<(realize_types::Path, std::io::Result<HashResult>)>) -> Self {
-        Self { tx }
+#[derive(Default)]
+struct State {
+    /// Queued requests, smallest file first, then oldest request
+    /// first.
+    ///
+    /// Entries whose counter doesn't match the one in `queued` are
+    /// obsolete and skipped.
+    queue: BinaryHeap<Reverse<(u64, u64, realize_types::Path)>>,
+
+    /// Realpath and counter of the current request for queued paths.
+    queued: HashMap<realize_types::Path, (u64, PathBuf)>,
+
+    /// Paths currently being hashed, with the counter of the request
+    /// and the token that cancels it.
+    running: HashMap<realize_types::Path, (u64, CancellationToken)>,
+
+    /// Increasing request counter.
+    counter: u64,
+}
+
+impl State {
+    /// Queue a request, cancelling any ongoing hash of the same path.
+    fn push(&mut self, realpath: PathBuf, path: realize_types::Path, size: u64) {
+        if let Some((_, token)) = self.running.remove(&path) {
+            log::debug!("Cancel ongoing hash of {path}");
+            token.cancel();
+        }
+        self.counter += 1;
+        let counter = self.counter;
+        self.queue.push(Reverse((size, counter, path.clone())));
+        self.queued.insert(path, (counter, realpath));
     }
 
-    pub fn request_hash(&self, realpath: PathBuf, path: realize_types::Path) {
-        let tx = self.tx.clone();
-        tokio::spawn(async move {
-            let _ = tx.send((path.clone(), do_hash(realpath).await)).await;
+    /// Drop queued requests and cancel ongoing hashes for `path` and
+    /// anything within it.
+    fn cancel(&mut self, path: &realize_types::Path) {
+        self.queued.retain(|p, _| !p.starts_with(path));
+        self.running.retain(|p, (_, token)| {
+            if p.starts_with(path) {
+                token.cancel();
+                return false;
+            }
+
+            true
         });
     }
+
+    /// Take the next request to process, if any, and mark it
+    /// running.
+    fn start_next(
+        &mut self,
+        token: &CancellationToken,
+    ) -> Option<(realize_types::Path, u64, PathBuf)> {
+        while let Some(Reverse((_, counter, path))) = self.queue.pop() {
+            if self
+                .queued
+                .get(&path)
+                .map(|(c, _)| *c == counter)
+                .unwrap_or(false)
+                && let Some((_, realpath)) = self.queued.remove(&path)
+            {
+                self.running.insert(path.clone(), (counter, token.clone()));
+                return Some((path, counter, realpath));
+            }
+        }
+
+        None
+    }
+}
+
+impl Hasher {
+    /// Create a hasher and start its workers.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(
+        config: &HasherConfig,
+        tx: mpsc::Sender<(realize_types::Path, std::io::Result<HashResult>)>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            queued: Notify::new(),
+            tx,
+            limiter: config
+                .max_bytes_per_second
+                .map(|bps| Limiter::new(bps as f64)),
+            shutdown: CancellationToken::new(),
+        });
+        for _ in 0..config.concurrency.max(1) {
+            tokio::spawn(worker(Arc::clone(&shared)));
+        }
+
+        Self { shared }
+    }
+
+    /// Request the hash of the file at `realpath`, stored as `path`.
+    ///
+    /// `size` is the current size of the file, used to prioritize
+    /// requests.
+    pub fn request_hash(&self, realpath: PathBuf, path: realize_types::Path, size: u64) {
+        self.shared.lock().push(realpath, path, size);
+        self.shared.queued.notify_one();
+    }
+
+    /// Cancel any queued or ongoing hash for `path` and, if it is a
+    /// directory, any file within it.
+    pub fn cancel(&self, path: &realize_types::Path) {
+        self.shared.lock().cancel(path);
+    }
+}
+
+impl Drop for Hasher {
+    fn drop(&mut self) {
+        self.shared.shutdown.cancel();
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // A panic while holding the lock leaves the state consistent
+        // enough to keep going.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Process requests until the hasher is dropped.
+async fn worker(shared: Arc<Shared>) {
+    loop {
+        let token = shared.shutdown.child_token();
+        let next = {
+            let mut state = shared.lock();
+            let next = state.start_next(&token);
+            if next.is_some() && !state.queue.is_empty() {
+                // Make sure another worker picks up the rest.
+                shared.queued.notify_one();
+            }
+
+            next
+        };
+        let (path, counter, realpath) = match next {
+            Some(next) => next,
+            None => {
+                tokio::select!(
+                    _ = shared.shutdown.cancelled() => {
+                        return;
+                    }
+                    _ = shared.queued.notified() => {
+                        continue;
+                    }
+                );
+            }
+        };
+
+        let res = do_hash(realpath, shared.limiter.as_ref(), &token).await;
+        {
+            let mut state = shared.lock();
+            if state
+                .running
+                .get(&path)
+                .map(|(c, _)| *c == counter)
+                .unwrap_or(false)
+            {
+                state.running.remove(&path);
+            }
+        }
+        if token.is_cancelled() {
+            continue;
+        }
+        if shared.tx.send((path, res)).await.is_err() {
+            return;
+        }
+    }
 }
 
-async fn do_hash(realpath: PathBuf) -> std::io::Result<HashResult> {
+async fn do_hash(
+    realpath: PathBuf,
+    limiter: Option<&Limiter>,
+    token: &CancellationToken,
+) -> std::io::Result<HashResult> {
     let f = File::open(&realpath).await?;
 
     // Take the metadata on the open file, to be sure that that's what we hashed.
     let m = f.metadata().await?;
-    let mtime = UnixTime::mtime(&m);
-    let size = m.len();
+    let fingerprint = FileFingerprint::from_metadata(&m);
+    let hash = hash_throttled(f, limiter, token).await?;
 
-    let hash = hash_file(f).await?;
+    Ok(HashResult { hash, fingerprint })
+}
 
-    Ok(HashResult { hash, mtime, size })
+/// Hash the content of `f`, limiting the read rate with `limiter`,
+/// if set.
+///
+/// Fails with [std::io::ErrorKind::Interrupted] if `token` is
+/// cancelled.
+pub(crate) async fn hash_throttled<R: AsyncRead>(
+    f: R,
+    limiter: Option<&Limiter>,
+    token: &CancellationToken,
+) -> std::io::Result<Hash> {
+    let mut hasher = hash::running();
+    let mut chunks = ReaderStream::with_capacity(f, 8 * 1024);
+    while let Some(chunk) = chunks.try_next().await? {
+        if token.is_cancelled() {
+            return Err(std::io::ErrorKind::Interrupted.into());
+        }
+        if let Some(limiter) = limiter {
+            limiter.consume(chunk.len()).await;
+        }
+        hasher.update(chunk);
+    }
+
+    Ok(hasher.finalize())
 }
 
 pub(crate) async fn hash_file<R: AsyncRead>(f: R) -> Result<Hash, std::io::Error> {
@@ -60,3 +280,119 @@ pub(crate) async fn hash_file<R: AsyncRead>(f: R) -> Result<Hash, std::io::Error
     let hash = hasher.finalize();
     Ok(hash)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::TempDir;
+    use assert_fs::prelude::*;
+    use std::time::Duration;
+
+    fn test_path(path: &str) -> anyhow::Result<realize_types::Path> {
+        Ok(realize_types::Path::parse(path)?)
+    }
+
+    fn next_path(state: &mut State) -> Option<String> {
+        state
+            .start_next(&CancellationToken::new())
+            .map(|(p, _, _)| p.as_str().to_string())
+    }
+
+    #[test]
+    fn smallest_first() -> anyhow::Result<()> {
+        let mut state = State::default();
+        state.push(PathBuf::from("/big"), test_path("big")?, 1000);
+        state.push(PathBuf::from("/small"), test_path("small")?, 10);
+        state.push(PathBuf::from("/medium"), test_path("medium")?, 100);
+        state.push(PathBuf::from("/small2"), test_path("small2")?, 10);
+
+        assert_eq!(Some("small".to_string()), next_path(&mut state));
+        assert_eq!(Some("small2".to_string()), next_path(&mut state));
+        assert_eq!(Some("medium".to_string()), next_path(&mut state));
+        assert_eq!(Some("big".to_string()), next_path(&mut state));
+        assert_eq!(None, next_path(&mut state));
+
+        Ok(())
+    }
+
+    #[test]
+    fn coalesce_duplicates() -> anyhow::Result<()> {
+        let mut state = State::default();
+        state.push(PathBuf::from("/foo"), test_path("foo")?, 10);
+        state.push(PathBuf::from("/foo"), test_path("foo")?, 1000);
+
+        assert_eq!(Some("foo".to_string()), next_path(&mut state));
+        assert_eq!(None, next_path(&mut state));
+
+        Ok(())
+    }
+
+    #[test]
+    fn request_cancels_running() -> anyhow::Result<()> {
+        let mut state = State::default();
+        state.push(PathBuf::from("/foo"), test_path("foo")?, 10);
+        let token = CancellationToken::new();
+        assert!(state.start_next(&token).is_some());
+
+        state.push(PathBuf::from("/foo"), test_path("foo")?, 10);
+
+        assert!(token.is_cancelled());
+        assert_eq!(Some("foo".to_string()), next_path(&mut state));
+
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_dir() -> anyhow::Result<()> {
+        let mut state = State::default();
+        state.push(PathBuf::from("/a/foo"), test_path("a/foo")?, 10);
+        state.push(PathBuf::from("/a/bar"), test_path("a/bar")?, 20);
+        state.push(PathBuf::from("/b/foo"), test_path("b/foo")?, 30);
+        let token = CancellationToken::new();
+        assert!(state.start_next(&token).is_some());
+
+        state.cancel(&test_path("a")?);
+
+        assert!(token.is_cancelled());
+        assert_eq!(Some("b/foo".to_string()), next_path(&mut state));
+        assert_eq!(None, next_path(&mut state));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hash_files() -> anyhow::Result<()> {
+        let tempdir = TempDir::new()?;
+        let (tx, mut rx) = mpsc::channel(10);
+        let hasher = Hasher::new(
+            &HasherConfig {
+                concurrency: 2,
+                max_bytes_per_second: Some(1024 * 1024),
+            },
+            tx,
+        );
+        for i in 0..5 {
+            let name = format!("file{i}");
+            let child = tempdir.child(&name);
+            child.write_str(&name)?;
+            hasher.request_hash(child.to_path_buf(), test_path(&name)?, name.len() as u64);
+        }
+
+        let mut results = vec![];
+        for _ in 0..5 {
+            let (path, res) = tokio::time::timeout(Duration::from_secs(3), rx.recv())
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("channel closed"))?;
+            let res = res?;
+            assert_eq!(hash::digest(path.as_str()), res.hash);
+            assert_eq!(path.as_str().len() as u64, res.fingerprint.size);
+            results.push(path.as_str().to_string());
+        }
+        assert_unordered::assert_eq_unordered!(
+            (0..5).map(|i| format!("file{i}")).collect::<Vec<_>>(),
+            results
+        );
+
+        Ok(())
+    }
+}
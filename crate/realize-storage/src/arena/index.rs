@@ -1,12 +1,17 @@
 #![allow(dead_code)] // work in progress
 
-use super::db::{ArenaDatabase, ArenaWriteTransaction};
-use super::types::{HistoryTableEntry, IndexedFileTableEntry};
+use super::db::{ArenaDatabase, ArenaReadTransaction, ArenaWriteTransaction};
+use super::scrubber;
+use super::types::{
+    CorruptionLocation, CorruptionTableEntry, FileFingerprint, HistoryTableEntry,
+    IndexedFileTableEntry, InodeTableEntry,
+};
 use crate::arena::engine::DirtyPaths;
 use crate::utils::holder::{ByteConversionError, Holder};
 use crate::{Notification, StorageError};
-use realize_types::{self, Arena, Hash, UnixTime};
+use realize_types::{self, Arena, Hash, Peer, UnixTime};
 use redb::ReadableTable as _;
+use std::collections::HashSet;
 use std::ops::RangeBounds;
 use std::sync::Arc;
 use tokio::sync::{mpsc, watch};
@@ -38,6 +43,8 @@ impl RealIndexBlocking {
             let txn = db.begin_write()?;
             {
                 txn.index_file_table()?;
+                txn.index_file_inode_table()?;
+                txn.index_inode_file_table()?;
                 let history_table = txn.index_history_table()?;
                 index = last_history_index(&history_table)?;
             }
@@ -136,6 +143,70 @@ impl RealIndexBlocking {
             .unwrap_or(false))
     }
 
+    /// Check whether a given file is in the index, unchanged.
+    ///
+    /// The file is considered unchanged if its size and mtime match
+    /// the index entry and, if the inode of the file was recorded
+    /// when it was indexed, the file is still the same inode, with
+    /// the same ctime.
+    ///
+    /// Checking ctime catches modifications that restored mtime. A
+    /// ctime change alone doesn't require hashing the file again, see
+    /// [RealIndexBlocking::hash_for_fingerprint].
+    pub fn is_unchanged(
+        &self,
+        path: &realize_types::Path,
+        fingerprint: &FileFingerprint,
+    ) -> Result<bool, StorageError> {
+        let txn = self.db.begin_read()?;
+        let entry = match get_file_entry(&txn, path)? {
+            None => return Ok(false),
+            Some(e) => e,
+        };
+        if entry.size != fingerprint.size || entry.mtime != fingerprint.mtime {
+            return Ok(false);
+        }
+        let file_inode_table = txn.index_file_inode_table()?;
+        if let Some(inode) = file_inode_table.get(path.as_str())? {
+            let inode = inode.value();
+            if inode != fingerprint.inode_key() {
+                return Ok(false);
+            }
+            let inode_table = txn.index_inode_table()?;
+            return Ok(match inode_table.get(inode)? {
+                Some(entry) => {
+                    let entry: InodeTableEntry = entry.value().parse()?;
+                    entry.ctime == fingerprint.ctime
+                }
+                None => false,
+            });
+        }
+
+        Ok(true)
+    }
+
+    /// Return the hash of a file with the given fingerprint, if
+    /// known.
+    ///
+    /// This is the hash of the inode with the same content that was
+    /// hashed last, even if it was indexed under another path that
+    /// has since been moved or removed.
+    pub fn hash_for_fingerprint(
+        &self,
+        fingerprint: &FileFingerprint,
+    ) -> Result<Option<Hash>, StorageError> {
+        let txn = self.db.begin_read()?;
+        let inode_table = txn.index_inode_table()?;
+        if let Some(entry) = inode_table.get(fingerprint.inode_key())? {
+            let entry: InodeTableEntry = entry.value().parse()?;
+            if entry.matches(fingerprint) {
+                return Ok(Some(entry.hash));
+            }
+        }
+
+        Ok(None)
+    }
+
     /// Add a file entry with the given values. Replace one if it exists.
     pub fn add_file(
         &self,
@@ -145,42 +216,214 @@ impl RealIndexBlocking {
         hash: Hash,
     ) -> Result<(), StorageError> {
         let txn = self.db.begin_write()?;
-        {
-            let mut file_table = txn.index_file_table()?;
-            let mut history_table = txn.index_history_table()?;
+        let was_link = link_target_in(&txn, path)?;
+        self.do_add_file(&txn, path, size, mtime, hash)?;
+        set_file_inode(&txn, path, None)?;
+        self.record_link_change(&txn, path, was_link)?;
+        txn.commit()?;
 
-            let old_hash = file_table
-                .get(path.as_str())?
-                .map(|e| e.value().parse().ok())
-                .flatten()
-                .map(|e| e.hash);
-            let same_hash = old_hash.as_ref().map(|h| *h == hash).unwrap_or(false);
-            file_table.insert(
-                path.as_str(),
-                Holder::with_content(IndexedFileTableEntry {
-                    size,
-                    mtime: mtime.clone(),
-                    hash,
-                    outdated_by: None,
-                })?,
-            )?;
-            if !same_hash {
-                (&self.dirty_paths).mark_dirty(&txn, path)?;
-                let index = self.allocate_history_index(&txn, &history_table)?;
-                let ev = if let Some(old_hash) = old_hash {
-                    HistoryTableEntry::Replace(path.clone(), old_hash)
-                } else {
-                    HistoryTableEntry::Add(path.clone())
-                };
-                log::debug!("[{}] History #{index}: {ev:?}", self.arena);
-                history_table.insert(index, Holder::with_content(ev)?)?;
+        Ok(())
+    }
+
+    /// Add a file entry for a file with the given fingerprint.
+    /// Replace one if it exists.
+    ///
+    /// The fingerprint is remembered, so that the file can later be
+    /// recognized without hashing it again.
+    pub fn add_fingerprinted_file(
+        &self,
+        path: &realize_types::Path,
+        hash: Hash,
+        fingerprint: &FileFingerprint,
+    ) -> Result<(), StorageError> {
+        let txn = self.db.begin_write()?;
+        let was_link = link_target_in(&txn, path)?;
+        self.do_add_file(
+            &txn,
+            path,
+            fingerprint.size,
+            &fingerprint.mtime,
+            hash.clone(),
+        )?;
+        let inode_key = fingerprint.inode_key();
+        set_file_inode(&txn, path, Some(inode_key))?;
+        txn.index_inode_table()?.insert(
+            inode_key,
+            Holder::with_content(InodeTableEntry::new(fingerprint, hash))?,
+        )?;
+        self.record_link_change(&txn, path, was_link)?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Add a history entry for `path` if it became, or stopped being,
+    /// a hard link of another file.
+    ///
+    /// `was_link` is the link target of `path` before it was
+    /// modified.
+    fn record_link_change(
+        &self,
+        txn: &ArenaWriteTransaction,
+        path: &realize_types::Path,
+        was_link: Option<realize_types::Path>,
+    ) -> Result<(), StorageError> {
+        if link_target_in(txn, path)? == was_link {
+            return Ok(());
+        }
+        let mut history_table = txn.index_history_table()?;
+        let index = self.allocate_history_index(txn, &history_table)?;
+        let ev = HistoryTableEntry::Link(path.clone());
+        log::debug!("[{}] History #{index}: {ev:?}", self.arena);
+        history_table.insert(index, Holder::with_content(ev)?)?;
+
+        Ok(())
+    }
+
+    /// Return another file in the index that `path` is a hard link
+    /// of, if any.
+    ///
+    /// Only files with the same hash are considered.
+    pub fn link_target(
+        &self,
+        path: &realize_types::Path,
+    ) -> Result<Option<realize_types::Path>, StorageError> {
+        let txn = self.db.begin_read()?;
+
+        do_link_target(
+            &txn.index_file_table()?,
+            &txn.index_file_inode_table()?,
+            &txn.index_inode_file_table()?,
+            path,
+        )
+    }
+
+    /// Send all files in the index that are hard links of another
+    /// file, with their hash and the file they're a link of, to the
+    /// given channel.
+    pub fn all_links(
+        &self,
+        tx: mpsc::Sender<(realize_types::Path, Hash, realize_types::Path)>,
+    ) -> Result<(), StorageError> {
+        let txn = self.db.begin_read()?;
+        let file_table = txn.index_file_table()?;
+        let inode_file_table = txn.index_inode_file_table()?;
+
+        let mut group: Vec<(String, Hash)> = vec![];
+        let mut group_inode = None;
+        let mut entries = inode_file_table.iter()?;
+        loop {
+            let next = match entries.next() {
+                Some(entry) => {
+                    let (k, _) = entry?;
+                    let (dev, ino, path) = k.value();
+                    Some(((dev, ino), path.to_string()))
+                }
+                None => None,
+            };
+            if group_inode.is_some() && next.as_ref().map(|(inode, _)| *inode) != group_inode {
+                // Files of the group, in path order, are links of
+                // the first file with the same hash.
+                for (path, hash) in &group {
+                    if let Some((target, _)) = group.iter().find(|(_, h)| h == hash)
+                        && target != path
+                        && tx
+                            .blocking_send((
+                                realize_types::Path::parse(path)?,
+                                hash.clone(),
+                                realize_types::Path::parse(target)?,
+                            ))
+                            .is_err()
+                    {
+                        return Ok(());
+                    }
+                }
+                group.clear();
+            }
+            let (inode, path) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            group_inode = Some(inode);
+            if let Some(entry) = file_table.get(path.as_str())? {
+                group.push((path, entry.value().parse()?.hash));
             }
         }
-        txn.commit()?;
 
         Ok(())
     }
 
+    fn do_add_file(
+        &self,
+        txn: &ArenaWriteTransaction,
+        path: &realize_types::Path,
+        size: u64,
+        mtime: &UnixTime,
+        hash: Hash,
+    ) -> Result<(), StorageError> {
+        let mut file_table = txn.index_file_table()?;
+        let mut history_table = txn.index_history_table()?;
+
+        let old_hash = file_table
+            .get(path.as_str())?
+            .map(|e| e.value().parse().ok())
+            .flatten()
+            .map(|e| e.hash);
+        let same_hash = old_hash.as_ref().map(|h| *h == hash).unwrap_or(false);
+        file_table.insert(
+            path.as_str(),
+            Holder::with_content(IndexedFileTableEntry {
+                size,
+                mtime: mtime.clone(),
+                hash,
+                outdated_by: None,
+            })?,
+        )?;
+        // The file was just hashed; whatever corruption was found before is gone.
+        scrubber::clear_corruption(txn, path, CorruptionLocation::Index)?;
+        if !same_hash {
+            (&self.dirty_paths).mark_dirty(&txn, path)?;
+            let index = self.allocate_history_index(&txn, &history_table)?;
+            let ev = if let Some(old_hash) = old_hash {
+                HistoryTableEntry::Replace(path.clone(), old_hash)
+            } else {
+                HistoryTableEntry::Add(path.clone())
+            };
+            log::debug!("[{}] History #{index}: {ev:?}", self.arena);
+            history_table.insert(index, Holder::with_content(ev)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove entries from the inode table that don't correspond to
+    /// any file in the index anymore.
+    ///
+    /// Return the number of entries that were removed.
+    pub fn prune_inodes(&self) -> Result<usize, StorageError> {
+        let txn = self.db.begin_write()?;
+        let mut removed = 0;
+        {
+            let file_inode_table = txn.index_file_inode_table()?;
+            let mut referenced = HashSet::new();
+            for entry in file_inode_table.iter()? {
+                let (_, v) = entry?;
+                referenced.insert(v.value());
+            }
+            let mut inode_table = txn.index_inode_table()?;
+            for entry in inode_table.extract_if(|k, _| !referenced.contains(&k))? {
+                entry?;
+                removed += 1;
+            }
+        }
+        txn.commit()?;
+        if removed > 0 {
+            log::debug!("[{}] Pruned {removed} inode entries", self.arena);
+        }
+
+        Ok(removed)
+    }
+
     /// Send all valid entries of the file table to the given channel.
     pub fn all_files(
         &self,
@@ -233,6 +476,91 @@ impl RealIndexBlocking {
         Ok(())
     }
 
+    /// Remember that `peer` has seen all history entries up to
+    /// `last_seen`.
+    pub fn record_peer_progress(&self, peer: Peer, last_seen: u64) -> Result<(), StorageError> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut peer_table = txn.index_peer_table()?;
+            peer_table.insert(peer.as_str(), last_seen)?;
+        }
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Index of the last history entry `peer` is known to have seen,
+    /// if any.
+    pub fn peer_progress(&self, peer: Peer) -> Result<Option<u64>, StorageError> {
+        let txn = self.db.begin_read()?;
+        let peer_table = txn.index_peer_table()?;
+
+        Ok(peer_table.get(peer.as_str())?.map(|v| v.value()))
+    }
+
+    /// Delete history entries that have been seen by all peers.
+    ///
+    /// Peers lagging more than `max_peer_lag` entries behind are
+    /// marked inactive: they're forgotten and don't prevent history
+    /// entries from being deleted anymore. Such peers need to go
+    /// through a full catchup the next time they subscribe.
+    ///
+    /// The last history entry is always kept, as it tracks the
+    /// current history index.
+    ///
+    /// Return the number of history entries that were deleted.
+    pub fn prune_history(&self, max_peer_lag: u64) -> Result<usize, StorageError> {
+        let txn = self.db.begin_write()?;
+        let mut removed = 0;
+        {
+            let mut history_table = txn.index_history_table()?;
+            let current = last_history_index(&history_table)?;
+            let mut peer_table = txn.index_peer_table()?;
+            for entry in peer_table
+                .extract_if(|_, last_seen| current.saturating_sub(last_seen) > max_peer_lag)?
+            {
+                let (peer, last_seen) = entry?;
+                log::info!(
+                    "[{}] Peer {} marked inactive; it is {} entries behind",
+                    self.arena,
+                    peer.value(),
+                    current.saturating_sub(last_seen.value())
+                );
+            }
+            let mut limit = current.saturating_sub(1);
+            for entry in peer_table.iter()? {
+                let (_, last_seen) = entry?;
+                limit = limit.min(last_seen.value());
+            }
+            for entry in history_table.extract_from_if(..=limit, |_, _| true)? {
+                entry?;
+                removed += 1;
+            }
+        }
+        txn.commit()?;
+        if removed > 0 {
+            log::debug!("[{}] Pruned {removed} history entries", self.arena);
+        }
+
+        Ok(removed)
+    }
+
+    /// Check whether all history entries after `last_seen` are still
+    /// available.
+    ///
+    /// If this returns false, some entries have been pruned, and a
+    /// subscriber that has seen entries up to `last_seen` must go
+    /// through a full catchup.
+    pub fn has_history_after(&self, last_seen: u64) -> Result<bool, StorageError> {
+        let txn = self.db.begin_read()?;
+        let history_table = txn.index_history_table()?;
+
+        Ok(match history_table.first()? {
+            None => true,
+            Some((k, _)) => k.value() <= last_seen + 1,
+        })
+    }
+
     /// Remove a path that can be a file or a directory.
     ///
     /// If the path is a directory, all files within that directory
@@ -250,6 +578,8 @@ impl RealIndexBlocking {
                 let (k, v) = entry?;
                 let index = self.allocate_history_index(&txn, &history_table)?;
                 let path = realize_types::Path::parse(k.value())?;
+                set_file_inode(&txn, &path, None)?;
+                scrubber::clear_corruption(&txn, &path, CorruptionLocation::Index)?;
                 (&self.dirty_paths).mark_dirty(&txn, &path)?;
                 let ev = HistoryTableEntry::Remove(path, v.value().parse()?.hash);
                 log::debug!("[{}] History #{index}: {ev:?}", self.arena);
@@ -278,6 +608,8 @@ impl RealIndexBlocking {
             && file_matches_index(&entry, realpath)
         {
             file_table.remove(path.as_str())?;
+            set_file_inode(txn, path, None)?;
+            scrubber::clear_corruption(txn, path, CorruptionLocation::Index)?;
 
             let index = self.allocate_history_index(&txn, &history_table)?;
             (&self.dirty_paths).mark_dirty(&txn, &path)?;
@@ -290,6 +622,38 @@ impl RealIndexBlocking {
         Ok(false)
     }
 
+    /// Record that the content of `path` was found to hash to
+    /// `actual` and mark it dirty.
+    ///
+    /// Gives up and returns false if the index entry of `path`
+    /// doesn't have the hash `expected` anymore.
+    pub fn mark_corrupted(
+        &self,
+        path: &realize_types::Path,
+        expected: &Hash,
+        actual: &Hash,
+    ) -> Result<bool, StorageError> {
+        let txn = self.db.begin_write()?;
+        match do_get_file_entry(&txn.index_file_table()?, path)? {
+            Some(entry) if entry.hash == *expected => {}
+            _ => return Ok(false),
+        }
+        scrubber::record_corruption(
+            &txn,
+            path,
+            CorruptionTableEntry {
+                location: CorruptionLocation::Index,
+                expected: expected.clone(),
+                actual: actual.clone(),
+                detected: UnixTime::now(),
+            },
+        )?;
+        (&self.dirty_paths).mark_dirty(&txn, path)?;
+        txn.commit()?;
+
+        Ok(true)
+    }
+
     fn allocate_history_index(
         &self,
         txn: &ArenaWriteTransaction,
@@ -359,6 +723,79 @@ impl RealIndexBlocking {
     }
 }
 
+/// Set or clear the inode of the indexed file at `path`, in both the
+/// file inode table and the inode file table.
+fn set_file_inode(
+    txn: &ArenaWriteTransaction,
+    path: &realize_types::Path,
+    inode: Option<(u64, u64)>,
+) -> Result<(), StorageError> {
+    let mut file_inode_table = txn.index_file_inode_table()?;
+    let mut inode_file_table = txn.index_inode_file_table()?;
+    let old = match inode {
+        Some(inode) => file_inode_table.insert(path.as_str(), inode)?,
+        None => file_inode_table.remove(path.as_str())?,
+    }
+    .map(|v| v.value());
+    if let Some((dev, ino)) = old {
+        inode_file_table.remove((dev, ino, path.as_str()))?;
+    }
+    if let Some((dev, ino)) = inode {
+        inode_file_table.insert((dev, ino, path.as_str()), ())?;
+    }
+
+    Ok(())
+}
+
+/// Return another file in the index that `path` is a hard link of,
+/// within a write transaction.
+fn link_target_in(
+    txn: &ArenaWriteTransaction,
+    path: &realize_types::Path,
+) -> Result<Option<realize_types::Path>, StorageError> {
+    do_link_target(
+        &txn.index_file_table()?,
+        &txn.index_file_inode_table()?,
+        &txn.index_inode_file_table()?,
+        path,
+    )
+}
+
+/// Return the first other file in the index with the same inode and
+/// hash as `path`, if any.
+fn do_link_target(
+    file_table: &impl redb::ReadableTable<&'static str, Holder<'static, IndexedFileTableEntry>>,
+    file_inode_table: &impl redb::ReadableTable<&'static str, (u64, u64)>,
+    inode_file_table: &impl redb::ReadableTable<(u64, u64, &'static str), ()>,
+    path: &realize_types::Path,
+) -> Result<Option<realize_types::Path>, StorageError> {
+    let (dev, ino) = match file_inode_table.get(path.as_str())? {
+        Some(v) => v.value(),
+        None => return Ok(None),
+    };
+    let hash = match do_get_file_entry(file_table, path)? {
+        Some(entry) => entry.hash,
+        None => return Ok(None),
+    };
+    for entry in inode_file_table.range((dev, ino, "")..)? {
+        let (k, _) = entry?;
+        let (other_dev, other_ino, other) = k.value();
+        if (other_dev, other_ino) != (dev, ino) {
+            break;
+        }
+        if other == path.as_str() {
+            continue;
+        }
+        if let Some(other_entry) = file_table.get(other)?
+            && other_entry.value().parse()?.hash == hash
+        {
+            return Ok(Some(realize_types::Path::parse(other)?));
+        }
+    }
+
+    Ok(None)
+}
+
 pub(crate) fn get_indexed_file(
     txn: &ArenaWriteTransaction,
     root: &std::path::Path,
@@ -547,6 +984,29 @@ impl RealIndexAsync {
         ReceiverStream::new(rx)
     }
 
+    /// Return all files that are hard links of another file in the
+    /// index as a stream of (path, hash, target).
+    pub fn all_links(&self) -> ReceiverStream<(realize_types::Path, Hash, realize_types::Path)> {
+        let (tx, rx) = mpsc::channel(100);
+
+        let inner = Arc::clone(&self.inner);
+        task::spawn_blocking(move || inner.all_links(tx));
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Return another file in the index that `path` is a hard link
+    /// of, if any.
+    pub async fn link_target(
+        &self,
+        path: &realize_types::Path,
+    ) -> Result<Option<realize_types::Path>, StorageError> {
+        let inner = Arc::clone(&self.inner);
+        let path = path.clone();
+
+        task::spawn_blocking(move || inner.link_target(&path)).await?
+    }
+
     /// Grab a range of history entries.
     pub fn history(
         &self,
@@ -612,6 +1072,52 @@ impl RealIndexAsync {
         task::spawn_blocking(move || inner.remove_file_or_dir(&path)).await?
     }
 
+    /// Record that the content of `path` was found to hash to
+    /// `actual` and mark it dirty.
+    ///
+    /// Gives up and returns false if the index entry of `path`
+    /// doesn't have the hash `expected` anymore.
+    pub async fn mark_corrupted(
+        &self,
+        path: &realize_types::Path,
+        expected: &Hash,
+        actual: &Hash,
+    ) -> Result<bool, StorageError> {
+        let inner = Arc::clone(&self.inner);
+        let path = path.clone();
+        let expected = expected.clone();
+        let actual = actual.clone();
+
+        task::spawn_blocking(move || inner.mark_corrupted(&path, &expected, &actual)).await?
+    }
+
+    /// Check whether a given file is in the index, unchanged.
+    ///
+    /// See [RealIndexBlocking::is_unchanged].
+    pub async fn is_unchanged(
+        &self,
+        path: &realize_types::Path,
+        fingerprint: &FileFingerprint,
+    ) -> Result<bool, StorageError> {
+        let inner = Arc::clone(&self.inner);
+        let path = path.clone();
+        let fingerprint = fingerprint.clone();
+
+        task::spawn_blocking(move || inner.is_unchanged(&path, &fingerprint)).await?
+    }
+
+    /// Return the hash of a file with the given fingerprint, if
+    /// known.
+    pub async fn hash_for_fingerprint(
+        &self,
+        fingerprint: &FileFingerprint,
+    ) -> Result<Option<Hash>, StorageError> {
+        let inner = Arc::clone(&self.inner);
+        let fingerprint = fingerprint.clone();
+
+        task::spawn_blocking(move || inner.hash_for_fingerprint(&fingerprint)).await?
+    }
+
     pub async fn add_file(
         &self,
         path: &realize_types::Path,
@@ -626,6 +1132,66 @@ impl RealIndexAsync {
         task::spawn_blocking(move || inner.add_file(&path, size, &mtime, hash)).await?
     }
 
+    /// Add a file entry for a file with the given fingerprint.
+    pub async fn add_fingerprinted_file(
+        &self,
+        path: &realize_types::Path,
+        hash: Hash,
+        fingerprint: &FileFingerprint,
+    ) -> Result<(), StorageError> {
+        let inner = Arc::clone(&self.inner);
+        let path = path.clone();
+        let fingerprint = fingerprint.clone();
+
+        task::spawn_blocking(move || inner.add_fingerprinted_file(&path, hash, &fingerprint))
+            .await?
+    }
+
+    /// Remove entries from the inode table that don't correspond to
+    /// any file in the index anymore.
+    pub async fn prune_inodes(&self) -> Result<usize, StorageError> {
+        let inner = Arc::clone(&self.inner);
+
+        task::spawn_blocking(move || inner.prune_inodes()).await?
+    }
+
+    /// Remember that `peer` has seen all history entries up to
+    /// `last_seen`.
+    pub async fn record_peer_progress(
+        &self,
+        peer: Peer,
+        last_seen: u64,
+    ) -> Result<(), StorageError> {
+        let inner = Arc::clone(&self.inner);
+
+        task::spawn_blocking(move || inner.record_peer_progress(peer, last_seen)).await?
+    }
+
+    /// Index of the last history entry `peer` is known to have seen,
+    /// if any.
+    pub async fn peer_progress(&self, peer: Peer) -> Result<Option<u64>, StorageError> {
+        let inner = Arc::clone(&self.inner);
+
+        task::spawn_blocking(move || inner.peer_progress(peer)).await?
+    }
+
+    /// Delete history entries that have been seen by all peers.
+    ///
+    /// See [RealIndexBlocking::prune_history].
+    pub async fn prune_history(&self, max_peer_lag: u64) -> Result<usize, StorageError> {
+        let inner = Arc::clone(&self.inner);
+
+        task::spawn_blocking(move || inner.prune_history(max_peer_lag)).await?
+    }
+
+    /// Check whether all history entries after `last_seen` are still
+    /// available.
+    pub async fn has_history_after(&self, last_seen: u64) -> Result<bool, StorageError> {
+        let inner = Arc::clone(&self.inner);
+
+        task::spawn_blocking(move || inner.has_history_after(last_seen)).await?
+    }
+
     /// Take a remote change into account, if it applies to a file in
     /// the index.
     pub(crate) async fn update(
@@ -669,6 +1235,41 @@ pub(crate) fn mark_dirty_recursive(
     Ok(())
 }
 
+/// List the files of the index that are at `path` or within `path`,
+/// or all files if `path` is None.
+pub(crate) fn files_within(
+    txn: &ArenaReadTransaction,
+    path: Option<&realize_types::Path>,
+) -> Result<Vec<realize_types::Path>, StorageError> {
+    let file_table = txn.index_file_table()?;
+    let mut ret = vec![];
+    match path {
+        None => {
+            for entry in file_table.iter()? {
+                let (k, _) = entry?;
+                if let Ok(path) = realize_types::Path::parse(k.value()) {
+                    ret.push(path);
+                }
+            }
+        }
+        Some(path) => {
+            let path_prefix = PathPrefix::new(path);
+            for entry in file_table.range(path_prefix.range())? {
+                let (k, _) = entry?;
+                let key = k.value();
+                if !path_prefix.accept(key) {
+                    continue;
+                }
+                if let Ok(path) = realize_types::Path::parse(key) {
+                    ret.push(path);
+                }
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
 /// Mark all files within the index dirty
 pub(crate) fn make_all_dirty(
     txn: &ArenaWriteTransaction,
@@ -916,6 +1517,200 @@ mod tests {
         Ok(())
     }
 
+    fn test_fingerprint(ino: u64, size: u64) -> FileFingerprint {
+        FileFingerprint {
+            dev: 1,
+            ino,
+            size,
+            mtime: UnixTime::from_secs(1234567890),
+            ctime: UnixTime::from_secs(1234567890),
+        }
+    }
+
+    #[tokio::test]
+    async fn add_fingerprinted_file() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let index = &fixture.index;
+        let path = realize_types::Path::parse("foo/bar")?;
+        let fingerprint = test_fingerprint(2, 100);
+        index.add_fingerprinted_file(&path, Hash([0xfa; 32]), &fingerprint)?;
+
+        assert_eq!(
+            Some(IndexedFileTableEntry {
+                size: 100,
+                mtime: fingerprint.mtime.clone(),
+                hash: Hash([0xfa; 32]),
+                outdated_by: None,
+            }),
+            index.get_file(&path)?
+        );
+        assert!(index.is_unchanged(&path, &fingerprint)?);
+        assert_eq!(
+            Some(Hash([0xfa; 32])),
+            index.hash_for_fingerprint(&fingerprint)?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_unchanged_detects_other_inode() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let index = &fixture.index;
+        let path = realize_types::Path::parse("foo/bar")?;
+        let fingerprint = test_fingerprint(2, 100);
+        index.add_fingerprinted_file(&path, Hash([0xfa; 32]), &fingerprint)?;
+
+        // Same size and mtime, but another inode
+        assert!(!index.is_unchanged(&path, &test_fingerprint(3, 100))?);
+
+        // Another size
+        assert!(!index.is_unchanged(&path, &test_fingerprint(2, 200))?);
+
+        // Another ctime
+        let other_ctime = FileFingerprint {
+            ctime: UnixTime::from_secs(1234567899),
+            ..fingerprint.clone()
+        };
+        assert!(!index.is_unchanged(&path, &other_ctime)?);
+
+        // The hash can still be reused when only ctime changed.
+        assert_eq!(
+            Some(Hash([0xfa; 32])),
+            index.hash_for_fingerprint(&other_ctime)?
+        );
+        index.add_fingerprinted_file(&path, Hash([0xfa; 32]), &other_ctime)?;
+        assert!(index.is_unchanged(&path, &other_ctime)?);
+
+        // Not in index
+        assert!(!index.is_unchanged(
+            &realize_types::Path::parse("other")?,
+            &test_fingerprint(2, 100)
+        )?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn is_unchanged_without_fingerprint() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let index = &fixture.index;
+        let path = realize_types::Path::parse("foo/bar")?;
+        let fingerprint = test_fingerprint(2, 100);
+        index.add_fingerprinted_file(&path, Hash([0xfa; 32]), &fingerprint)?;
+        index.add_file(&path, 100, &fingerprint.mtime, Hash([0xfa; 32]))?;
+
+        // Only size and mtime can be compared
+        assert!(index.is_unchanged(&path, &test_fingerprint(3, 100))?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hash_for_fingerprint_after_remove() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let index = &fixture.index;
+        let path = realize_types::Path::parse("foo/bar")?;
+        let fingerprint = test_fingerprint(2, 100);
+        index.add_fingerprinted_file(&path, Hash([0xfa; 32]), &fingerprint)?;
+        index.remove_file_or_dir(&path)?;
+
+        assert!(!index.has_file(&path)?);
+        assert_eq!(
+            Some(Hash([0xfa; 32])),
+            index.hash_for_fingerprint(&fingerprint)?
+        );
+        assert_eq!(None, index.hash_for_fingerprint(&test_fingerprint(2, 200))?);
+        assert_eq!(None, index.hash_for_fingerprint(&test_fingerprint(3, 100))?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prune_inodes() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let index = &fixture.index;
+        let foo = realize_types::Path::parse("foo")?;
+        let bar = realize_types::Path::parse("bar")?;
+        index.add_fingerprinted_file(&foo, Hash([1; 32]), &test_fingerprint(2, 100))?;
+        index.add_fingerprinted_file(&bar, Hash([2; 32]), &test_fingerprint(3, 100))?;
+        index.remove_file_or_dir(&foo)?;
+
+        assert_eq!(1, index.prune_inodes()?);
+        assert_eq!(None, index.hash_for_fingerprint(&test_fingerprint(2, 100))?);
+        assert_eq!(
+            Some(Hash([2; 32])),
+            index.hash_for_fingerprint(&test_fingerprint(3, 100))?
+        );
+        assert_eq!(0, index.prune_inodes()?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn hard_links() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let index = &fixture.index;
+        let foo = realize_types::Path::parse("foo")?;
+        let bar = realize_types::Path::parse("bar")?;
+        let baz = realize_types::Path::parse("baz")?;
+        index.add_fingerprinted_file(&foo, Hash([1; 32]), &test_fingerprint(2, 100))?;
+        index.add_fingerprinted_file(&bar, Hash([1; 32]), &test_fingerprint(2, 100))?;
+        index.add_fingerprinted_file(&baz, Hash([1; 32]), &test_fingerprint(3, 100))?;
+
+        assert_eq!(Some(foo.clone()), index.link_target(&bar)?);
+        assert_eq!(Some(bar.clone()), index.link_target(&foo)?);
+        assert_eq!(None, index.link_target(&baz)?);
+        assert_eq!(
+            vec![(foo.clone(), Hash([1; 32]), bar.clone())],
+            fixture.aindex.all_links().collect::<Vec<_>>().await
+        );
+
+        // bar is not a link anymore once it's been replaced.
+        index.add_fingerprinted_file(&bar, Hash([2; 32]), &test_fingerprint(4, 100))?;
+        assert_eq!(None, index.link_target(&bar)?);
+        assert_eq!(None, index.link_target(&foo)?);
+
+        let all = fixture.aindex.history(0..).try_collect::<Vec<_>>().await?;
+        assert_eq!(
+            vec![
+                (1, HistoryTableEntry::Add(foo.clone())),
+                (2, HistoryTableEntry::Add(bar.clone())),
+                (3, HistoryTableEntry::Link(bar.clone())),
+                (4, HistoryTableEntry::Add(baz.clone())),
+                (5, HistoryTableEntry::Replace(bar.clone(), Hash([1; 32]))),
+                (6, HistoryTableEntry::Link(bar.clone())),
+            ],
+            all
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn link_requires_same_hash() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        // This happens when a link was modified, but only one of the
+        // paths has been hashed again so far.
+        let index = &fixture.index;
+        let foo = realize_types::Path::parse("foo")?;
+        let bar = realize_types::Path::parse("bar")?;
+        index.add_fingerprinted_file(&foo, Hash([1; 32]), &test_fingerprint(2, 100))?;
+        index.add_fingerprinted_file(&bar, Hash([2; 32]), &test_fingerprint(2, 100))?;
+
+        assert_eq!(None, index.link_target(&bar)?);
+        assert_eq!(None, index.link_target(&foo)?);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn remove_file() -> anyhow::Result<()> {
         let fixture = Fixture::setup().await?;
@@ -1178,6 +1973,71 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn prune_history() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let index = &fixture.index;
+
+        let mtime = UnixTime::from_secs(1234567890);
+        for i in 0..6 {
+            index.add_file(
+                &realize_types::Path::parse(format!("file{i}"))?,
+                100,
+                &mtime,
+                Hash([1; 32]),
+            )?;
+        }
+        let a = Peer::from("a");
+        let b = Peer::from("b");
+        index.record_peer_progress(a, 4)?;
+        index.record_peer_progress(b, 2)?;
+
+        assert_eq!(2, index.prune_history(10)?);
+        assert_eq!(true, index.has_history_after(2)?);
+        assert_eq!(false, index.has_history_after(1)?);
+
+        index.record_peer_progress(b, 6)?;
+        assert_eq!(2, index.prune_history(10)?);
+        assert_eq!(true, index.has_history_after(4)?);
+        assert_eq!(false, index.has_history_after(3)?);
+
+        index.record_peer_progress(a, 6)?;
+        // The last entry is always kept.
+        assert_eq!(1, index.prune_history(10)?);
+        assert_eq!(6, index.last_history_index()?);
+        assert_eq!(true, index.has_history_after(6)?);
+        assert_eq!(false, index.has_history_after(4)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prune_history_marks_lagging_peer_inactive() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let index = &fixture.index;
+
+        let mtime = UnixTime::from_secs(1234567890);
+        for i in 0..6 {
+            index.add_file(
+                &realize_types::Path::parse(format!("file{i}"))?,
+                100,
+                &mtime,
+                Hash([1; 32]),
+            )?;
+        }
+        let a = Peer::from("a");
+        let b = Peer::from("b");
+        index.record_peer_progress(a, 5)?;
+        index.record_peer_progress(b, 1)?;
+
+        assert_eq!(5, index.prune_history(3)?);
+        assert_eq!(Some(5), index.peer_progress(a)?);
+        assert_eq!(None, index.peer_progress(b)?);
+        assert_eq!(false, index.has_history_after(1)?);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn watch_history() -> anyhow::Result<()> {
         let fixture = Fixture::setup().await?;
@@ -0,0 +1,253 @@
+use super::db::ArenaDatabase;
+use super::types::{JobHistoryTableEntry, JobOutcome};
+use crate::StorageError;
+use crate::config::JobHistoryConfig;
+use crate::utils::holder::Holder;
+use realize_types::{Path, UnixTime};
+use std::sync::Arc;
+
+/// Select entries of the job history.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JobHistoryFilter {
+    /// Only report jobs on that path or on files within that
+    /// directory.
+    pub path_prefix: Option<Path>,
+
+    /// Only report jobs that ended that way.
+    pub outcome: Option<JobOutcome>,
+
+    /// Only report jobs that ended at or after that time.
+    pub since: Option<UnixTime>,
+
+    /// Only report jobs that ended before that time.
+    pub until: Option<UnixTime>,
+
+    /// Report at most that many jobs, the most recent ones.
+    pub limit: Option<usize>,
+}
+
+impl JobHistoryFilter {
+    /// Check whether the given entry is selected by the filter.
+    ///
+    /// This ignores [JobHistoryFilter::limit].
+    pub fn matches(&self, entry: &JobHistoryTableEntry) -> bool {
+        if let Some(prefix) = &self.path_prefix
+            && !entry.path.starts_with(prefix)
+        {
+            return false;
+        }
+        if let Some(outcome) = self.outcome
+            && entry.outcome != outcome
+        {
+            return false;
+        }
+        if let Some(since) = &self.since
+            && entry.end < *since
+        {
+            return false;
+        }
+        if let Some(until) = &self.until
+            && entry.end >= *until
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Persistent record of the outcome of jobs run in an arena.
+pub(crate) struct JobHistory {
+    db: Arc<ArenaDatabase>,
+    config: JobHistoryConfig,
+}
+
+impl JobHistory {
+    pub(crate) fn new(db: Arc<ArenaDatabase>, config: &JobHistoryConfig) -> Self {
+        Self {
+            db,
+            config: config.clone(),
+        }
+    }
+
+    /// Add an entry to the history, then remove entries beyond the
+    /// limits of [JobHistoryConfig].
+    pub(crate) fn record(&self, entry: JobHistoryTableEntry) -> Result<(), StorageError> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut job_history_table = txn.job_history_table()?;
+            let key = match job_history_table.last()? {
+                Some((k, _)) => k.value() + 1,
+                None => 1,
+            };
+            job_history_table.insert(key, Holder::with_content(entry)?)?;
+
+            let oldest = self
+                .config
+                .max_age_secs
+                .map(|secs| UnixTime::now().as_secs().saturating_sub(secs))
+                .map(UnixTime::from_secs);
+            while let Some((k, v)) = job_history_table.first()? {
+                let key = k.value();
+                let too_many = job_history_table.len()? > self.config.max_entries as u64;
+                let too_old = match &oldest {
+                    Some(oldest) => v.value().parse()?.end < *oldest,
+                    None => false,
+                };
+                drop((k, v));
+                if !too_many && !too_old {
+                    break;
+                }
+                job_history_table.remove(key)?;
+            }
+        }
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Return the entries selected by `filter`, the most recent first.
+    pub(crate) fn query(
+        &self,
+        filter: &JobHistoryFilter,
+    ) -> Result<Vec<JobHistoryTableEntry>, StorageError> {
+        let txn = self.db.begin_read()?;
+        let mut ret = vec![];
+        for entry in txn.job_history_table()?.iter()?.rev() {
+            if let Some(limit) = filter.limit
+                && ret.len() >= limit
+            {
+                break;
+            }
+            let (_, v) = entry?;
+            let entry = v.value().parse()?;
+            if filter.matches(&entry) {
+                ret.push(entry);
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::types::JobKind;
+    use crate::utils::redb_utils;
+    use realize_types::Hash;
+
+    fn entry(path: &str, end: u64, outcome: JobOutcome) -> anyhow::Result<JobHistoryTableEntry> {
+        Ok(JobHistoryTableEntry {
+            path: Path::parse(path)?,
+            hash: Hash([1; 32]),
+            kind: JobKind::Download,
+            start: UnixTime::from_secs(end - 10),
+            end: UnixTime::from_secs(end),
+            bytes: 100,
+            outcome,
+            error: if outcome == JobOutcome::Failed {
+                Some("failed".to_string())
+            } else {
+                None
+            },
+        })
+    }
+
+    fn paths(entries: Vec<JobHistoryTableEntry>) -> Vec<String> {
+        entries
+            .into_iter()
+            .map(|e| e.path.as_str().to_string())
+            .collect()
+    }
+
+    fn setup(config: JobHistoryConfig) -> anyhow::Result<JobHistory> {
+        Ok(JobHistory::new(
+            ArenaDatabase::new(redb_utils::in_memory()?)?,
+            &config,
+        ))
+    }
+
+    #[test]
+    fn record_and_query() -> anyhow::Result<()> {
+        let history = setup(JobHistoryConfig {
+            max_entries: 100,
+            max_age_secs: None,
+        })?;
+        history.record(entry("foo/a", 1000, JobOutcome::Done)?)?;
+        history.record(entry("bar/b", 2000, JobOutcome::Failed)?)?;
+        history.record(entry("foo/c", 3000, JobOutcome::Abandoned)?)?;
+
+        assert_eq!(
+            vec!["foo/c", "bar/b", "foo/a"],
+            paths(history.query(&JobHistoryFilter::default())?)
+        );
+        assert_eq!(
+            vec!["foo/c", "foo/a"],
+            paths(history.query(&JobHistoryFilter {
+                path_prefix: Some(Path::parse("foo")?),
+                ..Default::default()
+            })?)
+        );
+        assert_eq!(
+            vec!["bar/b"],
+            paths(history.query(&JobHistoryFilter {
+                outcome: Some(JobOutcome::Failed),
+                ..Default::default()
+            })?)
+        );
+        assert_eq!(
+            vec!["bar/b"],
+            paths(history.query(&JobHistoryFilter {
+                since: Some(UnixTime::from_secs(2000)),
+                until: Some(UnixTime::from_secs(3000)),
+                ..Default::default()
+            })?)
+        );
+        assert_eq!(
+            vec!["foo/c"],
+            paths(history.query(&JobHistoryFilter {
+                limit: Some(1),
+                ..Default::default()
+            })?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_max_entries() -> anyhow::Result<()> {
+        let history = setup(JobHistoryConfig {
+            max_entries: 2,
+            max_age_secs: None,
+        })?;
+        history.record(entry("a", 1000, JobOutcome::Done)?)?;
+        history.record(entry("b", 2000, JobOutcome::Done)?)?;
+        history.record(entry("c", 3000, JobOutcome::Done)?)?;
+
+        assert_eq!(
+            vec!["c", "b"],
+            paths(history.query(&JobHistoryFilter::default())?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn drop_old_entries() -> anyhow::Result<()> {
+        let history = setup(JobHistoryConfig {
+            max_entries: 100,
+            max_age_secs: Some(3600),
+        })?;
+        let now = UnixTime::now().as_secs();
+        history.record(entry("old", now - 7200, JobOutcome::Done)?)?;
+        history.record(entry("recent", now - 60, JobOutcome::Done)?)?;
+
+        assert_eq!(
+            vec!["recent"],
+            paths(history.query(&JobHistoryFilter::default())?)
+        );
+
+        Ok(())
+    }
+}
@@ -1,19 +1,33 @@
 #![allow(dead_code)] // work in progress
 
 use super::db::{ArenaDatabase, ArenaReadTransaction, ArenaWriteTransaction};
-use super::types::{Mark, MarkTableEntry};
+use super::types::{Mark, MarkRule, MarkSource, MarkTableEntry};
 use crate::arena::arena_cache;
 use crate::arena::index;
-use crate::utils::holder::Holder;
+use crate::utils::holder::{ByteConversionError, Holder};
 use crate::{DirtyPaths, Inode, StorageError};
 use realize_types::Path;
 use std::sync::Arc;
 
+/// Prefix of the keys of mark rules in the mark table.
+///
+/// Paths can't contain ':', so these keys never conflict with the
+/// paths of marks.
+const RULE_KEY_PREFIX: &str = "rule:";
+
+/// End of the range of keys of mark rules; ';' follows ':'.
+const RULE_KEY_END: &str = "rule;";
+
 /// Tracks marks hierarchically by paths in an arena.
 ///
 /// Paths can be files or directories, and marks are inherited from the most specific
 /// path that has a mark set (file > directory > arena default).
 ///
+/// Mark rules select files by glob pattern or size, see [MarkRule].
+/// A mark set on the file itself wins over mark rules, which win
+/// over marks inherited from directories. If more than one rule
+/// matches, the one that was added first wins.
+///
 /// When a mark changes, affected paths in the index and arena cache
 /// are marked dirty. Changing the root mark will mark all files in
 /// the cache and index dirty.
@@ -46,13 +60,108 @@ impl PathMarks {
 
     /// Get the mark for a specific path.
     ///
-    /// The mark might have been set on the given path, one of its
-    /// parents or it can be the root mark.
+    /// The mark might have been set on the given path, come from a
+    /// mark rule, have been set on one of its parents or it can be
+    /// the root mark.
     pub fn get_mark(&self, path: &Path) -> Result<Mark, StorageError> {
         let txn = self.db.begin_read()?;
+
+        get_mark(&txn, self.arena_root, path)
+    }
+
+    /// Get the mark for a specific path and where it comes from.
+    pub fn explain_mark(&self, path: &Path) -> Result<(Mark, MarkSource), StorageError> {
+        let txn = self.db.begin_read()?;
+
+        explain_mark(&txn, self.arena_root, path)
+    }
+
+    /// List the marks set on the arena and on paths, ordered by path.
+    ///
+    /// The arena mark, if set, comes first, with no path. This
+    /// doesn't include mark rules.
+    pub fn marks(&self) -> Result<Vec<(Option<Path>, Mark)>, StorageError> {
+        let txn = self.db.begin_read()?;
+        let mark_table = txn.mark_table()?;
+        let mut ret = vec![];
+        for entry in mark_table.iter()? {
+            let (k, v) = entry?;
+            let entry = v.value().parse()?;
+            if entry.rule.is_some() {
+                continue;
+            }
+            let path = match k.value() {
+                "" => None,
+                key => Some(Path::parse(key)?),
+            };
+            ret.push((path, entry.mark));
+        }
+
+        Ok(ret)
+    }
+
+    /// Add a rule that sets `mark` on the files it selects.
+    ///
+    /// The new rule is evaluated after the existing ones. Files the
+    /// rule might apply to are marked dirty.
+    ///
+    /// Return the ID of the new rule.
+    pub fn add_rule(&self, rule: MarkRule, mark: Mark) -> Result<u64, StorageError> {
+        validate_rule(&rule)?;
+        let txn = self.db.begin_write()?;
+        let id;
+        {
+            let mut mark_table = txn.mark_table()?;
+            let last = match mark_table.range(RULE_KEY_PREFIX..RULE_KEY_END)?.next_back() {
+                None => None,
+                Some(entry) => Some(parse_rule_key(entry?.0.value())?),
+            };
+            id = last.unwrap_or(0) + 1;
+            let scope = rule_scope(&rule);
+            mark_table.insert(
+                rule_key(id).as_str(),
+                Holder::with_content(MarkTableEntry {
+                    mark,
+                    rule: Some(rule),
+                })?,
+            )?;
+            self.mark_dirty(&txn, scope.as_ref())?;
+        }
+        txn.commit()?;
+
+        Ok(id)
+    }
+
+    /// Remove the rule with the given ID.
+    ///
+    /// Files the rule might have applied to are marked dirty.
+    ///
+    /// Return false if there was no such rule.
+    pub fn remove_rule(&self, id: u64) -> Result<bool, StorageError> {
+        let txn = self.db.begin_write()?;
+        let removed;
+        {
+            let mut mark_table = txn.mark_table()?;
+            removed = match mark_table.remove(rule_key(id).as_str())? {
+                None => None,
+                Some(e) => e.value().parse()?.rule,
+            };
+            if let Some(rule) = &removed {
+                self.mark_dirty(&txn, rule_scope(rule).as_ref())?;
+            }
+        }
+        txn.commit()?;
+
+        Ok(removed.is_some())
+    }
+
+    /// Return the mark rules, with their ID and mark, in the order
+    /// in which they're evaluated.
+    pub fn rules(&self) -> Result<Vec<(u64, MarkRule, Mark)>, StorageError> {
+        let txn = self.db.begin_read()?;
         let mark_table = txn.mark_table()?;
 
-        do_get_mark(&mark_table, Some(path))
+        do_rules(&mark_table)
     }
 
     /// Set the default mark for the arena.
@@ -78,9 +187,11 @@ impl PathMarks {
                 }
                 Some(e) => e.value().parse()?.mark,
             };
-            let after = do_get_mark(&mark_table, Some(path))?;
+            let after = do_get_path_mark(&mark_table, Some(path))?;
 
-            if before != after {
+            // With rules, the mark of the path itself might change
+            // even if the inherited mark doesn't.
+            if before != after || has_rules(&mark_table)? {
                 // Clearing the mark had an impact, mark affected files.
                 self.mark_dirty(&txn, Some(path))?;
             }
@@ -98,13 +209,15 @@ impl PathMarks {
         let txn = self.db.begin_write()?;
         {
             let mut mark_table = txn.mark_table()?;
-            let before = do_get_mark(&mark_table, path_or_root)?;
+            let before = do_get_path_mark(&mark_table, path_or_root)?;
             mark_table.insert(
                 path_or_root.map(|p| p.as_str()).unwrap_or(""),
-                Holder::with_content(MarkTableEntry { mark })?,
+                Holder::with_content(MarkTableEntry { mark, rule: None })?,
             )?;
 
-            if before != mark {
+            // With rules, the mark of the path itself might change
+            // even if the inherited mark doesn't.
+            if before != mark || has_rules(&mark_table)? {
                 // Setting the mark had an impact, mark affected files.
                 self.mark_dirty(&txn, path_or_root)?;
             }
@@ -144,25 +257,117 @@ impl PathMarks {
     }
 }
 
-pub(crate) fn get_mark(txn: &ArenaReadTransaction, path: &Path) -> Result<Mark, StorageError> {
+pub(crate) fn get_mark(
+    txn: &ArenaReadTransaction,
+    arena_root: Inode,
+    path: &Path,
+) -> Result<Mark, StorageError> {
+    Ok(explain_mark(txn, arena_root, path)?.0)
+}
+
+pub(crate) fn explain_mark(
+    txn: &ArenaReadTransaction,
+    arena_root: Inode,
+    path: &Path,
+) -> Result<(Mark, MarkSource), StorageError> {
     let mark_table = txn.mark_table()?;
-    do_get_mark(&mark_table, Some(path))
+    do_explain_mark(&mark_table, path, || file_size(txn, arena_root, path))
 }
 
-fn do_get_mark(
+/// Return the mark of `path` before and after setting `mark` on
+/// `changed`, or on the arena if `changed` is None, without changing
+/// anything.
+///
+/// `path` must be `changed` or within `changed`.
+pub(crate) fn mark_change(
+    txn: &ArenaReadTransaction,
+    arena_root: Inode,
+    path: &Path,
+    changed: Option<&Path>,
+    mark: Mark,
+) -> Result<(Mark, Mark), StorageError> {
+    let (before, source) = explain_mark(txn, arena_root, path)?;
+    if changed == Some(path) {
+        return Ok((before, mark));
+    }
+    let after = match (source, changed) {
+        (MarkSource::Rule(_, _), _) => before,
+        (MarkSource::Arena | MarkSource::Default, _) => mark,
+        (MarkSource::Path(_), None) => before,
+        (MarkSource::Path(source), Some(changed)) => {
+            if source != *changed && source.starts_with(changed) {
+                // A mark closer to the path wins.
+                before
+            } else {
+                mark
+            }
+        }
+    };
+
+    Ok((before, after))
+}
+
+/// Get the mark of `path`, taking rules into account, and where it
+/// comes from.
+///
+/// `size` is only called if a rule needs the size of the file.
+fn do_explain_mark(
+    mark_table: &impl redb::ReadableTable<&'static str, Holder<'static, MarkTableEntry>>,
+    path: &Path,
+    size: impl FnOnce() -> Option<u64>,
+) -> Result<(Mark, MarkSource), StorageError> {
+    if let Some(entry) = mark_table.get(path.as_str())? {
+        return Ok((entry.value().parse()?.mark, MarkSource::Path(path.clone())));
+    }
+    let rules = do_rules(mark_table)?;
+    if !rules.is_empty() {
+        let size = if rules
+            .iter()
+            .any(|(_, r, _)| r.min_size.is_some() || r.max_size.is_some())
+        {
+            size()
+        } else {
+            None
+        };
+        for (id, rule, mark) in rules {
+            if rule_matches(&rule, path, size) {
+                return Ok((mark, MarkSource::Rule(id, rule)));
+            }
+        }
+    }
+
+    do_explain_path_mark(mark_table, path.parent().as_ref())
+}
+
+/// Get the mark set on `path` or inherited from its parents,
+/// ignoring rules.
+fn do_get_path_mark(
     mark_table: &impl redb::ReadableTable<&'static str, Holder<'static, MarkTableEntry>>,
     path: Option<&Path>,
 ) -> Result<Mark, StorageError> {
+    Ok(do_explain_path_mark(mark_table, path)?.0)
+}
+
+/// Get the mark set on `path` or inherited from its parents,
+/// ignoring rules, and where it comes from.
+fn do_explain_path_mark(
+    mark_table: &impl redb::ReadableTable<&'static str, Holder<'static, MarkTableEntry>>,
+    path: Option<&Path>,
+) -> Result<(Mark, MarkSource), StorageError> {
     let mut current = path.cloned();
 
     loop {
         let key = current.as_ref().map(|v| v.as_str()).unwrap_or("");
         if let Some(entry) = mark_table.get(key)? {
-            return Ok(entry.value().parse()?.mark);
+            let mark = entry.value().parse()?.mark;
+            return Ok(match current {
+                None => (mark, MarkSource::Arena),
+                Some(p) => (mark, MarkSource::Path(p)),
+            });
         }
         match current {
             None => {
-                return Ok(Mark::default());
+                return Ok((Mark::default(), MarkSource::Default));
             }
             Some(p) => {
                 current = p.parent();
@@ -171,6 +376,162 @@ fn do_get_mark(
     }
 }
 
+/// Return the mark rules of the table, in order.
+fn do_rules(
+    mark_table: &impl redb::ReadableTable<&'static str, Holder<'static, MarkTableEntry>>,
+) -> Result<Vec<(u64, MarkRule, Mark)>, StorageError> {
+    let mut rules = vec![];
+    for entry in mark_table.range(RULE_KEY_PREFIX..RULE_KEY_END)? {
+        let (k, v) = entry?;
+        let entry = v.value().parse()?;
+        if let Some(rule) = entry.rule {
+            rules.push((parse_rule_key(k.value())?, rule, entry.mark));
+        }
+    }
+
+    Ok(rules)
+}
+
+fn has_rules(
+    mark_table: &impl redb::ReadableTable<&'static str, Holder<'static, MarkTableEntry>>,
+) -> Result<bool, StorageError> {
+    Ok(mark_table
+        .range(RULE_KEY_PREFIX..RULE_KEY_END)?
+        .next()
+        .is_some())
+}
+
+fn rule_key(id: u64) -> String {
+    format!("{RULE_KEY_PREFIX}{id:020}")
+}
+
+fn parse_rule_key(key: &str) -> Result<u64, StorageError> {
+    Ok(key
+        .strip_prefix(RULE_KEY_PREFIX)
+        .and_then(|id| id.parse().ok())
+        .ok_or(ByteConversionError::Invalid("rule key"))?)
+}
+
+fn validate_rule(rule: &MarkRule) -> Result<(), StorageError> {
+    if rule.pattern.is_none() && rule.min_size.is_none() && rule.max_size.is_none() {
+        return Err(StorageError::InvalidMarkRule(
+            "a rule must have a pattern or a size limit".to_string(),
+        ));
+    }
+    if let Some(pattern) = &rule.pattern
+        && pattern.split('/').any(|c| c.is_empty())
+    {
+        return Err(StorageError::InvalidMarkRule(format!(
+            "invalid pattern: \"{pattern}\""
+        )));
+    }
+    if let (Some(min), Some(max)) = (rule.min_size, rule.max_size)
+        && min > max
+    {
+        return Err(StorageError::InvalidMarkRule(format!(
+            "minimum size {min} is larger than maximum size {max}"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Size of the file at `path` in the index or, if it's not there, in
+/// the cache.
+fn file_size(txn: &ArenaReadTransaction, arena_root: Inode, path: &Path) -> Option<u64> {
+    if let Ok(Some(entry)) = index::get_file_entry(txn, path) {
+        return Some(entry.size);
+    }
+
+    arena_cache::get_file_entry_for_path(txn, arena_root, path)
+        .ok()
+        .map(|e| e.metadata.size)
+}
+
+/// Check whether the file at `path`, of size `size`, if known, is
+/// selected by `rule`.
+fn rule_matches(rule: &MarkRule, path: &Path, size: Option<u64>) -> bool {
+    if let Some(pattern) = &rule.pattern
+        && !glob_matches(pattern, path)
+    {
+        return false;
+    }
+    if rule.min_size.is_some() || rule.max_size.is_some() {
+        let Some(size) = size else {
+            return false;
+        };
+        if let Some(min) = rule.min_size
+            && size < min
+        {
+            return false;
+        }
+        if let Some(max) = rule.max_size
+            && size > max
+        {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Directory that contains all the files `rule` can select, None for
+/// the whole arena.
+fn rule_scope(rule: &MarkRule) -> Option<Path> {
+    let pattern = rule.pattern.as_ref()?;
+    if !pattern.contains('/') {
+        return None;
+    }
+    let literal = pattern
+        .split('/')
+        .take_while(|c| !c.contains(['*', '?']))
+        .collect::<Vec<_>>();
+
+    Path::parse(literal.join("/")).ok()
+}
+
+/// Check whether `path` matches the glob `pattern`.
+///
+/// A pattern without `/` is matched against the file name.
+fn glob_matches(pattern: &str, path: &Path) -> bool {
+    if !pattern.contains('/') {
+        return component_matches(
+            &pattern.chars().collect::<Vec<_>>(),
+            &path.name().chars().collect::<Vec<_>>(),
+        );
+    }
+
+    components_match(
+        &pattern.split('/').collect::<Vec<_>>(),
+        &path.as_str().split('/').collect::<Vec<_>>(),
+    )
+}
+
+fn components_match(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| components_match(rest, &path[i..])),
+        Some((first, rest)) => match path.split_first() {
+            None => false,
+            Some((name, path_rest)) => {
+                component_matches(
+                    &first.chars().collect::<Vec<_>>(),
+                    &name.chars().collect::<Vec<_>>(),
+                ) && components_match(rest, path_rest)
+            }
+        },
+    }
+}
+
+fn component_matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| component_matches(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && component_matches(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && component_matches(rest, &name[1..]),
+    }
+}
+
 /// Entry in the mark
 #[cfg(test)]
 mod tests {
@@ -790,4 +1151,246 @@ mod tests {
 
         Ok(())
     }
+
+    fn pattern(pattern: &str) -> MarkRule {
+        MarkRule {
+            pattern: Some(pattern.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob() -> anyhow::Result<()> {
+        assert!(glob_matches("*.mp4", &Path::parse("videos/a/b.mp4")?));
+        assert!(!glob_matches("*.mp4", &Path::parse("videos/a/b.mp3")?));
+        assert!(glob_matches("videos/*.mp4", &Path::parse("videos/b.mp4")?));
+        assert!(!glob_matches(
+            "videos/*.mp4",
+            &Path::parse("videos/a/b.mp4")?
+        ));
+        assert!(glob_matches(
+            "videos/**/*.mp4",
+            &Path::parse("videos/b.mp4")?
+        ));
+        assert!(glob_matches(
+            "videos/**/*.mp4",
+            &Path::parse("videos/a/b.mp4")?
+        ));
+        assert!(!glob_matches(
+            "videos/**/*.mp4",
+            &Path::parse("other/b.mp4")?
+        ));
+        assert!(glob_matches("**/*.psd", &Path::parse("a/b/c.psd")?));
+        assert!(glob_matches("doc/?.txt", &Path::parse("doc/a.txt")?));
+        assert!(!glob_matches("doc/?.txt", &Path::parse("doc/ab.txt")?));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pattern_rule() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        fixture
+            .marks
+            .add_rule(pattern("videos/**/*.mp4"), Mark::Keep)?;
+
+        assert_eq!(
+            Mark::Keep,
+            fixture.marks.get_mark(&Path::parse("videos/2024/a.mp4")?)?
+        );
+        assert_eq!(
+            Mark::Watch,
+            fixture.marks.get_mark(&Path::parse("videos/2024/a.txt")?)?
+        );
+        assert_eq!(
+            Mark::Watch,
+            fixture.marks.get_mark(&Path::parse("other/a.mp4")?)?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn size_rule() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let small = Path::parse("dir/small.txt")?;
+        fixture.add_file_to_index(&small)?; // 100 bytes
+        let unknown = Path::parse("dir/unknown.txt")?;
+
+        fixture.marks.add_rule(
+            MarkRule {
+                max_size: Some(1000),
+                ..Default::default()
+            },
+            Mark::Keep,
+        )?;
+        assert_eq!(Mark::Keep, fixture.marks.get_mark(&small)?);
+        assert_eq!(Mark::Watch, fixture.marks.get_mark(&unknown)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rule_precedence() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let file = Path::parse("photos/a.psd")?;
+
+        fixture
+            .marks
+            .set_mark(&Path::parse("photos")?, Mark::Keep)?;
+        fixture.marks.add_rule(pattern("**/*.psd"), Mark::Own)?;
+        fixture.marks.add_rule(pattern("*.psd"), Mark::Watch)?;
+
+        // The first rule wins over the second rule and the directory mark.
+        assert_eq!(Mark::Own, fixture.marks.get_mark(&file)?);
+
+        // A mark on the file itself wins over rules.
+        fixture.marks.set_mark(&file, Mark::Keep)?;
+        assert_eq!(Mark::Keep, fixture.marks.get_mark(&file)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_and_remove_rules() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let first = fixture.marks.add_rule(pattern("*.mp4"), Mark::Keep)?;
+        let second = fixture.marks.add_rule(pattern("*.psd"), Mark::Own)?;
+        assert_eq!(
+            vec![
+                (first, pattern("*.mp4"), Mark::Keep),
+                (second, pattern("*.psd"), Mark::Own)
+            ],
+            fixture.marks.rules()?
+        );
+
+        assert!(fixture.marks.remove_rule(first)?);
+        assert!(!fixture.marks.remove_rule(first)?);
+        assert_eq!(
+            vec![(second, pattern("*.psd"), Mark::Own)],
+            fixture.marks.rules()?
+        );
+        assert_eq!(Mark::Watch, fixture.marks.get_mark(&Path::parse("a.mp4")?)?);
+
+        // IDs aren't reused while there are rules after them.
+        let third = fixture.marks.add_rule(pattern("*.mp4"), Mark::Keep)?;
+        assert!(third > second);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_rules() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        assert!(matches!(
+            fixture.marks.add_rule(MarkRule::default(), Mark::Keep),
+            Err(StorageError::InvalidMarkRule(_))
+        ));
+        assert!(matches!(
+            fixture.marks.add_rule(pattern("/foo"), Mark::Keep),
+            Err(StorageError::InvalidMarkRule(_))
+        ));
+        assert!(matches!(
+            fixture.marks.add_rule(
+                MarkRule {
+                    min_size: Some(100),
+                    max_size: Some(10),
+                    ..Default::default()
+                },
+                Mark::Keep
+            ),
+            Err(StorageError::InvalidMarkRule(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rule_changes_mark_dirty() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let video = Path::parse("videos/a.mp4")?;
+        let other = Path::parse("docs/a.mp4")?;
+        fixture.add_file_to_index(&video)?;
+        fixture.add_file_to_cache(&other)?;
+        fixture.clear_all_dirty()?;
+
+        let id = fixture.marks.add_rule(pattern("videos/*.mp4"), Mark::Own)?;
+        assert!(fixture.is_dirty(&video)?);
+        assert!(!fixture.is_dirty(&other)?);
+        fixture.clear_all_dirty()?;
+
+        fixture.marks.remove_rule(id)?;
+        assert!(fixture.is_dirty(&video)?);
+        assert!(!fixture.is_dirty(&other)?);
+        fixture.clear_all_dirty()?;
+
+        // Patterns without directory can match anywhere.
+        fixture.marks.add_rule(pattern("*.mp4"), Mark::Own)?;
+        assert!(fixture.is_dirty(&video)?);
+        assert!(fixture.is_dirty(&other)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn explain_mark() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let file = Path::parse("photos/2024/a.psd")?;
+
+        assert_eq!(
+            (Mark::Watch, MarkSource::Default),
+            fixture.marks.explain_mark(&file)?
+        );
+
+        fixture.marks.set_arena_mark(Mark::Keep)?;
+        assert_eq!(
+            (Mark::Keep, MarkSource::Arena),
+            fixture.marks.explain_mark(&file)?
+        );
+
+        fixture.marks.set_mark(&Path::parse("photos")?, Mark::Own)?;
+        assert_eq!(
+            (Mark::Own, MarkSource::Path(Path::parse("photos")?)),
+            fixture.marks.explain_mark(&file)?
+        );
+
+        let id = fixture.marks.add_rule(pattern("*.psd"), Mark::Watch)?;
+        assert_eq!(
+            (Mark::Watch, MarkSource::Rule(id, pattern("*.psd"))),
+            fixture.marks.explain_mark(&file)?
+        );
+
+        fixture.marks.set_mark(&file, Mark::Keep)?;
+        assert_eq!(
+            (Mark::Keep, MarkSource::Path(file.clone())),
+            fixture.marks.explain_mark(&file)?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_marks() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        assert!(fixture.marks.marks()?.is_empty());
+
+        fixture.marks.set_mark(&Path::parse("b/file")?, Mark::Own)?;
+        fixture.marks.set_mark(&Path::parse("a")?, Mark::Keep)?;
+        fixture.marks.set_arena_mark(Mark::Watch)?;
+        fixture.marks.add_rule(pattern("*.psd"), Mark::Own)?;
+
+        assert_eq!(
+            vec![
+                (None, Mark::Watch),
+                (Some(Path::parse("a")?), Mark::Keep),
+                (Some(Path::parse("b/file")?), Mark::Own),
+            ],
+            fixture.marks.marks()?
+        );
+
+        Ok(())
+    }
 }
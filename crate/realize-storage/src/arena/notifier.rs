@@ -2,8 +2,9 @@
 
 use super::index::RealIndexAsync;
 use super::types::{HistoryTableEntry, IndexedFileTableEntry};
+use crate::config::HistoryConfig;
 use futures::StreamExt as _;
-use realize_types::{Arena, Hash, Path, UnixTime};
+use realize_types::{Arena, Hash, Path, Peer, UnixTime};
 use tokio::{sync::mpsc, task::JoinHandle};
 use uuid::Uuid;
 
@@ -89,6 +90,27 @@ pub enum Notification {
         old_hash: Hash,
     },
 
+    /// A file became or stopped being a hard link of another file.
+    Link {
+        /// Containing arena.
+        arena: Arena,
+
+        /// Notification index.
+        ///
+        /// Should be stored and reported back as [Progress::last_seen] when re-subscribing.
+        index: u64,
+
+        /// File path within the arena.
+        path: Path,
+
+        /// Hash of the file content.
+        hash: Hash,
+
+        /// File `path` is a hard link of, with the same content, or
+        /// None if it isn't a link anymore.
+        target: Option<Path>,
+    },
+
     /// Let the subscriber know that catchup has started.
     ///
     /// Catchup might need to be run even when the subscriber provided
@@ -144,6 +166,7 @@ impl Notification {
             Notification::Replace { arena, .. } => *arena,
             Notification::Remove { arena, .. } => *arena,
             Notification::Drop { arena, .. } => *arena,
+            Notification::Link { arena, .. } => *arena,
             Notification::CatchupStart(arena) => *arena,
             Notification::Catchup { arena, .. } => *arena,
             Notification::CatchupComplete { arena, .. } => *arena,
@@ -156,6 +179,7 @@ impl Notification {
             Notification::Replace { path, .. } => Some(path),
             Notification::Remove { path, .. } => Some(path),
             Notification::Drop { path, .. } => Some(path),
+            Notification::Link { path, .. } => Some(path),
             Notification::CatchupStart(_) => None,
             Notification::Catchup { path, .. } => Some(path),
             Notification::CatchupComplete { .. } => None,
@@ -168,6 +192,7 @@ impl Notification {
             Notification::Replace { index, .. } => Some(*index),
             Notification::Remove { index, .. } => Some(*index),
             Notification::Drop { index, .. } => Some(*index),
+            Notification::Link { index, .. } => Some(*index),
             Notification::CatchupStart(_) => None,
             Notification::Catchup { .. } => None,
             Notification::CatchupComplete { index, .. } => Some(*index),
@@ -204,10 +229,20 @@ impl Progress {
 /// long as the given channel is alive.
 ///
 /// This call creates a task that sends notifications in the background.
+///
+/// The progress of the peer is recorded, so history entries seen by
+/// all peers can be deleted. It is updated whenever notifications
+/// have been sent to the peer. If some history entries the peer
+/// hasn't seen have already been deleted, the peer goes through a
+/// full catchup.
+///
+/// History is pruned whenever the progress of a peer changes.
 pub async fn subscribe(
     index: RealIndexAsync,
+    peer: Peer,
     tx: mpsc::Sender<Notification>,
     progress: Option<Progress>,
+    config: &HistoryConfig,
 ) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
     let mut last_seen = if let Some(progress) = progress
         && progress.uuid == *index.uuid()
@@ -216,6 +251,10 @@ pub async fn subscribe(
     } else {
         0
     };
+    let max_peer_lag = config.max_peer_lag;
+    if last_seen > 0 {
+        record_progress(&index, peer, last_seen, max_peer_lag).await?;
+    }
 
     tx.send(Notification::Connected {
         arena: index.arena(),
@@ -227,11 +266,8 @@ pub async fn subscribe(
     let current = *watch_rx.borrow_and_update();
 
     Ok(tokio::spawn(async move {
-        if last_seen == 0 && current > last_seen {
-            catchup(&index, current, &tx).await?;
-        } else {
-            send_notifications(&index, last_seen, current, &tx).await?;
-        }
+        let initial = (last_seen > 0).then_some(last_seen);
+        send_changes(&index, peer, initial, current, &tx, max_peer_lag).await?;
         last_seen = current;
         loop {
             tokio::select!(
@@ -243,7 +279,9 @@ pub async fn subscribe(
                         break;
                     }
                     let current = *watch_rx.borrow_and_update();
-                    if let Err(err) =  send_notifications(&index, last_seen, current, &tx).await {
+                    if let Err(err) =
+                        send_changes(&index, peer, Some(last_seen), current, &tx, max_peer_lag).await
+                    {
                         if tx.is_closed() {
                             break;
                         }
@@ -257,6 +295,62 @@ pub async fn subscribe(
     }))
 }
 
+/// Bring `peer` from `last_seen` to `current` and record its
+/// progress.
+///
+/// This goes through a full catchup if `last_seen` is `None`, as the
+/// peer hasn't seen anything yet, or if some history entries the
+/// peer needs have been deleted.
+async fn send_changes(
+    index: &RealIndexAsync,
+    peer: Peer,
+    last_seen: Option<u64>,
+    current: u64,
+    tx: &mpsc::Sender<Notification>,
+    max_peer_lag: u64,
+) -> anyhow::Result<()> {
+    if let Some(last_seen) = last_seen {
+        if send_notifications(index, last_seen, current, tx).await? {
+            record_progress(index, peer, current, max_peer_lag).await?;
+
+            return Ok(());
+        }
+        log::debug!(
+            "[{}] History needed by {peer} has been pruned; catchup",
+            index.arena()
+        );
+    }
+    if current == 0 {
+        return Ok(());
+    }
+
+    // Keep the history entries after current, which the peer
+    // hasn't seen, while catchup runs.
+    record_progress(index, peer, current, max_peer_lag).await?;
+    catchup(index, current, tx).await?;
+
+    Ok(())
+}
+
+/// Record the progress of `peer` and prune history entries that
+/// aren't needed anymore.
+///
+/// Does nothing if the progress of `peer` hasn't changed.
+async fn record_progress(
+    index: &RealIndexAsync,
+    peer: Peer,
+    last_seen: u64,
+    max_peer_lag: u64,
+) -> anyhow::Result<()> {
+    if index.peer_progress(peer).await? == Some(last_seen) {
+        return Ok(());
+    }
+    index.record_peer_progress(peer, last_seen).await?;
+    index.prune_history(max_peer_lag).await?;
+
+    Ok(())
+}
+
 /// Report all files currently in the index as catchup.
 ///
 /// catchup_index should be the current history index, as the
@@ -287,6 +381,18 @@ async fn catchup(
         .await?;
     }
 
+    let mut all_links = index.all_links();
+    while let Some((path, hash, target)) = all_links.next().await {
+        tx.send(Notification::Link {
+            arena: index.arena(),
+            index: catchup_index,
+            path,
+            hash,
+            target: Some(target),
+        })
+        .await?;
+    }
+
     tx.send(Notification::CatchupComplete {
         arena: index.arena(),
         index: catchup_index,
@@ -303,15 +409,23 @@ async fn catchup(
 /// notifications that are reported will only ever report the existing
 /// file version even for older changes, so notifications might not
 /// match history events.
+///
+/// Return false if some history entries are missing, because they've
+/// been deleted. The subscriber then needs a full catchup.
 async fn send_notifications(
     index: &RealIndexAsync,
     last_seen: u64,
     current: u64,
     tx: &mpsc::Sender<Notification>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<bool> {
+    let mut expected = last_seen + 1;
     let mut range = index.history(last_seen + 1..current + 1);
     while let Some(entry) = range.next().await {
         let (hist_index, hist_entry) = entry?;
+        if hist_index != expected {
+            return Ok(false);
+        }
+        expected += 1;
         let notification = match hist_entry {
             HistoryTableEntry::Add(path) => {
                 if let Some(IndexedFileTableEntry {
@@ -373,6 +487,21 @@ async fn send_notifications(
                     })
                 }
             }
+            HistoryTableEntry::Link(path) => {
+                if let Some(IndexedFileTableEntry { hash, .. }) = index.get_file(&path).await? {
+                    let target = index.link_target(&path).await?;
+                    Some(Notification::Link {
+                        index: hist_index,
+                        arena: index.arena(),
+                        path,
+                        hash,
+                        target,
+                    })
+                } else {
+                    // A Remove or Drop takes care of it.
+                    None
+                }
+            }
         };
 
         if let Some(notification) = notification {
@@ -380,7 +509,7 @@ async fn send_notifications(
         }
     }
 
-    Ok(())
+    Ok(current < expected)
 }
 
 #[cfg(test)]
@@ -388,7 +517,7 @@ mod tests {
     use super::*;
     use crate::{
         DirtyPaths,
-        arena::db::ArenaDatabase,
+        arena::{db::ArenaDatabase, types::FileFingerprint},
         utils::{hash, redb_utils},
     };
     use std::{sync::Arc, time::Duration};
@@ -397,6 +526,10 @@ mod tests {
         Arena::from("myarena")
     }
 
+    fn test_peer() -> Peer {
+        Peer::from("peer")
+    }
+
     struct Fixture {
         index: RealIndexAsync,
         current_time: UnixTime,
@@ -425,7 +558,14 @@ mod tests {
 
         async fn subscribe(&self) -> anyhow::Result<mpsc::Receiver<Notification>> {
             let (tx, mut rx) = mpsc::channel(128);
-            subscribe(self.index.clone(), tx, None).await?;
+            subscribe(
+                self.index.clone(),
+                test_peer(),
+                tx,
+                None,
+                &HistoryConfig::default(),
+            )
+            .await?;
             self.expect_connected(&mut rx).await?;
 
             Ok(rx)
@@ -438,8 +578,10 @@ mod tests {
             let (tx, mut rx) = mpsc::channel(128);
             subscribe(
                 self.index.clone(),
+                test_peer(),
                 tx,
                 Some(Progress::new(self.index.uuid().clone(), index)),
+                &HistoryConfig::default(),
             )
             .await?;
             self.expect_connected(&mut rx).await?;
@@ -486,6 +628,21 @@ mod tests {
             Ok(())
         }
 
+        /// Wait until the progress of [test_peer] has been recorded.
+        async fn wait_for_progress(&self, expected: u64) -> anyhow::Result<()> {
+            tokio::time::timeout(Duration::from_secs(3), async {
+                while self.index.peer_progress(test_peer()).await? != Some(expected) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+
+                Ok::<(), anyhow::Error>(())
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("progress: timed out"))??;
+
+            Ok(())
+        }
+
         async fn consume(
             &self,
             mut rx: mpsc::Receiver<Notification>,
@@ -622,6 +779,45 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn link_notification() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let mut rx = fixture.subscribe().await?;
+        let foo = Path::parse("foo")?;
+        let bar = Path::parse("bar")?;
+        let fingerprint = FileFingerprint {
+            dev: 1,
+            ino: 2,
+            size: 3,
+            mtime: fixture.now(),
+            ctime: fixture.now(),
+        };
+        fixture
+            .index
+            .add_fingerprinted_file(&foo, hash::digest("foo"), &fingerprint)
+            .await?;
+        fixture
+            .index
+            .add_fingerprinted_file(&bar, hash::digest("foo"), &fingerprint)
+            .await?;
+
+        next(&mut rx, "add foo").await?;
+        next(&mut rx, "add bar").await?;
+        assert_eq!(
+            Notification::Link {
+                arena: test_arena(),
+                index: 3,
+                path: bar.clone(),
+                hash: hash::digest("foo"),
+                target: Some(foo.clone()),
+            },
+            next(&mut rx, "link bar").await?
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn catchup_then_continue() -> anyhow::Result<()> {
         let fixture = Fixture::setup().await?;
@@ -743,4 +939,178 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn subscribe_records_progress() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        fixture.add("foo", "foo").await?;
+        fixture.add("bar", "bar").await?;
+        fixture.add("baz", "baz").await?;
+
+        let rx = fixture.subscribe_with_progress(2).await?;
+        fixture.consume(rx).await?;
+
+        // The peer has been sent everything.
+        fixture.wait_for_progress(3).await?;
+        // Entries seen by the only peer have been pruned
+        assert_eq!(false, fixture.index.has_history_after(1).await?);
+        assert_eq!(true, fixture.index.has_history_after(2).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_progress_while_connected() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        let mut rx = fixture.subscribe().await?;
+        fixture.add("foo", "foo").await?;
+        next(&mut rx, "add foo").await?;
+        fixture.wait_for_progress(1).await?;
+
+        fixture.add("bar", "bar").await?;
+        next(&mut rx, "add bar").await?;
+        fixture.wait_for_progress(2).await?;
+        assert_eq!(false, fixture.index.has_history_after(0).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn send_notifications_detects_gap() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        fixture.add("foo", "foo").await?;
+        fixture.add("bar", "bar").await?;
+        let baz = fixture.add("baz", "baz").await?;
+        fixture
+            .index
+            .record_peer_progress(Peer::from("other"), 3)
+            .await?;
+        fixture
+            .index
+            .prune_history(HistoryConfig::default().max_peer_lag)
+            .await?;
+
+        let (tx, mut rx) = mpsc::channel(128);
+        assert_eq!(false, send_notifications(&fixture.index, 1, 3, &tx).await?);
+        assert_eq!(true, send_notifications(&fixture.index, 2, 3, &tx).await?);
+        assert_eq!(
+            Notification::Add {
+                arena: test_arena(),
+                index: 3,
+                path: baz,
+                size: 3,
+                mtime: fixture.now(),
+                hash: hash::digest("baz"),
+            },
+            rx.try_recv()?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn catchup_once_history_pruned() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        fixture.add("foo", "foo").await?;
+        fixture.add("bar", "bar").await?;
+        let baz = fixture.add("baz", "baz").await?;
+        fixture.delete("foo").await?;
+        fixture.delete("bar").await?;
+
+        // Another peer has seen everything but the last entry, which
+        // is too far ahead.
+        fixture
+            .index
+            .record_peer_progress(Peer::from("other"), 4)
+            .await?;
+        let (tx, mut rx) = mpsc::channel(128);
+        subscribe(
+            fixture.index.clone(),
+            test_peer(),
+            tx,
+            Some(Progress::new(fixture.index.uuid().clone(), 1)),
+            &HistoryConfig { max_peer_lag: 2 },
+        )
+        .await?;
+        fixture.expect_connected(&mut rx).await?;
+
+        assert_eq!(
+            Notification::CatchupStart(test_arena()),
+            next(&mut rx, "catchup start").await?
+        );
+        assert_eq!(
+            Notification::Catchup {
+                arena: test_arena(),
+                path: baz.clone(),
+                size: 3,
+                mtime: fixture.now(),
+                hash: hash::digest("baz")
+            },
+            next(&mut rx, "catchup").await?
+        );
+        assert_eq!(
+            Notification::CatchupComplete {
+                arena: test_arena(),
+                index: 5,
+            },
+            next(&mut rx, "catchup complete").await?
+        );
+        fixture.wait_for_progress(5).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keep_history_during_catchup() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+
+        fixture.add("foo", "foo").await?;
+        fixture.add("bar", "bar").await?;
+
+        // The channel is full until Connected is read, so catchup
+        // cannot complete.
+        let (tx, mut rx) = mpsc::channel(1);
+        subscribe(
+            fixture.index.clone(),
+            test_peer(),
+            tx,
+            None,
+            &HistoryConfig::default(),
+        )
+        .await?;
+        fixture.wait_for_progress(2).await?;
+
+        // Another peer, which is ahead, doesn't get the entries after
+        // the catchup deleted.
+        fixture.add("baz", "baz").await?;
+        fixture.add("qux", "qux").await?;
+        fixture
+            .index
+            .record_peer_progress(Peer::from("other"), 4)
+            .await?;
+        fixture
+            .index
+            .prune_history(HistoryConfig::default().max_peer_lag)
+            .await?;
+        assert_eq!(true, fixture.index.has_history_after(2).await?);
+
+        fixture.expect_connected(&mut rx).await?;
+        assert_eq!(
+            Notification::CatchupStart(test_arena()),
+            next(&mut rx, "catchup start").await?
+        );
+        while !matches!(
+            next(&mut rx, "catchup").await?,
+            Notification::CatchupComplete { .. }
+        ) {}
+        assert_eq!(Some(3), next(&mut rx, "add baz").await?.index());
+        assert_eq!(Some(4), next(&mut rx, "add qux").await?.index());
+        fixture.wait_for_progress(4).await?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,587 @@
+use super::arena_cache::ArenaCache;
+use super::db::{ArenaDatabase, ArenaReadTransaction, ArenaWriteTransaction};
+use super::hasher::hash_throttled;
+use super::index::RealIndexAsync;
+use super::types::{CorruptionLocation, CorruptionTableEntry};
+use crate::StorageError;
+use crate::config::ScrubConfig;
+use crate::utils::holder::Holder;
+use async_speed_limit::Limiter;
+use futures::StreamExt as _;
+use prometheus::{IntCounterVec, register_int_counter_vec};
+use realize_types::{Arena, Hash, Path, UnixTime};
+use redb::ReadableTable as _;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::task;
+use tokio_util::sync::CancellationToken;
+
+lazy_static::lazy_static! {
+    pub static ref METRIC_SCRUB_CHECKED_COUNT: IntCounterVec =
+        register_int_counter_vec!(
+            "realize_scrub_checked_count",
+            "Number of files hashed by the scrubber, with location label (index or blob)",
+            &["location"]
+        ).unwrap();
+    pub static ref METRIC_SCRUB_CHECKED_BYTES: IntCounterVec =
+        register_int_counter_vec!(
+            "realize_scrub_checked_bytes",
+            "Number of bytes hashed by the scrubber, with location label (index or blob)",
+            &["location"]
+        ).unwrap();
+    pub static ref METRIC_SCRUB_CORRUPTED_COUNT: IntCounterVec =
+        register_int_counter_vec!(
+            "realize_scrub_corrupted_count",
+            "Number of corrupted files found by the scrubber, with location label (index or blob)",
+            &["location"]
+        ).unwrap();
+}
+
+/// Regularly hash indexed files and verified blobs again, to detect
+/// content that doesn't match its hash anymore.
+///
+/// Corrupted files are recorded in the corruption table and marked
+/// dirty, so the engine can fetch a good copy from another peer if
+/// the file is to be kept locally.
+///
+/// The scrubber stops when dropped.
+pub(crate) struct Scrubber {
+    shutdown: CancellationToken,
+}
+
+impl Scrubber {
+    /// Start scrubbing the files of `arena` in the background.
+    ///
+    /// `indexed` is the root and index of the arena, if it has one.
+    ///
+    /// Nothing is started if the configuration disables scrubbing.
+    pub(crate) fn spawn(
+        arena: Arena,
+        db: Arc<ArenaDatabase>,
+        cache: Arc<ArenaCache>,
+        indexed: Option<(PathBuf, RealIndexAsync)>,
+        config: &ScrubConfig,
+    ) -> Self {
+        let shutdown = CancellationToken::new();
+        if let Some(interval) = config.interval_secs {
+            let pass = Pass {
+                arena,
+                cache,
+                indexed,
+                limiter: config
+                    .max_bytes_per_second
+                    .map(|bps| Limiter::new(bps as f64)),
+                shutdown: shutdown.clone(),
+            };
+            tokio::spawn(run(db, pass, Duration::from_secs(interval)));
+        }
+
+        Self { shutdown }
+    }
+}
+
+impl Drop for Scrubber {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+/// Run a scrub pass every `interval`, until shutdown.
+async fn run(db: Arc<ArenaDatabase>, pass: Pass, interval: Duration) {
+    let arena = pass.arena;
+    loop {
+        let last_pass = match blocking(&db, last_pass_or_now).await {
+            Ok(t) => t,
+            Err(err) => {
+                log::warn!("[{arena}] Scrubber failed to read last pass: {err}");
+                return;
+            }
+        };
+        let wait = last_pass.plus(interval).duration_since(&UnixTime::now());
+        tokio::select!(
+            _ = pass.shutdown.cancelled() => {
+                return;
+            }
+            _ = tokio::time::sleep(wait) => {}
+        );
+
+        if let Err(err) = blocking(&db, |db| record_pass(db, &UnixTime::now())).await {
+            log::warn!("[{arena}] Scrubber failed to record pass: {err}");
+        }
+        log::info!("[{arena}] Scrub pass started");
+        match pass.run().await {
+            Ok(()) => log::info!("[{arena}] Scrub pass done"),
+            Err(err) => log::warn!("[{arena}] Scrub pass failed: {err}"),
+        }
+    }
+}
+
+/// Run `f` on the database in a blocking task.
+async fn blocking<T: Send + 'static>(
+    db: &Arc<ArenaDatabase>,
+    f: impl FnOnce(&ArenaDatabase) -> Result<T, StorageError> + Send + 'static,
+) -> Result<T, StorageError> {
+    let db = Arc::clone(db);
+
+    task::spawn_blocking(move || f(&db)).await?
+}
+
+/// A single pass over all indexed files and verified blobs of an
+/// arena.
+struct Pass {
+    arena: Arena,
+    cache: Arc<ArenaCache>,
+    indexed: Option<(PathBuf, RealIndexAsync)>,
+    limiter: Option<Limiter>,
+    shutdown: CancellationToken,
+}
+
+impl Pass {
+    async fn run(&self) -> Result<(), StorageError> {
+        self.scrub_index().await?;
+        self.scrub_blobs().await?;
+
+        Ok(())
+    }
+
+    /// Hash indexed files and compare with the hash in the index.
+    ///
+    /// Files whose size or mtime don't match the index are skipped,
+    /// as they've been modified and are going to be hashed by the
+    /// watcher anyway.
+    async fn scrub_index(&self) -> Result<(), StorageError> {
+        let (root, index) = match &self.indexed {
+            None => return Ok(()),
+            Some(indexed) => indexed,
+        };
+        let arena = self.arena;
+        let mut files = index.all_files();
+        while let Some((path, entry)) = files.next().await {
+            if self.shutdown.is_cancelled() {
+                return Ok(());
+            }
+            let realpath = path.within(root);
+            let actual = match self
+                .hash_if_unchanged(&realpath, Some((entry.size, &entry.mtime)))
+                .await
+            {
+                Ok(Some(hash)) => hash,
+                Ok(None) => continue,
+                Err(err) => {
+                    log::debug!("[{arena}] Scrubber skipped {realpath:?}: {err}");
+                    continue;
+                }
+            };
+            METRIC_SCRUB_CHECKED_COUNT
+                .with_label_values(&["index"])
+                .inc();
+            METRIC_SCRUB_CHECKED_BYTES
+                .with_label_values(&["index"])
+                .inc_by(entry.size);
+            if actual != entry.hash && index.mark_corrupted(&path, &entry.hash, &actual).await? {
+                log::warn!(
+                    "[{arena}] Corrupted file {realpath:?}: expected {}, got {actual}",
+                    entry.hash
+                );
+                METRIC_SCRUB_CORRUPTED_COUNT
+                    .with_label_values(&["index"])
+                    .inc();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hash verified blobs and compare with the hash of the file
+    /// they contain.
+    async fn scrub_blobs(&self) -> Result<(), StorageError> {
+        let arena = self.arena;
+        let blobs = task::spawn_blocking({
+            let cache = Arc::clone(&self.cache);
+            move || cache.verified_blobs()
+        })
+        .await??;
+        for (path, blob_id, expected, blobpath) in blobs {
+            if self.shutdown.is_cancelled() {
+                return Ok(());
+            }
+            let actual = match self.hash_if_unchanged(&blobpath, None).await {
+                Ok(Some(hash)) => hash,
+                Ok(None) => continue,
+                Err(err) => {
+                    log::debug!("[{arena}] Scrubber skipped blob {blobpath:?}: {err}");
+                    continue;
+                }
+            };
+            METRIC_SCRUB_CHECKED_COUNT
+                .with_label_values(&["blob"])
+                .inc();
+            if let Ok(m) = tokio::fs::metadata(&blobpath).await {
+                METRIC_SCRUB_CHECKED_BYTES
+                    .with_label_values(&["blob"])
+                    .inc_by(m.len());
+            }
+            if actual == expected {
+                continue;
+            }
+            let cache = Arc::clone(&self.cache);
+            let marked = task::spawn_blocking({
+                let path = path.clone();
+                let actual = actual.clone();
+                let expected = expected.clone();
+                move || cache.mark_blob_corrupted(&path, blob_id, &expected, &actual)
+            })
+            .await??;
+            if marked {
+                log::warn!(
+                    "[{arena}] Corrupted blob {blobpath:?} for {path}: expected {expected}, got {actual}"
+                );
+                METRIC_SCRUB_CORRUPTED_COUNT
+                    .with_label_values(&["blob"])
+                    .inc();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hash the file at `realpath`.
+    ///
+    /// If `expected` is set, return None if the size and mtime of the
+    /// file don't match, before or after hashing.
+    async fn hash_if_unchanged(
+        &self,
+        realpath: &std::path::Path,
+        expected: Option<(u64, &UnixTime)>,
+    ) -> std::io::Result<Option<Hash>> {
+        let matches = |m: &std::fs::Metadata| match expected {
+            None => true,
+            Some((size, mtime)) => m.len() == size && UnixTime::mtime(m) == *mtime,
+        };
+        let f = File::open(realpath).await?;
+        if !matches(&f.metadata().await?) {
+            return Ok(None);
+        }
+        let hash = match hash_throttled(f, self.limiter.as_ref(), &self.shutdown).await {
+            Ok(hash) => hash,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if !matches(&tokio::fs::metadata(realpath).await?) {
+            return Ok(None);
+        }
+
+        Ok(Some(hash))
+    }
+}
+
+/// Return the time the last pass started.
+///
+/// If no pass was ever run, record the current time, so the first
+/// pass happens one interval later.
+fn last_pass_or_now(db: &ArenaDatabase) -> Result<UnixTime, StorageError> {
+    {
+        let txn = db.begin_read()?;
+        if let Some(secs) = txn.scrub_last_pass_table()?.get(())? {
+            return Ok(UnixTime::from_secs(secs.value()));
+        }
+    }
+    let now = UnixTime::now();
+    record_pass(db, &now)?;
+
+    Ok(now)
+}
+
+fn record_pass(db: &ArenaDatabase, time: &UnixTime) -> Result<(), StorageError> {
+    let txn = db.begin_write()?;
+    txn.scrub_last_pass_table()?.insert((), time.as_secs())?;
+    txn.commit()?;
+
+    Ok(())
+}
+
+/// Record a corruption for `path`, replacing any existing one.
+pub(crate) fn record_corruption(
+    txn: &ArenaWriteTransaction,
+    path: &Path,
+    entry: CorruptionTableEntry,
+) -> Result<(), StorageError> {
+    txn.scrub_corruption_table()?
+        .insert(path.as_str(), Holder::with_content(entry)?)?;
+
+    Ok(())
+}
+
+/// Forget about any corruption of `path` found at `location`.
+pub(crate) fn clear_corruption(
+    txn: &ArenaWriteTransaction,
+    path: &Path,
+    location: CorruptionLocation,
+) -> Result<(), StorageError> {
+    let mut corruption_table = txn.scrub_corruption_table()?;
+    let found = match corruption_table.get(path.as_str())? {
+        Some(v) => v.value().parse()?.location == location,
+        None => false,
+    };
+    if found {
+        corruption_table.remove(path.as_str())?;
+    }
+
+    Ok(())
+}
+
+/// Return the corruption recorded for `path`, if any.
+pub(crate) fn get_corruption(
+    txn: &ArenaReadTransaction,
+    path: &Path,
+) -> Result<Option<CorruptionTableEntry>, StorageError> {
+    match txn.scrub_corruption_table()?.get(path.as_str())? {
+        Some(v) => Ok(Some(v.value().parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Return all corruptions recorded in the arena.
+pub(crate) fn all_corruptions(
+    db: &ArenaDatabase,
+) -> Result<Vec<(Path, CorruptionTableEntry)>, StorageError> {
+    let txn = db.begin_read()?;
+    let mut ret = vec![];
+    for entry in txn.scrub_corruption_table()?.iter()? {
+        let (k, v) = entry?;
+        ret.push((Path::parse(k.value())?, v.value().parse()?));
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::engine::DirtyPaths;
+    use crate::arena::types::LocalAvailability;
+    use crate::utils::{hash, redb_utils};
+    use crate::{GlobalDatabase, InodeAllocator, Notification};
+    use assert_fs::TempDir;
+    use assert_fs::fixture::ChildPath;
+    use assert_fs::prelude::*;
+    use realize_types::Peer;
+    use tokio::io::AsyncWriteExt as _;
+
+    fn test_arena() -> Arena {
+        Arena::from("test_arena")
+    }
+
+    struct Fixture {
+        db: Arc<ArenaDatabase>,
+        cache: Arc<ArenaCache>,
+        index: RealIndexAsync,
+        root: ChildPath,
+        _tempdir: TempDir,
+    }
+
+    impl Fixture {
+        async fn setup() -> anyhow::Result<Fixture> {
+            let _ = env_logger::try_init();
+            let arena = test_arena();
+            let tempdir = TempDir::new()?;
+            let allocator =
+                InodeAllocator::new(GlobalDatabase::new(redb_utils::in_memory()?)?, [arena])?;
+            let db = ArenaDatabase::new(redb_utils::in_memory()?)?;
+            let dirty_paths = DirtyPaths::new(Arc::clone(&db)).await?;
+            let cache = ArenaCache::new(
+                arena,
+                allocator,
+                Arc::clone(&db),
+                tempdir.child("blobs").path(),
+                Arc::clone(&dirty_paths),
+            )?;
+            let index =
+                RealIndexAsync::with_db(arena, Arc::clone(&db), Arc::clone(&dirty_paths)).await?;
+            let root = tempdir.child("root");
+            root.create_dir_all()?;
+
+            Ok(Self {
+                db,
+                cache,
+                index,
+                root,
+                _tempdir: tempdir,
+            })
+        }
+
+        fn pass(&self) -> Pass {
+            Pass {
+                arena: test_arena(),
+                cache: Arc::clone(&self.cache),
+                indexed: Some((self.root.to_path_buf(), self.index.clone())),
+                limiter: None,
+                shutdown: CancellationToken::new(),
+            }
+        }
+
+        /// Write `content` into `path` and index it with `hash`.
+        async fn add_indexed_file(
+            &self,
+            path_str: &str,
+            content: &str,
+            hash: Hash,
+        ) -> anyhow::Result<Path> {
+            let path = Path::parse(path_str)?;
+            let child = self.root.child(path_str);
+            child.write_str(content)?;
+            let m = std::fs::metadata(child.path())?;
+            self.index
+                .add_file(&path, m.len(), &UnixTime::mtime(&m), hash)
+                .await?;
+
+            Ok(path)
+        }
+
+        /// Add `path` to the cache, with a blob containing `content`,
+        /// marked verified as `hash`.
+        async fn add_verified_blob(
+            &self,
+            path_str: &str,
+            content: &str,
+            hash: Hash,
+        ) -> anyhow::Result<Path> {
+            let path = Path::parse(path_str)?;
+            self.cache.update(
+                Peer::from("other"),
+                Notification::Add {
+                    arena: test_arena(),
+                    index: 1,
+                    path: path.clone(),
+                    mtime: UnixTime::from_secs(1234567890),
+                    size: content.len() as u64,
+                    hash,
+                },
+            )?;
+            let (inode, _) = self.cache.lookup_path(&path)?;
+            let mut blob = self.cache.open_file(inode)?;
+            blob.write_all(content.as_bytes()).await?;
+            blob.flush_and_sync().await?;
+            blob.update_db().await?;
+            blob.mark_verified().await?;
+
+            Ok(path)
+        }
+
+        fn corruption(&self, path: &Path) -> anyhow::Result<Option<CorruptionTableEntry>> {
+            let txn = self.db.begin_read()?;
+
+            Ok(get_corruption(&txn, path)?)
+        }
+    }
+
+    #[tokio::test]
+    async fn detect_corrupted_indexed_file() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let good = fixture
+            .add_indexed_file("good", "good", hash::digest("good"))
+            .await?;
+        let bad = fixture
+            .add_indexed_file("bad", "rotten", hash::digest("bad!!!"))
+            .await?;
+
+        fixture.pass().run().await?;
+
+        assert_eq!(None, fixture.corruption(&good)?);
+        let corruption = fixture.corruption(&bad)?.unwrap();
+        assert_eq!(CorruptionLocation::Index, corruption.location);
+        assert_eq!(hash::digest("bad!!!"), corruption.expected);
+        assert_eq!(hash::digest("rotten"), corruption.actual);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ignore_modified_file() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let path = fixture
+            .add_indexed_file("foo", "foo", hash::digest("foo"))
+            .await?;
+        fixture.root.child("foo").write_str("modified")?;
+
+        fixture.pass().run().await?;
+
+        assert_eq!(None, fixture.corruption(&path)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reindexing_clears_corruption() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let path = fixture
+            .add_indexed_file("foo", "rotten", hash::digest("foo"))
+            .await?;
+        fixture.pass().run().await?;
+        assert!(fixture.corruption(&path)?.is_some());
+
+        fixture
+            .add_indexed_file("foo", "foo", hash::digest("foo"))
+            .await?;
+
+        assert_eq!(None, fixture.corruption(&path)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detect_corrupted_blob() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let good = fixture
+            .add_verified_blob("good", "good", hash::digest("good"))
+            .await?;
+        let bad = fixture
+            .add_verified_blob("bad", "rotten", hash::digest("bad!!!"))
+            .await?;
+
+        fixture.pass().run().await?;
+
+        assert_eq!(None, fixture.corruption(&good)?);
+        let corruption = fixture.corruption(&bad)?.unwrap();
+        assert_eq!(CorruptionLocation::Blob, corruption.location);
+        assert_eq!(hash::digest("bad!!!"), corruption.expected);
+        assert_eq!(hash::digest("rotten"), corruption.actual);
+
+        let (inode, _) = fixture.cache.lookup_path(&bad)?;
+        assert_eq!(
+            LocalAvailability::Complete,
+            fixture.cache.local_availability(inode)?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_all_corruptions() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        fixture
+            .add_indexed_file("a", "rotten", hash::digest("a"))
+            .await?;
+        fixture
+            .add_verified_blob("b", "rotten", hash::digest("b"))
+            .await?;
+
+        fixture.pass().run().await?;
+
+        let corruptions = all_corruptions(&fixture.db)?;
+        assert_eq!(
+            vec![
+                ("a".to_string(), CorruptionLocation::Index),
+                ("b".to_string(), CorruptionLocation::Blob),
+            ],
+            corruptions
+                .into_iter()
+                .map(|(p, c)| (p.as_str().to_string(), c.location))
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+}
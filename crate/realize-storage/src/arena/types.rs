@@ -2,6 +2,7 @@ use crate::utils::holder::{ByteConversionError, ByteConvertible, NamedType};
 use capnp::message::ReaderOptions;
 use capnp::serialize_packed;
 use realize_types::{self, ByteRanges, Hash, UnixTime};
+use std::os::unix::fs::MetadataExt as _;
 
 #[allow(dead_code)]
 #[allow(unknown_lints)]
@@ -31,6 +32,13 @@ mod mark_capnp {
 mod engine_capnp {
     include!(concat!(env!("OUT_DIR"), "/arena/engine_capnp.rs"));
 }
+#[allow(dead_code)]
+#[allow(unknown_lints)]
+#[allow(clippy::uninlined_format_args)]
+#[allow(clippy::extra_unused_type_parameters)]
+mod scrub_capnp {
+    include!(concat!(env!("OUT_DIR"), "/arena/scrub_capnp.rs"));
+}
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum LocalAvailability {
@@ -184,6 +192,140 @@ impl ByteConvertible<IndexedFileTableEntry> for IndexedFileTableEntry {
     }
 }
 
+/// Identity and metadata of a file on the local filesystem.
+///
+/// A fingerprint is taken when a file is hashed. As long as the
+/// file's fingerprint doesn't change, its content can be assumed to
+/// be the same, so it doesn't need to be hashed again, even if it
+/// was moved or linked to another path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileFingerprint {
+    /// Device the file is on.
+    pub dev: u64,
+
+    /// Inode number of the file on the device.
+    pub ino: u64,
+
+    /// File size, in bytes.
+    pub size: u64,
+
+    /// Last modification time.
+    pub mtime: UnixTime,
+
+    /// Last status change time.
+    pub ctime: UnixTime,
+}
+
+impl FileFingerprint {
+    /// Build a fingerprint from file metadata.
+    pub fn from_metadata(m: &std::fs::Metadata) -> Self {
+        Self {
+            dev: m.dev(),
+            ino: m.ino(),
+            size: m.len(),
+            mtime: UnixTime::mtime(m),
+            ctime: UnixTime::ctime(m),
+        }
+    }
+
+    /// Key of the inode in the inode table.
+    pub fn inode_key(&self) -> (u64, u64) {
+        (self.dev, self.ino)
+    }
+
+    /// Check whether `other` refers to the same inode, with the same
+    /// content.
+    ///
+    /// The status change time is not compared, as it changes when a
+    /// file is renamed, linked or has its permissions changed, none
+    /// of which modify its content.
+    pub fn same_content(&self, other: &FileFingerprint) -> bool {
+        self.dev == other.dev
+            && self.ino == other.ino
+            && self.size == other.size
+            && self.mtime == other.mtime
+    }
+}
+
+/// An entry in the inode table.
+///
+/// The inode table remembers the hash of a version of an inode, so it
+/// can be reused instead of hashing the file again. Entries are keyed
+/// by (dev, ino).
+#[derive(Debug, Clone, PartialEq)]
+pub struct InodeTableEntry {
+    pub hash: Hash,
+    pub size: u64,
+    pub mtime: UnixTime,
+    pub ctime: UnixTime,
+}
+
+impl InodeTableEntry {
+    /// Create an entry for the given fingerprint and hash.
+    pub fn new(fingerprint: &FileFingerprint, hash: Hash) -> Self {
+        Self {
+            hash,
+            size: fingerprint.size,
+            mtime: fingerprint.mtime.clone(),
+            ctime: fingerprint.ctime.clone(),
+        }
+    }
+
+    /// Check whether this entry describes the content of the file
+    /// with the given fingerprint.
+    ///
+    /// The caller is responsible for looking up the entry using the
+    /// fingerprint's inode key.
+    pub fn matches(&self, fingerprint: &FileFingerprint) -> bool {
+        self.size == fingerprint.size && self.mtime == fingerprint.mtime
+    }
+}
+
+impl NamedType for InodeTableEntry {
+    fn typename() -> &'static str {
+        "index.inode"
+    }
+}
+
+impl ByteConvertible<InodeTableEntry> for InodeTableEntry {
+    fn from_bytes(data: &[u8]) -> Result<InodeTableEntry, ByteConversionError> {
+        let message_reader = serialize_packed::read_message(&mut &data[..], ReaderOptions::new())?;
+        let msg: index_capnp::inode_table_entry::Reader =
+            message_reader.get_root::<index_capnp::inode_table_entry::Reader>()?;
+
+        let mtime = msg.get_mtime()?;
+        let ctime = msg.get_ctime()?;
+        Ok(InodeTableEntry {
+            hash: parse_hash(msg.get_hash()?)?,
+            size: msg.get_size(),
+            mtime: UnixTime::new(mtime.get_secs(), mtime.get_nsecs()),
+            ctime: UnixTime::new(ctime.get_secs(), ctime.get_nsecs()),
+        })
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, ByteConversionError> {
+        let mut message = ::capnp::message::Builder::new_default();
+        let mut builder: index_capnp::inode_table_entry::Builder =
+            message.init_root::<index_capnp::inode_table_entry::Builder>();
+
+        builder.set_hash(&self.hash.0);
+        builder.set_size(self.size);
+
+        let mut mtime = builder.reborrow().init_mtime();
+        mtime.set_secs(self.mtime.as_secs());
+        mtime.set_nsecs(self.mtime.subsec_nanos());
+
+        let mut ctime = builder.init_ctime();
+        ctime.set_secs(self.ctime.as_secs());
+        ctime.set_nsecs(self.ctime.subsec_nanos());
+
+        let mut buffer: Vec<u8> = Vec::new();
+        serialize_packed::write_message(&mut buffer, &message)?;
+
+        Ok(buffer)
+    }
+}
+
 /// An entry in the file table.
 #[derive(Debug, Clone, PartialEq)]
 pub enum HistoryTableEntry {
@@ -204,6 +346,10 @@ pub enum HistoryTableEntry {
     ///
     /// The hash is the dropped hash version.
     Drop(realize_types::Path, Hash),
+
+    /// The file became, or stopped being, a hard link of other files
+    /// in the index.
+    Link(realize_types::Path),
 }
 
 impl NamedType for HistoryTableEntry {
@@ -233,6 +379,9 @@ impl ByteConvertible<HistoryTableEntry> for HistoryTableEntry {
                 parse_path(msg.get_path()?)?,
                 parse_hash(msg.get_old_hash()?)?,
             )),
+            index_capnp::history_table_entry::Kind::Link => {
+                Ok(HistoryTableEntry::Link(parse_path(msg.get_path()?)?))
+            }
         }
     }
 
@@ -261,6 +410,10 @@ impl ByteConvertible<HistoryTableEntry> for HistoryTableEntry {
                 builder.set_path(path.as_str());
                 builder.set_old_hash(&old_hash.0);
             }
+            HistoryTableEntry::Link(path) => {
+                builder.set_kind(index_capnp::history_table_entry::Kind::Link);
+                builder.set_path(path.as_str());
+            }
         }
 
         let mut buffer: Vec<u8> = Vec::new();
@@ -309,6 +462,181 @@ impl ByteConvertible<FailedJobTableEntry> for FailedJobTableEntry {
     }
 }
 
+/// Type of a job kept in the job history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobKind {
+    Download,
+    Realize,
+    Unrealize,
+}
+
+/// How a job kept in the job history ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobOutcome {
+    Done,
+    Abandoned,
+    Cancelled,
+    Failed,
+}
+
+/// Outcome of a job that was run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobHistoryTableEntry {
+    pub path: realize_types::Path,
+    pub hash: Hash,
+    pub kind: JobKind,
+
+    /// Time at which the job started.
+    pub start: UnixTime,
+
+    /// Time at which the job ended.
+    pub end: UnixTime,
+
+    /// Number of bytes written to the file by the job.
+    pub bytes: u64,
+
+    pub outcome: JobOutcome,
+
+    /// Error message, if the job failed.
+    pub error: Option<String>,
+}
+
+impl NamedType for JobHistoryTableEntry {
+    fn typename() -> &'static str {
+        "engine.job_history"
+    }
+}
+
+impl ByteConvertible<JobHistoryTableEntry> for JobHistoryTableEntry {
+    fn from_bytes(data: &[u8]) -> Result<JobHistoryTableEntry, ByteConversionError> {
+        let message_reader = serialize_packed::read_message(&mut &data[..], ReaderOptions::new())?;
+        let msg: engine_capnp::job_history_table_entry::Reader =
+            message_reader.get_root::<engine_capnp::job_history_table_entry::Reader>()?;
+
+        let error = msg.get_error()?.to_str()?;
+        Ok(JobHistoryTableEntry {
+            path: parse_path(msg.get_path()?)?,
+            hash: parse_hash(msg.get_hash()?)?,
+            kind: match msg.get_kind()? {
+                engine_capnp::job_history_table_entry::Kind::Download => JobKind::Download,
+                engine_capnp::job_history_table_entry::Kind::Realize => JobKind::Realize,
+                engine_capnp::job_history_table_entry::Kind::Unrealize => JobKind::Unrealize,
+            },
+            start: UnixTime::from_secs(msg.get_start_secs()),
+            end: UnixTime::from_secs(msg.get_end_secs()),
+            bytes: msg.get_bytes(),
+            outcome: match msg.get_outcome()? {
+                engine_capnp::job_history_table_entry::Outcome::Done => JobOutcome::Done,
+                engine_capnp::job_history_table_entry::Outcome::Abandoned => JobOutcome::Abandoned,
+                engine_capnp::job_history_table_entry::Outcome::Cancelled => JobOutcome::Cancelled,
+                engine_capnp::job_history_table_entry::Outcome::Failed => JobOutcome::Failed,
+            },
+            error: if error.is_empty() {
+                None
+            } else {
+                Some(error.to_string())
+            },
+        })
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, ByteConversionError> {
+        let mut message = ::capnp::message::Builder::new_default();
+        let mut builder: engine_capnp::job_history_table_entry::Builder =
+            message.init_root::<engine_capnp::job_history_table_entry::Builder>();
+
+        builder.set_path(self.path.as_str());
+        builder.set_hash(&self.hash.0);
+        builder.set_kind(match self.kind {
+            JobKind::Download => engine_capnp::job_history_table_entry::Kind::Download,
+            JobKind::Realize => engine_capnp::job_history_table_entry::Kind::Realize,
+            JobKind::Unrealize => engine_capnp::job_history_table_entry::Kind::Unrealize,
+        });
+        builder.set_start_secs(self.start.as_secs());
+        builder.set_end_secs(self.end.as_secs());
+        builder.set_bytes(self.bytes);
+        builder.set_outcome(match self.outcome {
+            JobOutcome::Done => engine_capnp::job_history_table_entry::Outcome::Done,
+            JobOutcome::Abandoned => engine_capnp::job_history_table_entry::Outcome::Abandoned,
+            JobOutcome::Cancelled => engine_capnp::job_history_table_entry::Outcome::Cancelled,
+            JobOutcome::Failed => engine_capnp::job_history_table_entry::Outcome::Failed,
+        });
+        builder.set_error(self.error.as_deref().unwrap_or(""));
+
+        let mut buffer: Vec<u8> = Vec::new();
+        serialize_packed::write_message(&mut buffer, &message)?;
+
+        Ok(buffer)
+    }
+}
+
+/// Where a corrupted copy of a file was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionLocation {
+    /// The real file, in the index.
+    Index,
+
+    /// A verified blob, in the cache.
+    Blob,
+}
+
+/// A file whose content was found not to match its hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptionTableEntry {
+    pub location: CorruptionLocation,
+
+    /// Hash the content is expected to have.
+    pub expected: Hash,
+
+    /// Hash the content was found to have.
+    pub actual: Hash,
+
+    /// Time at which the corruption was detected.
+    pub detected: UnixTime,
+}
+
+impl NamedType for CorruptionTableEntry {
+    fn typename() -> &'static str {
+        "scrub.corruption"
+    }
+}
+
+impl ByteConvertible<CorruptionTableEntry> for CorruptionTableEntry {
+    fn from_bytes(data: &[u8]) -> Result<CorruptionTableEntry, ByteConversionError> {
+        let message_reader = serialize_packed::read_message(&mut &data[..], ReaderOptions::new())?;
+        let msg: scrub_capnp::corruption_table_entry::Reader =
+            message_reader.get_root::<scrub_capnp::corruption_table_entry::Reader>()?;
+
+        Ok(CorruptionTableEntry {
+            location: match msg.get_location()? {
+                scrub_capnp::corruption_table_entry::Location::Index => CorruptionLocation::Index,
+                scrub_capnp::corruption_table_entry::Location::Blob => CorruptionLocation::Blob,
+            },
+            expected: parse_hash(msg.get_expected()?)?,
+            actual: parse_hash(msg.get_actual()?)?,
+            detected: UnixTime::from_secs(msg.get_detected_secs()),
+        })
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, ByteConversionError> {
+        let mut message = ::capnp::message::Builder::new_default();
+        let mut builder: scrub_capnp::corruption_table_entry::Builder =
+            message.init_root::<scrub_capnp::corruption_table_entry::Builder>();
+
+        builder.set_location(match self.location {
+            CorruptionLocation::Index => scrub_capnp::corruption_table_entry::Location::Index,
+            CorruptionLocation::Blob => scrub_capnp::corruption_table_entry::Location::Blob,
+        });
+        builder.set_expected(&self.expected.0);
+        builder.set_actual(&self.actual.0);
+        builder.set_detected_secs(self.detected.as_secs());
+
+        let mut buffer: Vec<u8> = Vec::new();
+        serialize_packed::write_message(&mut buffer, &message)?;
+
+        Ok(buffer)
+    }
+}
+
 fn parse_hash(hash: &[u8]) -> Result<Hash, ByteConversionError> {
     let hash: [u8; 32] = hash
         .try_into()
@@ -336,6 +664,47 @@ pub enum Mark {
 #[derive(Debug, Clone, PartialEq)]
 pub struct MarkTableEntry {
     pub mark: Mark,
+
+    /// Set for mark rules, stored next to path marks.
+    pub rule: Option<MarkRule>,
+}
+
+/// Where the mark of a path comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarkSource {
+    /// The mark was set on that path, which is either the path
+    /// itself or one of its parent directories.
+    Path(realize_types::Path),
+
+    /// The mark was set by the mark rule with that ID.
+    Rule(u64, MarkRule),
+
+    /// The mark was set on the arena.
+    Arena,
+
+    /// No mark was set, so the default mark applies.
+    Default,
+}
+
+/// Select the files a mark rule applies to.
+///
+/// A file matches the rule if it matches all the criteria that are
+/// set.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MarkRule {
+    /// Glob pattern the path must match.
+    ///
+    /// `*` and `?` match within a path component, `**` matches any
+    /// number of components. A pattern without `/` is matched
+    /// against the file name, so `*.mp4` matches mp4 files in all
+    /// directories.
+    pub pattern: Option<String>,
+
+    /// Minimum size of the file, in bytes, inclusive.
+    pub min_size: Option<u64>,
+
+    /// Maximum size of the file, in bytes, inclusive.
+    pub max_size: Option<u64>,
 }
 
 impl NamedType for MarkTableEntry {
@@ -355,8 +724,23 @@ impl ByteConvertible<MarkTableEntry> for MarkTableEntry {
             mark_capnp::Mark::Watch => Mark::Watch,
             mark_capnp::Mark::Keep => Mark::Keep,
         };
+        let rule = if msg.has_rule() {
+            let rule = msg.get_rule()?;
+            let pattern = rule.get_pattern()?.to_str()?;
+            Some(MarkRule {
+                pattern: if pattern.is_empty() {
+                    None
+                } else {
+                    Some(pattern.to_string())
+                },
+                min_size: Some(rule.get_min_size()).filter(|s| *s > 0),
+                max_size: Some(rule.get_max_size()).filter(|s| *s > 0),
+            })
+        } else {
+            None
+        };
 
-        Ok(MarkTableEntry { mark })
+        Ok(MarkTableEntry { mark, rule })
     }
 
     fn to_bytes(&self) -> Result<Vec<u8>, ByteConversionError> {
@@ -370,6 +754,12 @@ impl ByteConvertible<MarkTableEntry> for MarkTableEntry {
             Mark::Keep => mark_capnp::Mark::Keep,
         };
         builder.set_mark(mark);
+        if let Some(rule) = &self.rule {
+            let mut rule_builder = builder.init_rule();
+            rule_builder.set_pattern(rule.pattern.as_deref().unwrap_or(""));
+            rule_builder.set_min_size(rule.min_size.unwrap_or(0));
+            rule_builder.set_max_size(rule.max_size.unwrap_or(0));
+        }
 
         let mut buffer: Vec<u8> = Vec::new();
         serialize_packed::write_message(&mut buffer, &message)?;
@@ -434,6 +824,56 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn convert_inode_table_entry() -> anyhow::Result<()> {
+        let entry = InodeTableEntry {
+            hash: Hash([0xf0; 32]),
+            size: 200,
+            mtime: UnixTime::new(1234567890, 111),
+            ctime: UnixTime::new(1234567891, 222),
+        };
+
+        assert_eq!(
+            entry,
+            InodeTableEntry::from_bytes(entry.clone().to_bytes()?.as_slice())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprint_same_content_ignores_ctime() -> anyhow::Result<()> {
+        let fingerprint = FileFingerprint {
+            dev: 1,
+            ino: 2,
+            size: 100,
+            mtime: UnixTime::from_secs(1234567890),
+            ctime: UnixTime::from_secs(1234567890),
+        };
+
+        assert!(fingerprint.same_content(&FileFingerprint {
+            ctime: UnixTime::from_secs(1234567899),
+            ..fingerprint.clone()
+        }));
+        assert!(!fingerprint.same_content(&FileFingerprint {
+            ino: 3,
+            ..fingerprint.clone()
+        }));
+        assert!(!fingerprint.same_content(&FileFingerprint {
+            mtime: UnixTime::from_secs(1234567899),
+            ..fingerprint.clone()
+        }));
+
+        let entry = InodeTableEntry::new(&fingerprint, Hash([1; 32]));
+        assert!(entry.matches(&fingerprint));
+        assert!(!entry.matches(&FileFingerprint {
+            size: 101,
+            ..fingerprint.clone()
+        }));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn convert_history_table_entry() -> anyhow::Result<()> {
         let add = HistoryTableEntry::Add(realize_types::Path::parse("foo/bar.txt")?);
@@ -458,6 +898,12 @@ mod tests {
             HistoryTableEntry::from_bytes(replace.clone().to_bytes()?.as_slice())?
         );
 
+        let link = HistoryTableEntry::Link(realize_types::Path::parse("foo/bar.txt")?);
+        assert_eq!(
+            link,
+            HistoryTableEntry::from_bytes(link.clone().to_bytes()?.as_slice())?
+        );
+
         Ok(())
     }
     #[tokio::test]
@@ -477,14 +923,34 @@ mod tests {
 
     #[tokio::test]
     async fn convert_mark_table_entry() -> anyhow::Result<()> {
-        let entry = MarkTableEntry { mark: Mark::Own };
+        let entry = MarkTableEntry {
+            mark: Mark::Own,
+            rule: None,
+        };
+
+        assert_eq!(
+            entry,
+            MarkTableEntry::from_bytes(entry.clone().to_bytes()?.as_slice())?
+        );
+
+        let entry = MarkTableEntry {
+            mark: Mark::Keep,
+            rule: None,
+        };
 
         assert_eq!(
             entry,
             MarkTableEntry::from_bytes(entry.clone().to_bytes()?.as_slice())?
         );
 
-        let entry = MarkTableEntry { mark: Mark::Keep };
+        let entry = MarkTableEntry {
+            mark: Mark::Own,
+            rule: Some(MarkRule {
+                pattern: Some("**/*.psd".to_string()),
+                min_size: None,
+                max_size: Some(10 * 1024 * 1024),
+            }),
+        };
 
         assert_eq!(
             entry,
@@ -493,4 +959,42 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn convert_corruption_table_entry() -> anyhow::Result<()> {
+        let entry = CorruptionTableEntry {
+            location: CorruptionLocation::Blob,
+            expected: Hash([1; 32]),
+            actual: Hash([2; 32]),
+            detected: UnixTime::from_secs(1234567890),
+        };
+
+        assert_eq!(
+            entry,
+            CorruptionTableEntry::from_bytes(entry.clone().to_bytes()?.as_slice())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn convert_job_history_table_entry() -> anyhow::Result<()> {
+        let entry = JobHistoryTableEntry {
+            path: realize_types::Path::parse("foo/bar.txt")?,
+            hash: Hash([1; 32]),
+            kind: JobKind::Realize,
+            start: UnixTime::from_secs(1234567890),
+            end: UnixTime::from_secs(1234567900),
+            bytes: 1024,
+            outcome: JobOutcome::Failed,
+            error: Some("no peers".to_string()),
+        };
+
+        assert_eq!(
+            entry,
+            JobHistoryTableEntry::from_bytes(entry.clone().to_bytes()?.as_slice())?
+        );
+
+        Ok(())
+    }
 }
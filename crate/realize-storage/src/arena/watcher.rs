@@ -1,9 +1,11 @@
 #![allow(dead_code)] // work in progress
 
+use crate::config::HasherConfig;
 use crate::utils::hash;
 
 use super::hasher::{self, HashResult, Hasher};
 use super::index::RealIndexAsync;
+use super::types::FileFingerprint;
 use futures::StreamExt as _;
 use notify::event::{CreateKind, DataChange, MetadataKind, ModifyKind, RemoveKind};
 use notify::{Event, EventKind, RecommendedWatcher, Watcher as _};
@@ -12,11 +14,21 @@ use std::fs::Metadata;
 use std::os::unix::fs::MetadataExt as _;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs::{self, File};
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::task;
 
+/// Info of the event sent once catchup is done.
+const CATCHUP_DONE: &str = "catchup-done";
+
+/// Info of the event sent regularly to prune the inode table.
+const PRUNE_INODES: &str = "prune-inodes";
+
+/// How often to prune the inode table, after catchup.
+const PRUNE_INODES_INTERVAL: Duration = Duration::from_secs(3600);
+
 /// Watch an arena directory and update its index.
 ///
 /// This is created with `RealWatcher::builder()`
@@ -30,6 +42,7 @@ pub struct RealWatcherBuilder {
     index: RealIndexAsync,
     exclude: Vec<realize_types::Path>,
     catchup: bool,
+    hasher_config: HasherConfig,
 }
 
 impl RealWatcherBuilder {
@@ -40,6 +53,7 @@ impl RealWatcherBuilder {
             index,
             exclude: Vec::new(),
             catchup: false,
+            hasher_config: HasherConfig::default(),
         }
     }
 
@@ -50,6 +64,13 @@ impl RealWatcherBuilder {
         self
     }
 
+    /// Configure the pool that hashes new or modified files.
+    pub fn with_hasher_config(mut self, config: &HasherConfig) -> Self {
+        self.hasher_config = config.clone();
+
+        self
+    }
+
     /// Add a single path to exclude from watching.
     pub fn exclude(mut self, path: &realize_types::Path) -> Self {
         self.exclude.push(path.clone());
@@ -71,7 +92,14 @@ impl RealWatcherBuilder {
     ///
     /// Background work is also stopped at some point after the instance is dropped.
     pub async fn spawn(self) -> anyhow::Result<RealWatcher> {
-        RealWatcher::spawn(&self.root, self.exclude, self.index, self.catchup).await
+        RealWatcher::spawn(
+            &self.root,
+            self.exclude,
+            self.index,
+            self.catchup,
+            &self.hasher_config,
+        )
+        .await
     }
 }
 
@@ -86,6 +114,7 @@ impl RealWatcher {
         exclude: Vec<realize_types::Path>,
         index: RealIndexAsync,
         catchup: bool,
+        hasher_config: &HasherConfig,
     ) -> anyhow::Result<Self> {
         let root = fs::canonicalize(&root).await?;
         let arena = index.arena().clone();
@@ -115,12 +144,12 @@ impl RealWatcher {
         let worker = Arc::new(RealWatcherWorker {
             root,
             index,
-            hasher: hasher::Hasher::new(hashed_tx),
+            hasher: hasher::Hasher::new(hasher_config, hashed_tx),
             exclude,
         });
 
         if catchup {
-            task::spawn({
+            let removed_or_modified = task::spawn({
                 let worker = Arc::clone(&worker);
                 let watch_tx = watch_tx.clone();
                 let shutdown_rx = shutdown_tx.subscribe();
@@ -136,7 +165,7 @@ impl RealWatcher {
                     }
                 }
             });
-            task::spawn({
+            let added = task::spawn({
                 let worker = Arc::clone(&worker);
                 let watch_tx = watch_tx.clone();
                 let shutdown_rx = shutdown_tx.subscribe();
@@ -146,6 +175,24 @@ impl RealWatcher {
                     }
                 }
             });
+            task::spawn({
+                let watch_tx = watch_tx.clone();
+                let shutdown_rx = shutdown_tx.subscribe();
+                async move {
+                    let _ = tokio::join!(removed_or_modified, added);
+
+                    // Going through the event loop guarantees that
+                    // all events sent by catchup have been handled,
+                    // and moved files recognized, before the inode
+                    // table is pruned.
+                    let ev = Event::new(EventKind::Other).set_info(CATCHUP_DONE);
+                    if watch_tx.send(Ok(ev)).await.is_ok() {
+                        prune_inodes_loop(watch_tx, shutdown_rx).await;
+                    }
+                }
+            });
+        } else {
+            task::spawn(prune_inodes_loop(watch_tx.clone(), shutdown_tx.subscribe()));
         }
         task::spawn({
             let worker = Arc::clone(&worker);
@@ -169,6 +216,31 @@ impl RealWatcher {
     }
 }
 
+/// Regularly ask the event loop to prune the inode table, until
+/// shutdown.
+///
+/// Inode entries are kept when files are removed, so moves can be
+/// recognized. Without pruning, entries of files that are gone would
+/// accumulate until the next catchup.
+async fn prune_inodes_loop(
+    watch_tx: mpsc::Sender<Result<Event, notify::Error>>,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    loop {
+        tokio::select!(
+            _ = shutdown_rx.recv() => {
+                break;
+            }
+            _ = tokio::time::sleep(PRUNE_INODES_INTERVAL) => {
+                let ev = Event::new(EventKind::Other).set_info(PRUNE_INODES);
+                if watch_tx.send(Ok(ev)).await.is_err() {
+                    break;
+                }
+            }
+        );
+    }
+}
+
 struct RealWatcherWorker {
     root: PathBuf,
     index: RealIndexAsync,
@@ -221,6 +293,11 @@ impl RealWatcherWorker {
                                 is_deleted = true;
                             } else if m.len() != entry.size || UnixTime::mtime(&m) != entry.mtime {
                                 is_modified = true;
+                            } else if !self.index.is_unchanged(&path, &FileFingerprint::from_metadata(&m)).await.unwrap_or(false) {
+                                // Same size and mtime, but not the
+                                // same inode or ctime; the file might
+                                // have been replaced or modified.
+                                is_modified = true;
                             }
                         }
                     }
@@ -349,11 +426,11 @@ impl RealWatcherWorker {
                         None => {
                             break;
                         }
-                        Some((path, Ok(HashResult { size, mtime, hash }))) => {
+                        Some((path, Ok(HashResult { hash, fingerprint }))) => {
                             let realpath = path.within(&self.root);
-                            if let Ok(m) = fs::symlink_metadata(realpath).await && m.len() == size && UnixTime::mtime(&m) == mtime {
+                            if let Ok(m) = fs::symlink_metadata(realpath).await && FileFingerprint::from_metadata(&m).same_content(&fingerprint) {
                                 log::debug!("[{}] Add file {path} with hash {hash}", self.index.arena());
-                                if let Err(err) = self.index.add_file(&path, size, &mtime, hash).await {
+                                if let Err(err) = self.index.add_fingerprinted_file(&path, hash, &fingerprint).await {
                                     log::debug!("[{}] Failed to add {path}: {err}", self.index.arena());
                                 }
                             }
@@ -473,6 +550,17 @@ impl RealWatcherWorker {
                     self.file_created_or_modified(realpath, &m).await?;
                 }
             }
+
+            EventKind::Other if ev.info() == Some(CATCHUP_DONE) => {
+                // Inode entries that weren't used to recognize moved
+                // files during catchup are of no use anymore.
+                self.index.prune_inodes().await?;
+            }
+            EventKind::Other if ev.info() == Some(PRUNE_INODES) => {
+                // Inode entries of files that were removed while
+                // watching, and not recognized since.
+                self.index.prune_inodes().await?;
+            }
             _ => {}
         }
 
@@ -487,6 +575,7 @@ impl RealWatcherWorker {
             }
         };
 
+        self.hasher.cancel(&path);
         self.index.remove_file_or_dir(&path).await?;
 
         Ok(())
@@ -559,10 +648,10 @@ impl RealWatcherWorker {
             return Ok(());
         }
 
-        let mtime = UnixTime::mtime(m);
+        let fingerprint = FileFingerprint::from_metadata(m);
         if self
             .index
-            .has_matching_file(&path, m.len(), &mtime)
+            .is_unchanged(&path, &fingerprint)
             .await
             .unwrap_or(false)
         {
@@ -571,11 +660,35 @@ impl RealWatcherWorker {
         if m.len() == 0 {
             log::debug!("[{}] Empty file at {path}", self.index.arena());
             self.index
-                .add_file(&path, 0, &UnixTime::mtime(&m), hash::empty())
+                .add_fingerprinted_file(&path, hash::empty(), &fingerprint)
+                .await?;
+        } else if !self
+            .index
+            .has_matching_file(&path, fingerprint.size, &fingerprint.mtime)
+            .await
+            .unwrap_or(false)
+            && let Some(hash) = self
+                .index
+                .hash_for_fingerprint(&fingerprint)
+                .await
+                .unwrap_or(None)
+        {
+            // The file was moved or linked from a file whose hash is
+            // known; no need to read it again. This doesn't apply to
+            // a file whose size and mtime still match the index entry
+            // of its path, as it might have been modified in place,
+            // with its mtime restored.
+            log::debug!(
+                "[{}] Recognized {path} as {hash} from its fingerprint",
+                self.index.arena()
+            );
+            self.index
+                .add_fingerprinted_file(&path, hash, &fingerprint)
                 .await?;
         } else {
             log::debug!("[{}] Requesting hash of {path}", self.index.arena());
-            self.hasher.request_hash(realpath.to_path_buf(), path);
+            self.hasher
+                .request_hash(realpath.to_path_buf(), path, m.len());
         }
         Ok(())
     }
@@ -1160,6 +1273,103 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn catchup_recognizes_moved_file() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let index = &fixture.index;
+
+        let foo = realize_types::Path::parse("foo")?;
+        let bar = realize_types::Path::parse("bar")?;
+        let foo_child = fixture.root.child("foo");
+        foo_child.write_str("foo")?;
+
+        // Use a fake hash; if the file is read again, the real hash
+        // ends up in the index instead.
+        let fake_hash = Hash([1; 32]);
+        index
+            .add_fingerprinted_file(
+                &foo,
+                fake_hash.clone(),
+                &FileFingerprint::from_metadata(&fs::metadata(foo_child.path()).await?),
+            )
+            .await?;
+        fs::rename(foo_child.path(), fixture.root.child("bar").path()).await?;
+
+        let _watcher = fixture.catchup_and_watch().await?;
+
+        fixture.wait_for_history_event(3).await?;
+        assert!(!index.has_file(&foo).await?);
+        assert_eq!(Some(fake_hash), index.get_file(&bar).await?.map(|e| e.hash));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn catchup_rehashes_file_with_other_ctime() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let index = &fixture.index;
+
+        let foo = realize_types::Path::parse("foo")?;
+        let foo_child = fixture.root.child("foo");
+        foo_child.write_str("foo")?;
+
+        // Same inode, size and mtime, but ctime changed, as if the
+        // file had been modified and its mtime restored.
+        let fingerprint = FileFingerprint::from_metadata(&fs::metadata(foo_child.path()).await?);
+        index
+            .add_fingerprinted_file(
+                &foo,
+                Hash([1; 32]),
+                &FileFingerprint {
+                    ctime: UnixTime::from_secs(1234567890),
+                    ..fingerprint
+                },
+            )
+            .await?;
+
+        let _watcher = fixture.catchup_and_watch().await?;
+
+        fixture.wait_for_history_event(2).await?;
+        assert_eq!(
+            Some(hash::digest("foo".as_bytes())),
+            index.get_file(&foo).await?.map(|e| e.hash)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_file_keeps_hash() -> anyhow::Result<()> {
+        let fixture = Fixture::setup().await?;
+        let index = &fixture.index;
+        let foo_child = fixture.root.child("foo");
+        foo_child.write_str("foo")?;
+
+        let foo = realize_types::Path::parse("foo")?;
+        let fake_hash = Hash([1; 32]);
+        index
+            .add_fingerprinted_file(
+                &foo,
+                fake_hash.clone(),
+                &FileFingerprint::from_metadata(&fs::metadata(foo_child.path()).await?),
+            )
+            .await?;
+
+        let _watcher = fixture.watch().await?;
+        fs::rename(foo_child.path(), fixture.root.child("bar").path()).await?;
+
+        fixture.wait_for_history_event(3).await?;
+        assert_eq!(
+            Some(fake_hash),
+            index
+                .get_file(&realize_types::Path::parse("bar")?)
+                .await?
+                .map(|e| e.hash)
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn catchup_removes_inaccessible_files() -> anyhow::Result<()> {
         let fixture = Fixture::setup().await?;
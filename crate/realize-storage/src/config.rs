@@ -40,6 +40,35 @@ pub struct ArenaConfig {
     pub db: PathBuf,
     /// Path to the directory where blob files are stored (required for arena cache).
     pub blob_dir: PathBuf,
+
+    /// Configure how files in the root are hashed.
+    #[serde(default)]
+    pub hasher: HasherConfig,
+
+    /// Configure how long local history is kept for peers.
+    #[serde(default)]
+    pub history: HistoryConfig,
+
+    /// Configure background verification of local files.
+    #[serde(default)]
+    pub scrub: ScrubConfig,
+
+    /// Configure how long the outcome of jobs is kept.
+    #[serde(default)]
+    pub job_history: JobHistoryConfig,
+
+    /// Configure when local files are moved back to the cache.
+    #[serde(default)]
+    pub unrealize: UnrealizeConfig,
+
+    /// Number of bytes at the beginning and at the end of files
+    /// newly added to the arena by peers to download in the
+    /// background.
+    ///
+    /// Media tools often look at both ends of a file before reading
+    /// it. Files aren't prefetched if unset.
+    #[serde(default)]
+    pub prefetch_head_tail: Option<u64>,
 }
 
 impl ArenaConfig {
@@ -48,6 +77,12 @@ impl ArenaConfig {
             root: Some(root),
             db,
             blob_dir,
+            hasher: HasherConfig::default(),
+            history: HistoryConfig::default(),
+            scrub: ScrubConfig::default(),
+            job_history: JobHistoryConfig::default(),
+            unrealize: UnrealizeConfig::default(),
+            prefetch_head_tail: None,
         }
     }
 
@@ -57,6 +92,139 @@ impl ArenaConfig {
             root: None,
             db,
             blob_dir,
+            hasher: HasherConfig::default(),
+            history: HistoryConfig::default(),
+            scrub: ScrubConfig::default(),
+            job_history: JobHistoryConfig::default(),
+            unrealize: UnrealizeConfig::default(),
+            prefetch_head_tail: None,
+        }
+    }
+}
+
+/// Configure hashing of local files.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct HasherConfig {
+    /// Maximum number of files hashed at the same time.
+    #[serde(default = "default_hasher_concurrency")]
+    pub concurrency: usize,
+
+    /// Maximum number of bytes read per second, shared by all
+    /// files being hashed. Unlimited if unset.
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl Default for HasherConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: default_hasher_concurrency(),
+            max_bytes_per_second: None,
         }
     }
 }
+
+fn default_hasher_concurrency() -> usize {
+    2
+}
+
+/// Configure the history of local changes kept for peers.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct HistoryConfig {
+    /// Maximum number of history entries a peer can lag behind.
+    ///
+    /// Peers lagging further behind are marked inactive, so older
+    /// history entries can be deleted. Inactive peers must go through
+    /// a full catchup the next time they connect.
+    #[serde(default = "default_max_peer_lag")]
+    pub max_peer_lag: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_peer_lag: default_max_peer_lag(),
+        }
+    }
+}
+
+fn default_max_peer_lag() -> u64 {
+    100_000
+}
+
+/// Configure the scrubber, which regularly hashes local files again to
+/// detect silent corruption.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct ScrubConfig {
+    /// Time between two scrub passes, in seconds. Scrubbing is
+    /// disabled if unset.
+    #[serde(default = "default_scrub_interval_secs")]
+    pub interval_secs: Option<u64>,
+
+    /// Maximum number of bytes read per second while scrubbing.
+    /// Unlimited if unset.
+    #[serde(default = "default_scrub_max_bytes_per_second")]
+    pub max_bytes_per_second: Option<u64>,
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_scrub_interval_secs(),
+            max_bytes_per_second: default_scrub_max_bytes_per_second(),
+        }
+    }
+}
+
+fn default_scrub_interval_secs() -> Option<u64> {
+    // Once a week
+    Some(7 * 24 * 3600)
+}
+
+fn default_scrub_max_bytes_per_second() -> Option<u64> {
+    Some(8 * 1024 * 1024)
+}
+
+/// Configure the history of jobs run in an arena.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq)]
+pub struct JobHistoryConfig {
+    /// Maximum number of entries kept; older entries are removed
+    /// first.
+    #[serde(default = "default_job_history_max_entries")]
+    pub max_entries: usize,
+
+    /// Maximum age of the entries kept, in seconds. Entries are kept
+    /// regardless of age if unset.
+    #[serde(default = "default_job_history_max_age_secs")]
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for JobHistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: default_job_history_max_entries(),
+            max_age_secs: default_job_history_max_age_secs(),
+        }
+    }
+}
+
+fn default_job_history_max_entries() -> usize {
+    10_000
+}
+
+fn default_job_history_max_age_secs() -> Option<u64> {
+    // 30 days
+    Some(30 * 24 * 3600)
+}
+
+/// Configure when files of the arena root that aren't marked Own are
+/// moved back to the cache.
+#[derive(Clone, serde::Deserialize, serde::Serialize, Debug, PartialEq, Eq, Default)]
+pub struct UnrealizeConfig {
+    /// Number of peers that must have the same version of a file
+    /// marked Watch before it is moved to the cache, where it might
+    /// later be evicted.
+    ///
+    /// Files marked Watch are left in the arena root if unset.
+    #[serde(default)]
+    pub min_peers: Option<usize>,
+}
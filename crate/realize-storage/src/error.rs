@@ -40,6 +40,9 @@ pub enum StorageError {
 
     #[error("arena {0} has no local storage")]
     NoLocalStorage(Arena),
+
+    #[error("invalid mark rule: {0}")]
+    InvalidMarkRule(String),
 }
 
 impl StorageError {
@@ -4,21 +4,44 @@
 
 use super::db::GlobalDatabase;
 use super::inode_allocator::InodeAllocator;
-use super::types::{FileAvailability, FileMetadata, InodeAssignment, ReadDirEntry};
+use super::types::{FileAvailability, FileMetadata, FileUsage, InodeAssignment, ReadDirEntry};
 use crate::arena::arena_cache::{self, ArenaCache};
 use crate::arena::notifier::{Notification, Progress};
 use crate::arena::types::LocalAvailability;
 use crate::{Blob, Inode, StorageError};
 use realize_types::{Arena, Path, Peer, UnixTime};
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
 use tokio::task;
 
+/// Maximum number of entries kept by [UnrealCacheBlocking::record_access].
+const MAX_RECENT_ACCESSES: usize = 1024;
+
+/// Period of time covered by one bucket of usage data.
+const USAGE_BUCKET: Duration = Duration::from_secs(60);
+
+/// Maximum number of buckets of usage data kept per file.
+///
+/// This limits how far back [UnrealCacheBlocking::usage] can look to
+/// `USAGE_BUCKET * MAX_USAGE_BUCKETS`.
+const MAX_USAGE_BUCKETS: usize = 60;
+
+/// Capacity of the channel returned by [UnrealCacheBlocking::usage_updates].
+const USAGE_CHANNEL_CAPACITY: usize = 128;
+
 /// A cache of remote files.
 pub struct UnrealCacheBlocking {
     db: Arc<GlobalDatabase>,
     allocator: Arc<InodeAllocator>,
     arena_caches: HashMap<Arena, Arc<ArenaCache>>,
+
+    /// Last time files were read through the cache.
+    recent_accesses: Mutex<RecentAccesses>,
+
+    /// Reports inodes whose usage changed.
+    usage_tx: broadcast::Sender<Inode>,
 }
 
 impl UnrealCacheBlocking {
@@ -31,9 +54,63 @@ impl UnrealCacheBlocking {
             db,
             allocator,
             arena_caches: HashMap::new(),
+            recent_accesses: Mutex::new(RecentAccesses::default()),
+            usage_tx: broadcast::channel(USAGE_CHANNEL_CAPACITY).0,
         }
     }
 
+    /// Remember that the file with the given inode was just opened
+    /// by a user.
+    ///
+    /// Only the most recent accesses are kept.
+    pub fn record_access(&self, inode: Inode) {
+        self.recent_accesses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(inode, 1, 0);
+        let _ = self.usage_tx.send(inode);
+    }
+
+    /// Remember that `bytes` were just read by a user from the file
+    /// with the given inode.
+    pub fn record_read(&self, inode: Inode, bytes: u64) {
+        self.recent_accesses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(inode, 0, bytes);
+        let _ = self.usage_tx.send(inode);
+    }
+
+    /// Return how much the file with the given inode was used within
+    /// the last `window`.
+    ///
+    /// Usage data is only kept for recently accessed files and for
+    /// at most an hour.
+    pub fn usage(&self, inode: Inode, window: Duration) -> FileUsage {
+        self.recent_accesses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .usage(inode, window)
+    }
+
+    /// Subscribe to changes to the usage of files.
+    ///
+    /// The channel reports the inode of the files whose usage was
+    /// just recorded by [UnrealCacheBlocking::record_access] or
+    /// [UnrealCacheBlocking::record_read].
+    pub fn usage_updates(&self) -> broadcast::Receiver<Inode> {
+        self.usage_tx.subscribe()
+    }
+
+    /// Return the last time the file with the given inode was read by
+    /// a user, if it was read recently.
+    pub fn last_access(&self, inode: Inode) -> Option<Instant> {
+        self.recent_accesses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(inode)
+    }
+
     /// Lists arenas available in this database
     pub fn arenas(&self) -> impl Iterator<Item = Arena> {
         self.arena_caches.keys().map(|a| *a)
@@ -177,6 +254,94 @@ impl UnrealCacheBlocking {
     }
 }
 
+/// Most recent accesses, by inode.
+#[derive(Default)]
+struct RecentAccesses {
+    /// Order and usage of the last accesses, by inode.
+    accesses: HashMap<Inode, RecentAccess>,
+
+    /// Increasing access counter, to find the oldest access.
+    counter: u64,
+}
+
+/// Usage of a recently accessed file.
+struct RecentAccess {
+    /// Value of [RecentAccesses::counter] at the last access.
+    counter: u64,
+
+    /// Time of the last access.
+    last: Instant,
+
+    /// Usage data, oldest bucket first.
+    buckets: VecDeque<UsageBucket>,
+}
+
+/// Usage of a file within [USAGE_BUCKET] starting at `start`.
+struct UsageBucket {
+    start: Instant,
+    opens: u32,
+    bytes: u64,
+}
+
+impl RecentAccesses {
+    fn record(&mut self, inode: Inode, opens: u32, bytes: u64) {
+        if self.accesses.len() >= MAX_RECENT_ACCESSES
+            && !self.accesses.contains_key(&inode)
+            && let Some(oldest) = self
+                .accesses
+                .iter()
+                .min_by_key(|(_, access)| access.counter)
+                .map(|(inode, _)| *inode)
+        {
+            self.accesses.remove(&oldest);
+        }
+        self.counter += 1;
+        let now = Instant::now();
+        let access = self.accesses.entry(inode).or_insert_with(|| RecentAccess {
+            counter: 0,
+            last: now,
+            buckets: VecDeque::new(),
+        });
+        access.counter = self.counter;
+        access.last = now;
+        match access.buckets.back_mut() {
+            Some(bucket) if now.duration_since(bucket.start) < USAGE_BUCKET => {
+                bucket.opens += opens;
+                bucket.bytes += bytes;
+            }
+            _ => {
+                if access.buckets.len() >= MAX_USAGE_BUCKETS {
+                    access.buckets.pop_front();
+                }
+                access.buckets.push_back(UsageBucket {
+                    start: now,
+                    opens,
+                    bytes,
+                });
+            }
+        }
+    }
+
+    fn get(&self, inode: Inode) -> Option<Instant> {
+        self.accesses.get(&inode).map(|access| access.last)
+    }
+
+    fn usage(&self, inode: Inode, window: Duration) -> FileUsage {
+        let mut usage = FileUsage::default();
+        if let Some(access) = self.accesses.get(&inode) {
+            for bucket in access.buckets.iter().rev() {
+                if bucket.start.elapsed() > window {
+                    break;
+                }
+                usage.opens += bucket.opens;
+                usage.bytes += bucket.bytes;
+            }
+        }
+
+        usage
+    }
+}
+
 #[derive(Clone)]
 pub struct UnrealCacheAsync {
     inner: Arc<UnrealCacheBlocking>,
@@ -226,6 +391,35 @@ impl UnrealCacheAsync {
         self.inner.arena_root(arena)
     }
 
+    /// Remember that the file with the given inode was just opened
+    /// by a user.
+    pub fn record_access(&self, inode: Inode) {
+        self.inner.record_access(inode)
+    }
+
+    /// Remember that `bytes` were just read by a user from the file
+    /// with the given inode.
+    pub fn record_read(&self, inode: Inode, bytes: u64) {
+        self.inner.record_read(inode, bytes)
+    }
+
+    /// Return how much the file with the given inode was used within
+    /// the last `window`.
+    pub fn usage(&self, inode: Inode, window: Duration) -> FileUsage {
+        self.inner.usage(inode, window)
+    }
+
+    /// Subscribe to changes to the usage of files.
+    pub fn usage_updates(&self) -> broadcast::Receiver<Inode> {
+        self.inner.usage_updates()
+    }
+
+    /// Return the last time the file with the given inode was read by
+    /// a user, if it was read recently.
+    pub fn last_access(&self, inode: Inode) -> Option<Instant> {
+        self.inner.last_access(inode)
+    }
+
     pub async fn lookup(
         &self,
         parent_inode: Inode,
@@ -503,4 +697,72 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn record_access() -> anyhow::Result<()> {
+        let fixture = Fixture::setup_with_arena(test_arena()).await?;
+        let cache = &fixture.cache;
+
+        assert_eq!(None, cache.last_access(Inode(10)));
+        cache.record_access(Inode(10));
+        let first = cache.last_access(Inode(10));
+        assert!(first.is_some());
+
+        cache.record_access(Inode(10));
+        assert!(cache.last_access(Inode(10)) >= first);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_usage() -> anyhow::Result<()> {
+        let fixture = Fixture::setup_with_arena(test_arena()).await?;
+        let cache = &fixture.cache;
+        let mut updates = cache.usage_updates();
+
+        assert_eq!(
+            FileUsage::default(),
+            cache.usage(Inode(10), Duration::from_secs(3600))
+        );
+        cache.record_access(Inode(10));
+        cache.record_read(Inode(10), 100);
+        cache.record_access(Inode(10));
+        cache.record_read(Inode(10), 50);
+        cache.record_access(Inode(11));
+
+        assert_eq!(
+            FileUsage {
+                opens: 2,
+                bytes: 150
+            },
+            cache.usage(Inode(10), Duration::from_secs(3600))
+        );
+        assert_eq!(
+            FileUsage { opens: 1, bytes: 0 },
+            cache.usage(Inode(11), Duration::from_secs(3600))
+        );
+        assert_eq!(Inode(10), updates.recv().await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn record_access_forgets_oldest() -> anyhow::Result<()> {
+        let fixture = Fixture::setup_with_arena(test_arena()).await?;
+        let cache = &fixture.cache;
+
+        for i in 0..(MAX_RECENT_ACCESSES as u64 + 1) {
+            cache.record_access(Inode(1000 + i));
+        }
+
+        assert_eq!(None, cache.last_access(Inode(1000)));
+        assert!(cache.last_access(Inode(1001)).is_some());
+        assert!(
+            cache
+                .last_access(Inode(1000 + MAX_RECENT_ACCESSES as u64))
+                .is_some()
+        );
+
+        Ok(())
+    }
 }
@@ -23,6 +23,17 @@ pub struct FileAvailability {
     pub peers: Vec<Peer>,
 }
 
+/// How much a file was used through the cache within a period of
+/// time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileUsage {
+    /// Number of times the file was opened.
+    pub opens: u32,
+
+    /// Number of bytes read from the file.
+    pub bytes: u64,
+}
+
 /// An entry in a directory listing.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ReadDirEntry {
@@ -4,10 +4,10 @@ use config::StorageConfig;
 use futures::Stream;
 use global::db::GlobalDatabase;
 use global::inode_allocator::InodeAllocator;
-use realize_types::{self, Arena, ByteRange, Delta, Hash, Path, Peer, Signature};
+use realize_types::{self, Arena, ByteRange, Delta, Hash, Path, Peer, Signature, UnixTime};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio::task::{self, JoinHandle};
 use tokio_stream::{StreamExt, StreamMap};
 use utils::redb_utils;
@@ -22,22 +22,30 @@ mod types;
 pub mod utils;
 
 pub use arena::blob::{Blob, BlobIncomplete};
-pub use arena::engine::{Job, JobStatus};
+pub use arena::engine::{Job, JobStatus, MarkImpact, PlannedJob};
 pub use arena::indexed_store::Reader;
+pub use arena::job_history::JobHistoryFilter;
 pub use arena::notifier::Notification;
 pub use arena::notifier::Progress;
 pub use arena::store::{Options as RealStoreOptions, RealStore, RealStoreError, SyncedFile};
-pub use arena::types::{LocalAvailability, Mark};
+pub use arena::types::{
+    CorruptionLocation, CorruptionTableEntry, FailedJobTableEntry, JobHistoryTableEntry, JobKind,
+    JobOutcome, LocalAvailability, Mark, MarkRule, MarkSource,
+};
 pub use error::StorageError;
 pub use global::cache::UnrealCacheAsync;
-pub use global::types::{FileAvailability, FileMetadata, InodeAssignment};
+pub use global::types::{FileAvailability, FileMetadata, FileUsage, InodeAssignment};
 pub use types::{Inode, JobId};
 
+/// Capacity of the channel returned by [Storage::new_files].
+const NEW_FILE_CHANNEL_CAPACITY: usize = 128;
+
 /// Local storage, including the real store and an unreal cache.
 pub struct Storage {
     cache: UnrealCacheAsync,
     arena_storage: HashMap<Arena, ArenaStorage>,
     store: RealStore,
+    new_file_tx: broadcast::Sender<(Arena, Path)>,
 }
 
 impl Storage {
@@ -69,10 +77,13 @@ impl Storage {
         )
         .await?;
 
+        let (new_file_tx, _) = broadcast::channel(NEW_FILE_CHANNEL_CAPACITY);
+
         Ok(Arc::new(Self {
             cache,
             arena_storage,
             store,
+            new_file_tx,
         }))
     }
 
@@ -98,17 +109,22 @@ impl Storage {
     /// Subscribe to files in the given arena.
     ///
     /// The arena must have an index; check with [Storage::indexed_arenas] first.
+    ///
+    /// The progress reported by `peer` is recorded, so history
+    /// entries it has seen can eventually be deleted.
     pub async fn subscribe(
         &self,
         arena: Arena,
+        peer: Peer,
         tx: mpsc::Sender<Notification>,
         progress: Option<Progress>,
     ) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
-        let index = match &self.arena_storage(arena)?.indexed {
+        let indexed = match &self.arena_storage(arena)?.indexed {
             None => return Err(StorageError::NoLocalStorage(arena).into()),
-            Some(indexed) => indexed.index.clone(),
+            Some(indexed) => indexed,
         };
-        arena::notifier::subscribe(index, tx, progress).await
+        arena::notifier::subscribe(indexed.index.clone(), peer, tx, progress, &indexed.history)
+            .await
     }
 
     /// Take into account notification from a remote peer.
@@ -124,12 +140,29 @@ impl Storage {
                 log::warn!("Failed to update local store for {notification:?}: {err:?}",);
             }
         }
+        let new_file = match &notification {
+            Notification::Add { arena, path, .. } | Notification::Replace { arena, path, .. } => {
+                Some((*arena, path.clone()))
+            }
+            _ => None,
+        };
         let cache = Arc::clone(&arena_storage.cache);
         task::spawn_blocking(move || cache.update(peer, notification)).await??;
+        if let Some(new_file) = new_file {
+            let _ = self.new_file_tx.send(new_file);
+        }
 
         Ok(())
     }
 
+    /// Subscribe to files added to the cache or replaced by
+    /// [Storage::update].
+    ///
+    /// Files reported during catchup aren't included.
+    pub fn new_files(&self) -> broadcast::Receiver<(Arena, Path)> {
+        self.new_file_tx.subscribe()
+    }
+
     /// Set the default mark for the files in the given arena.
     pub async fn set_arena_mark(
         self: &Arc<Self>,
@@ -165,6 +198,86 @@ impl Storage {
         task::spawn_blocking(move || this.arena_storage(arena)?.pathmarks.get_mark(&path)).await?
     }
 
+    /// Unset the mark of a specific path in the given arena, so it
+    /// inherits its mark again.
+    pub async fn clear_mark(
+        self: &Arc<Self>,
+        arena: Arena,
+        path: &Path,
+    ) -> Result<(), StorageError> {
+        let this = Arc::clone(&self);
+        let path = path.clone();
+        task::spawn_blocking(move || this.arena_storage(arena)?.pathmarks.clear_mark(&path)).await?
+    }
+
+    /// Get the mark for a specific path in the given arena and where
+    /// it comes from.
+    pub async fn explain_mark(
+        self: &Arc<Self>,
+        arena: Arena,
+        path: &Path,
+    ) -> Result<(Mark, MarkSource), StorageError> {
+        let this = Arc::clone(&self);
+        let path = path.clone();
+        task::spawn_blocking(move || this.arena_storage(arena)?.pathmarks.explain_mark(&path))
+            .await?
+    }
+
+    /// List the marks set on the given arena, with no path, and on
+    /// paths in that arena.
+    pub async fn list_marks(
+        self: &Arc<Self>,
+        arena: Arena,
+    ) -> Result<Vec<(Option<Path>, Mark)>, StorageError> {
+        let this = Arc::clone(&self);
+        task::spawn_blocking(move || this.arena_storage(arena)?.pathmarks.marks()).await?
+    }
+
+    /// Compute what setting `mark` on `path`, or on the arena if
+    /// `path` is None, would do, without changing any mark.
+    pub async fn mark_impact(
+        &self,
+        arena: Arena,
+        path: Option<&Path>,
+        mark: Mark,
+    ) -> Result<MarkImpact, StorageError> {
+        self.engine(arena)?.mark_impact(path.cloned(), mark).await
+    }
+
+    /// Add a rule that sets `mark` on the files of the arena it
+    /// selects. Return the ID of the new rule.
+    pub async fn add_mark_rule(
+        self: &Arc<Self>,
+        arena: Arena,
+        rule: MarkRule,
+        mark: Mark,
+    ) -> Result<u64, StorageError> {
+        let this = Arc::clone(&self);
+        task::spawn_blocking(move || this.arena_storage(arena)?.pathmarks.add_rule(rule, mark))
+            .await?
+    }
+
+    /// Remove a mark rule from the arena. Return false if there was
+    /// no rule with that ID.
+    pub async fn remove_mark_rule(
+        self: &Arc<Self>,
+        arena: Arena,
+        id: u64,
+    ) -> Result<bool, StorageError> {
+        let this = Arc::clone(&self);
+        task::spawn_blocking(move || this.arena_storage(arena)?.pathmarks.remove_rule(id)).await?
+    }
+
+    /// List the mark rules of the arena, with their ID and mark, in
+    /// the order in which they're evaluated.
+    pub async fn mark_rules(
+        self: &Arc<Self>,
+        arena: Arena,
+    ) -> Result<Vec<(u64, MarkRule, Mark)>, StorageError> {
+        let this = Arc::clone(&self);
+        task::spawn_blocking(move || this.arena_storage(arena)?.pathmarks.rules()).await?
+    }
+
     /// Get a reader on the given file, if possible.
     pub async fn reader(
         &self,
@@ -216,6 +329,25 @@ impl Storage {
             .await
     }
 
+    /// Create a file as a hard link of another file in the
+    /// filesystem, when peers reported it as such, instead of
+    /// downloading it.
+    ///
+    /// Gives up and returns false if `path` isn't known to be a
+    /// link of a local file with `cache_hash` or if the current
+    /// version of `path` doesn't match `index_hash`.
+    pub async fn link(
+        &self,
+        arena: Arena,
+        path: &realize_types::Path,
+        cache_hash: &Hash,
+        index_hash: Option<&Hash>,
+    ) -> Result<bool, StorageError> {
+        self.arena_storage(arena)?
+            .link(path, cache_hash, index_hash)
+            .await
+    }
+
     pub async fn unrealize(
         &self,
         arena: Arena,
@@ -280,6 +412,195 @@ impl Storage {
         self.engine(arena)?.job_for_path(path).await
     }
 
+    /// Return files whose content was found by the scrubber not to
+    /// match their hash, in all arenas.
+    pub async fn corruptions(
+        self: &Arc<Self>,
+    ) -> Result<Vec<(Arena, Path, CorruptionTableEntry)>, StorageError> {
+        let this = Arc::clone(&self);
+        task::spawn_blocking(move || {
+            let mut ret = vec![];
+            for (arena, storage) in &this.arena_storage {
+                for (path, entry) in arena::scrubber::all_corruptions(&storage.db)? {
+                    ret.push((*arena, path, entry));
+                }
+            }
+
+            Ok(ret)
+        })
+        .await?
+    }
+
+    /// Keep the outcome of a job in the job history of its arena.
+    pub async fn record_job(
+        self: &Arc<Self>,
+        arena: Arena,
+        entry: JobHistoryTableEntry,
+    ) -> Result<(), StorageError> {
+        let this = Arc::clone(self);
+        task::spawn_blocking(move || this.arena_storage(arena)?.job_history.record(entry)).await?
+    }
+
+    /// Return the job history entries selected by `filter`, the most
+    /// recent first.
+    ///
+    /// Entries of all arenas are returned if `arena` is `None`.
+    pub async fn job_history(
+        self: &Arc<Self>,
+        arena: Option<Arena>,
+        filter: JobHistoryFilter,
+    ) -> Result<Vec<(Arena, JobHistoryTableEntry)>, StorageError> {
+        let this = Arc::clone(self);
+        task::spawn_blocking(move || {
+            let arenas = match arena {
+                Some(arena) => vec![(arena, this.arena_storage(arena)?)],
+                None => this.arena_storage.iter().map(|(a, s)| (*a, s)).collect(),
+            };
+            let mut ret = vec![];
+            for (arena, storage) in arenas {
+                for entry in storage.job_history.query(&filter)? {
+                    ret.push((arena, entry));
+                }
+            }
+            ret.sort_by(|(_, a), (_, b)| b.end.cmp(&a.end));
+            if let Some(limit) = filter.limit {
+                ret.truncate(limit);
+            }
+
+            Ok(ret)
+        })
+        .await?
+    }
+
+    /// List the jobs that are still to be run, together with how
+    /// much of the files is already available locally and which
+    /// peers can serve them.
+    ///
+    /// Jobs of all arenas are returned if `arena` is `None`. If
+    /// `path_prefix` is set, only jobs on that path or on files
+    /// within that directory are returned.
+    ///
+    /// This doesn't consume the jobs.
+    pub async fn job_plan(
+        &self,
+        arena: Option<Arena>,
+        path_prefix: Option<Path>,
+    ) -> Result<Vec<PlannedJob>, StorageError> {
+        let mut ret = vec![];
+        for (arena, engine) in self.engines(arena)? {
+            for (job_id, job, backoff_until) in engine.pending_jobs(path_prefix.clone()).await? {
+                let mut planned = PlannedJob {
+                    arena,
+                    job_id,
+                    job,
+                    size: None,
+                    local_bytes: 0,
+                    peers: vec![],
+                    backoff_until,
+                };
+                let inode = match self.cache.lookup_path(arena, planned.job.path()).await {
+                    Ok((inode, _)) => inode,
+                    // The file isn't in the cache; size and peers are unknown.
+                    Err(StorageError::NotFound) => {
+                        ret.push(planned);
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                match self.cache.file_availability(inode).await {
+                    Ok(availability) => {
+                        planned.size = Some(availability.metadata.size);
+                        planned.peers = availability.peers;
+                    }
+                    Err(StorageError::NotFound) => {}
+                    Err(err) => return Err(err),
+                }
+                planned.local_bytes = match &planned.job {
+                    // The file to unrealize is in the index.
+                    Job::Unrealize(_, _) => planned.size.unwrap_or(0),
+                    _ => match self.cache.local_availability(inode).await? {
+                        LocalAvailability::Missing => 0,
+                        LocalAvailability::Partial(_, ranges) => ranges.bytecount(),
+                        LocalAvailability::Complete | LocalAvailability::Verified => {
+                            planned.size.unwrap_or(0)
+                        }
+                    },
+                };
+                ret.push(planned);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// List the jobs that failed and are waiting to be retried, with
+    /// the number of times they failed and the time after which
+    /// they'll be retried.
+    ///
+    /// Jobs of all arenas are returned if `arena` is `None`.
+    pub async fn failed_jobs(
+        &self,
+        arena: Option<Arena>,
+    ) -> Result<Vec<(Arena, JobId, Path, FailedJobTableEntry)>, StorageError> {
+        let mut ret = vec![];
+        for (arena, engine) in self.engines(arena)? {
+            for (job_id, path, entry) in engine.failed_jobs().await? {
+                ret.push((arena, job_id, path, entry));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Have a failed job retried right away, with a new [JobId].
+    ///
+    /// Return false if the job isn't a failed job.
+    pub async fn retry_job(&self, arena: Arena, job_id: JobId) -> Result<bool, StorageError> {
+        self.engine(arena)?.retry_job(job_id).await
+    }
+
+    /// Stop creating jobs for `path` until its version changes.
+    ///
+    /// Return the ignored version or `None` if the file is unknown.
+    pub async fn ignore_path(
+        &self,
+        arena: Arena,
+        path: &Path,
+    ) -> Result<Option<Hash>, StorageError> {
+        self.engine(arena)?.ignore_path(path).await
+    }
+
+    /// Keep the file at `path` in the cache until `until`, downloading
+    /// it completely, even if it's only marked Watch.
+    ///
+    /// Return false if the file isn't in the cache.
+    pub async fn pin_path(
+        &self,
+        arena: Arena,
+        path: &Path,
+        until: UnixTime,
+    ) -> Result<bool, StorageError> {
+        self.engine(arena)?.pin_path(path, until).await
+    }
+
+    /// Return the directory where the blobs of the given arena are
+    /// stored.
+    ///
+    /// This is where files are downloaded before they're moved into
+    /// the arena root, on the same filesystem.
+    pub fn blob_dir(&self, arena: Arena) -> Result<&std::path::Path, StorageError> {
+        Ok(&self.arena_storage(arena)?.blob_dir)
+    }
+
+    /// Return the root directory of the given arena, if it has one.
+    pub fn arena_root(&self, arena: Arena) -> Result<Option<&std::path::Path>, StorageError> {
+        Ok(self
+            .arena_storage(arena)?
+            .indexed
+            .as_ref()
+            .map(|indexed| indexed.root.as_path()))
+    }
+
     /// Return the engine for an arena.
     ///
     /// Only indexed arenas have engines.
@@ -287,6 +608,22 @@ impl Storage {
         Ok(&self.arena_storage(arena)?.engine)
     }
 
+    /// Return the engine of `arena` or, if `arena` is `None`, the
+    /// engines of all arenas, sorted by arena name.
+    fn engines(&self, arena: Option<Arena>) -> Result<Vec<(Arena, &Arc<Engine>)>, StorageError> {
+        let mut engines = match arena {
+            Some(arena) => vec![(arena, self.engine(arena)?)],
+            None => self
+                .arena_storage
+                .iter()
+                .map(|(a, s)| (*a, &s.engine))
+                .collect(),
+        };
+        engines.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        Ok(engines)
+    }
+
     /// Return the index for the given arena, if one exists.
     fn arena_storage(&self, arena: Arena) -> Result<&ArenaStorage, StorageError> {
         self.arena_storage
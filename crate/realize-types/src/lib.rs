@@ -3,6 +3,7 @@ mod byterange;
 mod data;
 mod path;
 mod peer;
+pub mod schedule;
 mod time;
 
 pub use arena::Arena;
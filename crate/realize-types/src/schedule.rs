@@ -0,0 +1,388 @@
+use crate::UnixTime;
+use std::fmt;
+use std::str::FromStr;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+
+/// Day of the week.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+
+    /// Number of days since Monday.
+    fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+/// A time of day, in local time, written "HH:MM".
+///
+/// "24:00" is accepted, as the end of the day.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay {
+    /// Minutes since midnight.
+    minutes: u32,
+}
+
+impl TimeOfDay {
+    /// Create a time of day, if valid.
+    pub fn new(hour: u32, minute: u32) -> Option<Self> {
+        if minute >= 60 || hour > 24 || (hour == 24 && minute != 0) {
+            return None;
+        }
+
+        Some(Self {
+            minutes: hour * 60 + minute,
+        })
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time of day: \"{s}\", expected HH:MM");
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        let hour = hour.parse().map_err(|_| invalid())?;
+        let minute = minute.parse().map_err(|_| invalid())?;
+
+        TimeOfDay::new(hour, minute).ok_or_else(invalid)
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(value: TimeOfDay) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// A point in the week, in local time, with a resolution of one
+/// minute.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct WeekMinute(u32);
+
+impl WeekMinute {
+    pub fn new(day: Weekday, time: TimeOfDay) -> Self {
+        Self((day.index() * MINUTES_PER_DAY + time.minutes) % MINUTES_PER_WEEK)
+    }
+
+    /// The current point in the week, in the local timezone.
+    pub fn now() -> Self {
+        let now = jiff::Zoned::now();
+
+        Self::from_zoned(&now)
+    }
+
+    fn from_zoned(zoned: &jiff::Zoned) -> Self {
+        let day = zoned.weekday().to_monday_zero_offset() as u32;
+        let minutes = zoned.hour() as u32 * 60 + zoned.minute() as u32;
+
+        Self(day * MINUTES_PER_DAY + minutes)
+    }
+
+    /// Number of minutes from `self` to the next time it is `other`.
+    fn minutes_until(self, other: WeekMinute) -> u32 {
+        (other.0 + MINUTES_PER_WEEK - self.0) % MINUTES_PER_WEEK
+    }
+
+    fn plus(self, minutes: u32) -> WeekMinute {
+        WeekMinute((self.0 + minutes) % MINUTES_PER_WEEK)
+    }
+}
+
+/// A range of time repeated on some days of the week.
+#[derive(Clone, Eq, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+pub struct TimeWindow {
+    /// Days on which the window starts; every day if empty.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+
+    /// Start of the window, in local time.
+    pub start: TimeOfDay,
+
+    /// End of the window, in local time.
+    ///
+    /// If it is not after `start`, the window ends on the next day.
+    pub end: TimeOfDay,
+}
+
+impl TimeWindow {
+    /// Check whether `now` is within the window.
+    pub fn contains(&self, now: WeekMinute) -> bool {
+        self.remaining(now).is_some()
+    }
+
+    /// Length of the window, in minutes.
+    fn length(&self) -> u32 {
+        if self.end.minutes > self.start.minutes {
+            self.end.minutes - self.start.minutes
+        } else {
+            MINUTES_PER_DAY - self.start.minutes + self.end.minutes
+        }
+    }
+
+    /// Points in the week at which the window starts.
+    fn starts(&self) -> impl Iterator<Item = WeekMinute> {
+        let days = if self.days.is_empty() {
+            &Weekday::ALL[..]
+        } else {
+            &self.days[..]
+        };
+
+        days.iter().map(|day| WeekMinute::new(*day, self.start))
+    }
+
+    /// Number of minutes left until the end of the window, if `now`
+    /// is within the window.
+    fn remaining(&self, now: WeekMinute) -> Option<u32> {
+        let length = self.length();
+
+        self.starts()
+            .map(|start| start.minutes_until(now))
+            .filter(|elapsed| *elapsed < length)
+            .map(|elapsed| length - elapsed)
+            .max()
+    }
+}
+
+/// Time windows within which something is allowed.
+///
+/// An empty schedule allows it at any time.
+#[derive(Clone, Default, Eq, PartialEq, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct Schedule(pub Vec<TimeWindow>);
+
+/// State of a [Schedule] at a given time.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ScheduleState {
+    /// Whether the schedule currently allows it.
+    pub open: bool,
+
+    /// When `open` changes next, if ever.
+    pub next_change: Option<UnixTime>,
+}
+
+impl Schedule {
+    /// Check whether the schedule allows it at time `now`.
+    pub fn is_open(&self, now: WeekMinute) -> bool {
+        self.0.is_empty() || self.0.iter().any(|w| w.contains(now))
+    }
+
+    /// Number of minutes from `now` until the schedule changes from
+    /// open to closed or closed to open, if ever.
+    ///
+    /// Windows that overlap or follow each other are merged.
+    pub fn minutes_until_change(&self, now: WeekMinute) -> Option<u32> {
+        if self.0.is_empty() {
+            return None;
+        }
+        if !self.is_open(now) {
+            return self
+                .0
+                .iter()
+                .flat_map(|w| w.starts())
+                .map(|start| now.minutes_until(start))
+                .min();
+        }
+
+        let mut elapsed = 0;
+        while elapsed < MINUTES_PER_WEEK {
+            match self
+                .0
+                .iter()
+                .flat_map(|w| w.remaining(now.plus(elapsed)))
+                .max()
+            {
+                None => return Some(elapsed),
+                Some(remaining) => elapsed += remaining,
+            }
+        }
+
+        // Always open
+        None
+    }
+
+    /// State of the schedule, in the local timezone.
+    pub fn state_now(&self) -> ScheduleState {
+        let zoned = jiff::Zoned::now();
+        let now = WeekMinute::from_zoned(&zoned);
+        let start_of_minute = zoned.timestamp().as_second() - zoned.second() as i64;
+
+        ScheduleState {
+            open: self.is_open(now),
+            next_change: self.minutes_until_change(now).map(|minutes| {
+                UnixTime::from_secs((start_of_minute + minutes as i64 * 60).max(0) as u64)
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: Weekday, time: &str) -> anyhow::Result<WeekMinute> {
+        Ok(WeekMinute::new(
+            day,
+            time.parse().map_err(anyhow::Error::msg)?,
+        ))
+    }
+
+    fn window(days: &[Weekday], start: &str, end: &str) -> anyhow::Result<TimeWindow> {
+        Ok(TimeWindow {
+            days: days.to_vec(),
+            start: start.parse().map_err(anyhow::Error::msg)?,
+            end: end.parse().map_err(anyhow::Error::msg)?,
+        })
+    }
+
+    #[test]
+    fn parse_time_of_day() -> anyhow::Result<()> {
+        assert_eq!(TimeOfDay::new(7, 30), "07:30".parse().ok());
+        assert_eq!(TimeOfDay::new(24, 0), "24:00".parse().ok());
+        assert!("24:01".parse::<TimeOfDay>().is_err());
+        assert!("12:60".parse::<TimeOfDay>().is_err());
+        assert!("noon".parse::<TimeOfDay>().is_err());
+        assert_eq!("07:30", TimeOfDay::new(7, 30).unwrap().to_string());
+
+        Ok(())
+    }
+
+    #[test]
+    fn empty_schedule_is_always_open() -> anyhow::Result<()> {
+        let schedule = Schedule::default();
+        let now = at(Weekday::Wed, "12:00")?;
+
+        assert!(schedule.is_open(now));
+        assert_eq!(None, schedule.minutes_until_change(now));
+
+        Ok(())
+    }
+
+    #[test]
+    fn overnight_window() -> anyhow::Result<()> {
+        let schedule = Schedule(vec![window(&[], "19:00", "07:00")?]);
+
+        assert!(!schedule.is_open(at(Weekday::Wed, "12:00")?));
+        assert_eq!(
+            Some(7 * 60),
+            schedule.minutes_until_change(at(Weekday::Wed, "12:00")?)
+        );
+        assert!(schedule.is_open(at(Weekday::Wed, "19:00")?));
+        assert!(schedule.is_open(at(Weekday::Thu, "06:59")?));
+        assert_eq!(
+            Some(1),
+            schedule.minutes_until_change(at(Weekday::Thu, "06:59")?)
+        );
+        assert!(!schedule.is_open(at(Weekday::Thu, "07:00")?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn weekend_window_wraps_around_week() -> anyhow::Result<()> {
+        let schedule = Schedule(vec![window(&[Weekday::Sun], "22:00", "02:00")?]);
+
+        assert!(schedule.is_open(at(Weekday::Mon, "01:00")?));
+        assert!(!schedule.is_open(at(Weekday::Mon, "02:00")?));
+        assert_eq!(
+            Some(6 * MINUTES_PER_DAY + 20 * 60),
+            schedule.minutes_until_change(at(Weekday::Mon, "02:00")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_adjacent_windows() -> anyhow::Result<()> {
+        let schedule = Schedule(vec![
+            window(&[Weekday::Sat, Weekday::Sun], "00:00", "24:00")?,
+            window(&[Weekday::Mon], "00:00", "08:00")?,
+        ]);
+
+        assert_eq!(
+            Some(MINUTES_PER_DAY + 12 * 60 + 8 * 60),
+            schedule.minutes_until_change(at(Weekday::Sat, "12:00")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn always_open_window() -> anyhow::Result<()> {
+        let schedule = Schedule(vec![window(&[], "00:00", "00:00")?]);
+
+        assert!(schedule.is_open(at(Weekday::Fri, "09:00")?));
+        assert_eq!(
+            None,
+            schedule.minutes_until_change(at(Weekday::Fri, "09:00")?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_schedule() -> anyhow::Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Config {
+            schedule: Schedule,
+        }
+        let config: Config = toml::from_str(
+            r#"
+            [[schedule]]
+            days = ["sat", "sun"]
+            start = "00:00"
+            end = "24:00"
+
+            [[schedule]]
+            start = "19:00"
+            end = "07:00"
+            "#,
+        )?;
+
+        assert_eq!(
+            Schedule(vec![
+                window(&[Weekday::Sat, Weekday::Sun], "00:00", "24:00")?,
+                window(&[], "19:00", "07:00")?,
+            ]),
+            config.schedule
+        );
+
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use std::os::unix::fs::MetadataExt as _;
 use std::{
     fs::Metadata,
     time::{Duration, SystemTime, SystemTimeError},
@@ -53,6 +54,14 @@ impl UnixTime {
             .unwrap_or(UnixTime::ZERO)
     }
 
+    /// Extract status change time from the given file metadata.
+    pub fn ctime(m: &Metadata) -> UnixTime {
+        match (u64::try_from(m.ctime()), u32::try_from(m.ctime_nsec())) {
+            (Ok(secs), Ok(nsecs)) => UnixTime::new(secs, nsecs),
+            _ => UnixTime::ZERO,
+        }
+    }
+
     /// Seconds since start of the UNIX epoch.
     pub fn as_secs(&self) -> u64 {
         self.0.as_secs()